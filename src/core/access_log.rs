@@ -0,0 +1,416 @@
+use crate::core::error::ServerError;
+use crate::core::http::HttpRequest;
+use crate::core::util::DateTime;
+use crate::core::ConfigFile;
+use crate::error;
+use std::fs::{File, OpenOptions};
+use std::io::{Result, Write};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+const COMMON: &str = "%h %l %u %t \"%r\" %>s %b";
+const COMBINED: &str = "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+/**
+    The format of each access log line, using the directives of Apache's `mod_log_config`.
+
+    %h  client IP           %a  peer address        %l  always `-`
+    %u  user                %t  time received       %r  request line
+    %m  method              %U  path                %q  query string
+    %H  protocol            %s  status              %b  body bytes, `-` for none
+    %B  body bytes          %D  duration in µs      %T  duration in seconds
+    %{ms}T  duration in ms  %{Name}i  request header    %L  request ID
+    %%  a literal `%`
+
+    `%r` is the request line as the client sent it, while `%U` and `%q` are the path and
+    query after normalization. Values from the client are escaped.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogFormat {
+    /** `%h %l %u %t "%r" %>s %b` */
+    Common,
+    /** The common format followed by the `Referer` and `User-Agent` headers. */
+    Combined,
+    Custom(String),
+}
+
+impl LogFormat {
+    /** Parse `common`, `combined`, or a custom format string. */
+    pub fn parse(format: &str) -> Self {
+        match format.trim() {
+            "common" => LogFormat::Common,
+            "combined" => LogFormat::Combined,
+            format => LogFormat::Custom(format.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            LogFormat::Common => COMMON,
+            LogFormat::Combined => COMBINED,
+            LogFormat::Custom(format) => format,
+        }
+    }
+}
+
+enum Output {
+    Stdout,
+    File(Mutex<File>),
+}
+
+/**
+    Writes one line for every completed request to a file or stdout.
+
+    let access_log = AccessLog::file("./logs/access.log")?.format(LogFormat::Combined);
+    server.access_log(access_log);
+
+    Lines are written in the combined format by default, e.g.
+
+    127.0.0.1 - alice [19/Oct/2026:09:30:12 +0000] "GET /log HTTP/1.1" 200 512 "-" "curl/8.0"
+*/
+pub struct AccessLog {
+    format: LogFormat,
+    output: Output,
+}
+
+impl AccessLog {
+    /** Write the access log to stdout. */
+    pub fn stdout() -> Self {
+        AccessLog {
+            format: LogFormat::Combined,
+            output: Output::Stdout,
+        }
+    }
+
+    /** Append the access log to a file, creating it and its directory if needed. */
+    pub fn file(path: &str) -> Result<Self> {
+        if let Some(directory) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(AccessLog {
+            format: LogFormat::Combined,
+            output: Output::File(Mutex::new(file)),
+        })
+    }
+
+    /**
+        Create from the `[access_log]` section of a config file, the path `-` writes to
+        stdout.
+
+        [access_log]
+        path = ./logs/access.log
+        format = combined          # or common, or e.g. %h "%r" %>s %D
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let access_log = match config.get("access_log.path") {
+            None | Some("-") => AccessLog::stdout(),
+            Some(path) => AccessLog::file(path).map_err(|err| {
+                ServerError::error(&format!("access_log: failed to open {}: {}", path, err))
+            })?,
+        };
+        Ok(match config.get("access_log.format") {
+            Some(format) => access_log.format(LogFormat::parse(format)),
+            None => access_log,
+        })
+    }
+
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /** Write the line for a request which was received at `received_at`. */
+    pub fn record(&self, request: &HttpRequest, received_at: SystemTime, duration: Duration) {
+        let mut line = self.line(request, received_at, duration);
+        line.push('\n');
+        let result = match &self.output {
+            Output::Stdout => std::io::stdout().lock().write_all(line.as_bytes()),
+            Output::File(file) => file.lock().unwrap().write_all(line.as_bytes()),
+        };
+        if let Err(err) = result {
+            error!("failed to write: {}", err);
+        }
+    }
+
+    /** Format the line for a request, without the trailing newline. */
+    pub fn line(
+        &self,
+        request: &HttpRequest,
+        received_at: SystemTime,
+        duration: Duration,
+    ) -> String {
+        let format = self.format.as_str();
+        let mut line = String::with_capacity(format.len() * 2);
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                line.push(c);
+                continue;
+            }
+            // `%>s` is the final status in Apache, which is the only status here
+            if chars.peek() == Some(&'>') {
+                chars.next();
+            }
+            let mut argument = None;
+            if chars.peek() == Some(&'{') {
+                chars.next();
+                argument = Some(chars.by_ref().take_while(|c| *c != '}').collect::<String>());
+            }
+            match chars.next() {
+                Some(directive) => {
+                    let value = directive_value(
+                        directive,
+                        argument.as_deref(),
+                        request,
+                        received_at,
+                        duration,
+                    );
+                    match value {
+                        Some(value) => line.push_str(&value),
+                        None => {
+                            line.push('%');
+                            line.push(directive);
+                        }
+                    }
+                }
+                None => line.push('%'),
+            }
+        }
+        line
+    }
+}
+
+/** The value of a single directive, or None if the directive is unknown. */
+fn directive_value(
+    directive: char,
+    argument: Option<&str>,
+    request: &HttpRequest,
+    received_at: SystemTime,
+    duration: Duration,
+) -> Option<String> {
+    let url = request.url();
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (url.as_str(), None),
+    };
+    // the method can be any token the client sent, so it is escaped like the target
+    let method = escape(request.headers.method.as_str());
+    let protocol = request.headers.version_string().trim().to_string();
+    let value = match directive {
+        '%' => "%".to_string(),
+        'h' => match request.client_ip {
+            Some(ip) => ip.to_string(),
+            None => "-".to_string(),
+        },
+        'a' => match request.peer_addr {
+            Some(addr) => addr.ip().to_string(),
+            None => "-".to_string(),
+        },
+        'l' => "-".to_string(),
+        'u' => match &request.identity {
+            Some(identity) => escape(&identity.user),
+            None => "-".to_string(),
+        },
+        't' => format!("[{}]", clf_time(received_at)),
+        // the request line as it was received, before the path was normalized
+        'r' => format!(
+            "{} {} {}",
+            method,
+            escape(&request.headers.uri),
+            escape(&protocol)
+        ),
+        'm' => method,
+        'U' => escape(path),
+        'q' => match query {
+            Some(query) => escape(&format!("?{}", query)),
+            None => String::new(),
+        },
+        'H' => escape(&protocol),
+        's' => match request.response_status {
+            Some(status) => status.to_string(),
+            None => "-".to_string(),
+        },
+        'b' => match request.response_bytes {
+            0 => "-".to_string(),
+            bytes => bytes.to_string(),
+        },
+        'B' => request.response_bytes.to_string(),
+        'D' => duration.as_micros().to_string(),
+        'T' => match argument {
+            Some("ms") => duration.as_millis().to_string(),
+            Some("us") => duration.as_micros().to_string(),
+            _ => duration.as_secs().to_string(),
+        },
+        'L' => request.request_id.clone(),
+        'i' => match argument.and_then(|name| request.headers.get(name)) {
+            Some(value) => escape(value),
+            None => "-".to_string(),
+        },
+        _ => return None,
+    };
+    Some(value)
+}
+
+/**
+    Escape quotes, backslashes and control characters so a client can not break up a line
+    or forge another one.
+*/
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/** Format a time as `10/Oct/2000:13:55:36 +0000` in UTC. */
+fn clf_time(time: SystemTime) -> String {
+    let date = DateTime::from_system_time(time);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        date.day,
+        date.month_name(),
+        date.year,
+        date.hour,
+        date.minute,
+        date.second
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::Identity;
+    use crate::core::http::http_headers::{HttpMethod, HttpVersion};
+    use std::time::UNIX_EPOCH;
+
+    fn request() -> HttpRequest {
+        let mut request = HttpRequest::to("/log?day=today");
+        request.headers.uri = "/log?day=today".to_string();
+        request.headers.set_version(HttpVersion::HTTP1_1);
+        request.headers.set("Referer", "http://localhost/");
+        request.headers.set("User-Agent", "curl/8.0");
+        request.client_ip = Some("192.0.2.1".parse().unwrap());
+        request.peer_addr = Some("10.0.0.1:4000".parse().unwrap());
+        request.identity = Some(Identity::new("alice", "Basic"));
+        request.response_status = Some(200);
+        request.response_bytes = 512;
+        request.request_id = "abc123".to_string();
+        request
+    }
+
+    fn line(format: LogFormat, request: &HttpRequest) -> String {
+        let received_at = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        AccessLog::stdout()
+            .format(format)
+            .line(request, received_at, Duration::from_micros(1500))
+    }
+
+    #[test]
+    fn formats_common_and_combined_lines() {
+        let request = request();
+        assert_eq!(
+            line(LogFormat::Common, &request),
+            "192.0.2.1 - alice [09/Sep/2001:01:46:40 +0000] \"GET /log?day=today HTTP/1.1\" 200 512"
+        );
+        assert_eq!(
+            line(LogFormat::Combined, &request),
+            "192.0.2.1 - alice [09/Sep/2001:01:46:40 +0000] \"GET /log?day=today HTTP/1.1\" 200 512 \"http://localhost/\" \"curl/8.0\""
+        );
+    }
+
+    #[test]
+    fn formats_custom_directives() {
+        let request = request();
+        let custom = |format: &str| line(LogFormat::parse(format), &request);
+        assert_eq!(
+            custom("%a %m %U%q %H"),
+            "10.0.0.1 GET /log?day=today HTTP/1.1"
+        );
+        assert_eq!(custom("%D %T %{ms}T %{us}T"), "1500 0 1 1500");
+        assert_eq!(custom("%L %B %s 100%%"), "abc123 512 200 100%");
+        assert_eq!(custom("%{X-Missing}i %{user-agent}i"), "- curl/8.0");
+    }
+
+    #[test]
+    fn logs_the_request_line_as_it_was_received() {
+        let mut request = request();
+        request.headers.uri = "/a/../log//?day=%74oday".to_string();
+        assert_eq!(
+            line(LogFormat::parse("\"%r\" %U%q"), &request),
+            "\"GET /a/../log//?day=%74oday HTTP/1.1\" /log?day=today"
+        );
+
+        request.headers.method = HttpMethod::Name("EVIL\"\n".to_string());
+        assert_eq!(
+            line(LogFormat::parse("%m \"%r\""), &request),
+            "EVIL\\\"\\x0a \"EVIL\\\"\\x0a /a/../log//?day=%74oday HTTP/1.1\""
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_directives() {
+        let request = request();
+        assert_eq!(line(LogFormat::parse("%Z %s %"), &request), "%Z 200 %");
+    }
+
+    #[test]
+    fn uses_dashes_for_missing_values() {
+        let mut request = HttpRequest::to("/");
+        request.response_bytes = 0;
+        assert_eq!(
+            line(LogFormat::parse("%h %a %u %s %b %B %q."), &request),
+            "- - - - - 0 ."
+        );
+    }
+
+    #[test]
+    fn escapes_values_sent_by_the_client() {
+        let mut request = request();
+        request
+            .headers
+            .set("User-Agent", "evil\" \\ \n127.0.0.1 - -");
+        request.identity = Some(Identity::new("a\"b", "Basic"));
+        assert_eq!(
+            line(LogFormat::parse("%u \"%{User-Agent}i\""), &request),
+            "a\\\"b \"evil\\\" \\\\ \\x0a127.0.0.1 - -\""
+        );
+    }
+
+    #[test]
+    fn appends_lines_to_a_file() {
+        let directory = std::env::temp_dir().join(format!("access_log_{}", std::process::id()));
+        let path = directory.join("logs/access.log");
+        let access_log = AccessLog::file(path.to_str().unwrap())
+            .unwrap()
+            .format(LogFormat::parse("%s %U"));
+        access_log.record(&request(), SystemTime::now(), Duration::ZERO);
+        access_log.record(
+            &HttpRequest::to("/other"),
+            SystemTime::now(),
+            Duration::ZERO,
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "200 /log\n- /other\n"
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn parses_named_formats() {
+        assert_eq!(LogFormat::parse(" common "), LogFormat::Common);
+        assert_eq!(LogFormat::parse("combined"), LogFormat::Combined);
+        assert_eq!(
+            LogFormat::parse("%h %>s"),
+            LogFormat::Custom("%h %>s".to_string())
+        );
+        assert_eq!(LogFormat::Common.as_str(), COMMON);
+    }
+}
@@ -0,0 +1,764 @@
+use super::{AuthOutcome, Authenticator, Htpasswd, Identity};
+use crate::core::error::ServerError;
+use crate::core::http::http_headers::HttpMethod;
+use crate::core::http::{BodyError, HttpRequest, HttpResponse, HttpStatus};
+use crate::core::json::Value;
+use crate::core::middleware::Middleware;
+use crate::core::server::Flag;
+use crate::{error, info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/** The session key which holds the name of the logged in user. */
+pub const SESSION_USER: &str = "user";
+
+/**
+    Reasons a login or password change can be rejected.
+*/
+#[derive(Debug)]
+pub enum AccountError {
+    /** The user is unknown or the password is wrong. */
+    InvalidCredentials,
+    /** Too many failed attempts were made, the account is locked for this long. */
+    LockedOut(Duration),
+    /** The new password or user name does not meet the requirements. */
+    Rejected(String),
+    /** The user store could not be written. */
+    Storage(std::io::Error),
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::InvalidCredentials => write!(f, "invalid user or password"),
+            AccountError::LockedOut(_) => write!(f, "too many failed attempts, try again later"),
+            AccountError::Rejected(reason) => write!(f, "{}", reason),
+            AccountError::Storage(err) => write!(f, "failed to save users: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+impl From<std::io::Error> for AccountError {
+    fn from(err: std::io::Error) -> Self {
+        AccountError::Storage(err)
+    }
+}
+
+/** Consecutive failed logins for a user. */
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/**
+    A store of local user accounts, kept in a htpasswd-style file with one entry per line
+    (see `Htpasswd`) and PBKDF2 password hashes. Changes are written back to the file
+    immediately.
+
+    let users = UserStore::open("./users")?
+        .max_failures(5)
+        .lockout(Duration::from_secs(15 * 60));
+
+    After `max_failures` consecutive failed logins the account is locked for the lockout
+    duration, during which even the correct password is rejected.
+*/
+pub struct UserStore {
+    path: Option<PathBuf>,
+    users: RwLock<Htpasswd>,
+    failures: Mutex<HashMap<String, Failures>>,
+    max_failures: u32,
+    lockout: Duration,
+    min_password_length: usize,
+}
+
+impl UserStore {
+    /** Create an empty store which is only kept in memory. */
+    pub fn new() -> Self {
+        UserStore::with_users(None, Htpasswd::new())
+    }
+
+    /**
+        Open a store backed by a file, which is created on the first change if it does
+        not exist yet.
+    */
+    pub fn open(path: &str) -> Result<Self> {
+        let users = match fs::read_to_string(path) {
+            Ok(contents) => Htpasswd::parse(&contents)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Htpasswd::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(UserStore::with_users(Some(PathBuf::from(path)), users))
+    }
+
+    fn with_users(path: Option<PathBuf>, users: Htpasswd) -> Self {
+        UserStore {
+            path,
+            users: RwLock::new(users),
+            failures: Mutex::new(HashMap::new()),
+            max_failures: 5,
+            lockout: Duration::from_secs(15 * 60),
+            min_password_length: 8,
+        }
+    }
+
+    /** Number of consecutive failed logins before an account is locked. */
+    pub fn max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures.max(1);
+        self
+    }
+
+    /** How long an account stays locked after too many failed logins. */
+    pub fn lockout(mut self, lockout: Duration) -> Self {
+        self.lockout = lockout;
+        self
+    }
+
+    pub fn min_password_length(mut self, length: usize) -> Self {
+        self.min_password_length = length;
+        self
+    }
+
+    /** Add a user or replace an existing one, and save the store. */
+    pub fn add_user(
+        &self,
+        user: &str,
+        password: &str,
+        roles: &[&str],
+    ) -> std::result::Result<(), AccountError> {
+        let is_valid_name = |name: &str| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| !c.is_whitespace() && !c.is_control() && c != ':' && c != ',')
+        };
+        if !is_valid_name(user) || !roles.iter().all(|role| is_valid_name(role)) {
+            return Err(AccountError::Rejected(
+                "invalid user or role name".to_string(),
+            ));
+        }
+        self.check_password(password)?;
+        let mut users = self.users.write().unwrap();
+        users.add_user(user, password, roles)?;
+        self.save(&users)
+    }
+
+    /** Remove a user and save the store, returns false if the user is unknown. */
+    pub fn remove_user(&self, user: &str) -> std::result::Result<bool, AccountError> {
+        let mut users = self.users.write().unwrap();
+        if !users.remove_user(user) {
+            return Ok(false);
+        }
+        self.save(&users)?;
+        Ok(true)
+    }
+
+    /** Get the roles for a user, returns None for unknown users. */
+    pub fn roles(&self, user: &str) -> Option<Vec<String>> {
+        self.users.read().unwrap().roles(user)
+    }
+
+    /**
+        Check a user's password and return their roles, failed attempts count towards
+        locking the account.
+    */
+    pub fn login(
+        &self,
+        user: &str,
+        password: &str,
+    ) -> std::result::Result<Vec<String>, AccountError> {
+        if let Some(remaining) = self.locked_for(user) {
+            return Err(AccountError::LockedOut(remaining));
+        }
+        let roles = self.users.read().unwrap().verify(user, password);
+        match roles {
+            Some(roles) => {
+                self.failures.lock().unwrap().remove(user);
+                Ok(roles)
+            }
+            None => {
+                self.record_failure(user);
+                Err(AccountError::InvalidCredentials)
+            }
+        }
+    }
+
+    /**
+        Change a user's password after checking their current one, a wrong current
+        password counts as a failed login.
+    */
+    pub fn change_password(
+        &self,
+        user: &str,
+        current: &str,
+        new: &str,
+    ) -> std::result::Result<(), AccountError> {
+        self.login(user, current)?;
+        self.check_password(new)?;
+        let mut users = self.users.write().unwrap();
+        match users.set_password(user, new)? {
+            true => self.save(&users),
+            false => Err(AccountError::InvalidCredentials),
+        }
+    }
+
+    fn check_password(&self, password: &str) -> std::result::Result<(), AccountError> {
+        match password.chars().count() >= self.min_password_length {
+            true => Ok(()),
+            false => Err(AccountError::Rejected(format!(
+                "password must be at least {} characters",
+                self.min_password_length
+            ))),
+        }
+    }
+
+    /** The time left until a locked account can log in again. */
+    fn locked_for(&self, user: &str) -> Option<Duration> {
+        let mut failures = self.failures.lock().unwrap();
+        let locked_until = failures.get(user)?.locked_until?;
+        let now = Instant::now();
+        if locked_until > now {
+            return Some(locked_until - now);
+        }
+        failures.remove(user);
+        None
+    }
+
+    /**
+        Count a failed login, only known users are tracked so the map cannot be grown by
+        guessing user names.
+    */
+    fn record_failure(&self, user: &str) {
+        if !self.users.read().unwrap().contains(user) {
+            return;
+        }
+        let mut failures = self.failures.lock().unwrap();
+        let entry = failures.entry(user.to_string()).or_default();
+        entry.count += 1;
+        if entry.count >= self.max_failures {
+            warn!("locking account: {}", user);
+            entry.count = 0;
+            entry.locked_until = Some(Instant::now() + self.lockout);
+        }
+    }
+
+    /**
+        Write the users to a temporary file and rename it into place, so the file is never
+        left partially written.
+    */
+    fn save(&self, users: &Htpasswd) -> std::result::Result<(), AccountError> {
+        if let Some(path) = &self.path {
+            let temp_path = path.with_extension("tmp");
+            fs::write(&temp_path, users.to_file_string())?;
+            fs::rename(temp_path, path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for UserStore {
+    fn default() -> Self {
+        UserStore::new()
+    }
+}
+
+/**
+    Middleware which mounts the account endpoints, the `Sessions` middleware must be
+    registered before it since the logged in user is kept in the session.
+
+    POST /account/login       {"user": "...", "password": "..."}
+    POST /account/logout
+    POST /account/password    {"current_password": "...", "new_password": "..."}
+
+    let accounts = Accounts::new(UserStore::open("./users")?);
+    let guard = AuthGuard::new(accounts.authenticator()).protect("/admin");
+    server.middleware(sessions);
+    server.middleware(accounts);
+    server.middleware(guard);
+
+    Request bodies must be sent as `application/json`, which browsers will not send
+    cross-origin without a CORS preflight.
+*/
+pub struct Accounts {
+    store: Arc<UserStore>,
+    prefix: String,
+}
+
+impl Accounts {
+    pub fn new(store: UserStore) -> Self {
+        Accounts {
+            store: Arc::new(store),
+            prefix: "/account".to_string(),
+        }
+    }
+
+    /** Mount the endpoints under a different path, defaults to `/account`. */
+    pub fn mount(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn store(&self) -> Arc<UserStore> {
+        Arc::clone(&self.store)
+    }
+
+    /** An authenticator which accepts users logged in through these endpoints. */
+    pub fn authenticator(&self) -> SessionAuth {
+        SessionAuth::new(self.store())
+    }
+
+    fn login(&self, request: &mut HttpRequest) -> Result<Flag> {
+        let body = match json_object(request) {
+            Ok(body) => body,
+            Err((status, message)) => {
+                return request.send(HttpResponse::json_error(status, &message))
+            }
+        };
+        let (user, password) = match (field(&body, "user"), field(&body, "password")) {
+            (Some(user), Some(password)) => (user, password),
+            _ => {
+                return request.send(HttpResponse::json_error(
+                    HttpStatus::BadRequest,
+                    "missing user or password",
+                ))
+            }
+        };
+        let roles = match self.store.login(user, password) {
+            Ok(roles) => roles,
+            Err(err) => return request.send(account_error_response(&err)),
+        };
+        // a new session ID is issued on login to prevent session fixation
+        let session = request.session.as_mut().ok_or_else(missing_sessions)?;
+        session.rotate()?;
+        session.set(SESSION_USER, user)?;
+        info!("logged in: {}", user);
+
+        let roles = roles.into_iter().map(Value::from).collect();
+        let body = Value::object()
+            .with("user", Value::from(user))
+            .with("roles", Value::Array(roles));
+        request.send(json_response(HttpStatus::OK, &body))
+    }
+
+    fn logout(&self, request: &mut HttpRequest) -> Result<Flag> {
+        let session = request.session.as_mut().ok_or_else(missing_sessions)?;
+        session.destroy()?;
+        request.send(no_content())
+    }
+
+    fn change_password(&self, request: &mut HttpRequest) -> Result<Flag> {
+        let user = match session_user(request) {
+            Some(user) => user,
+            None => {
+                return request.send(HttpResponse::json_error(
+                    HttpStatus::Unauthorized,
+                    "not logged in",
+                ))
+            }
+        };
+        let body = match json_object(request) {
+            Ok(body) => body,
+            Err((status, message)) => {
+                return request.send(HttpResponse::json_error(status, &message))
+            }
+        };
+        let (current, new) = match (
+            field(&body, "current_password"),
+            field(&body, "new_password"),
+        ) {
+            (Some(current), Some(new)) => (current, new),
+            _ => {
+                return request.send(HttpResponse::json_error(
+                    HttpStatus::BadRequest,
+                    "missing password",
+                ))
+            }
+        };
+        if let Err(err) = self.store.change_password(&user, current, new) {
+            return request.send(account_error_response(&err));
+        }
+        let session = request.session.as_mut().ok_or_else(missing_sessions)?;
+        session.rotate()?;
+        info!("changed password: {}", user);
+        request.send(no_content())
+    }
+}
+
+impl Middleware for Accounts {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+        let path = request.path().to_string();
+        let endpoint = match path.strip_prefix(self.prefix.as_str()) {
+            Some(endpoint @ ("/login" | "/logout" | "/password")) => endpoint,
+            _ => return Ok(None),
+        };
+        if request.headers.method != HttpMethod::POST {
+            let mut response =
+                HttpResponse::json_error(HttpStatus::MethodNotAllowed, "method not allowed");
+            response.set_header("Allow", "POST");
+            return request.send(response).map(Some);
+        }
+        let flag = match endpoint {
+            "/login" => self.login(request)?,
+            "/logout" => self.logout(request)?,
+            _ => self.change_password(request)?,
+        };
+        Ok(Some(flag))
+    }
+}
+
+/**
+    Authenticates requests from users who logged in through the `Accounts` endpoints, the
+    user's roles are looked up on every request so changes take effect immediately.
+*/
+pub struct SessionAuth {
+    store: Arc<UserStore>,
+}
+
+impl SessionAuth {
+    pub fn new(store: Arc<UserStore>) -> Self {
+        SessionAuth { store }
+    }
+}
+
+impl Authenticator for SessionAuth {
+    fn authenticate(&self, request: &HttpRequest) -> AuthOutcome {
+        let user = match session_user(request) {
+            Some(user) => user,
+            None => return AuthOutcome::Missing,
+        };
+        match self.store.roles(&user) {
+            Some(roles) => {
+                AuthOutcome::Authenticated(Identity::new(&user, "Session").with_roles(&roles))
+            }
+            None => AuthOutcome::Invalid,
+        }
+    }
+
+    /** Sessions are started by logging in rather than with a challenge. */
+    fn challenges(&self, _outcome: &AuthOutcome) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+fn session_user(request: &HttpRequest) -> Option<String> {
+    request.session.as_ref()?.get(SESSION_USER).cloned()
+}
+
+fn missing_sessions() -> std::io::Error {
+    ServerError::error("the Sessions middleware must be registered before Accounts")
+}
+
+/**
+    Parse the request body as a JSON object, or return the error status and message to
+    send. The content type is checked so the endpoints can not be posted to by a
+    cross-origin form.
+*/
+fn json_object(request: &HttpRequest) -> std::result::Result<Value, (HttpStatus, String)> {
+    let body = match request.has_content_type("application/json") {
+        true => request.json(),
+        false => Err(BodyError::ContentType("application/json")),
+    };
+    match body {
+        Ok(body @ Value::Object(_)) => Ok(body),
+        Ok(_) => Err((HttpStatus::BadRequest, "expected a json object".to_string())),
+        Err(err) => Err((err.status(), err.to_string())),
+    }
+}
+
+fn field<'a>(body: &'a Value, key: &str) -> Option<&'a str> {
+    body.get(key).and_then(|value| value.as_str())
+}
+
+fn json_response(status: HttpStatus, body: &Value) -> HttpResponse {
+    let mut response = HttpResponse::new();
+    response.set_status(status);
+    response.set_header("Cache-Control", "no-store");
+    response.set_body(body.to_string().into_bytes(), "application/json");
+    response
+}
+
+fn no_content() -> HttpResponse {
+    let mut response = HttpResponse::new();
+    response.set_status(HttpStatus::NoContent);
+    response.set_header("Content-Length", "0");
+    response.set_header("Cache-Control", "no-store");
+    response
+}
+
+fn account_error_response(err: &AccountError) -> HttpResponse {
+    match err {
+        AccountError::InvalidCredentials => {
+            HttpResponse::json_error(HttpStatus::Unauthorized, &err.to_string())
+        }
+        AccountError::LockedOut(remaining) => {
+            let mut response =
+                HttpResponse::json_error(HttpStatus::TooManyRequests, &err.to_string());
+            let seconds = remaining.as_secs() + 1;
+            response.set_header("Retry-After", &seconds.to_string());
+            response
+        }
+        AccountError::Rejected(_) => {
+            HttpResponse::json_error(HttpStatus::BadRequest, &err.to_string())
+        }
+        AccountError::Storage(_) => {
+            error!("{}", err);
+            HttpResponse::json_error(HttpStatus::InternalServerError, "failed to save account")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::json;
+    use crate::core::session::{MemoryStore, Sessions};
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /**
+        `password` with 4096 iterations from the PBKDF2 test vectors, which keeps the tests
+        quick where a new hash would take the full iteration count.
+    */
+    const ADA: &str = "ada:$pbkdf2-sha256$4096$73616c74$\
+                       c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a:admin";
+
+    fn store() -> UserStore {
+        UserStore::with_users(None, Htpasswd::parse(ADA).unwrap()).max_failures(3)
+    }
+
+    /** A JSON post to an account endpoint with a fresh session. */
+    fn post(path: &str, body: &str, sessions: &Sessions) -> HttpRequest {
+        let mut request = HttpRequest::to(path);
+        request.headers.method = HttpMethod::POST;
+        request.set_body(body.as_bytes().to_vec(), "application/json");
+        sessions.before(&mut request).unwrap();
+        request
+    }
+
+    /** Run the middleware and return the response it wrote to the connection. */
+    fn respond(accounts: &Accounts, request: &mut HttpRequest) -> HttpResponse {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        request.connection = Some(Arc::new(listener.accept().unwrap().0));
+        assert!(accounts.before(request).unwrap().is_some());
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).unwrap();
+        HttpResponse::parse(&data).unwrap()
+    }
+
+    fn log_in(accounts: &Accounts, sessions: &Sessions) -> HttpRequest {
+        let body = r#"{"user":"ada","password":"password"}"#;
+        let mut request = post("/account/login", body, sessions);
+        assert_eq!(respond(accounts, &mut request).status.code(), 200);
+        request
+    }
+
+    /** The next request from the same client, which carries on the session. */
+    fn follow(previous: &HttpRequest, path: &str, body: &str) -> HttpRequest {
+        let mut request = HttpRequest::to(path);
+        request.headers.method = HttpMethod::POST;
+        request.set_body(body.as_bytes().to_vec(), "application/json");
+        request.session = previous.session.clone();
+        request
+    }
+
+    #[test]
+    fn locks_an_account_after_repeated_failures() {
+        let store = store().lockout(Duration::from_millis(200));
+        for _ in 0..2 {
+            assert!(matches!(
+                store.login("ada", "wrong"),
+                Err(AccountError::InvalidCredentials)
+            ));
+        }
+        // a successful login resets the count
+        assert_eq!(store.login("ada", "password").unwrap(), vec!["admin"]);
+        for _ in 0..2 {
+            assert!(store.login("ada", "wrong").is_err());
+        }
+        assert!(store.login("ada", "password").is_ok());
+
+        for _ in 0..3 {
+            assert!(store.login("ada", "wrong").is_err());
+        }
+        match store.login("ada", "password") {
+            Err(AccountError::LockedOut(remaining)) => {
+                assert!(remaining <= Duration::from_millis(200))
+            }
+            other => panic!("expected the account to be locked, got {:?}", other),
+        }
+        thread::sleep(Duration::from_millis(250));
+        assert!(store.login("ada", "password").is_ok());
+
+        // unknown users are not tracked, so guessing names can't grow the map
+        assert!(store.login("nobody", "wrong").is_err());
+        assert!(store.failures.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn login_starts_a_new_session() {
+        let accounts = Accounts::new(store());
+        let sessions = Sessions::new(MemoryStore::new(), b"secret");
+        let body = r#"{"user":"ada","password":"password"}"#;
+        let mut request = post("/account/login", body, &sessions);
+        request
+            .session
+            .as_mut()
+            .unwrap()
+            .set("visited", "yes")
+            .unwrap();
+        let anonymous_id = request.session.as_ref().unwrap().id().to_string();
+
+        let response = respond(&accounts, &mut request);
+        assert_eq!(response.status.code(), 200);
+        assert_eq!(response.headers.get("Cache-Control").unwrap(), "no-store");
+        assert_eq!(
+            json::parse(&response.text()).unwrap().to_string(),
+            r#"{"user":"ada","roles":["admin"]}"#
+        );
+        let session = request.session.as_ref().unwrap();
+        assert_ne!(session.id(), anonymous_id);
+        assert_eq!(session.get(SESSION_USER).unwrap(), "ada");
+        match accounts.authenticator().authenticate(&request) {
+            AuthOutcome::Authenticated(identity) => {
+                assert_eq!(identity.user, "ada");
+                assert_eq!(identity.roles, vec!["admin".to_string()]);
+            }
+            _ => panic!("expected the logged in user"),
+        }
+    }
+
+    #[test]
+    fn login_refuses_bad_requests() {
+        let accounts = Accounts::new(store());
+        let sessions = Sessions::new(MemoryStore::new(), b"secret");
+        let mut respond_to = |body: &str| {
+            let mut request = post("/account/login", body, &sessions);
+            let response = respond(&accounts, &mut request);
+            assert_eq!(request.session.as_ref().unwrap().get(SESSION_USER), None);
+            response
+        };
+        let wrong = respond_to(r#"{"user":"ada","password":"wrong"}"#);
+        assert_eq!(wrong.status.code(), 401);
+        assert_eq!(wrong.text(), r#"{"error":"invalid user or password"}"#);
+        assert_eq!(respond_to(r#"{"user":"ada"}"#).status.code(), 400);
+        assert_eq!(respond_to(r#"["ada","password"]"#).status.code(), 400);
+        assert_eq!(respond_to("{").status.code(), 400);
+
+        let mut form = post("/account/login", "user=ada", &sessions);
+        form.headers
+            .set_content_type("application/x-www-form-urlencoded");
+        assert_eq!(respond(&accounts, &mut form).status.code(), 415);
+
+        let mut get = HttpRequest::to("/account/login");
+        let response = respond(&accounts, &mut get);
+        assert_eq!(response.status.code(), 405);
+        assert_eq!(response.headers.get("Allow").unwrap(), "POST");
+    }
+
+    #[test]
+    fn locked_accounts_are_told_when_to_retry() {
+        let accounts = Accounts::new(store());
+        let sessions = Sessions::new(MemoryStore::new(), b"secret");
+        for _ in 0..3 {
+            let body = r#"{"user":"ada","password":"wrong"}"#;
+            respond(&accounts, &mut post("/account/login", body, &sessions));
+        }
+        let body = r#"{"user":"ada","password":"password"}"#;
+        let response = respond(&accounts, &mut post("/account/login", body, &sessions));
+        assert_eq!(response.status.code(), 429);
+        let retry_after = response.headers.get("Retry-After").unwrap();
+        assert_eq!(retry_after.parse::<u64>().unwrap(), 15 * 60);
+    }
+
+    #[test]
+    fn logout_ends_the_session() {
+        let accounts = Accounts::new(store());
+        let sessions = Sessions::new(MemoryStore::new(), b"secret");
+        let login = log_in(&accounts, &sessions);
+        let login_id = login.session.as_ref().unwrap().id().to_string();
+
+        let mut logout = follow(&login, "/account/logout", "");
+        let response = respond(&accounts, &mut logout);
+        assert_eq!(response.status.code(), 204);
+        let session = logout.session.as_ref().unwrap();
+        assert_ne!(session.id(), login_id);
+        assert_eq!(session.get(SESSION_USER), None);
+        assert!(response.headers.get("Set-Cookie").is_some());
+        assert!(matches!(
+            accounts.authenticator().authenticate(&logout),
+            AuthOutcome::Missing
+        ));
+    }
+
+    #[test]
+    fn password_change_checks_the_current_password_and_rotates_the_session() {
+        let accounts = Accounts::new(store());
+        let sessions = Sessions::new(MemoryStore::new(), b"secret");
+        let change = r#"{"current_password":"password","new_password":"battery staple"}"#;
+
+        let mut anonymous = post("/account/password", change, &sessions);
+        assert_eq!(respond(&accounts, &mut anonymous).status.code(), 401);
+
+        let login = log_in(&accounts, &sessions);
+        let login_id = login.session.as_ref().unwrap().id().to_string();
+        let wrong = r#"{"current_password":"wrong","new_password":"battery staple"}"#;
+        let mut request = follow(&login, "/account/password", wrong);
+        assert_eq!(respond(&accounts, &mut request).status.code(), 401);
+        let short = r#"{"current_password":"password","new_password":"short"}"#;
+        let mut request = follow(&login, "/account/password", short);
+        let response = respond(&accounts, &mut request);
+        assert_eq!(response.status.code(), 400);
+        assert!(response.text().contains("at least 8 characters"));
+
+        let mut request = follow(&login, "/account/password", change);
+        assert_eq!(respond(&accounts, &mut request).status.code(), 204);
+        let session = request.session.as_ref().unwrap();
+        assert_ne!(session.id(), login_id);
+        assert_eq!(session.get(SESSION_USER).unwrap(), "ada");
+        assert!(accounts.store().login("ada", "battery staple").is_ok());
+    }
+
+    #[test]
+    fn saves_changes_and_reloads_them() {
+        let dir = std::env::temp_dir().join(format!("accounts_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("users");
+        let path = path.to_str().unwrap();
+
+        // the file is only created once something changes
+        let store = UserStore::open(path).unwrap();
+        assert!(!dir.join("users").exists());
+        store.add_user("bob", "hunter2 hunter2", &["ops"]).unwrap();
+        assert_eq!(
+            UserStore::open(path).unwrap().roles("bob"),
+            Some(vec!["ops".to_string()])
+        );
+        assert!(store.remove_user("bob").unwrap());
+        assert!(!store.remove_user("bob").unwrap());
+        assert_eq!(UserStore::open(path).unwrap().roles("bob"), None);
+
+        fs::write(path, ADA).unwrap();
+        let store = UserStore::open(path).unwrap();
+        store
+            .change_password("ada", "password", "battery staple")
+            .unwrap();
+        assert!(!dir.join("users.tmp").exists());
+        let reloaded = UserStore::open(path).unwrap();
+        assert_eq!(reloaded.roles("ada"), Some(vec!["admin".to_string()]));
+        assert!(reloaded.login("ada", "battery staple").is_ok());
+        assert!(matches!(
+            reloaded.add_user("bad name", "battery staple", &[]),
+            Err(AccountError::Rejected(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
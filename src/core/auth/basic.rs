@@ -0,0 +1,134 @@
+use super::{parse_authorization, quote, AuthOutcome, Authenticator, Htpasswd, Identity};
+use crate::core::http::HttpRequest;
+use crate::core::util::base64_decode;
+
+/**
+    HTTP Basic authentication (RFC 7617) with credentials from a htpasswd-style file.
+
+    let basic = BasicAuth::new("admin", Htpasswd::load("./htpasswd")?);
+    server.middleware(AuthGuard::new(basic).protect("/log").protect("/events"));
+*/
+pub struct BasicAuth {
+    realm: String,
+    credentials: Htpasswd,
+}
+
+impl BasicAuth {
+    pub fn new(realm: &str, credentials: Htpasswd) -> Self {
+        BasicAuth {
+            realm: realm.to_string(),
+            credentials,
+        }
+    }
+
+    /**
+        Decode the `user:password` pair from the base64 credentials, note the password
+        may contain colons but the user may not.
+    */
+    fn decode(credentials: &str) -> Option<(String, String)> {
+        let decoded = String::from_utf8(base64_decode(credentials).ok()?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        Some((user.to_string(), password.to_string()))
+    }
+}
+
+impl Authenticator for BasicAuth {
+    fn authenticate(&self, request: &HttpRequest) -> AuthOutcome {
+        let credentials = match request
+            .headers
+            .get("Authorization")
+            .and_then(|header| parse_authorization(header, "Basic"))
+        {
+            Some(credentials) => credentials,
+            None => return AuthOutcome::Missing,
+        };
+        let (user, password) = match BasicAuth::decode(credentials) {
+            Some(pair) => pair,
+            None => return AuthOutcome::Invalid,
+        };
+        match self.credentials.verify(&user, &password) {
+            Some(roles) => {
+                AuthOutcome::Authenticated(Identity::new(&user, "Basic").with_roles(&roles))
+            }
+            None => AuthOutcome::Invalid,
+        }
+    }
+
+    fn challenges(&self, _outcome: &AuthOutcome) -> Vec<String> {
+        vec![format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            quote(&self.realm)
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::util::{base64_encode, hex_encode, sha256};
+
+    fn basic() -> BasicAuth {
+        let hash = hex_encode(&sha256(b"0102secret"));
+        let htpasswd = Htpasswd::parse(&format!("admin:$sha256$30313032${}:ops", hash)).unwrap();
+        BasicAuth::new("admin", htpasswd)
+    }
+
+    fn request(credentials: Option<&str>) -> HttpRequest {
+        let mut request = HttpRequest::to("/log");
+        if let Some(credentials) = credentials {
+            let encoded = base64_encode(credentials.as_bytes());
+            request
+                .headers
+                .set("Authorization", &format!("Basic {}", encoded));
+        }
+        request
+    }
+
+    #[test]
+    fn decodes_user_and_password() {
+        let encoded = base64_encode(b"admin:pass:word");
+        assert_eq!(
+            BasicAuth::decode(&encoded),
+            Some(("admin".to_string(), "pass:word".to_string()))
+        );
+        assert_eq!(BasicAuth::decode(&base64_encode(b"admin")), None);
+        assert_eq!(BasicAuth::decode("not base64!"), None);
+    }
+
+    #[test]
+    fn authenticates_valid_credentials() {
+        match basic().authenticate(&request(Some("admin:secret"))) {
+            AuthOutcome::Authenticated(identity) => {
+                assert_eq!(identity.user, "admin");
+                assert_eq!(identity.roles, vec!["ops".to_string()]);
+            }
+            _ => panic!("expected the request to be authenticated"),
+        }
+    }
+
+    #[test]
+    fn refuses_missing_or_wrong_credentials() {
+        let basic = basic();
+        assert!(matches!(
+            basic.authenticate(&request(None)),
+            AuthOutcome::Missing
+        ));
+        assert!(matches!(
+            basic.authenticate(&request(Some("admin:wrong"))),
+            AuthOutcome::Invalid
+        ));
+        assert!(matches!(
+            basic.authenticate(&request(Some("nobody:secret"))),
+            AuthOutcome::Invalid
+        ));
+    }
+
+    #[test]
+    fn escapes_quotes_in_the_realm() {
+        let basic = BasicAuth::new("a \"b\"", Htpasswd::new());
+        assert_eq!(
+            basic.challenges(&AuthOutcome::Missing),
+            vec!["Basic realm=\"a \\\"b\\\"\", charset=\"UTF-8\"".to_string()]
+        );
+    }
+}
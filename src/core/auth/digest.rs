@@ -0,0 +1,550 @@
+use super::{parse_authorization, quote, AuthOutcome, Authenticator, Identity};
+use crate::core::error::ServerError;
+use crate::core::http::HttpRequest;
+use crate::core::util::{
+    constant_time_eq, hex_decode, hex_encode, hmac_sha256, md5, secure_random_bytes, sha256,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Result;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/**
+    The hash algorithms supported for Digest authentication (RFC 7616 section 3.2).
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    pub fn name(&self) -> &str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "MD5" => Some(DigestAlgorithm::Md5),
+            "SHA-256" => Some(DigestAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    /** Hash the data and return it as lowercase hex. */
+    pub fn hash(&self, data: &str) -> String {
+        match self {
+            DigestAlgorithm::Md5 => hex_encode(&md5(data.as_bytes())),
+            DigestAlgorithm::Sha256 => hex_encode(&sha256(data.as_bytes())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct DigestUser {
+    ha1_md5: Option<String>,
+    ha1_sha256: Option<String>,
+    roles: Vec<String>,
+}
+
+impl DigestUser {
+    fn ha1(&self, algorithm: DigestAlgorithm) -> Option<&String> {
+        match algorithm {
+            DigestAlgorithm::Md5 => self.ha1_md5.as_ref(),
+            DigestAlgorithm::Sha256 => self.ha1_sha256.as_ref(),
+        }
+    }
+}
+
+/**
+    The parameters sent by the client in a `Digest` authorization header.
+*/
+#[derive(Clone, Debug)]
+struct DigestResponse {
+    username: String,
+    realm: String,
+    nonce: String,
+    uri: String,
+    response: String,
+    algorithm: DigestAlgorithm,
+    qop: String,
+    nc: String,
+    cnonce: String,
+    opaque: Option<String>,
+}
+
+impl DigestResponse {
+    fn parse(credentials: &str) -> Option<Self> {
+        let params = parse_params(credentials);
+        let get = |key: &str| params.get(key).cloned();
+        let algorithm = match get("algorithm") {
+            Some(name) => DigestAlgorithm::from_name(&name)?,
+            None => DigestAlgorithm::Md5,
+        };
+        Some(DigestResponse {
+            username: get("username")?,
+            realm: get("realm")?,
+            nonce: get("nonce")?,
+            uri: get("uri")?,
+            response: get("response")?.to_lowercase(),
+            algorithm,
+            qop: get("qop")?,
+            nc: get("nc")?,
+            cnonce: get("cnonce")?,
+            opaque: get("opaque"),
+        })
+    }
+}
+
+/**
+    HTTP Digest authentication (RFC 7616) with `qop=auth`, supporting both the MD5 and
+    SHA-256 algorithms.
+
+    Nonces are signed with a secret so they can be checked without storing them, they
+    expire after the nonce lifetime and the nonce-count sent with each request must
+    increase to prevent replay attacks.
+
+    let digest = DigestAuth::new("admin")?
+        .user("admin", "password", &["admin"]);
+
+    server.middleware(AuthGuard::new(digest).protect("/log"));
+*/
+pub struct DigestAuth {
+    realm: String,
+    algorithms: Vec<DigestAlgorithm>,
+    users: HashMap<String, DigestUser>,
+    nonce_lifetime: Duration,
+    secret: Vec<u8>,
+    opaque: String,
+    nonce_counts: Mutex<HashMap<String, u32>>,
+    /** Checked in place of unknown users, so the time taken does not reveal who exists. */
+    dummy: DigestUser,
+}
+
+impl DigestAuth {
+    pub fn new(realm: &str) -> Result<Self> {
+        let dummy_secret = hex_encode(&secure_random_bytes(16)?);
+        Ok(DigestAuth {
+            realm: realm.to_string(),
+            algorithms: vec![DigestAlgorithm::Sha256, DigestAlgorithm::Md5],
+            users: HashMap::new(),
+            nonce_lifetime: Duration::from_secs(300),
+            secret: secure_random_bytes(32)?,
+            opaque: hex_encode(&secure_random_bytes(16)?),
+            nonce_counts: Mutex::new(HashMap::new()),
+            dummy: DigestUser {
+                ha1_md5: Some(DigestAlgorithm::Md5.hash(&dummy_secret)),
+                ha1_sha256: Some(DigestAlgorithm::Sha256.hash(&dummy_secret)),
+                roles: Vec::new(),
+            },
+        })
+    }
+
+    /**
+        Set the algorithms which are offered to the client, in order of preference.
+    */
+    pub fn algorithms(mut self, algorithms: &[DigestAlgorithm]) -> Self {
+        self.algorithms = algorithms.to_vec();
+        self
+    }
+
+    /** How long a nonce is valid for before the client must request a new one. */
+    pub fn nonce_lifetime(mut self, lifetime: Duration) -> Self {
+        self.nonce_lifetime = lifetime;
+        self
+    }
+
+    /**
+        Add a user with a password, only the hashed `username:realm:password` (HA1) is
+        kept in memory for each algorithm.
+    */
+    pub fn user(mut self, username: &str, password: &str, roles: &[&str]) -> Self {
+        let secret = format!("{}:{}:{}", username, self.realm, password);
+        let user = DigestUser {
+            ha1_md5: Some(DigestAlgorithm::Md5.hash(&secret)),
+            ha1_sha256: Some(DigestAlgorithm::Sha256.hash(&secret)),
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        };
+        self.users.insert(username.to_string(), user);
+        self
+    }
+
+    /**
+        Load users from a htdigest-style file, each line is `user:realm:ha1-md5` which
+        may be followed by `:ha1-sha256` and `:role,role`. Entries for other realms are
+        ignored and an empty hash disables that algorithm for the user.
+
+        admin:admin:939e7578ed9e3c518a452acee763bce9:2c9d...:admin
+    */
+    pub fn load_users(mut self, path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split(':').collect::<Vec<&str>>();
+            if fields.len() < 3 {
+                return Err(ServerError::error(&format!(
+                    "htdigest line {}: expected user:realm:hash",
+                    number + 1
+                )));
+            }
+            if fields[1] != self.realm {
+                continue;
+            }
+            let parse_hash = |index: usize, length: usize| {
+                fields
+                    .get(index)
+                    .map(|hash| hash.to_lowercase())
+                    .filter(|hash| hash.len() == length && hex_decode(hash).is_ok())
+            };
+            let roles = fields
+                .get(4)
+                .map(|roles| {
+                    roles
+                        .split(',')
+                        .map(|role| role.trim().to_string())
+                        .collect()
+                })
+                .unwrap_or_default();
+            let user = DigestUser {
+                ha1_md5: parse_hash(2, 32),
+                ha1_sha256: parse_hash(3, 64),
+                roles,
+            };
+            self.users.insert(fields[0].to_string(), user);
+        }
+        Ok(self)
+    }
+
+    /**
+        Create a new nonce, which is the issue time followed by its signature.
+    */
+    fn create_nonce(&self) -> String {
+        let timestamp = format!("{:016x}", unix_now());
+        let signature = hmac_sha256(&self.secret, timestamp.as_bytes());
+        format!("{}{}", timestamp, hex_encode(&signature[..16]))
+    }
+
+    /**
+        Check a nonce was issued by this server, returns whether it is still fresh or None
+        if the signature is invalid.
+    */
+    fn check_nonce(&self, nonce: &str) -> Option<bool> {
+        if nonce.len() != 48 || !nonce.is_ascii() {
+            return None;
+        }
+        let (timestamp, signature) = nonce.split_at(16);
+        let expected = hmac_sha256(&self.secret, timestamp.as_bytes());
+        if !constant_time_eq(hex_encode(&expected[..16]).as_bytes(), signature.as_bytes()) {
+            return None;
+        }
+        let issued_at = u64::from_str_radix(timestamp, 16).ok()?;
+        Some(unix_now().saturating_sub(issued_at) < self.nonce_lifetime.as_secs())
+    }
+
+    /**
+        Record the nonce-count for a nonce, returns false if the count has already been
+        used which means the request is being replayed. Expired nonces are dropped.
+    */
+    fn record_nonce_count(&self, nonce: &str, nc: u32) -> bool {
+        let mut counts = self.nonce_counts.lock().unwrap();
+        counts.retain(|nonce, _| self.check_nonce(nonce) == Some(true));
+        match counts.get(nonce) {
+            Some(last) if *last >= nc => false,
+            _ => {
+                counts.insert(nonce.to_string(), nc);
+                true
+            }
+        }
+    }
+
+    /**
+        KD(HA1, nonce:nc:cnonce:qop:HA2) where HA2 = H(method:uri), see RFC 7616 3.4.1.
+    */
+    fn expected_response(&self, ha1: &str, method: &str, digest: &DigestResponse) -> String {
+        let algorithm = digest.algorithm;
+        let ha2 = algorithm.hash(&format!("{}:{}", method, digest.uri));
+        algorithm.hash(&format!(
+            "{}:{}:{}:{}:{}:{}",
+            ha1, digest.nonce, digest.nc, digest.cnonce, digest.qop, ha2
+        ))
+    }
+}
+
+impl Authenticator for DigestAuth {
+    fn authenticate(&self, request: &HttpRequest) -> AuthOutcome {
+        let credentials = match request
+            .headers
+            .get("Authorization")
+            .and_then(|header| parse_authorization(header, "Digest"))
+        {
+            Some(credentials) => credentials,
+            None => return AuthOutcome::Missing,
+        };
+        let digest = match DigestResponse::parse(credentials) {
+            Some(digest) => digest,
+            None => return AuthOutcome::Invalid,
+        };
+
+        let is_valid_request = digest.realm == self.realm
+            && digest.qop == "auth"
+            && digest.uri == request.headers.uri
+            && self.algorithms.contains(&digest.algorithm)
+            && digest
+                .opaque
+                .as_ref()
+                .is_none_or(|opaque| *opaque == self.opaque);
+        let nc = match u32::from_str_radix(&digest.nc, 16) {
+            Ok(nc) if is_valid_request && digest.nc.len() == 8 => nc,
+            _ => return AuthOutcome::Invalid,
+        };
+        let is_fresh = match self.check_nonce(&digest.nonce) {
+            Some(is_fresh) => is_fresh,
+            None => return AuthOutcome::Invalid,
+        };
+
+        // unknown users are hashed against the dummy so they take as long as known users
+        let user = self
+            .users
+            .get(&digest.username)
+            .filter(|user| user.ha1(digest.algorithm).is_some());
+        let ha1 = match user.unwrap_or(&self.dummy).ha1(digest.algorithm) {
+            Some(ha1) => ha1,
+            None => return AuthOutcome::Invalid,
+        };
+        let method = request.headers.method_string();
+        let expected = self.expected_response(ha1, &method, &digest);
+        let is_match = constant_time_eq(expected.as_bytes(), digest.response.as_bytes());
+        if user.is_none() || !is_match {
+            return AuthOutcome::Invalid;
+        }
+
+        // the credentials are correct but the client must retry with a new nonce
+        if !is_fresh {
+            return AuthOutcome::Stale;
+        }
+        if !self.record_nonce_count(&digest.nonce, nc) {
+            return AuthOutcome::Invalid;
+        }
+
+        let roles = user.map(|user| user.roles.clone()).unwrap_or_default();
+        AuthOutcome::Authenticated(Identity::new(&digest.username, "Digest").with_roles(&roles))
+    }
+
+    fn challenges(&self, outcome: &AuthOutcome) -> Vec<String> {
+        let nonce = self.create_nonce();
+        let stale = match outcome {
+            AuthOutcome::Stale => ", stale=true",
+            _ => "",
+        };
+        self.algorithms
+            .iter()
+            .map(|algorithm| {
+                format!(
+                    "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"{}\", opaque=\"{}\"{}",
+                    quote(&self.realm),
+                    algorithm.name(),
+                    nonce,
+                    self.opaque,
+                    stale
+                )
+            })
+            .collect()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/**
+    Parse comma separated `key=value` auth-params where values may be quoted strings
+    containing commas and backslash escapes. Keys are lowercased.
+
+    username="Mufasa", realm="http-auth@example.org", nc=00000001
+*/
+pub fn parse_params(input: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| *c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c == ',' {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        if key.is_empty() {
+            break;
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            }
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => value.extend(chars.next()),
+                        _ => value.push(c),
+                    }
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c == ',' {
+                        break;
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+            }
+        }
+        params.insert(key.trim().to_lowercase(), value.trim().to_string());
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REALM: &str = "http-auth@example.org";
+
+    /** The example exchange from RFC 7616 section 3.9.1. */
+    fn rfc_response(algorithm: &str, response: &str) -> String {
+        format!(
+            "username=\"Mufasa\", realm=\"{}\", uri=\"/dir/index.html\", algorithm={}, \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", nc=00000001, \
+             cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, \
+             response=\"{}\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+            REALM, algorithm, response
+        )
+    }
+
+    fn expected(algorithm: DigestAlgorithm, credentials: &str) -> String {
+        let digest = DigestAuth::new(REALM).unwrap();
+        let response = DigestResponse::parse(credentials).unwrap();
+        let ha1 = algorithm.hash(&format!("Mufasa:{}:Circle of Life", REALM));
+        digest.expected_response(&ha1, "GET", &response)
+    }
+
+    #[test]
+    fn matches_the_rfc_7616_md5_example() {
+        let credentials = rfc_response("MD5", "8ca523f5e9506fed4657c9700eebdbec");
+        assert_eq!(
+            expected(DigestAlgorithm::Md5, &credentials),
+            "8ca523f5e9506fed4657c9700eebdbec"
+        );
+    }
+
+    #[test]
+    fn matches_the_rfc_7616_sha256_example() {
+        let response = "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1";
+        let credentials = rfc_response("SHA-256", response);
+        assert_eq!(expected(DigestAlgorithm::Sha256, &credentials), response);
+    }
+
+    fn request(digest: &DigestAuth, user: &str, password: &str, nc: &str) -> HttpRequest {
+        let nonce = digest.create_nonce();
+        let algorithm = DigestAlgorithm::Sha256;
+        let ha1 = algorithm.hash(&format!("{}:{}:{}", user, REALM, password));
+        let ha2 = algorithm.hash("GET:/log?day=1");
+        let response = algorithm.hash(&format!("{}:{}:{}:abc:auth:{}", ha1, nonce, nc, ha2));
+        let mut request = HttpRequest::to("/log?day=1");
+        request.headers.uri = "/log?day=1".to_string();
+        request.headers.set(
+            "Authorization",
+            &format!(
+                "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"/log?day=1\", \
+                 response=\"{}\", algorithm=SHA-256, qop=auth, nc={}, cnonce=\"abc\"",
+                user, REALM, nonce, response, nc
+            ),
+        );
+        request
+    }
+
+    #[test]
+    fn authenticates_and_refuses_replays() {
+        let digest = DigestAuth::new(REALM)
+            .unwrap()
+            .user("Mufasa", "Circle of Life", &["admin"]);
+        let request = request(&digest, "Mufasa", "Circle of Life", "00000001");
+        match digest.authenticate(&request) {
+            AuthOutcome::Authenticated(identity) => {
+                assert_eq!(identity.user, "Mufasa");
+                assert_eq!(identity.roles, vec!["admin".to_string()]);
+            }
+            _ => panic!("expected the request to be authenticated"),
+        }
+        assert!(matches!(
+            digest.authenticate(&request),
+            AuthOutcome::Invalid
+        ));
+    }
+
+    #[test]
+    fn refuses_wrong_passwords_and_unknown_users() {
+        let digest = DigestAuth::new(REALM)
+            .unwrap()
+            .user("Mufasa", "Circle of Life", &[]);
+        let wrong = request(&digest, "Mufasa", "wrong", "00000001");
+        assert!(matches!(digest.authenticate(&wrong), AuthOutcome::Invalid));
+        let unknown = request(&digest, "Scar", "Circle of Life", "00000001");
+        assert!(matches!(
+            digest.authenticate(&unknown),
+            AuthOutcome::Invalid
+        ));
+        let missing = HttpRequest::to("/log");
+        assert!(matches!(
+            digest.authenticate(&missing),
+            AuthOutcome::Missing
+        ));
+    }
+
+    #[test]
+    fn nonces_are_signed() {
+        let digest = DigestAuth::new(REALM).unwrap();
+        let nonce = digest.create_nonce();
+        assert_eq!(digest.check_nonce(&nonce), Some(true));
+        let other = DigestAuth::new(REALM).unwrap();
+        assert_eq!(other.check_nonce(&nonce), None);
+        assert_eq!(digest.check_nonce("short"), None);
+    }
+
+    #[test]
+    fn escapes_the_realm_in_challenges() {
+        let digest = DigestAuth::new("a \"b\" \\ c")
+            .unwrap()
+            .algorithms(&[DigestAlgorithm::Md5]);
+        let challenge = digest.challenges(&AuthOutcome::Missing).remove(0);
+        assert!(challenge.starts_with("Digest realm=\"a \\\"b\\\" \\\\ c\", qop=\"auth\""));
+        let params = parse_params(challenge.strip_prefix("Digest ").unwrap());
+        assert_eq!(params["realm"], "a \"b\" \\ c");
+        assert_eq!(params["algorithm"], "MD5");
+    }
+
+    #[test]
+    fn parses_quoted_params() {
+        let params = parse_params("a=\"x, y\", B=2 ,c=\"q\\\"s\", d");
+        assert_eq!(params["a"], "x, y");
+        assert_eq!(params["b"], "2");
+        assert_eq!(params["c"], "q\"s");
+        assert_eq!(params["d"], "");
+    }
+}
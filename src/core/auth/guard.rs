@@ -0,0 +1,177 @@
+use super::{AuthOutcome, Authenticator};
+use crate::core::http::{HttpRequest, HttpResponse, HttpStatus};
+use crate::core::middleware::{has_path_prefix, Middleware};
+use crate::core::server::Flag;
+use crate::core::url::path::normalize_path;
+use crate::core::util::encode_path;
+use std::io::Result;
+
+/**
+    Middleware which requires authentication for a set of path prefixes, covering both
+    dynamic routes and static files. Several authenticators can be combined, in which case
+    the first one to accept the request's credentials wins.
+
+    let guard = AuthGuard::new(BasicAuth::new("admin", htpasswd))
+        .protect("/log")
+        .protect("/events")
+        .allow_users(&["admin"]);
+
+    server.middleware(guard);
+
+    Requests without valid credentials receive a 401 with a `WWW-Authenticate` challenge
+    for every authenticator, while authenticated users who are not allowed receive a 403.
+*/
+pub struct AuthGuard {
+    authenticators: Vec<Box<dyn Authenticator>>,
+    prefixes: Vec<String>,
+    users: Option<Vec<String>>,
+}
+
+impl AuthGuard {
+    pub fn new<A>(authenticator: A) -> Self
+    where
+        A: Authenticator + 'static,
+    {
+        AuthGuard {
+            authenticators: vec![Box::new(authenticator)],
+            prefixes: Vec::new(),
+            users: None,
+        }
+    }
+
+    /** Accept credentials from another authentication scheme as well. */
+    pub fn with<A>(mut self, authenticator: A) -> Self
+    where
+        A: Authenticator + 'static,
+    {
+        self.authenticators.push(Box::new(authenticator));
+        self
+    }
+
+    /**
+        Require authentication for a path and everything below it, e.g. protecting `/log`
+        covers `/log` and `/log/today` but not `/login`. Use `/` to protect every path.
+    */
+    pub fn protect(mut self, prefix: &str) -> Self {
+        self.prefixes.push(prefix.trim_end_matches('/').to_string());
+        self
+    }
+
+    /** Only allow these users, any other authenticated user will receive a 403. */
+    pub fn allow_users(mut self, users: &[&str]) -> Self {
+        self.users = Some(users.iter().map(|user| user.to_string()).collect());
+        self
+    }
+
+    /**
+        Check a decoded path against the protected prefixes once slashes are merged and dot
+        segments removed, so `//log.html` is protected like `/log.html`. A path which can not
+        be normalized is treated as protected.
+    */
+    pub fn is_protected(&self, path: &str) -> bool {
+        let path = match normalize_path(&encode_path(path), true) {
+            Ok(path) => path,
+            Err(_) => return true,
+        };
+        self.prefixes
+            .iter()
+            .any(|prefix| has_path_prefix(&path, prefix))
+    }
+
+    fn is_allowed_user(&self, user: &str) -> bool {
+        match &self.users {
+            Some(users) => users.iter().any(|allowed| allowed == user),
+            None => true,
+        }
+    }
+
+    /**
+        Send a 401 with the challenges from every authenticator, when the credentials were
+        invalid the challenges are built from that outcome.
+    */
+    fn unauthorized(&self, request: &mut HttpRequest, outcomes: &[AuthOutcome]) -> Result<Flag> {
+        let mut response = HttpResponse::error(HttpStatus::Unauthorized);
+        for (authenticator, outcome) in self.authenticators.iter().zip(outcomes.iter()) {
+            for challenge in authenticator.challenges(outcome) {
+                response.headers.append("WWW-Authenticate", &challenge);
+            }
+        }
+        request.send(response)
+    }
+}
+
+impl Middleware for AuthGuard {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+        if !self.is_protected(request.path()) {
+            return Ok(None);
+        }
+
+        // an earlier layer may have already authenticated the request
+        if request.identity.is_none() {
+            let mut outcomes = Vec::with_capacity(self.authenticators.len());
+            for authenticator in self.authenticators.iter() {
+                match authenticator.authenticate(request) {
+                    AuthOutcome::Authenticated(identity) => {
+                        request.identity = Some(identity);
+                        break;
+                    }
+                    outcome => outcomes.push(outcome),
+                }
+            }
+            if request.identity.is_none() {
+                return self.unauthorized(request, &outcomes).map(Some);
+            }
+        }
+
+        let is_allowed = request
+            .identity
+            .as_ref()
+            .is_some_and(|identity| self.is_allowed_user(&identity.user));
+
+        match is_allowed {
+            true => Ok(None),
+            false => request
+                .send(HttpResponse::error(HttpStatus::Forbidden))
+                .map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::{BasicAuth, Htpasswd};
+
+    fn guard() -> AuthGuard {
+        AuthGuard::new(BasicAuth::new("admin", Htpasswd::new()))
+            .protect("/log")
+            .protect("/log.html")
+    }
+
+    #[test]
+    fn protects_prefixes_on_segment_boundaries() {
+        let guard = guard();
+        assert!(guard.is_protected("/log"));
+        assert!(guard.is_protected("/log/today"));
+        assert!(guard.is_protected("/log.html"));
+        assert!(!guard.is_protected("/logo.png"));
+        assert!(!guard.is_protected("/index.html"));
+    }
+
+    #[test]
+    fn protects_paths_with_empty_or_dot_segments() {
+        let guard = guard();
+        assert!(guard.is_protected("//log.html"));
+        assert!(guard.is_protected("///log"));
+        assert!(guard.is_protected("/./log.html"));
+        assert!(guard.is_protected("/x/../log.html"));
+        assert!(guard.is_protected("/static//../log/today"));
+    }
+
+    #[test]
+    fn does_not_decode_the_path_twice() {
+        let guard = guard();
+        assert!(!guard.is_protected("/%6Cog.html"));
+        assert!(!guard.is_protected("/a%2F..%2Flog.html"));
+    }
+}
@@ -0,0 +1,321 @@
+use crate::core::error::ServerError;
+use crate::core::util::{
+    constant_time_eq, hex_decode, hex_encode, pbkdf2_hmac_sha256, secure_random_bytes, sha256,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Result;
+
+/** Number of random salt bytes used when hashing a new password. */
+const SALT_BYTES: usize = 16;
+
+/** Number of PBKDF2 iterations used when hashing a new password. */
+pub const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/** Number of bytes of PBKDF2 output which are stored. */
+const PBKDF2_BYTES: usize = 32;
+
+/**
+    A password hash as stored in a credentials file, new passwords are always hashed with
+    PBKDF2 while salted SHA-256 hashes are still accepted for existing files.
+
+    $pbkdf2-sha256$<iterations>$<salt hex>$<hex of pbkdf2(password, salt)>
+    $sha256$<salt hex>$<hex of sha256(salt + password)>
+*/
+#[derive(Clone, Debug)]
+pub enum PasswordHash {
+    Pbkdf2Sha256 {
+        iterations: u32,
+        salt: Vec<u8>,
+        hash: Vec<u8>,
+    },
+    Sha256 {
+        salt: Vec<u8>,
+        hash: Vec<u8>,
+    },
+}
+
+impl PasswordHash {
+    /** Hash a password with PBKDF2 and a new random salt. */
+    pub fn new(password: &str) -> Result<Self> {
+        let salt = secure_random_bytes(SALT_BYTES)?;
+        let hash = pbkdf2_hmac_sha256(password.as_bytes(), &salt, PBKDF2_ITERATIONS, PBKDF2_BYTES);
+        Ok(PasswordHash::Pbkdf2Sha256 {
+            iterations: PBKDF2_ITERATIONS,
+            salt,
+            hash,
+        })
+    }
+
+    /** Parse a stored hash, plaintext or unsupported formats are rejected. */
+    pub fn parse(encoded: &str) -> Option<Self> {
+        let parts = encoded.split('$').collect::<Vec<&str>>();
+        match parts.as_slice() {
+            ["", "pbkdf2-sha256", iterations, salt, hash] => Some(PasswordHash::Pbkdf2Sha256 {
+                iterations: iterations
+                    .parse()
+                    .ok()
+                    .filter(|&iterations| iterations > 0)?,
+                salt: hex_decode(salt).ok()?,
+                hash: hex_decode(hash).ok().filter(|hash| !hash.is_empty())?,
+            }),
+            ["", "sha256", salt, hash] => Some(PasswordHash::Sha256 {
+                salt: hex_decode(salt).ok()?,
+                hash: hex_decode(hash).ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /** Check a password against this hash in constant time. */
+    pub fn verify(&self, password: &str) -> bool {
+        match self {
+            PasswordHash::Pbkdf2Sha256 {
+                iterations,
+                salt,
+                hash,
+            } => {
+                let derived =
+                    pbkdf2_hmac_sha256(password.as_bytes(), salt, *iterations, hash.len());
+                constant_time_eq(&derived, hash)
+            }
+            PasswordHash::Sha256 { salt, hash } => {
+                constant_time_eq(&salted_sha256(salt, password), hash)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PasswordHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordHash::Pbkdf2Sha256 {
+                iterations,
+                salt,
+                hash,
+            } => write!(
+                f,
+                "$pbkdf2-sha256${}${}${}",
+                iterations,
+                hex_encode(salt),
+                hex_encode(hash)
+            ),
+            PasswordHash::Sha256 { salt, hash } => {
+                write!(f, "$sha256${}${}", hex_encode(salt), hex_encode(hash))
+            }
+        }
+    }
+}
+
+fn salted_sha256(salt: &[u8], password: &str) -> [u8; 32] {
+    let mut input = salt.to_vec();
+    input.extend_from_slice(password.as_bytes());
+    sha256(&input)
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    hash: PasswordHash,
+    roles: Vec<String>,
+}
+
+/**
+    Credentials loaded from a htpasswd-style file, with one `user:hash` entry per line and
+    an optional comma separated list of roles. Blank lines and lines starting with `#` are
+    ignored.
+
+    # ./htpasswd
+    admin:$pbkdf2-sha256$100000$9f2c...$41d0...:admin,editor
+    viewer:$sha256$77ab...$0c5e...
+*/
+#[derive(Clone, Debug)]
+pub struct Htpasswd {
+    users: HashMap<String, Entry>,
+    dummy: PasswordHash,
+}
+
+impl Htpasswd {
+    pub fn new() -> Self {
+        Htpasswd {
+            users: HashMap::new(),
+            dummy: PasswordHash::Pbkdf2Sha256 {
+                iterations: PBKDF2_ITERATIONS,
+                salt: vec![0u8; SALT_BYTES],
+                hash: vec![0u8; PBKDF2_BYTES],
+            },
+        }
+    }
+
+    /** Load credentials from a file, see `Htpasswd::parse` for the format. */
+    pub fn load(path: &str) -> Result<Self> {
+        Htpasswd::parse(&fs::read_to_string(path)?)
+    }
+
+    /**
+        Parse credentials from the contents of a htpasswd file, an error is returned for
+        any entry which is not a supported salted hash.
+    */
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut htpasswd = Htpasswd::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, ':');
+            let user = fields.next().unwrap_or_default();
+            let hash = fields.next().and_then(PasswordHash::parse).ok_or_else(|| {
+                ServerError::error(&format!("htpasswd line {}: unsupported hash", number + 1))
+            })?;
+            let roles = fields
+                .next()
+                .map(|roles| {
+                    roles
+                        .split(',')
+                        .map(|role| role.trim().to_string())
+                        .filter(|role| !role.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default();
+            htpasswd
+                .users
+                .insert(user.to_string(), Entry { hash, roles });
+        }
+        Ok(htpasswd)
+    }
+
+    /** Add a user with a password, which is hashed with a new random salt. */
+    pub fn add_user(&mut self, user: &str, password: &str, roles: &[&str]) -> Result<()> {
+        let entry = Entry {
+            hash: PasswordHash::new(password)?,
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        };
+        self.users.insert(user.to_string(), entry);
+        Ok(())
+    }
+
+    /** Check if there is an entry for a user. */
+    pub fn contains(&self, user: &str) -> bool {
+        self.users.contains_key(user)
+    }
+
+    /** Get the roles for a user, returns None for unknown users. */
+    pub fn roles(&self, user: &str) -> Option<Vec<String>> {
+        self.users.get(user).map(|entry| entry.roles.clone())
+    }
+
+    /**
+        Replace the password for an existing user while keeping their roles, returns false
+        if the user is unknown.
+    */
+    pub fn set_password(&mut self, user: &str, password: &str) -> Result<bool> {
+        match self.users.get_mut(user) {
+            Some(entry) => {
+                entry.hash = PasswordHash::new(password)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /** Remove a user, returns false if the user is unknown. */
+    pub fn remove_user(&mut self, user: &str) -> bool {
+        self.users.remove(user).is_some()
+    }
+
+    /**
+        Verify a user's password and return their roles. Unknown users are checked against
+        a dummy hash so the time taken does not reveal which users exist.
+    */
+    pub fn verify(&self, user: &str, password: &str) -> Option<Vec<String>> {
+        match self.users.get(user) {
+            Some(entry) => match entry.hash.verify(password) {
+                true => Some(entry.roles.clone()),
+                false => None,
+            },
+            None => {
+                self.dummy.verify(password);
+                None
+            }
+        }
+    }
+
+    /** Format the credentials as the contents of a htpasswd file. */
+    pub fn to_file_string(&self) -> String {
+        let mut users = self.users.iter().collect::<Vec<_>>();
+        users.sort_by(|a, b| a.0.cmp(b.0));
+        users
+            .into_iter()
+            .map(|(user, entry)| match entry.roles.is_empty() {
+                true => format!("{}:{}\n", user, entry.hash),
+                false => format!("{}:{}:{}\n", user, entry.hash, entry.roles.join(",")),
+            })
+            .collect()
+    }
+}
+
+impl Default for Htpasswd {
+    fn default() -> Self {
+        Htpasswd::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** `password` with the salt `salt` and 4096 iterations, from the PBKDF2 test vectors. */
+    const PBKDF2_ENTRY: &str = "$pbkdf2-sha256$4096$73616c74$\
+                                c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a";
+
+    fn sha256_entry(salt: &str, password: &str) -> String {
+        let hash = salted_sha256(salt.as_bytes(), password);
+        format!(
+            "$sha256${}${}",
+            hex_encode(salt.as_bytes()),
+            hex_encode(&hash)
+        )
+    }
+
+    #[test]
+    fn verifies_pbkdf2_entries() {
+        let htpasswd = Htpasswd::parse(&format!("admin:{}:admin,ops\n", PBKDF2_ENTRY)).unwrap();
+        assert_eq!(
+            htpasswd.verify("admin", "password"),
+            Some(vec!["admin".to_string(), "ops".to_string()])
+        );
+        assert_eq!(htpasswd.verify("admin", "Password"), None);
+    }
+
+    #[test]
+    fn verifies_legacy_sha256_entries() {
+        let contents = format!("# legacy\n\nviewer:{}\n", sha256_entry("77ab", "secret"));
+        let htpasswd = Htpasswd::parse(&contents).unwrap();
+        assert_eq!(htpasswd.verify("viewer", "secret"), Some(Vec::new()));
+        assert_eq!(htpasswd.verify("viewer", "secrets"), None);
+        assert_eq!(htpasswd.verify("nobody", "secret"), None);
+    }
+
+    #[test]
+    fn refuses_plaintext_and_unknown_hashes() {
+        assert!(Htpasswd::parse("admin:password\n").is_err());
+        assert!(Htpasswd::parse("admin:$apr1$abc$def\n").is_err());
+        assert!(PasswordHash::parse("$pbkdf2-sha256$0$73616c74$00").is_none());
+        assert!(PasswordHash::parse("$pbkdf2-sha256$1$zz$00").is_none());
+    }
+
+    #[test]
+    fn hashes_round_trip_through_the_file() {
+        let mut htpasswd = Htpasswd::parse(&format!("admin:{}\n", PBKDF2_ENTRY)).unwrap();
+        htpasswd.add_user("ada", "lovelace", &["editor"]).unwrap();
+        let parsed = Htpasswd::parse(&htpasswd.to_file_string()).unwrap();
+        assert_eq!(parsed.verify("admin", "password"), Some(Vec::new()));
+        assert_eq!(
+            parsed.verify("ada", "lovelace"),
+            Some(vec!["editor".to_string()])
+        );
+        assert!(htpasswd
+            .to_file_string()
+            .contains("ada:$pbkdf2-sha256$100000$"));
+    }
+}
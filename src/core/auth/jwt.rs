@@ -0,0 +1,483 @@
+use super::{parse_authorization, quote, AuthOutcome, Authenticator, Identity};
+use crate::core::error::ServerError;
+use crate::core::http::HttpRequest;
+use crate::core::json::{self, Value};
+use crate::core::util::p256::PublicKey;
+use crate::core::util::{base64_decode, base64url_decode, constant_time_eq, hmac_sha256};
+use crate::core::ConfigFile;
+use crate::debug;
+use std::fmt;
+use std::fs;
+use std::io::Result;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/**
+    The reasons a token can fail verification. The client is only told the token is
+    invalid with `error="invalid_token"`, the reason is logged at debug level.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum JwtError {
+    Malformed,
+    UnsupportedAlgorithm(String),
+    InvalidSignature,
+    Expired,
+    NotYetValid,
+    InvalidIssuer,
+    InvalidAudience,
+}
+
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtError::Malformed => write!(f, "malformed token"),
+            JwtError::UnsupportedAlgorithm(alg) => write!(f, "unsupported algorithm {}", alg),
+            JwtError::InvalidSignature => write!(f, "invalid signature"),
+            JwtError::Expired => write!(f, "token has expired"),
+            JwtError::NotYetValid => write!(f, "token is not yet valid"),
+            JwtError::InvalidIssuer => write!(f, "invalid issuer"),
+            JwtError::InvalidAudience => write!(f, "invalid audience"),
+        }
+    }
+}
+
+/**
+    Bearer token authentication with JSON Web Tokens (RFC 7519) signed with either HS256
+    or ES256. The `exp` and `nbf` claims are checked with an allowance for clock skew, and
+    the `iss` and `aud` claims must match when an issuer or audience is configured.
+
+    let jwt = JwtAuth::new("api")
+        .hs256_secret(secret.as_bytes())
+        .issuer("https://auth.asleepace.com")
+        .audience("api");
+
+    server.middleware(AuthGuard::new(jwt).protect("/api"));
+
+    The verified claims are available to handlers with `request.claims()`, the `sub` claim
+    is used as the user and the roles are read from the `roles` claim.
+*/
+pub struct JwtAuth {
+    realm: String,
+    hs256_secret: Option<Vec<u8>>,
+    es256_key: Option<PublicKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: Duration,
+    roles_claim: String,
+}
+
+impl JwtAuth {
+    pub fn new(realm: &str) -> Self {
+        JwtAuth {
+            realm: realm.to_string(),
+            hs256_secret: None,
+            es256_key: None,
+            issuer: None,
+            audience: None,
+            leeway: Duration::from_secs(60),
+            roles_claim: "roles".to_string(),
+        }
+    }
+
+    /**
+        Create from the `[jwt]` section of a config file, at least one key must be set.
+
+        [jwt]
+        realm = api
+        hs256_secret = "base64:c2VjcmV0"    # or a plain string, or hs256_secret_file
+        es256_public_key = ./keys/jwt.pem   # PEM encoded P-256 public key
+        issuer = https://auth.asleepace.com
+        audience = api
+        leeway = 60
+        roles_claim = roles
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let mut jwt = JwtAuth::new(config.get("jwt.realm").unwrap_or("api"));
+
+        if let Some(secret) = config.get("jwt.hs256_secret") {
+            jwt.hs256_secret = Some(decode_secret(secret)?);
+        }
+        if let Some(path) = config.get("jwt.hs256_secret_file") {
+            jwt.hs256_secret = Some(fs::read(path)?.trim_ascii().to_vec());
+        }
+        if let Some(path) = config.get("jwt.es256_public_key") {
+            let pem = fs::read_to_string(path)?;
+            let key = PublicKey::from_pem(&pem).ok_or_else(|| {
+                ServerError::error(&format!("{} is not a P-256 public key", path))
+            })?;
+            jwt.es256_key = Some(key);
+        }
+        if jwt.hs256_secret.is_none() && jwt.es256_key.is_none() {
+            return Err(ServerError::error(
+                "jwt: no hs256_secret or es256_public_key set",
+            ));
+        }
+
+        jwt.issuer = config.get("jwt.issuer").map(|issuer| issuer.to_string());
+        jwt.audience = config
+            .get("jwt.audience")
+            .map(|audience| audience.to_string());
+        if let Some(leeway) = config.get_u64("jwt.leeway") {
+            jwt.leeway = Duration::from_secs(leeway);
+        }
+        if let Some(claim) = config.get("jwt.roles_claim") {
+            jwt.roles_claim = claim.to_string();
+        }
+        Ok(jwt)
+    }
+
+    pub fn hs256_secret(mut self, secret: &[u8]) -> Self {
+        self.hs256_secret = Some(secret.to_vec());
+        self
+    }
+
+    pub fn es256_key(mut self, key: PublicKey) -> Self {
+        self.es256_key = Some(key);
+        self
+    }
+
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    /** How much clock skew to allow when checking the `exp` and `nbf` claims. */
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /** The claim which holds the user's roles, either an array or a space separated string. */
+    pub fn roles_claim(mut self, claim: &str) -> Self {
+        self.roles_claim = claim.to_string();
+        self
+    }
+
+    /**
+        Verify a compact serialized token and return its claims.
+    */
+    pub fn verify(&self, token: &str) -> std::result::Result<Value, JwtError> {
+        let parts = token.split('.').collect::<Vec<&str>>();
+        let (header, payload, signature) = match parts.as_slice() {
+            [header, payload, signature] => (*header, *payload, *signature),
+            _ => return Err(JwtError::Malformed),
+        };
+
+        let header_json = decode_json(header)?;
+        let signature = base64url_decode(signature).map_err(|_| JwtError::Malformed)?;
+        let signing_input = &token[..header.len() + 1 + payload.len()];
+
+        // tokens with critical extensions we don't understand must be rejected
+        if header_json.get("crit").is_some() {
+            return Err(JwtError::UnsupportedAlgorithm("crit".to_string()));
+        }
+
+        let algorithm = header_json
+            .get("alg")
+            .and_then(|alg| alg.as_str())
+            .ok_or(JwtError::Malformed)?;
+        let is_valid = match (algorithm, &self.hs256_secret, &self.es256_key) {
+            ("HS256", Some(secret), _) => {
+                let expected = hmac_sha256(secret, signing_input.as_bytes());
+                constant_time_eq(&expected, &signature)
+            }
+            ("ES256", _, Some(key)) => key.verify(signing_input.as_bytes(), &signature),
+            _ => return Err(JwtError::UnsupportedAlgorithm(algorithm.to_string())),
+        };
+        if !is_valid {
+            return Err(JwtError::InvalidSignature);
+        }
+
+        let claims = decode_json(payload)?;
+        self.validate_claims(&claims)?;
+        Ok(claims)
+    }
+
+    fn validate_claims(&self, claims: &Value) -> std::result::Result<(), JwtError> {
+        if !matches!(claims, Value::Object(_)) {
+            return Err(JwtError::Malformed);
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or(0.0);
+        let leeway = self.leeway.as_secs_f64();
+
+        match claims.get("exp").map(|exp| exp.as_f64()) {
+            Some(None) => return Err(JwtError::Malformed),
+            Some(Some(exp)) if now > exp + leeway => return Err(JwtError::Expired),
+            _ => {}
+        }
+        match claims.get("nbf").map(|nbf| nbf.as_f64()) {
+            Some(None) => return Err(JwtError::Malformed),
+            Some(Some(nbf)) if now + leeway < nbf => return Err(JwtError::NotYetValid),
+            _ => {}
+        }
+
+        if let Some(issuer) = &self.issuer {
+            let iss = claims.get("iss").and_then(|iss| iss.as_str());
+            if iss != Some(issuer.as_str()) {
+                return Err(JwtError::InvalidIssuer);
+            }
+        }
+
+        // the audience claim may be a single string or an array of strings
+        if let Some(audience) = &self.audience {
+            let matches = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+                _ => false,
+            };
+            if !matches {
+                return Err(JwtError::InvalidAudience);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn roles(&self, claims: &Value) -> Vec<String> {
+        match claims.get(&self.roles_claim) {
+            Some(Value::Array(roles)) => roles
+                .iter()
+                .filter_map(|role| role.as_str())
+                .map(|role| role.to_string())
+                .collect(),
+            Some(Value::String(roles)) => roles
+                .split_whitespace()
+                .map(|role| role.to_string())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn bearer_token<'a>(&self, request: &'a HttpRequest) -> Option<&'a str> {
+        request
+            .headers
+            .get("Authorization")
+            .and_then(|header| parse_authorization(header, "Bearer"))
+    }
+}
+
+impl Authenticator for JwtAuth {
+    fn authenticate(&self, request: &HttpRequest) -> AuthOutcome {
+        let token = match self.bearer_token(request) {
+            Some(token) => token,
+            None => return AuthOutcome::Missing,
+        };
+        match self.verify(token) {
+            Ok(claims) => {
+                let user = claims
+                    .get("sub")
+                    .and_then(|sub| sub.as_str())
+                    .unwrap_or_default();
+                let identity = Identity::new(user, "Bearer")
+                    .with_roles(&self.roles(&claims))
+                    .with_claims(claims);
+                AuthOutcome::Authenticated(identity)
+            }
+            Err(err) => {
+                debug!("invalid bearer token: {}", err);
+                AuthOutcome::Invalid
+            }
+        }
+    }
+
+    fn challenges(&self, outcome: &AuthOutcome) -> Vec<String> {
+        match outcome {
+            AuthOutcome::Invalid => vec![format!(
+                "Bearer realm=\"{}\", error=\"invalid_token\"",
+                quote(&self.realm)
+            )],
+            _ => vec![format!("Bearer realm=\"{}\"", quote(&self.realm))],
+        }
+    }
+}
+
+fn decode_json(segment: &str) -> std::result::Result<Value, JwtError> {
+    let bytes = base64url_decode(segment).map_err(|_| JwtError::Malformed)?;
+    let text = String::from_utf8(bytes).map_err(|_| JwtError::Malformed)?;
+    json::parse(&text).map_err(|_| JwtError::Malformed)
+}
+
+/**
+    Decode a secret from the config, values prefixed with `base64:` or `hex:` are decoded
+    and anything else is used as is.
+*/
+fn decode_secret(secret: &str) -> Result<Vec<u8>> {
+    let invalid = |_| ServerError::error("jwt: invalid hs256_secret encoding");
+    if let Some(encoded) = secret.strip_prefix("base64:") {
+        return base64_decode(encoded).map_err(invalid);
+    }
+    if let Some(encoded) = secret.strip_prefix("hex:") {
+        return crate::core::util::hex_decode(encoded).map_err(invalid);
+    }
+    Ok(secret.as_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::util::base64url_encode;
+
+    /** The payload of the RFC 7515 examples, which expired in 2011. */
+    const RFC_PAYLOAD: &str = "eyJpc3MiOiJqb2UiLA0KICJleHAiOjEzMDA4MTkzODAsDQogImh0dHA6Ly9leGFt\
+                               cGxlLmNvbS9pc19yb290Ijp0cnVlfQ";
+
+    /** RFC 7515 appendix A.3, an ES256 signature with the example P-256 key. */
+    const ES256_TOKEN: (&str, &str) = (
+        "eyJhbGciOiJFUzI1NiJ9",
+        "DtEhU3ljbEg8L38VWAfUAqOyKAM6-Xx-F4GawxaepmXFCgfTjDxw5djxLa8ISlSApmWQxfKTUJqPP3-Kg6NU1Q",
+    );
+
+    /** RFC 7515 appendix A.1, an HS256 signature with the example key. */
+    const HS256_TOKEN: (&str, &str) = (
+        "eyJ0eXAiOiJKV1QiLA0KICJhbGciOiJIUzI1NiJ9",
+        "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk",
+    );
+
+    fn rfc_token((header, signature): (&str, &str)) -> String {
+        format!("{}.{}.{}", header, RFC_PAYLOAD, signature)
+    }
+
+    fn es256_key() -> PublicKey {
+        let x = base64url_decode("f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU").unwrap();
+        let y = base64url_decode("x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0").unwrap();
+        PublicKey::from_coordinates(&x, &y).unwrap()
+    }
+
+    fn hs256_key() -> Vec<u8> {
+        base64url_decode(
+            "AyM1SysPpbyDfgZld3umj1qzKObwVMkoqQ-EstJQLr_T-1qS0gZH75aKtMN3Yj0iPS4hcgUuTwjAzZr1Z9CAow",
+        )
+        .unwrap()
+    }
+
+    /** Flip a bit in the middle of the signature segment. */
+    fn tamper(token: &str) -> String {
+        let (signing_input, signature) = token.rsplit_once('.').unwrap();
+        let mut bytes = base64url_decode(signature).unwrap();
+        bytes[10] ^= 1;
+        format!("{}.{}", signing_input, base64url_encode(&bytes))
+    }
+
+    fn hs256_token(claims: &str) -> String {
+        let header = base64url_encode(b"{\"alg\":\"HS256\",\"typ\":\"JWT\"}");
+        let payload = base64url_encode(claims.as_bytes());
+        let signing_input = format!("{}.{}", header, payload);
+        let signature = hmac_sha256(b"secret", signing_input.as_bytes());
+        format!("{}.{}", signing_input, base64url_encode(&signature))
+    }
+
+    #[test]
+    fn verifies_the_rfc_7515_es256_signature() {
+        let token = rfc_token(ES256_TOKEN);
+        let (signing_input, signature) = token.rsplit_once('.').unwrap();
+        let signature = base64url_decode(signature).unwrap();
+        assert!(es256_key().verify(signing_input.as_bytes(), &signature));
+
+        // the signature is checked before the claims, so an expired token got that far
+        let jwt = JwtAuth::new("api").es256_key(es256_key());
+        assert_eq!(jwt.verify(&token), Err(JwtError::Expired));
+    }
+
+    #[test]
+    fn verifies_the_rfc_7515_hs256_signature() {
+        let jwt = JwtAuth::new("api").hs256_secret(&hs256_key());
+        assert_eq!(jwt.verify(&rfc_token(HS256_TOKEN)), Err(JwtError::Expired));
+    }
+
+    #[test]
+    fn refuses_tampered_signatures() {
+        let es256 = JwtAuth::new("api").es256_key(es256_key());
+        let token = tamper(&rfc_token(ES256_TOKEN));
+        assert_eq!(es256.verify(&token), Err(JwtError::InvalidSignature));
+        let (signing_input, signature) = token.rsplit_once('.').unwrap();
+        let signature = base64url_decode(signature).unwrap();
+        assert!(!es256_key().verify(signing_input.as_bytes(), &signature));
+
+        let hs256 = JwtAuth::new("api").hs256_secret(&hs256_key());
+        let token = tamper(&rfc_token(HS256_TOKEN));
+        assert_eq!(hs256.verify(&token), Err(JwtError::InvalidSignature));
+    }
+
+    #[test]
+    fn refuses_algorithms_without_a_key() {
+        let jwt = JwtAuth::new("api").hs256_secret(&hs256_key());
+        assert_eq!(
+            jwt.verify(&rfc_token(ES256_TOKEN)),
+            Err(JwtError::UnsupportedAlgorithm("ES256".to_string()))
+        );
+        let none = format!(
+            "{}.{}.",
+            base64url_encode(b"{\"alg\":\"none\"}"),
+            RFC_PAYLOAD
+        );
+        assert_eq!(
+            jwt.verify(&none),
+            Err(JwtError::UnsupportedAlgorithm("none".to_string()))
+        );
+        assert_eq!(jwt.verify("a.b"), Err(JwtError::Malformed));
+    }
+
+    #[test]
+    fn checks_registered_claims() {
+        let jwt = JwtAuth::new("api")
+            .hs256_secret(b"secret")
+            .issuer("https://auth.example.com")
+            .audience("api");
+        let claims = |extra: &str| {
+            format!(
+                "{{\"sub\":\"ada\",\"iss\":\"https://auth.example.com\",\"roles\":[\"admin\"]{}}}",
+                extra
+            )
+        };
+        let valid = jwt
+            .verify(&hs256_token(&claims(",\"aud\":[\"web\",\"api\"]")))
+            .unwrap();
+        assert_eq!(jwt.roles(&valid), vec!["admin".to_string()]);
+        assert_eq!(
+            jwt.verify(&hs256_token(&claims(",\"aud\":\"web\""))),
+            Err(JwtError::InvalidAudience)
+        );
+        assert_eq!(
+            jwt.verify(&hs256_token(&claims(",\"aud\":\"api\",\"exp\":1"))),
+            Err(JwtError::Expired)
+        );
+        assert_eq!(
+            jwt.verify(&hs256_token(&claims(
+                ",\"aud\":\"api\",\"nbf\":99999999999"
+            ))),
+            Err(JwtError::NotYetValid)
+        );
+        let other_issuer = hs256_token("{\"iss\":\"https://evil.example.com\",\"aud\":\"api\"}");
+        assert_eq!(jwt.verify(&other_issuer), Err(JwtError::InvalidIssuer));
+    }
+
+    #[test]
+    fn invalid_tokens_get_an_invalid_token_challenge() {
+        let jwt = JwtAuth::new("api").hs256_secret(b"secret");
+        let mut request = HttpRequest::to("/api");
+        request.headers.set("Authorization", "Bearer not.a.token");
+        let outcome = jwt.authenticate(&request);
+        assert!(matches!(outcome, AuthOutcome::Invalid));
+        assert_eq!(
+            jwt.challenges(&outcome),
+            vec!["Bearer realm=\"api\", error=\"invalid_token\"".to_string()]
+        );
+    }
+
+    #[test]
+    fn escapes_quotes_in_the_realm() {
+        let jwt = JwtAuth::new("a \"b\" \\").hs256_secret(b"secret");
+        assert_eq!(
+            jwt.challenges(&AuthOutcome::Missing),
+            vec!["Bearer realm=\"a \\\"b\\\" \\\\\"".to_string()]
+        );
+    }
+}
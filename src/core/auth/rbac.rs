@@ -0,0 +1,220 @@
+use super::Identity;
+use crate::core::error::ServerError;
+use crate::core::http::http_headers::{HttpHeaders, HttpMethod};
+use crate::core::util::glob_match;
+use crate::core::ConfigFile;
+use std::io::Result;
+
+/** Role which matches any authenticated user. */
+pub const ANY_ROLE: &str = "*";
+
+/**
+    A single authorization rule, which matches requests by a glob path pattern and an
+    optional list of methods, and lists the roles which are allowed. Note that `*` in the
+    pattern also matches `/`, so `/admin*` covers every path below `/admin`.
+*/
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub pattern: String,
+    pub methods: Option<Vec<HttpMethod>>,
+    pub roles: Vec<String>,
+}
+
+impl Rule {
+    pub fn new(pattern: &str, methods: &[HttpMethod], roles: &[&str]) -> Self {
+        Rule {
+            pattern: pattern.to_string(),
+            methods: match methods.is_empty() {
+                true => None,
+                false => Some(methods.to_vec()),
+            },
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        }
+    }
+
+    /**
+        Parse a rule from a config line with the path pattern, the methods (or `*` for any
+        method) and the allowed roles.
+
+        /admin* GET,POST admin,editor
+    */
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        let [pattern, methods, roles] = fields.as_slice() else {
+            return None;
+        };
+        let methods = match *methods {
+            "*" => Vec::new(),
+            methods => methods
+                .split(',')
+                .map(|method| HttpHeaders::method_from_string(method.trim()))
+                .collect(),
+        };
+        let roles = roles
+            .split(',')
+            .map(|role| role.trim())
+            .collect::<Vec<&str>>();
+        Some(Rule::new(pattern, &methods, &roles))
+    }
+
+    pub fn matches(&self, path: &str, method: &HttpMethod) -> bool {
+        let method_matches = match &self.methods {
+            Some(methods) => methods.contains(method),
+            None => true,
+        };
+        method_matches && glob_match(&self.pattern, path)
+    }
+
+    /** Check if an identity has one of the roles allowed by this rule. */
+    pub fn allows(&self, identity: Option<&Identity>) -> bool {
+        let identity = match identity {
+            Some(identity) => identity,
+            None => return false,
+        };
+        self.roles
+            .iter()
+            .any(|role| role == ANY_ROLE || identity.has_role(role))
+    }
+}
+
+/**
+    A table of authorization rules which is checked after the request has been
+    authenticated, regardless of which authentication scheme was used. The first rule
+    matching the path and method decides whether the request is allowed, and requests
+    which no rule matches are allowed unless `deny_by_default` is set.
+
+    let rules = Rules::new()
+        .rule("/admin*", &[], &["admin"])
+        .rule("/api*", &[HttpMethod::DELETE], &["admin"])
+        .rule("/api*", &[], &["*"]);
+
+    server.authorize(rules);
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+    deny_by_default: bool,
+}
+
+impl Rules {
+    pub fn new() -> Self {
+        Rules::default()
+    }
+
+    /**
+        Load rules from the `[authorization]` section of a config file, with one `rule`
+        entry per line in the format accepted by `Rule::parse`.
+
+        [authorization]
+        deny_by_default = false
+        rule = /admin* * admin
+        rule = /api* DELETE admin
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let mut rules = Rules::new();
+        for line in config.get_all("authorization.rule") {
+            let rule = Rule::parse(line).ok_or_else(|| {
+                ServerError::error(&format!("invalid authorization rule: {}", line))
+            })?;
+            rules.rules.push(rule);
+        }
+        rules.deny_by_default = config
+            .get_bool("authorization.deny_by_default")
+            .unwrap_or(false);
+        Ok(rules)
+    }
+
+    /** Add a rule, an empty list of methods matches every method. */
+    pub fn rule(mut self, pattern: &str, methods: &[HttpMethod], roles: &[&str]) -> Self {
+        self.rules.push(Rule::new(pattern, methods, roles));
+        self
+    }
+
+    pub fn push(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /** Deny requests which do not match any rule. */
+    pub fn deny_by_default(mut self, deny: bool) -> Self {
+        self.deny_by_default = deny;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /** Check if the identity may access the path with the given method. */
+    pub fn is_allowed(&self, path: &str, method: &HttpMethod, identity: Option<&Identity>) -> bool {
+        match self.rules.iter().find(|rule| rule.matches(path, method)) {
+            Some(rule) => rule.allows(identity),
+            None => !self.deny_by_default,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(roles: &[&str]) -> Identity {
+        let roles = roles
+            .iter()
+            .map(|role| role.to_string())
+            .collect::<Vec<_>>();
+        Identity::new("ada", "Basic").with_roles(&roles)
+    }
+
+    fn rules() -> Rules {
+        Rules::new()
+            .rule("/admin*", &[], &["admin"])
+            .rule("/api*", &[HttpMethod::DELETE], &["admin"])
+            .rule("/api*", &[], &[ANY_ROLE])
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let rules = rules();
+        let (admin, editor) = (user(&["admin"]), user(&["editor"]));
+        assert!(rules.is_allowed("/admin/users", &HttpMethod::GET, Some(&admin)));
+        assert!(!rules.is_allowed("/admin/users", &HttpMethod::GET, Some(&editor)));
+        assert!(!rules.is_allowed("/api/items/1", &HttpMethod::DELETE, Some(&editor)));
+        assert!(rules.is_allowed("/api/items/1", &HttpMethod::DELETE, Some(&admin)));
+        assert!(rules.is_allowed("/api/items", &HttpMethod::GET, Some(&editor)));
+    }
+
+    #[test]
+    fn any_role_still_requires_an_identity() {
+        let rules = rules();
+        assert!(rules.is_allowed("/api", &HttpMethod::GET, Some(&user(&[]))));
+        assert!(!rules.is_allowed("/api", &HttpMethod::GET, None));
+    }
+
+    #[test]
+    fn unmatched_paths_follow_the_default() {
+        assert!(rules().is_allowed("/public", &HttpMethod::GET, None));
+        assert!(!rules()
+            .deny_by_default(true)
+            .is_allowed("/public", &HttpMethod::GET, None));
+    }
+
+    #[test]
+    fn parses_rules_from_config() {
+        let rule = Rule::parse("/admin* GET,post admin,editor").unwrap();
+        assert_eq!(rule.pattern, "/admin*");
+        assert_eq!(rule.methods, Some(vec![HttpMethod::GET, HttpMethod::POST]));
+        assert_eq!(rule.roles, vec!["admin".to_string(), "editor".to_string()]);
+        assert_eq!(Rule::parse("/api* * *").unwrap().methods, None);
+        assert!(Rule::parse("/api* GET").is_none());
+
+        let config = ConfigFile::parse(
+            "[authorization]\ndeny_by_default = true\nrule = /admin* * admin\nrule = /api* * *\n",
+        );
+        let rules = Rules::from_config(&config).unwrap();
+        assert!(rules.is_allowed("/api", &HttpMethod::GET, Some(&user(&[]))));
+        assert!(!rules.is_allowed("/other", &HttpMethod::GET, Some(&user(&["admin"]))));
+
+        let invalid = ConfigFile::parse("[authorization]\nrule = /admin*\n");
+        assert!(Rules::from_config(&invalid).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::debug;
 use std::collections::HashMap;
 use std::env;
 use std::pin::Pin;
@@ -11,8 +12,9 @@ pub enum Args {
     Bool(bool),
 }
 
+/** A lone `-` is a value, such as stdout for `--access-log -`. */
 pub fn is_token(token: &str) -> bool {
-    token.starts_with("-")
+    token.starts_with("-") && token != "-"
 }
 
 pub fn process_args() -> HashMap<String, Args> {
@@ -21,7 +23,7 @@ pub fn process_args() -> HashMap<String, Args> {
     // The arguments to be returned to the caller
     let mut arguments: HashMap<String, Args> = HashMap::new();
 
-    println!("[serveros] args: {:?}", args);
+    debug!("args: {:?}", args);
 
     let total_length = args.len() - 1;
     let mut i = 0;
@@ -1,3 +1,4 @@
+use crate::{info, trace};
 pub struct Config {
     pub host: String,
     pub port: u16,
@@ -20,13 +21,13 @@ impl Config {
     }
 
     pub fn print(&self) {
-        println!("[config] host: {}", self.host);
-        println!("[config] port: {}", self.port);
+        info!("host: {}", self.host);
+        info!("port: {}", self.port);
     }
 
     pub fn public(&self, path: &str) -> String {
         let asset_path = format!("./public/{}", path);
-        println!("[config] public: {}", asset_path);
+        trace!("public: {}", asset_path);
         asset_path
     }
 
@@ -37,3 +38,108 @@ impl Config {
         }
     }
 }
+
+/**
+    Settings loaded from a simple configuration file, with `key = value` pairs grouped into
+    `[section]` blocks. Keys are looked up as `section.key`, blank lines and lines starting
+    with `#` are ignored, and values may be wrapped in double quotes.
+
+    # server.conf
+    [jwt]
+    issuer = "https://auth.asleepace.com"
+    audience = api
+*/
+#[derive(Clone, Debug, Default)]
+pub struct ConfigFile {
+    pub path: String,
+    values: Vec<(String, String)>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut config = ConfigFile::parse(&contents);
+        config.path = path.to_string();
+        Ok(config)
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut section = String::new();
+        let mut values = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_string();
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let key = match section.is_empty() {
+                    true => key.trim().to_string(),
+                    false => format!("{}.{}", section, key.trim()),
+                };
+                let value = value.trim();
+                let value = match value.len() >= 2 && value.starts_with('"') && value.ends_with('"')
+                {
+                    true => &value[1..value.len() - 1],
+                    false => value,
+                };
+                values.push((key, value.to_string()));
+            }
+        }
+        ConfigFile {
+            path: String::new(),
+            values,
+        }
+    }
+
+    /** Get the last value set for a key such as `jwt.issuer`. */
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /** Get every value set for a key, for settings which may be repeated. */
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.values
+            .iter()
+            .filter(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /** Get a comma separated value as a list, empty entries are skipped. */
+    pub fn get_list(&self, key: &str) -> Vec<String> {
+        match self.get(key) {
+            Some(value) => value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        self.get(key).and_then(|value| value.parse::<u64>().ok())
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)?.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(true),
+            "false" | "no" | "off" | "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    /** True if any key has been set in the given section. */
+    pub fn has_section(&self, section: &str) -> bool {
+        let prefix = format!("{}.", section);
+        self.values.iter().any(|(key, _)| key.starts_with(&prefix))
+    }
+}
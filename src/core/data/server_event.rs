@@ -60,7 +60,11 @@ impl ServerEvent {
         if let Some(retry) = &self.retry {
             event.push_str(&format!("retry: {}\r\n", retry));
         }
-        event.push_str(&format!("data: {}\r\n\r\n", self.data));
+        // each line of the data needs its own field or it would end the event
+        for line in self.data.split('\n') {
+            event.push_str(&format!("data: {}\r\n", line.trim_end_matches('\r')));
+        }
+        event.push_str("\r\n");
         event
     }
 
@@ -1,5 +1,6 @@
 use crate::core::file::URI;
 use crate::core::util::get_mime_type;
+use crate::{debug, error, trace};
 use std::error::Error;
 use std::fs;
 use std::fs::FileType;
@@ -19,7 +20,7 @@ impl Doc {
         return match fs::read(url) {
             Ok(data) => Ok(data),
             Err(err) => {
-                eprintln!("[response] file not found: {} error: {}", url, err);
+                debug!("file not found: {} error: {}", url, err);
                 return Result::Err(());
             }
         };
@@ -36,11 +37,11 @@ impl Doc {
             Some(meta) => {
                 let file_type = meta.file_type();
                 let mime_type = format!("{:?}", file_type);
-                println!("[doc] file type: {:?}", mime_type);
+                trace!("file type: {:?}", mime_type);
                 mime_type
             }
             None => {
-                eprintln!("[doc] error: unable to read file meta data");
+                error!("error: unable to read file meta data");
                 return None;
             }
         };
@@ -63,7 +64,7 @@ impl Doc {
             match self.data.clone() {
                 Some(data) => return Some(data),
                 None => {
-                    eprintln!("[doc] error: failed to read file data");
+                    error!("error: failed to read file data");
                     // fall through
                 }
             }
@@ -1,3 +1,4 @@
+use crate::trace;
 use std::fs;
 
 #[derive(Clone, Debug)]
@@ -53,7 +54,7 @@ impl URI {
             .collect();
 
         let public_path = format!("./src/public/{}", components.join("/"));
-        println!("[uri] public path: {}", public_path.as_str());
+        trace!("public path: {}", public_path.as_str());
         URI::new(&public_path)
     }
 
@@ -0,0 +1,261 @@
+use crate::core::error::ServerError;
+use crate::core::http::HttpStatus;
+use crate::core::json::Value;
+use crate::core::ConfigFile;
+use std::fs::{self, OpenOptions};
+use std::io::Result;
+use std::time::Duration;
+
+/** A readiness check, which returns the reason it failed. */
+pub type Check = Box<dyn Fn() -> std::result::Result<(), String> + Send + Sync>;
+
+/**
+    Liveness and readiness endpoints for an orchestrator. Liveness only shows the server is
+    answering, readiness runs every registered check and fails while the server is shutting
+    down, so traffic can be moved away before the listener closes.
+
+    let health = Health::new()
+        .check("public_dir", health::dir_readable("./src/public"))
+        .check("database", || db.ping().map_err(|err| err.to_string()));
+    server.health(health);
+
+    Both respond with a JSON breakdown, with a 503 when any check fails.
+
+    {"status":"fail","checks":{"shutdown":{"status":"fail","error":"shutting down"}}}
+*/
+pub struct Health {
+    live_path: String,
+    ready_path: String,
+    shutdown_delay: Option<Duration>,
+    checks: Vec<(String, Check)>,
+}
+
+impl Health {
+    /** Serve liveness at `/healthz` and readiness at `/readyz`, without a graceful shutdown. */
+    pub fn new() -> Self {
+        Health {
+            live_path: "/healthz".to_string(),
+            ready_path: "/readyz".to_string(),
+            shutdown_delay: None,
+            checks: Vec::new(),
+        }
+    }
+
+    /**
+        Create from the `[health]` section of a config file, the shutdown delay is how many
+        seconds readiness fails before the listener closes, and only when it is set does the
+        server handle `SIGTERM` and `SIGINT` with a graceful shutdown.
+
+        [health]
+        live_path = /healthz
+        ready_path = /readyz
+        shutdown_delay = 5
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let mut health = Health::new();
+        if let Some(path) = config.get("health.live_path") {
+            health = health.live(&parse_path(path)?);
+        }
+        if let Some(path) = config.get("health.ready_path") {
+            health = health.ready(&parse_path(path)?);
+        }
+        if let Some(seconds) = config.get_u64("health.shutdown_delay") {
+            health = health.shutdown_after(Duration::from_secs(seconds));
+        }
+        Ok(health)
+    }
+
+    /** Serve liveness at a path. */
+    pub fn live(mut self, path: &str) -> Self {
+        self.live_path = path.to_string();
+        self
+    }
+
+    /** Serve readiness at a path. */
+    pub fn ready(mut self, path: &str) -> Self {
+        self.ready_path = path.to_string();
+        self
+    }
+
+    /**
+        How long readiness fails before the listener closes during a graceful shutdown, see
+        `Server::graceful_shutdown`.
+    */
+    pub fn shutdown_after(mut self, delay: Duration) -> Self {
+        self.shutdown_delay = Some(delay);
+        self
+    }
+
+    /** Add a readiness check, which is run on every request for the readiness path. */
+    pub fn check<F>(mut self, name: &str, check: F) -> Self
+    where
+        F: Fn() -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        self.checks.push((name.to_string(), Box::new(check)));
+        self
+    }
+
+    pub fn live_path(&self) -> &str {
+        &self.live_path
+    }
+
+    pub fn ready_path(&self) -> &str {
+        &self.ready_path
+    }
+
+    pub fn shutdown_delay(&self) -> Option<Duration> {
+        self.shutdown_delay
+    }
+
+    /** Run the registered checks in the order they were added. */
+    pub fn run_checks(&self) -> Vec<(String, std::result::Result<(), String>)> {
+        self.checks
+            .iter()
+            .map(|(name, check)| (name.clone(), check()))
+            .collect()
+    }
+
+    /**
+        Build the response for a set of check results, `200` with `pass` when every check
+        passed and `503` with `fail` otherwise.
+    */
+    pub fn report(results: &[(String, std::result::Result<(), String>)]) -> (HttpStatus, Value) {
+        let mut checks = Value::object();
+        for (name, result) in results.iter() {
+            let check = match result {
+                Ok(()) => Value::object().with("status", "pass".into()),
+                Err(error) => Value::object()
+                    .with("status", "fail".into())
+                    .with("error", error.as_str().into()),
+            };
+            checks.set(name, check);
+        }
+        let (status, text) = match results.iter().all(|(_, result)| result.is_ok()) {
+            true => (HttpStatus::OK, "pass"),
+            false => (HttpStatus::ServiceUnavailable, "fail"),
+        };
+        let body = Value::object()
+            .with("status", text.into())
+            .with("checks", checks);
+        (status, body)
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health::new()
+    }
+}
+
+/** A check which passes if a directory exists and its entries can be listed. */
+pub fn dir_readable(path: &str) -> impl Fn() -> std::result::Result<(), String> {
+    let path = path.to_string();
+    move || match fs::read_dir(&path) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("{}: {}", path, err)),
+    }
+}
+
+/** A check which passes if a file can be opened for appending, creating it if needed. */
+pub fn file_writable(path: &str) -> impl Fn() -> std::result::Result<(), String> {
+    let path = path.to_string();
+    move || match OpenOptions::new().append(true).create(true).open(&path) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("{}: {}", path, err)),
+    }
+}
+
+fn parse_path(path: &str) -> Result<String> {
+    match path.starts_with('/') {
+        true => Ok(path.to_string()),
+        false => Err(ServerError::error(&format!(
+            "health: path must start with /: {}",
+            path
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(
+        checks: &[(&str, std::result::Result<(), &str>)],
+    ) -> Vec<(String, std::result::Result<(), String>)> {
+        checks
+            .iter()
+            .map(|(name, result)| (name.to_string(), result.map_err(str::to_string)))
+            .collect()
+    }
+
+    #[test]
+    fn passes_when_every_check_passes() {
+        let (status, body) = Health::report(&results(&[("public_dir", Ok(()))]));
+        assert_eq!(status.code(), 200);
+        assert_eq!(
+            body.to_string(),
+            r#"{"status":"pass","checks":{"public_dir":{"status":"pass"}}}"#
+        );
+
+        let (status, body) = Health::report(&[]);
+        assert_eq!(status.code(), 200);
+        assert_eq!(body.to_string(), r#"{"status":"pass","checks":{}}"#);
+    }
+
+    #[test]
+    fn fails_when_any_check_fails() {
+        let (status, body) = Health::report(&results(&[
+            ("public_dir", Ok(())),
+            ("shutdown", Err("shutting down")),
+        ]));
+        assert_eq!(status.code(), 503);
+        assert_eq!(
+            body.to_string(),
+            r#"{"status":"fail","checks":{"public_dir":{"status":"pass"},"shutdown":{"status":"fail","error":"shutting down"}}}"#
+        );
+    }
+
+    #[test]
+    fn runs_checks_in_order() {
+        let health = Health::new()
+            .check("first", || Ok(()))
+            .check("second", || Err("down".to_string()));
+        assert_eq!(
+            health.run_checks(),
+            results(&[("first", Ok(())), ("second", Err("down"))])
+        );
+    }
+
+    #[test]
+    fn checks_directories_and_files() {
+        let directory = std::env::temp_dir().join(format!("health_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let directory_path = directory.to_str().unwrap();
+        assert!(dir_readable(directory_path)().is_ok());
+        assert!(file_writable(&format!("{}/events.csv", directory_path))().is_ok());
+
+        let missing = format!("{}/missing", directory_path);
+        assert!(dir_readable(&missing)().unwrap_err().starts_with(&missing));
+        assert!(file_writable(&format!("{}/events.csv", missing))().is_err());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn reads_paths_and_delay_from_config() {
+        let config = ConfigFile::parse(
+            "[health]\nlive_path = /live\nready_path = /ready\nshutdown_delay = 5\n",
+        );
+        let health = Health::from_config(&config).unwrap();
+        assert_eq!(health.live_path(), "/live");
+        assert_eq!(health.ready_path(), "/ready");
+        assert_eq!(health.shutdown_delay(), Some(Duration::from_secs(5)));
+
+        let defaults = Health::from_config(&ConfigFile::parse("")).unwrap();
+        assert_eq!(defaults.live_path(), "/healthz");
+        assert_eq!(defaults.ready_path(), "/readyz");
+        assert_eq!(defaults.shutdown_delay(), None);
+
+        let invalid = ConfigFile::parse("[health]\nready_path = readyz\n");
+        assert!(Health::from_config(&invalid).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::core::json::JsonError;
+use crate::core::url::ParamsError;
+use std::fmt;
+
+use super::{HttpResponse, HttpStatus, MultipartError};
+
+/**
+    Why a request body could not be read, which can be sent straight back to the client.
+
+    match sr.json() {
+        Ok(value) => sr.send(HttpResponse::json(&value)),
+        Err(err) => sr.send(err.to_response()),
+    }
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum BodyError {
+    /** The body is larger than the limit in bytes, which is a `413`. */
+    TooLarge(usize),
+    /** The body was sent with another `Content-Type` than this one, which is a `415`. */
+    ContentType(&'static str),
+    /** The body is not valid JSON, which is a `400`. */
+    Json(JsonError),
+    /** The body is not a valid url-encoded form, which is a `400`. */
+    Form(ParamsError),
+    /** The body was sent without a `Content-Length`, which is a `411`. */
+    LengthRequired,
+    /** The multipart body is malformed or over a limit, see `MultipartError::status`. */
+    Multipart(MultipartError),
+}
+
+impl BodyError {
+    pub fn status(&self) -> HttpStatus {
+        match self {
+            BodyError::TooLarge(_) => HttpStatus::Status(413, "Payload Too Large".to_string()),
+            BodyError::ContentType(_) => {
+                HttpStatus::Status(415, "Unsupported Media Type".to_string())
+            }
+            BodyError::Json(_) | BodyError::Form(_) => HttpStatus::BadRequest,
+            BodyError::LengthRequired => HttpStatus::Status(411, "Length Required".to_string()),
+            BodyError::Multipart(err) => err.status(),
+        }
+    }
+
+    /** A JSON error response such as `{"error":"invalid json: ... at line 3 column 7"}`. */
+    pub fn to_response(&self) -> HttpResponse {
+        HttpResponse::json_error(self.status(), &self.to_string())
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "body is larger than {} bytes", limit),
+            BodyError::ContentType(expected) => write!(f, "expected {}", expected),
+            BodyError::Json(err) => write!(f, "invalid json: {}", err),
+            BodyError::Form(err) => write!(f, "invalid form: {}", err),
+            BodyError::LengthRequired => write!(f, "a Content-Length is required"),
+            BodyError::Multipart(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::json;
+
+    #[test]
+    fn maps_errors_to_status_codes() {
+        let json_error = json::parse("{").unwrap_err();
+        assert_eq!(BodyError::TooLarge(10).status().code(), 413);
+        assert_eq!(
+            BodyError::ContentType("application/json").status().code(),
+            415
+        );
+        assert_eq!(BodyError::Json(json_error).status().code(), 400);
+        assert_eq!(BodyError::LengthRequired.status().code(), 411);
+    }
+
+    #[test]
+    fn responds_with_a_json_error() {
+        let err = BodyError::Json(json::parse("{\"a\" 1}").unwrap_err());
+        let response = err.to_response();
+        assert_eq!(response.status.code(), 400);
+        assert_eq!(
+            response.headers.get("Content-Type").unwrap(),
+            "application/json"
+        );
+        assert_eq!(
+            response.text(),
+            r#"{"error":"invalid json: expected ':' at line 1 column 6"}"#
+        );
+    }
+
+    #[test]
+    fn reports_invalid_forms() {
+        let err = BodyError::Form(ParamsError { pair: 2 });
+        assert_eq!(err.status().code(), 400);
+        assert_eq!(
+            err.to_string(),
+            "invalid form: malformed percent escape or invalid utf-8 in pair 2"
+        );
+        assert_eq!(
+            BodyError::ContentType("application/x-www-form-urlencoded").to_string(),
+            "expected application/x-www-form-urlencoded"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use crate::core::error::ServerError;
+use crate::core::ConfigFile;
+use std::collections::HashMap;
+use std::io::Result;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/**
+    Limits on the number of open connections, globally and for each client IP. Event
+    streams are long lived so they are limited separately from the connections which are
+    still being handled, and do not use up a connection once they are added to
+    `HttpConnections`.
+
+    let limits = ConnectionLimits::new()
+        .max_connections(256)
+        .max_per_ip(16)
+        .max_streams(1024)
+        .max_streams_per_ip(4);
+
+    server.connection_limits(limits);
+
+    Connections over the limit are shed with a `503` and a `Retry-After` header straight
+    away, rather than waiting in the listen queue until the server catches up. The per-IP
+    connection limit uses the peer address, since the request has not been read yet, so
+    behind a reverse proxy every client shares the proxy's limit. Event streams are
+    counted by the client IP, after `TrustedProxies` has run.
+*/
+pub struct ConnectionLimits {
+    max_connections: usize,
+    max_per_ip: usize,
+    max_streams: usize,
+    max_streams_per_ip: usize,
+    retry_after: Duration,
+    open: Mutex<OpenConnections>,
+    shed: AtomicU64,
+}
+
+#[derive(Default)]
+struct OpenConnections {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/**
+    A connection counted against the limits, which is released when dropped.
+*/
+pub struct ConnectionSlot<'a> {
+    limits: &'a ConnectionLimits,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionSlot<'_> {
+    fn drop(&mut self) {
+        let mut open = self.limits.open.lock().unwrap();
+        open.total = open.total.saturating_sub(1);
+        if let Some(count) = open.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+impl ConnectionLimits {
+    /** Allow 256 open connections, 32 from each IP, and any number of event streams. */
+    pub fn new() -> Self {
+        ConnectionLimits {
+            max_connections: 256,
+            max_per_ip: 32,
+            max_streams: usize::MAX,
+            max_streams_per_ip: usize::MAX,
+            retry_after: Duration::from_secs(1),
+            open: Mutex::new(OpenConnections::default()),
+            shed: AtomicU64::new(0),
+        }
+    }
+
+    /**
+        Create from the `[connections]` section of a config file, limits which are not set
+        keep their defaults.
+
+        [connections]
+        max = 256
+        max_per_ip = 16
+        max_streams = 1024
+        max_streams_per_ip = 4
+        retry_after = 1
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let mut limits = ConnectionLimits::new();
+        let settings: [(&str, &mut usize); 4] = [
+            ("connections.max", &mut limits.max_connections),
+            ("connections.max_per_ip", &mut limits.max_per_ip),
+            ("connections.max_streams", &mut limits.max_streams),
+            (
+                "connections.max_streams_per_ip",
+                &mut limits.max_streams_per_ip,
+            ),
+        ];
+        for (key, limit) in settings {
+            if let Some(value) = config.get(key) {
+                *limit = match value.parse::<usize>() {
+                    Ok(value) if value > 0 => value,
+                    _ => return Err(invalid(key, value)),
+                };
+            }
+        }
+        if let Some(value) = config.get("connections.retry_after") {
+            let seconds = value
+                .parse::<u64>()
+                .map_err(|_| invalid("connections.retry_after", value))?;
+            limits.retry_after = Duration::from_secs(seconds);
+        }
+        Ok(limits)
+    }
+
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max.max(1);
+        self
+    }
+
+    pub fn max_per_ip(mut self, max: usize) -> Self {
+        self.max_per_ip = max.max(1);
+        self
+    }
+
+    pub fn max_streams(mut self, max: usize) -> Self {
+        self.max_streams = max.max(1);
+        self
+    }
+
+    pub fn max_streams_per_ip(mut self, max: usize) -> Self {
+        self.max_streams_per_ip = max.max(1);
+        self
+    }
+
+    /** How long clients are told to wait before retrying a shed connection. */
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs()
+    }
+
+    /**
+        Count a new connection from an IP, returns None if either the global or the
+        per-IP limit has been reached.
+    */
+    pub fn acquire(&self, ip: IpAddr) -> Option<ConnectionSlot<'_>> {
+        let mut open = self.open.lock().unwrap();
+        let from_ip = open.per_ip.get(&ip).copied().unwrap_or(0);
+        if open.total >= self.max_connections || from_ip >= self.max_per_ip {
+            return None;
+        }
+        open.total += 1;
+        open.per_ip.insert(ip, from_ip + 1);
+        Some(ConnectionSlot { limits: self, ip })
+    }
+
+    /**
+        Check if another event stream can be opened, given the number of open streams in
+        total and from the client's IP.
+    */
+    pub fn allows_stream(&self, total: usize, from_ip: usize) -> bool {
+        total < self.max_streams && from_ip < self.max_streams_per_ip
+    }
+
+    /** The number of connections which are currently being handled. */
+    pub fn open_connections(&self) -> usize {
+        self.open.lock().unwrap().total
+    }
+
+    /** Record a request which was turned away because of a limit. */
+    pub fn record_shed(&self) -> u64 {
+        self.shed.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /** The number of requests which have been shed since the server started. */
+    pub fn shed_count(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits::new()
+    }
+}
+
+fn invalid(key: &str, value: &str) -> std::io::Error {
+    ServerError::error(&format!("{}: invalid value {:?}", key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn slots_are_released_when_dropped() {
+        let limits = ConnectionLimits::new().max_connections(2);
+        let first = limits.acquire(ip("192.0.2.1")).unwrap();
+        let second = limits.acquire(ip("192.0.2.2")).unwrap();
+        assert_eq!(limits.open_connections(), 2);
+        assert!(limits.acquire(ip("192.0.2.3")).is_none());
+
+        drop(first);
+        assert_eq!(limits.open_connections(), 1);
+        let third = limits.acquire(ip("192.0.2.3"));
+        assert!(third.is_some());
+        drop((second, third));
+        assert_eq!(limits.open_connections(), 0);
+        assert!(limits.open.lock().unwrap().per_ip.is_empty());
+    }
+
+    #[test]
+    fn limits_connections_per_ip() {
+        let limits = ConnectionLimits::new().max_per_ip(2);
+        let _first = limits.acquire(ip("192.0.2.1")).unwrap();
+        let second = limits.acquire(ip("192.0.2.1")).unwrap();
+        assert!(limits.acquire(ip("192.0.2.1")).is_none());
+        assert!(limits.acquire(ip("2001:db8::1")).is_some());
+
+        drop(second);
+        assert!(limits.acquire(ip("192.0.2.1")).is_some());
+    }
+
+    #[test]
+    fn limits_streams_in_total_and_per_ip() {
+        let limits = ConnectionLimits::new()
+            .max_streams(10)
+            .max_streams_per_ip(2);
+        assert!(limits.allows_stream(0, 0));
+        assert!(limits.allows_stream(9, 1));
+        assert!(!limits.allows_stream(9, 2));
+        assert!(!limits.allows_stream(10, 0));
+        assert!(ConnectionLimits::new().allows_stream(100_000, 100_000));
+    }
+
+    #[test]
+    fn limits_are_at_least_one() {
+        let limits = ConnectionLimits::new().max_connections(0);
+        assert!(limits.acquire(ip("192.0.2.1")).is_some());
+    }
+
+    #[test]
+    fn counts_shed_requests() {
+        let limits = ConnectionLimits::new();
+        assert_eq!(limits.shed_count(), 0);
+        assert_eq!(limits.record_shed(), 1);
+        assert_eq!(limits.record_shed(), 2);
+        assert_eq!(limits.shed_count(), 2);
+    }
+
+    #[test]
+    fn never_admits_more_than_the_limit_concurrently() {
+        let limits = Arc::new(ConnectionLimits::new().max_connections(8));
+        let handles = (0..32)
+            .map(|n| {
+                let limits = Arc::clone(&limits);
+                thread::spawn(move || {
+                    let address = IpAddr::from([192, 0, 2, n as u8]);
+                    for _ in 0..100 {
+                        if let Some(_slot) = limits.acquire(address) {
+                            assert!(limits.open_connections() <= 8);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(limits.open_connections(), 0);
+    }
+
+    #[test]
+    fn reads_limits_from_config() {
+        let config = ConfigFile::parse(
+            "[connections]\nmax = 4\nmax_per_ip = 1\nmax_streams_per_ip = 3\nretry_after = 5\n",
+        );
+        let limits = ConnectionLimits::from_config(&config).unwrap();
+        assert_eq!(limits.max_connections, 4);
+        assert_eq!(limits.max_per_ip, 1);
+        assert_eq!(limits.max_streams, usize::MAX);
+        assert_eq!(ConnectionLimits::new().max_per_ip, 32);
+        assert_eq!(limits.max_streams_per_ip, 3);
+        assert_eq!(limits.retry_after_secs(), 5);
+
+        for invalid in [
+            "max = 0",
+            "max_per_ip = -1",
+            "max_streams = lots",
+            "retry_after = 1s",
+        ] {
+            let config = ConfigFile::parse(&format!("[connections]\n{}\n", invalid));
+            assert!(
+                ConnectionLimits::from_config(&config).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
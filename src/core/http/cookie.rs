@@ -0,0 +1,289 @@
+use crate::core::util::format_http_date;
+use std::fmt;
+use std::time::SystemTime;
+
+/**
+    The `SameSite` attribute controls whether a cookie is sent with cross-site requests.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/**
+    A cookie which was either parsed from the `Cookie` request header or will be sent to the
+    client in a `Set-Cookie` response header.
+
+    let cookie = Cookie::new("theme", "dark")
+        .path("/")
+        .max_age(3600)
+        .http_only(true)
+        .same_site(SameSite::Lax);
+
+    response.set_cookie(cookie)?;
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub expires: Option<SystemTime>,
+    pub max_age: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            expires: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /**
+        Create a cookie which tells the client to delete the cookie with the given name,
+        note the path and domain must match the original cookie.
+    */
+    pub fn removal(name: &str) -> Self {
+        Cookie::new(name, "")
+            .max_age(0)
+            .expires(SystemTime::UNIX_EPOCH)
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /** Number of seconds until the cookie expires, zero or less expires it immediately. */
+    pub fn max_age(mut self, seconds: i64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /**
+        Check the name is a valid token and the value only contains cookie-octets as
+        defined in RFC 6265 section 4.1.1, and that the path and domain can not end the
+        attribute or the header early.
+    */
+    pub fn is_valid(&self) -> bool {
+        is_token(&self.name)
+            && self.value.bytes().all(is_cookie_octet)
+            && [&self.path, &self.domain]
+                .into_iter()
+                .flatten()
+                .all(|value| is_attribute_value(value))
+    }
+
+    /**
+        Parse the value of a `Cookie` request header into a list of cookies in the order
+        they were sent, invalid pairs are skipped. Double quoted values are unquoted.
+
+        Cookie: SID=31d4d96e407aad42; lang="en-US"
+    */
+    pub fn parse_header(header: &str) -> Vec<Cookie> {
+        header
+            .split(';')
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let name = name.trim();
+                let value = value.trim();
+                let value = match value.len() >= 2 && value.starts_with('"') && value.ends_with('"')
+                {
+                    true => &value[1..value.len() - 1],
+                    false => value,
+                };
+                if !is_token(name) || !value.bytes().all(is_cookie_octet) {
+                    return None;
+                }
+                Some(Cookie::new(name, value))
+            })
+            .collect()
+    }
+}
+
+/**
+    Serialize the cookie as the value of a `Set-Cookie` header, cookies with `SameSite=None`
+    are always marked `Secure` since browsers will reject them otherwise.
+*/
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.max(0))?;
+        }
+        if self.secure || self.same_site == Some(SameSite::None) {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = &self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/**
+    A token is any visible ASCII character except separators (RFC 7230 section 3.2.6).
+*/
+fn is_token(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&byte))
+}
+
+/**
+    An attribute value may be any character except controls and `;` (RFC 6265 section
+    4.1.1), which would split the header or add attributes of its own.
+*/
+fn is_attribute_value(value: &str) -> bool {
+    !value.chars().any(|c| c.is_control() || c == ';')
+}
+
+/**
+    cookie-octet = %x21 / %x23-2B / %x2D-3A / %x3C-5B / %x5D-7E
+*/
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::http::HttpResponse;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_the_cookie_header_in_order() {
+        let cookies = Cookie::parse_header("SID=31d4d96e407aad42; lang=\"en-US\"; bad name=x; a=");
+        let pairs = cookies
+            .iter()
+            .map(|cookie| (cookie.name.as_str(), cookie.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            vec![("SID", "31d4d96e407aad42"), ("lang", "en-US"), ("a", "")]
+        );
+    }
+
+    #[test]
+    fn skips_values_with_invalid_octets() {
+        assert!(Cookie::parse_header("a=b c; d=e\\f; g=h,i").is_empty());
+    }
+
+    #[test]
+    fn serializes_attributes() {
+        let cookie = Cookie::new("theme", "dark")
+            .path("/")
+            .domain("example.com")
+            .expires(SystemTime::UNIX_EPOCH + Duration::from_secs(784111777))
+            .max_age(3600)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        assert_eq!(
+            cookie.to_string(),
+            "theme=dark; Path=/; Domain=example.com; Expires=Sun, 06 Nov 1994 08:49:37 GMT; \
+             Max-Age=3600; HttpOnly; SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn same_site_none_is_always_secure() {
+        let cookie = Cookie::new("id", "1").same_site(SameSite::None);
+        assert_eq!(cookie.to_string(), "id=1; Secure; SameSite=None");
+    }
+
+    #[test]
+    fn removal_expires_the_cookie() {
+        assert_eq!(
+            Cookie::removal("id").to_string(),
+            "id=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0"
+        );
+    }
+
+    #[test]
+    fn checks_names_and_values() {
+        assert!(Cookie::new("id", "abc123").is_valid());
+        assert!(!Cookie::new("", "x").is_valid());
+        assert!(!Cookie::new("a=b", "x").is_valid());
+        assert!(!Cookie::new("id", "a;b").is_valid());
+        assert!(!Cookie::new("id", "a b").is_valid());
+    }
+
+    #[test]
+    fn refuses_paths_and_domains_which_inject_attributes() {
+        assert!(Cookie::new("id", "1").path("/a b").is_valid());
+        assert!(!Cookie::new("id", "1").path("/; HttpOnly").is_valid());
+        assert!(!Cookie::new("id", "1").path("/\r\nX-Injected: 1").is_valid());
+        assert!(!Cookie::new("id", "1")
+            .domain("example.com; Secure")
+            .is_valid());
+        assert!(!Cookie::new("id", "1").domain("example.com\n").is_valid());
+
+        let mut response = HttpResponse::new();
+        let cookie = Cookie::new("id", "1").path("/\r\nSet-Cookie: admin=1");
+        assert!(response.set_cookie(cookie).is_err());
+        assert!(response
+            .set_cookie(Cookie::new("id", "1").path("/"))
+            .is_ok());
+    }
+}
@@ -1,13 +1,17 @@
 use std::borrow::{Borrow, BorrowMut};
+use std::net::IpAddr;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 use std::{collections::HashMap, net::TcpStream};
 
+use crate::core::log::{Record, Sink};
 use crate::core::ServerEvent;
+use crate::debug;
 
 use super::HttpRequest;
 
+#[derive(Clone)]
 pub struct HttpConnections {
     connections: Arc<Mutex<Vec<HttpRequest>>>,
     is_active: Arc<Mutex<bool>>,
@@ -22,14 +26,10 @@ impl HttpConnections {
     }
 
     pub fn send_event(&self, event: ServerEvent) {
-        let mut connections = self.connections.lock().unwrap();
-        connections.retain_mut(|stream| match stream.server_side_event(event.clone()) {
-            Ok(_) => true,
-            Err(_) => {
-                println!("[http_connections] dropping connection...");
-                false
-            }
-        });
+        let (_, dropped) = broadcast(&self.connections, &event);
+        if dropped > 0 {
+            debug!("dropped {} connections", dropped);
+        }
     }
 
     /**
@@ -45,8 +45,32 @@ impl HttpConnections {
         }
     }
 
+    /** The number of open event streams. */
+    pub fn len(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /** Check if the thread which sends keep alive events to the streams is running. */
+    pub fn is_keep_alive_active(&self) -> bool {
+        *self.is_active.lock().unwrap()
+    }
+
+    /** The number of open event streams from a client IP. */
+    pub fn count_from(&self, ip: &IpAddr) -> usize {
+        self.connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|stream| stream.client_ip.as_ref() == Some(ip))
+            .count()
+    }
+
     pub fn start_keep_alive_thread(&self) -> mpsc::Sender<()> {
-        println!("[http_connections] starting keep alive thread...");
+        debug!("starting keep alive thread...");
         let connections = Arc::clone(&self.connections);
         let is_active = Arc::clone(&self.is_active);
         let (tx, rx) = mpsc::channel::<()>();
@@ -55,33 +79,24 @@ impl HttpConnections {
             {
                 let mut is_active = is_active.lock().unwrap();
                 *is_active = true;
-                println!(
-                    "[http_connections] total connections: {}",
-                    connections.lock().unwrap().len()
-                );
             }
+            let total_connections = connections.lock().unwrap().len();
+            debug!("total connections: {}", total_connections);
             loop {
                 if rx.try_recv().is_ok() {
-                    println!("[http_connections] stopping keep alive thread");
+                    debug!("stopping keep alive thread");
                     break;
                 }
 
                 if last_keep_alive.elapsed().as_millis() > 300 {
-                    let event = ServerEvent::keep_alive();
-                    let mut connections_unlocked = connections.lock().unwrap();
-                    let total_connections = connections_unlocked.len();
-                    connections_unlocked.retain_mut(|stream| {
-                        match stream.server_side_event(event.clone()) {
-                            Ok(_) => true,
-                            Err(_) => {
-                                println!("[http_connections] dropping connection...");
-                                false
-                            }
-                        }
-                    });
+                    let (total_connections, dropped) =
+                        broadcast(&connections, &ServerEvent::keep_alive());
+                    if dropped > 0 {
+                        debug!("dropped {} connections", dropped);
+                    }
 
                     if total_connections == 0 {
-                        println!("[http_connections] no more connections, stopping thread.");
+                        debug!("no more connections, stopping thread.");
                         let mut is_active = is_active.lock().unwrap();
                         *is_active = false;
                         break;
@@ -98,3 +113,22 @@ impl HttpConnections {
         tx
     }
 }
+
+/** Send each log record to the open event streams as a line of text. */
+impl Sink for HttpConnections {
+    fn write(&self, record: &Record) {
+        self.send_event(ServerEvent::data(&record.to_text()));
+    }
+}
+
+/**
+    Send an event to every stream, dropping the streams which fail. Returns the number of
+    streams before sending and the number dropped, so they can be logged once the lock is
+    released since logging may send to these streams as well.
+*/
+fn broadcast(connections: &Mutex<Vec<HttpRequest>>, event: &ServerEvent) -> (usize, usize) {
+    let mut connections = connections.lock().unwrap();
+    let total = connections.len();
+    connections.retain_mut(|stream| stream.server_side_event(event.clone()).is_ok());
+    (total, total - connections.len())
+}
@@ -1,4 +1,4 @@
-use crate::core::file::URI;
+use crate::debug;
 use std::collections::HashMap;
 
 #[derive(Clone, Debug)]
@@ -23,12 +23,31 @@ pub enum HttpMethod {
     Name(String),
 }
 
+impl HttpMethod {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::TRACE => "TRACE",
+            HttpMethod::CONNECT => "CONNECT",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::Name(name) => name.as_str(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct HttpHeaders {
     pub method: HttpMethod,
     pub version: HttpVersion,
-    pub uri: URI,
+    /** The request target as it was sent, see `HttpRequest::path` for the decoded path. */
+    pub uri: String,
     pub raw: HashMap<String, String>,
+    pub repeated: Vec<(String, String)>,
 }
 
 impl HttpHeaders {
@@ -38,6 +57,7 @@ impl HttpHeaders {
             version: self.version.clone(),
             uri: self.uri.clone(),
             raw: self.raw.clone(),
+            repeated: self.repeated.clone(),
         }
     }
 
@@ -87,47 +107,36 @@ impl HttpHeaders {
         Some((name, value))
     }
 
-    pub fn parse_http(line: String) -> Result<(HttpMethod, HttpVersion, URI), ()> {
+    pub fn parse_http(line: String) -> Option<(HttpMethod, HttpVersion, String)> {
         let parts = line.split(" ").collect::<Vec<&str>>();
         if parts.len() < 3 {
-            println!("[http_headers] failed to parse http headers: {:?}", line);
-            return Err(());
-        } else {
-            let (raw_method, raw_uri, raw_version) = (parts[0], parts[1], parts[2]);
-            return Ok((
-                HttpHeaders::method_from_string(raw_method),
-                HttpHeaders::version_from_string(raw_version),
-                URI::new(raw_uri),
-            ));
+            debug!("failed to parse http headers: {:?}", line);
+            return None;
         }
+        let (raw_method, raw_uri, raw_version) = (parts[0], parts[1], parts[2]);
+        Some((
+            HttpHeaders::method_from_string(raw_method),
+            HttpHeaders::version_from_string(raw_version),
+            raw_uri.to_string(),
+        ))
     }
 
     pub fn new() -> Self {
         HttpHeaders {
             method: HttpMethod::GET,
             version: HttpVersion::HTTP2_0,
-            uri: URI::new("/"),
+            uri: "/".to_string(),
             raw: HashMap::new(),
+            repeated: Vec::new(),
         }
     }
 
     pub fn uri_string(&self) -> String {
-        self.uri.to_string()
+        self.uri.clone()
     }
 
     pub fn method_string(&self) -> String {
-        match self.method {
-            HttpMethod::GET => "GET".to_string(),
-            HttpMethod::POST => "POST".to_string(),
-            HttpMethod::PUT => "PUT".to_string(),
-            HttpMethod::DELETE => "DELETE".to_string(),
-            HttpMethod::HEAD => "HEAD".to_string(),
-            HttpMethod::OPTIONS => "OPTIONS".to_string(),
-            HttpMethod::TRACE => "TRACE".to_string(),
-            HttpMethod::CONNECT => "CONNECT".to_string(),
-            HttpMethod::PATCH => "PATCH".to_string(),
-            HttpMethod::Name(ref name) => name.to_string(),
-        }
+        self.method.as_str().to_string()
     }
 
     pub fn version_string(&self) -> String {
@@ -146,7 +155,7 @@ impl HttpHeaders {
     pub fn from(data: &Vec<String>) -> Option<Self> {
         // check if data is emtpy
         if data.is_empty() {
-            println!("[http_headers] failed to parse http headers: {:?}", data);
+            debug!("failed to parse http headers: {:?}", data);
             return None;
         }
 
@@ -157,14 +166,14 @@ impl HttpHeaders {
         // if these are not present then return None as this is an invalid request.
         let http_info = match http_request_info.first() {
             Some(first_line) => match HttpHeaders::parse_http(first_line.to_string()) {
-                Ok(info) => info,
-                Err(_) => {
-                    println!("[http_headers] failed to parse http headers: {:?}", data);
+                Some(info) => info,
+                None => {
+                    debug!("failed to parse http headers: {:?}", data);
                     return None;
                 }
             },
             None => {
-                println!("[http_headers] failed to parse http headers: {:?}", data);
+                debug!("failed to parse http headers: {:?}", data);
                 return None;
             }
         };
@@ -180,7 +189,7 @@ impl HttpHeaders {
                     raw.insert(name.to_string(), value.to_string());
                 }
                 None => {
-                    println!("[http_headers] invalid header {:?}", line);
+                    debug!("invalid header {:?}", line);
                 }
             }
         }
@@ -190,17 +199,41 @@ impl HttpHeaders {
             version: http_info.1,
             uri: http_info.2,
             raw,
+            repeated: Vec::new(),
         })
     }
 
+    /**
+        Get a header value by name, header names are case-insensitive so this will fall back
+        to a case-insensitive search if there is no exact match.
+    */
     pub fn get(&self, key: &str) -> Option<&String> {
-        self.raw.get(key)
+        match self.raw.get(key) {
+            Some(value) => Some(value),
+            None => self
+                .raw
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
     }
 
     pub fn set(&mut self, key: &str, value: &str) {
         self.raw.insert(key.to_string(), value.to_string());
     }
 
+    /**
+        Append a header which may appear more than once such as `Set-Cookie`, unlike `set`
+        this will not replace any previous value for the same name.
+    */
+    pub fn append(&mut self, key: &str, value: &str) {
+        self.repeated.push((key.to_string(), value.to_string()));
+    }
+
     pub fn info(&self) -> String {
         let mut info_str = format!("{:?} {:?} {:?}\n", self.method, self.version, self.uri);
         for (key, value) in self.raw.iter() {
@@ -1,19 +1,26 @@
 use super::http_headers::{HttpHeaders, HttpMethod};
 use super::http_response::HttpResponse;
+use crate::core::auth::Identity;
 use crate::core::error::ServerError;
-use crate::core::http::HttpStatus;
+use crate::core::file::uri::URI;
+use crate::core::http::multipart::{self, FormData, Multipart, MultipartError};
+use crate::core::http::{generate_request_id, is_valid_request_id, BodyError, Cookie, HttpStatus};
+use crate::core::json::{self, Limits, Value};
 use crate::core::server::Flag;
-use crate::core::util::get_mime_type;
-use crate::core::Path;
+use crate::core::session::Session;
+use crate::core::url::{Normalize, Params, ParamsError, PathError, Url};
+use crate::core::util::{get_mime_type, parse_http_date};
 use crate::core::ServerEvent;
+use crate::{debug, error, trace, warn};
 use std::borrow::BorrowMut;
 use std::collections::HashMap;
 use std::fs;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result};
 use std::io::{BufWriter, Write};
-use std::net::{Shutdown, TcpListener, TcpStream};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::ops::Deref;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /**
 
@@ -23,13 +30,51 @@ use std::sync::Arc;
 */
 const CRLF: &str = "\r\n";
 
+/** Largest request body which will be read into memory. */
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+const FORM_URLENCODED: &str = "application/x-www-form-urlencoded";
+
+const MULTIPART_FORM_DATA: &str = "multipart/form-data";
+
+/** Bodies of these types are left on the stream, to be read as they arrive. */
+const STREAMED_TYPES: [&str; 2] = [MULTIPART_FORM_DATA, "application/offset+octet-stream"];
+
+/**
+    A body which is left on the stream to be read as it arrives, with the start of it which
+    was already buffered while reading the headers.
+*/
+#[derive(Clone)]
+struct UnreadBody {
+    buffered: Vec<u8>,
+    length: usize,
+}
+
 #[derive(Clone)]
 pub struct HttpRequest {
     pub uri: String,
     pub headers: HttpHeaders,
     pub response: HttpResponse,
     pub connection: Option<Arc<TcpStream>>,
+    pub session: Option<Session>,
+    pub identity: Option<Identity>,
+    pub peer_addr: Option<SocketAddr>,
+    pub client_ip: Option<IpAddr>,
+    /**
+        Identifies the request in log records and the `X-Request-Id` response header, taken
+        from the incoming `X-Request-Id` when it is well formed.
+    */
+    pub request_id: String,
+    /** The status code and body size of the response once it has been written. */
+    pub response_status: Option<u16>,
+    pub response_bytes: usize,
+    /** The size of the request and of the responses written to it, including headers. */
+    pub bytes_read: usize,
+    pub bytes_written: usize,
+    target: Url,
     data: Vec<String>,
+    body: Vec<u8>,
+    unread_body: Option<UnreadBody>,
 }
 
 impl HttpRequest {
@@ -38,11 +83,11 @@ impl HttpRequest {
         and read the incoming data from the stream.
     */
     pub fn new(stream: Arc<TcpStream>) -> Self {
-        let data = match HttpRequest::read_stream_data(&stream) {
+        let (data, body, unread_body) = match HttpRequest::read_stream_data(&stream) {
             Ok(data) => data,
             Err(error) => {
-                println!("[http_request] could not read stream: {:?}", error);
-                Vec::new()
+                debug!("could not read stream: {:?}", error);
+                (Vec::new(), Vec::new(), None)
             }
         };
         let headers = match HttpHeaders::from(&data) {
@@ -50,15 +95,41 @@ impl HttpRequest {
             Some(headers) => headers,
         };
 
-        let uri = headers.uri.to_string();
+        let uri = headers.uri.clone();
+        // an invalid path is refused later by `normalize_path`
+        let target = Url::parse(&uri).unwrap_or_default();
+        // the blank line which ends the headers is not kept
+        let bytes_read = match data.is_empty() {
+            true => 0,
+            false => data.iter().map(String::len).sum::<usize>() + CRLF.len() + body.len(),
+        };
 
-        println!("[http_request] new request: {:}", uri);
+        let request_id = match headers.get("X-Request-Id") {
+            Some(id) if is_valid_request_id(id) => id.to_string(),
+            _ => generate_request_id(),
+        };
+        let mut response = HttpResponse::new();
+        response.set_header("X-Request-Id", &request_id);
+
+        debug!("new request: {:} ({})", uri, request_id);
 
         HttpRequest {
-            response: HttpResponse::new(),
+            response,
             connection: Some(stream),
+            session: None,
+            identity: None,
+            peer_addr: None,
+            client_ip: None,
+            request_id,
+            response_status: None,
+            response_bytes: 0,
+            bytes_read,
+            bytes_written: 0,
             headers,
             data,
+            body,
+            unread_body,
+            target,
             uri,
         }
     }
@@ -72,7 +143,19 @@ impl HttpRequest {
             headers: HttpHeaders::new(),
             response: HttpResponse::new(),
             connection: None,
+            session: None,
+            identity: None,
+            peer_addr: None,
+            client_ip: None,
+            request_id: generate_request_id(),
+            response_status: None,
+            response_bytes: 0,
+            bytes_read: 0,
+            bytes_written: 0,
+            target: Url::parse(uri).unwrap_or_default(),
             data: Vec::new(),
+            body: Vec::new(),
+            unread_body: None,
         }
     }
 
@@ -88,24 +171,40 @@ impl HttpRequest {
         Clone the current HttpRequest instance and TcpStream.
     */
     pub fn clone(&self) -> Self {
-        println!("[http_request] cloning request: {:?}", self.data);
+        trace!("cloning request: {}", self.uri);
         HttpRequest {
             uri: self.uri.clone(),
+            target: self.target.clone(),
             data: self.data.clone(),
+            body: self.body.clone(),
+            unread_body: self.unread_body.clone(),
             headers: self.headers.clone(),
             response: self.response.clone(),
             connection: match &self.connection {
                 Some(conn) => Some(Arc::clone(conn)),
                 None => None,
             },
+            session: self.session.clone(),
+            identity: self.identity.clone(),
+            peer_addr: self.peer_addr,
+            client_ip: self.client_ip,
+            request_id: self.request_id.clone(),
+            response_status: self.response_status,
+            response_bytes: self.response_bytes,
+            bytes_read: self.bytes_read,
+            bytes_written: self.bytes_written,
         }
     }
 
     /**
         Converts a TcpStream into a byte vector, reads until a CRLF is found.
-        or times out after 5 seconds.
+        or times out after 5 seconds. The body is then read from the same reader, since
+        it may already have buffered part of it, up to the `Content-Length`. Multipart and
+        other upload bodies are left on the stream, see `stream_body`.
     */
-    fn read_stream_data(tcp_stream: &TcpStream) -> Result<Vec<String>> {
+    fn read_stream_data(
+        tcp_stream: &TcpStream,
+    ) -> Result<(Vec<String>, Vec<u8>, Option<UnreadBody>)> {
         let mut reader = BufReader::new(tcp_stream);
         let mut header = Vec::new();
         loop {
@@ -119,13 +218,196 @@ impl HttpRequest {
                     }
                 }
                 Err(error) => {
-                    eprintln!("[http_request] error: {:?}", error);
+                    error!("error: {:?}", error);
                     return Err(error);
                 }
             }
         }
 
-        Ok(header)
+        let mut body = Vec::new();
+        let is_streamed = HttpRequest::header_value(&header, "Content-Type")
+            .and_then(|content_type| content_type.split(';').next())
+            .is_some_and(|media_type| {
+                STREAMED_TYPES
+                    .iter()
+                    .any(|streamed| media_type.trim().eq_ignore_ascii_case(streamed))
+            });
+        match HttpRequest::content_length(&header) {
+            Some(length) if is_streamed => {
+                let buffered = reader.buffer().to_vec();
+                return Ok((header, body, Some(UnreadBody { buffered, length })));
+            }
+            Some(length) if length > MAX_BODY_SIZE => {
+                warn!("body too large ({} bytes)", length);
+            }
+            Some(length) => {
+                reader.take(length as u64).read_to_end(&mut body)?;
+            }
+            None => (),
+        }
+
+        Ok((header, body, None))
+    }
+
+    /** Find the `Content-Length` in the raw header lines. */
+    fn content_length(header: &[String]) -> Option<usize> {
+        HttpRequest::header_value(header, "Content-Length").and_then(|value| value.parse().ok())
+    }
+
+    /** Find a header in the raw header lines. */
+    fn header_value<'a>(header: &'a [String], key: &str) -> Option<&'a str> {
+        header
+            .iter()
+            .skip(1)
+            .filter_map(|line| HttpHeaders::parse_header(line))
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    /** Set the body of a request which will be sent with the `HttpClient`. */
+    pub fn set_body(&mut self, body: Vec<u8>, mime: &str) {
+        self.headers.set_content_length(body.len());
+        self.headers.set_content_type(mime);
+        self.body = body;
+    }
+
+    /**
+        Format the request to be sent to another server, the request line uses the uri
+        the request was created with.
+    */
+    pub fn prepare(&self) -> Vec<u8> {
+        let mut head = format!(
+            "{} {} {}{}",
+            self.headers.method_string(),
+            self.uri,
+            self.headers.version_string(),
+            CRLF
+        );
+        for (key, value) in self.headers.raw.iter() {
+            head.push_str(&format!("{}: {}{}", key, value, CRLF));
+        }
+        for (key, value) in self.headers.repeated.iter() {
+            head.push_str(&format!("{}: {}{}", key, value, CRLF));
+        }
+        head.push_str(CRLF);
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /**
+        The body sent with the request, which is empty if there was no `Content-Length`
+        or the body was larger than the maximum size.
+    */
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /**
+        Parse the body as JSON. The error says where malformed input went wrong, and can be
+        sent back as a `400`, or a `413` for a body over the size limit.
+
+        server.route("/api/echo", |sr| match sr.json() {
+            Ok(value) => sr.send(HttpResponse::json(&value)),
+            Err(err) => sr.send(err.to_response()),
+        });
+    */
+    pub fn json(&self) -> std::result::Result<Value, BodyError> {
+        if HttpRequest::content_length(&self.data).is_some_and(|length| length > MAX_BODY_SIZE) {
+            return Err(BodyError::TooLarge(MAX_BODY_SIZE));
+        }
+        let limits = Limits::new().max_size(MAX_BODY_SIZE);
+        json::from_reader(self.body.as_slice(), &limits).map_err(BodyError::Json)
+    }
+
+    /**
+        Parse an `application/x-www-form-urlencoded` body, as posted by an HTML form, into
+        its fields in order.
+
+        server.route("/signup", |sr| match sr.form() {
+            Ok(form) => sr.send(HttpResponse::redirect("/welcome")),
+            Err(err) => sr.send(err.to_response()),
+        });
+    */
+    pub fn form(&self) -> std::result::Result<Params, BodyError> {
+        if !self.has_content_type(FORM_URLENCODED) {
+            return Err(BodyError::ContentType(FORM_URLENCODED));
+        }
+        if HttpRequest::content_length(&self.data).is_some_and(|length| length > MAX_BODY_SIZE) {
+            return Err(BodyError::TooLarge(MAX_BODY_SIZE));
+        }
+        // bytes which are not UTF-8 must be percent-encoded in a form body, the pair is
+        // counted like `Params::parse` does, without the empty ones
+        let body = std::str::from_utf8(&self.body).map_err(|err| {
+            let before = self.body[..err.valid_up_to()]
+                .split(|byte| *byte == b'&')
+                .rev()
+                .skip(1)
+                .filter(|pair| !pair.is_empty())
+                .count();
+            BodyError::Form(ParamsError { pair: before + 1 })
+        })?;
+        Params::parse(body).map_err(BodyError::Form)
+    }
+
+    /**
+        Read a `multipart/form-data` body, as posted by an HTML form with file inputs. The
+        body is read from the connection as it arrives and files are streamed into the
+        upload directory, see `Multipart`. It can only be read once.
+
+        server.route("/upload", move |sr| match sr.multipart(&uploads) {
+            Ok(form) => sr.send(HttpResponse::json(&Value::from(form.files.len() as u64))),
+            Err(err) => sr.send(err.to_response()),
+        });
+    */
+    pub fn multipart(&mut self, multipart: &Multipart) -> std::result::Result<FormData, BodyError> {
+        if !self.has_content_type(MULTIPART_FORM_DATA) {
+            return Err(BodyError::ContentType(MULTIPART_FORM_DATA));
+        }
+        let boundary = self
+            .headers
+            .get("Content-Type")
+            .and_then(|content_type| multipart::boundary(content_type))
+            .ok_or(BodyError::Multipart(MultipartError::Malformed(
+                "missing boundary".to_string(),
+            )))?;
+        self.stream_body(|body| multipart.read(body, &boundary))?
+            .map_err(BodyError::Multipart)
+    }
+
+    /**
+        Read a body which was left on the stream as it arrives, which is done for
+        `multipart/form-data` and `application/offset+octet-stream` bodies with a
+        `Content-Length`. The reader ends at the end of the body, and the body can only be
+        read once.
+
+        let written = sr.stream_body(|body| std::io::copy(body, &mut file))?;
+    */
+    pub fn stream_body<T, F>(&mut self, read: F) -> std::result::Result<T, BodyError>
+    where
+        F: FnOnce(&mut dyn Read) -> T,
+    {
+        let unread = self.unread_body.take().ok_or(BodyError::LengthRequired)?;
+        let stream = match &self.connection {
+            Some(stream) => Arc::clone(stream),
+            None => return Err(BodyError::LengthRequired),
+        };
+        let mut reader = unread
+            .buffered
+            .as_slice()
+            .chain(stream.deref())
+            .take(unread.length as u64);
+        let result = read(&mut reader);
+        self.bytes_read += unread.length - reader.limit() as usize;
+        Ok(result)
+    }
+
+    /** Check the media type of the `Content-Type` header, ignoring any parameters. */
+    pub fn has_content_type(&self, mime: &str) -> bool {
+        self.headers
+            .get("Content-Type")
+            .and_then(|content_type| content_type.split(';').next())
+            .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(mime))
     }
 
     pub fn info(&self) -> String {
@@ -145,13 +427,7 @@ impl HttpRequest {
     }
 
     pub fn is_file_request(&self) -> bool {
-        if self.headers.method != HttpMethod::GET {
-            return false;
-        }
-        if self.headers.uri.is_file() == false {
-            return false;
-        }
-        return true;
+        self.headers.method == HttpMethod::GET && URI::public(self.path()).is_file()
     }
 
     pub fn send_404(&mut self) -> Result<()> {
@@ -159,6 +435,72 @@ impl HttpRequest {
         let (body, mime) = HttpResponse::get_file("404.html")?;
         response.set_status(HttpStatus::NotFound);
         response.set_body(body, &mime);
+        self.write_response(&mut response, true)
+    }
+
+    /**
+        Serve the static file for the request url. A `304 Not Modified` is sent instead when
+        the client's copy from `If-Modified-Since` is still current.
+    */
+    pub fn serve_static_file(&mut self) -> Result<Flag> {
+        let file_url = self.path().to_string();
+        let mut response = match self.is_not_modified(&file_url) {
+            true => HttpResponse::not_modified(&file_url)?,
+            false => HttpResponse::with_static_file(&file_url)?,
+        };
+        self.write_response(&mut response, true)?;
+        Ok(Flag::StaticFile)
+    }
+
+    /** Check if the client's cached copy of a static file is still current. */
+    fn is_not_modified(&self, url: &str) -> bool {
+        if !matches!(self.headers.method, HttpMethod::GET | HttpMethod::HEAD) {
+            return false;
+        }
+        // dates in headers are in whole seconds
+        let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+        let since = self
+            .headers
+            .get("If-Modified-Since")
+            .and_then(|value| parse_http_date(value))
+            .and_then(seconds);
+        match (since, HttpResponse::last_modified(url).and_then(seconds)) {
+            (Some(since), Some(modified)) => modified <= since,
+            _ => false,
+        }
+    }
+
+    /**
+        Send a response which was built by a route handler and close the connection, any
+        headers which were added to the pending response (e.g. by middleware) are included.
+    */
+    pub fn send(&mut self, mut response: HttpResponse) -> Result<Flag> {
+        self.write_response(&mut response, true)?;
+        Ok(Flag::DynamicRoute)
+    }
+
+    /**
+        Write a response to the tcp stream, merging in the headers from the pending response
+        which have not been set on the response itself. The connection is shutdown afterwards
+        if `shutdown` is true.
+    */
+    fn write_response(&mut self, response: &mut HttpResponse, shutdown: bool) -> Result<()> {
+        for (key, value) in self.response.headers.raw.iter() {
+            if !response.headers.raw.contains_key(key) {
+                response.headers.set(key, value);
+            }
+        }
+        for (key, value) in self.response.headers.repeated.iter() {
+            response.headers.append(key, value);
+        }
+        if let Some(cookie) = self
+            .session
+            .as_mut()
+            .and_then(|session| session.take_cookie())
+        {
+            response.set_cookie(cookie)?;
+        }
+        let bytes = response.prepare();
         let stream_ref = self
             .connection
             .as_ref()
@@ -166,47 +508,110 @@ impl HttpRequest {
         {
             // hnadle this in a block to drop the mutable borrow
             let mut stream = stream_ref.as_ref();
-            let bytes = response.prepare();
             stream.write_all(&bytes)?;
             stream.flush()?;
-            stream.shutdown(Shutdown::Both)?;
+            self.response_status = Some(response.status.code());
+            self.response_bytes = response.body.as_ref().map_or(0, |body| body.len());
+            self.bytes_written += bytes.len();
+            if shutdown {
+                stream.shutdown(Shutdown::Both)?;
+            }
         }
         Ok(())
     }
 
-    pub fn serve_static_file(&mut self) -> Result<Flag> {
-        let file_url = self.url();
-        let mut response = HttpResponse::with_static_file(&file_url)?;
-        let bytes = response.prepare();
-        let stream = self
-            .connection
+    /**
+        Parse the cookies sent by the client in the `Cookie` header, in the order they were
+        sent. Returns an empty list if there is no `Cookie` header.
+    */
+    pub fn cookies(&self) -> Vec<Cookie> {
+        match self.headers.get("Cookie") {
+            Some(header) => Cookie::parse_header(header),
+            None => Vec::new(),
+        }
+    }
+
+    /** Get the value of the first cookie sent with the given name. */
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies()
+            .into_iter()
+            .find(|cookie| cookie.name == name)
+            .map(|cookie| cookie.value)
+    }
+
+    /**
+        Get the session for this request, which is only available when the `Sessions`
+        middleware has been registered on the server.
+    */
+    pub fn session(&mut self) -> Option<&mut Session> {
+        self.session.as_mut()
+    }
+
+    /**
+        Get the verified claims for this request, which are set when the request was
+        authenticated with a bearer token.
+    */
+    pub fn claims(&self) -> Option<&Value> {
+        self.identity
             .as_ref()
-            .ok_or(Error::new(ErrorKind::NotFound, "failed to get tcp stream"))?;
-        {
-            let mut stream = stream.as_ref();
-            stream.write_all(&bytes)?;
-            stream.flush()?;
-            stream.shutdown(Shutdown::Both)?;
+            .and_then(|identity| identity.claims.as_ref())
+    }
+
+    /**
+        Normalize the request path, so middleware, routes and static files all see the
+        same decoded path, see `Normalize`. When the path is not canonical and redirects
+        are enabled, the canonical location is returned instead of changing the path.
+    */
+    pub fn normalize_path(
+        &mut self,
+        normalize: &Normalize,
+    ) -> std::result::Result<Option<String>, PathError> {
+        let target = Url::parse_with(&self.headers.uri, normalize)?;
+        if normalize.is_redirect() && !target.is_canonical() {
+            return Ok(Some(target.canonical()));
         }
-        Ok(Flag::StaticFile)
+        if target.path != self.target.path {
+            debug!("normalized path: {} -> {}", self.headers.uri, target.path);
+        }
+        self.target = target;
+        Ok(None)
     }
 
+    /**
+        The request target with its path normalized and both path and query encoded, such
+        as `/My%20File.pdf?tag=a&tag=b`.
+    */
     pub fn url(&self) -> String {
-        self.headers.uri_string()
+        self.target.to_string()
+    }
+
+    /** The decoded request path without the query, this is what routes are matched on. */
+    pub fn path(&self) -> &str {
+        &self.target.path
+    }
+
+    /**
+        Get the first decoded value of a query parameter, use `query_all` when a key may
+        be repeated as in `?tag=a&tag=b`.
+    */
+    pub fn query(&self, key: &str) -> Option<&str> {
+        self.target.query.get(key)
+    }
+
+    /** Get every decoded value of a query parameter in the order they were sent. */
+    pub fn query_all(&self, key: &str) -> Vec<&str> {
+        self.target.query.get_all(key)
+    }
+
+    /** All query parameters in the order they were sent. */
+    pub fn query_params(&self) -> &Params {
+        &self.target.query
     }
 
     pub fn event_souce(&mut self) -> Result<Flag> {
-        let result = self.response.start_event_stream();
-        let stream_ref = self
-            .connection
-            .as_ref()
-            .ok_or(Error::new(ErrorKind::NotFound, "failed to get tcp stream"))?;
-        {
-            let mut stream = stream_ref.as_ref();
-            let bytes = self.response.prepare();
-            stream.write_all(&bytes)?;
-            stream.flush()?;
-        }
+        let mut response = HttpResponse::new();
+        let result = response.start_event_stream();
+        self.write_response(&mut response, false)?;
         result
     }
 
@@ -216,18 +621,7 @@ impl HttpRequest {
     */
     pub fn send_file(&mut self, url: &str) -> Result<Flag> {
         let mut response = HttpResponse::with_static_file(url)?;
-        let stream_ref = self
-            .connection
-            .as_ref()
-            .ok_or(ServerError::error("failed to get tcp stream"))?;
-        {
-            // hnadle this in a block to drop the mutable borrow
-            let mut stream = stream_ref.as_ref();
-            let bytes = response.prepare();
-            stream.write_all(&bytes)?;
-            stream.flush()?;
-            stream.shutdown(Shutdown::Both)?;
-        }
+        self.write_response(&mut response, true)?;
         Ok(Flag::StaticFile)
     }
 
@@ -238,13 +632,14 @@ impl HttpRequest {
     */
     pub fn append_body_data(&mut self, data: String) -> Result<bool> {
         let bytes = data.into_bytes();
-        println!("[http_request] appending body data ({} bytes)", bytes.len());
+        trace!("appending body data ({} bytes)", bytes.len());
         match self.connection.as_ref() {
             None => Ok(false),
             Some(stream) => {
                 let mut stream = stream.as_ref();
                 stream.write_all(&bytes)?;
                 stream.flush()?;
+                self.bytes_written += bytes.len();
                 Ok(true)
             }
         }
@@ -262,3 +657,230 @@ impl HttpRequest {
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::util::format_http_date;
+    use std::time::Duration;
+
+    /** Read a request from a connection which the raw request was written to. */
+    fn read(raw: &[u8]) -> HttpRequest {
+        connect(raw).0
+    }
+
+    /** Like `read`, but keep the client end to read the response from. */
+    fn connect(raw: &[u8]) -> (HttpRequest, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(raw).unwrap();
+        let request = HttpRequest::new(Arc::new(listener.accept().unwrap().0));
+        (request, client)
+    }
+
+    fn serve_static(method: &str, if_modified_since: &str) -> HttpResponse {
+        let raw = format!(
+            "{} /index.css HTTP/1.1\r\nHost: localhost\r\nIf-Modified-Since: {}\r\n\r\n",
+            method, if_modified_since
+        );
+        let (mut request, mut client) = connect(raw.as_bytes());
+        request.serve_static_file().unwrap();
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).unwrap();
+        HttpResponse::parse(&data).unwrap()
+    }
+
+    #[test]
+    fn reuses_a_valid_incoming_request_id() {
+        let request = read(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: edge-42\r\n\r\n");
+        assert_eq!(request.request_id, "edge-42");
+        assert_eq!(
+            request.response.headers.get("X-Request-Id").unwrap(),
+            "edge-42"
+        );
+    }
+
+    #[test]
+    fn replaces_an_invalid_incoming_request_id() {
+        let request = read(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: a b\r\n\r\n");
+        assert_ne!(request.request_id, "a b");
+        assert!(is_valid_request_id(&request.request_id));
+        assert_eq!(
+            request.response.headers.get("X-Request-Id"),
+            Some(&request.request_id)
+        );
+
+        let request = read(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(request.request_id.len(), 16);
+    }
+
+    fn post(body: &str) -> HttpRequest {
+        post_as("application/json", body.as_bytes())
+    }
+
+    #[test]
+    fn parses_json_bodies() {
+        let value = post(r#"{"id": 12345678901234567891}"#).json().unwrap();
+        assert_eq!(
+            value.get("id").and_then(Value::as_u64),
+            Some(12_345_678_901_234_567_891)
+        );
+
+        match post("{\n  \"id\" 1\n}").json() {
+            Err(BodyError::Json(err)) => assert_eq!((err.line, err.column), (2, 8)),
+            other => panic!("expected a json error, got {:?}", other),
+        }
+    }
+
+    fn post_as(content_type: &str, body: &[u8]) -> HttpRequest {
+        let mut raw = format!(
+            "POST /api HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        raw.extend_from_slice(body);
+        read(&raw)
+    }
+
+    #[test]
+    fn parses_form_bodies() {
+        let request = post_as(
+            "application/x-www-form-urlencoded; charset=UTF-8",
+            b"name=Ada+Lovelace&tag=math&tag=poetry",
+        );
+        assert!(request.has_content_type("Application/X-WWW-Form-Urlencoded"));
+        let form = request.form().unwrap();
+        assert_eq!(form.get("name"), Some("Ada Lovelace"));
+        assert_eq!(form.get_all("tag"), vec!["math", "poetry"]);
+    }
+
+    #[test]
+    fn refuses_forms_with_another_content_type() {
+        let request = post_as("application/json", b"a=1");
+        assert!(!request.has_content_type("application/x-www-form-urlencoded"));
+        assert_eq!(
+            request.form(),
+            Err(BodyError::ContentType("application/x-www-form-urlencoded"))
+        );
+    }
+
+    #[test]
+    fn reports_the_pair_which_is_not_utf8() {
+        let request = post_as("application/x-www-form-urlencoded", b"a=1&b=2&c=\xff");
+        assert_eq!(
+            request.form(),
+            Err(BodyError::Form(ParamsError { pair: 3 }))
+        );
+        let request = post_as("application/x-www-form-urlencoded", b"a=1&b=%zz");
+        assert_eq!(
+            request.form(),
+            Err(BodyError::Form(ParamsError { pair: 2 }))
+        );
+
+        // empty pairs are skipped when counting, for bad escapes and bad bytes alike
+        for body in [&b"&a=1&&b=%zz&"[..], b"&a=1&&b=\xff&", b"a=1&&\xff"] {
+            let request = post_as("application/x-www-form-urlencoded", body);
+            assert_eq!(
+                request.form(),
+                Err(BodyError::Form(ParamsError { pair: 2 })),
+                "{:?}",
+                String::from_utf8_lossy(body)
+            );
+        }
+    }
+
+    #[test]
+    fn streams_multipart_bodies_once() {
+        let dir = std::env::temp_dir().join(format!("request_multipart_{}", std::process::id()));
+        let uploads = Multipart::new().upload_dir(&dir);
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\nhello\r\n--XyZ--\r\n";
+        let mut request = post_as("multipart/form-data; boundary=XyZ", body.as_bytes());
+        let form = request.multipart(&uploads).unwrap();
+        let file = form.file("file").unwrap();
+        assert_eq!(std::fs::read_to_string(file.path()).unwrap(), "hello");
+        assert_eq!(
+            request.multipart(&uploads).unwrap_err(),
+            BodyError::LengthRequired
+        );
+        drop(form);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut request = post_as("multipart/form-data", body.as_bytes());
+        assert_eq!(
+            request.multipart(&uploads).unwrap_err(),
+            BodyError::Multipart(MultipartError::Malformed("missing boundary".to_string()))
+        );
+        let mut request = post_as("application/json", b"{}");
+        assert_eq!(
+            request.multipart(&uploads).unwrap_err(),
+            BodyError::ContentType("multipart/form-data")
+        );
+    }
+
+    #[test]
+    fn reads_repeated_query_parameters_in_order() {
+        let request =
+            read(b"GET /search/./My%20Docs?tag=a&q=x+y&tag=b HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(request.path(), "/search/My Docs");
+        assert_eq!(request.query("tag"), Some("a"));
+        assert_eq!(request.query_all("tag"), vec!["a", "b"]);
+        assert_eq!(request.query("q"), Some("x y"));
+        assert_eq!(request.query_params().len(), 3);
+        assert_eq!(request.url(), "/search/My%20Docs?tag=a&q=x%20y&tag=b");
+    }
+
+    #[test]
+    fn redirects_to_the_canonical_path_when_asked() {
+        let mut request = read(b"GET /a/../%7Euser?q=1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let redirect = Normalize::new().redirect(true);
+        assert_eq!(
+            request.normalize_path(&redirect),
+            Ok(Some("/~user?q=1".to_string()))
+        );
+        assert_eq!(request.normalize_path(&Normalize::new()), Ok(None));
+        assert_eq!(request.path(), "/~user");
+
+        let mut request = read(b"GET /a%2Fb HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(
+            request.normalize_path(&Normalize::new()),
+            Err(PathError::EncodedSlash)
+        );
+    }
+
+    #[test]
+    fn answers_a_current_copy_with_not_modified() {
+        let modified = HttpResponse::last_modified("/index.css").unwrap();
+        let since = format_http_date(modified + Duration::from_secs(1));
+        for method in ["GET", "HEAD"] {
+            let response = serve_static(method, &since);
+            assert_eq!(response.status.code(), 304, "{}", method);
+            assert_eq!(
+                response.headers.get("Last-Modified"),
+                Some(&format_http_date(modified))
+            );
+            assert!(response.text().is_empty());
+        }
+        // the date in the header is in whole seconds, so an equal date is current
+        assert_eq!(
+            serve_static("GET", &format_http_date(modified))
+                .status
+                .code(),
+            304
+        );
+    }
+
+    #[test]
+    fn sends_the_file_for_a_stale_or_invalid_date() {
+        let body = std::fs::read("./src/public/index.css").unwrap();
+        for since in ["Sun, 06 Nov 1994 08:49:37 GMT", "yesterday"] {
+            let response = serve_static("GET", since);
+            assert_eq!(response.status.code(), 200, "{}", since);
+            assert!(response.headers.get("Last-Modified").is_some());
+            assert_eq!(response.body.as_deref(), Some(body.as_slice()));
+        }
+        let modified = HttpResponse::last_modified("/index.css").unwrap();
+        let since = format_http_date(modified + Duration::from_secs(1));
+        assert_eq!(serve_static("POST", &since).status.code(), 200);
+    }
+}
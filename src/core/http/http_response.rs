@@ -1,14 +1,18 @@
 use crate::core::http::http_headers::HttpHeaders;
-use crate::core::util::get_mime_type;
+use crate::core::json::Value;
+use crate::core::util::{format_http_date, get_mime_type};
+use crate::debug;
 use std::borrow::{Borrow, BorrowMut};
 use std::cell::RefCell;
 use std::fs;
-use std::io::Error;
 use std::io::{BufRead, BufReader};
 use std::io::{BufWriter, Write};
+use std::io::{Error, ErrorKind};
 use std::net::{Shutdown, TcpListener, TcpStream};
+use std::time::SystemTime;
 
 use super::http_headers::HttpVersion;
+use super::Cookie;
 use super::HttpStatus;
 use crate::core::server::Flag;
 
@@ -50,6 +54,57 @@ impl HttpResponse {
         }
     }
 
+    /**
+        Create a plain text response for an error status, the body is the status line
+        e.g. `403 Forbidden`.
+    */
+    pub fn error(status: HttpStatus) -> Self {
+        let mut response = HttpResponse::new();
+        let body = format!("{} {}", status.code(), status.message());
+        response.set_body(body.into_bytes(), "text/plain");
+        response.set_status(status);
+        response
+    }
+
+    /**
+        Create a `200` response with a JSON body.
+
+        request.send(HttpResponse::json(&Value::object().with("ok", true.into())))
+    */
+    pub fn json(value: &Value) -> Self {
+        let mut response = HttpResponse::new();
+        response.set_body(value.to_string().into_bytes(), "application/json");
+        response
+    }
+
+    /** Create a JSON error response such as `{"error":"expected ':' at line 1 column 9"}`. */
+    pub fn json_error(status: HttpStatus, message: &str) -> Self {
+        let mut response = HttpResponse::json(&Value::object().with("error", message.into()));
+        response.set_status(status);
+        response
+    }
+
+    /** Create a `302` response which redirects the client to another location. */
+    pub fn redirect(location: &str) -> Self {
+        let mut response = HttpResponse::new();
+        response.set_status(HttpStatus::MovedTemporarily);
+        response.set_header("Location", location);
+        response.set_header("Content-Length", "0");
+        response
+    }
+
+    /**
+        Create a `308` response which sends the client to the canonical location of a
+        resource, keeping the method and body of the request.
+    */
+    pub fn permanent_redirect(location: &str) -> Self {
+        let mut response = HttpResponse::new();
+        response.set_status(HttpStatus::Status(308, "Permanent Redirect".to_string()));
+        response.set_header("Location", location);
+        response.set_header("Content-Length", "0");
+        response
+    }
+
     /**
         Create a new HttpResponse instance with a static file which is ready to be sent.
     */
@@ -61,18 +116,57 @@ impl HttpResponse {
         };
         let (file_bytes, file_type) = HttpResponse::get_file(url)?;
         response.set_body(file_bytes, file_type.as_str());
+        if let Some(modified) = HttpResponse::last_modified(url) {
+            response.set_header("Last-Modified", &format_http_date(modified));
+        }
+        Ok(response)
+    }
+
+    /**
+        Create a `304 Not Modified` response for a static file which the client already has,
+        the file must still exist.
+    */
+    pub fn not_modified(url: &str) -> Result<Self, Error> {
+        let modified = fs::metadata(HttpResponse::file_path(url)?)?.modified()?;
+        let mut response = HttpResponse::new();
+        response.set_status(HttpStatus::NotModified);
+        response.set_header("Last-Modified", &format_http_date(modified));
         Ok(response)
     }
 
     pub fn get_file(url: &str) -> Result<(Vec<u8>, String), Error> {
-        let path = url.trim_matches('/');
-        let file_path = format!("./src/public/{}", path);
-        println!("[http_request] fetch {:?}", file_path);
+        let file_path = HttpResponse::file_path(url)?;
+        debug!("fetch {:?}", file_path);
         let data = fs::read(file_path)?;
         let mime = get_mime_type(url);
         Ok((data, mime))
     }
 
+    /** The modification time of a static file, if it exists. */
+    pub fn last_modified(url: &str) -> Option<SystemTime> {
+        HttpResponse::file_path(url)
+            .and_then(fs::metadata)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /**
+        The location of a static file under `./src/public`. Paths with empty or dot
+        segments such as `//log.html` are refused rather than trimmed, since a prefix
+        check on the request path would not see them as the file they name.
+    */
+    fn file_path(url: &str) -> Result<String, Error> {
+        let path = url.strip_prefix('/').unwrap_or(url);
+        if path
+            .split('/')
+            .any(|segment| matches!(segment, "" | "." | ".."))
+        {
+            let message = format!("not a static file path: {:?}", url);
+            return Err(Error::new(ErrorKind::NotFound, message));
+        }
+        Ok(format!("./src/public/{}", path))
+    }
+
     pub fn set_body(&mut self, body: Vec<u8>, mime: &str) {
         // println!("[http_response] set_body {} bytes", body.len());
         self.headers.set_content_length(body.len());
@@ -84,6 +178,21 @@ impl HttpResponse {
         self.headers.set(key, value);
     }
 
+    /**
+        Add a `Set-Cookie` header to the response, multiple cookies can be set on the same
+        response. Will return an error if the cookie name or value contains invalid characters.
+    */
+    pub fn set_cookie(&mut self, cookie: Cookie) -> Result<(), Error> {
+        if !cookie.is_valid() {
+            return Err(Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid cookie: {}", cookie.name),
+            ));
+        }
+        self.headers.append("Set-Cookie", &cookie.to_string());
+        Ok(())
+    }
+
     pub fn set_status(&mut self, status: HttpStatus) {
         self.status = status;
     }
@@ -112,8 +221,14 @@ impl HttpResponse {
             1. The status line
             2. The headers
             3. The body
+
+        A `Date` header is added if the response does not already have one.
     */
     pub fn prepare(&mut self) -> Vec<u8> {
+        if self.headers.get("Date").is_none() {
+            self.headers
+                .set("Date", &format_http_date(SystemTime::now()));
+        }
         let mut response = String::new();
         response.push_str(&self.response_headers());
         let mut bytes = response.into_bytes();
@@ -134,10 +249,76 @@ impl HttpResponse {
         for (key, value) in self.headers.raw.borrow() {
             http_response_headers.push_str(&format!("{}: {}\r\n", key, value));
         }
+        for (key, value) in self.headers.repeated.iter() {
+            http_response_headers.push_str(&format!("{}: {}\r\n", key, value));
+        }
         http_response_headers.push_str("\r\n");
         http_response_headers
     }
 
+    /**
+        Parse a complete response received from another server, the body is read using the
+        `Content-Length` or chunked transfer encoding, otherwise it is the rest of the data.
+    */
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::new(std::io::ErrorKind::InvalidData, message);
+        let header_end = data
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| invalid("incomplete response headers"))?;
+        let head = String::from_utf8_lossy(&data[..header_end]);
+        let mut lines = head.split(CRLF);
+
+        let status_line = lines.next().unwrap_or_default();
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default();
+        let code = parts
+            .next()
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| invalid("invalid status line"))?;
+        let message = parts.next().unwrap_or_default();
+
+        let mut response = HttpResponse::new();
+        response.set_status(HttpStatus::from_code(code, message));
+        response.set_version(HttpHeaders::version_from_string(version));
+        for line in lines {
+            let (name, value) = HttpHeaders::parse_header(line)
+                .ok_or_else(|| invalid("invalid response header"))?;
+            match response.headers.contains(name) {
+                true => response.headers.append(name, value),
+                false => response.headers.set(name, value),
+            }
+        }
+
+        let rest = &data[header_end + 4..];
+        let is_chunked = response
+            .headers
+            .get("Transfer-Encoding")
+            .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+        let body = match response.headers.get("Content-Length") {
+            _ if is_chunked => decode_chunked(rest).ok_or_else(|| invalid("invalid chunk"))?,
+            Some(length) => {
+                let length = length
+                    .parse::<usize>()
+                    .map_err(|_| invalid("invalid content length"))?;
+                rest.get(..length)
+                    .ok_or_else(|| invalid("incomplete response body"))?
+                    .to_vec()
+            }
+            None => rest.to_vec(),
+        };
+        response.body = Some(body);
+        Ok(response)
+    }
+
+    /** The response body as text, invalid UTF-8 is replaced. */
+    pub fn text(&self) -> String {
+        match &self.body {
+            Some(body) => String::from_utf8_lossy(body).to_string(),
+            None => String::new(),
+        }
+    }
+
     /**
         Send the response to the client. This function takes a mutable reference to a TcpStream
         which is used to send the response to the client. NOTE: This will close the connection
@@ -151,3 +332,58 @@ impl HttpResponse {
         Ok(())
     }
 }
+
+/**
+    Decode a body sent with chunked transfer encoding, any trailers are ignored.
+*/
+fn decode_chunked(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|window| window == b"\r\n")?;
+        let size_line = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size_hex = size_line.split(';').next()?.trim();
+        let size = usize::from_str_radix(size_hex, 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_files_are_under_public() {
+        assert_eq!(
+            HttpResponse::file_path("/log.html").unwrap(),
+            "./src/public/log.html"
+        );
+        assert_eq!(
+            HttpResponse::file_path("404.html").unwrap(),
+            "./src/public/404.html"
+        );
+        assert_eq!(
+            HttpResponse::file_path("/scripts/app.js").unwrap(),
+            "./src/public/scripts/app.js"
+        );
+    }
+
+    #[test]
+    fn static_paths_with_empty_or_dot_segments_are_refused() {
+        for path in [
+            "//log.html",
+            "/a//log.html",
+            "/",
+            "/dir/",
+            "/./log.html",
+            "/a/../x",
+        ] {
+            let err = HttpResponse::file_path(path).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::NotFound, "{}", path);
+        }
+    }
+}
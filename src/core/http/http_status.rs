@@ -17,6 +17,8 @@ pub enum HttpStatus {
     Unauthorized,
     Forbidden,
     NotFound,
+    MethodNotAllowed,
+    TooManyRequests,
     InternalServerError,
     NotImplemented,
     BadGateway,
@@ -38,6 +40,8 @@ impl HttpStatus {
             HttpStatus::Unauthorized => "Unauthorized",
             HttpStatus::Forbidden => "Forbidden",
             HttpStatus::NotFound => "Not Found",
+            HttpStatus::MethodNotAllowed => "Method Not Allowed",
+            HttpStatus::TooManyRequests => "Too Many Requests",
             HttpStatus::InternalServerError => "Internal Server Error",
             HttpStatus::NotImplemented => "Not Implemented",
             HttpStatus::BadGateway => "Bad Gateway",
@@ -46,6 +50,33 @@ impl HttpStatus {
         }
     }
 
+    /**
+        Get the status for a code received from another server, unknown codes keep the
+        reason phrase which was sent with them.
+    */
+    pub fn from_code(code: u16, message: &str) -> Self {
+        match code {
+            200 => HttpStatus::OK,
+            201 => HttpStatus::Created,
+            202 => HttpStatus::Accepted,
+            204 => HttpStatus::NoContent,
+            301 => HttpStatus::MovedPermanently,
+            302 => HttpStatus::MovedTemporarily,
+            304 => HttpStatus::NotModified,
+            400 => HttpStatus::BadRequest,
+            401 => HttpStatus::Unauthorized,
+            403 => HttpStatus::Forbidden,
+            404 => HttpStatus::NotFound,
+            405 => HttpStatus::MethodNotAllowed,
+            429 => HttpStatus::TooManyRequests,
+            500 => HttpStatus::InternalServerError,
+            501 => HttpStatus::NotImplemented,
+            502 => HttpStatus::BadGateway,
+            503 => HttpStatus::ServiceUnavailable,
+            _ => HttpStatus::Status(code, message.to_string()),
+        }
+    }
+
     pub fn code(&self) -> u16 {
         match self {
             HttpStatus::OK => 200,
@@ -59,6 +90,8 @@ impl HttpStatus {
             HttpStatus::Unauthorized => 401,
             HttpStatus::Forbidden => 403,
             HttpStatus::NotFound => 404,
+            HttpStatus::MethodNotAllowed => 405,
+            HttpStatus::TooManyRequests => 429,
             HttpStatus::InternalServerError => 500,
             HttpStatus::NotImplemented => 501,
             HttpStatus::BadGateway => 502,
@@ -1,10 +1,24 @@
+pub mod body_error;
+pub mod client;
+pub mod connection_limits;
+pub mod cookie;
 pub mod http_connections;
 pub mod http_headers;
 pub mod http_request;
 pub mod http_response;
 pub mod http_status;
+pub mod multipart;
+pub mod request_id;
+pub mod tus;
 
+pub use self::body_error::BodyError;
+pub use self::client::HttpClient;
+pub use self::connection_limits::ConnectionLimits;
+pub use self::cookie::{Cookie, SameSite};
 pub use self::http_connections::HttpConnections;
 pub use self::http_request::HttpRequest;
 pub use self::http_response::HttpResponse;
 pub use self::http_status::HttpStatus;
+pub use self::multipart::{FormData, Multipart, MultipartError, UploadedFile};
+pub use self::request_id::{generate_request_id, is_valid_request_id};
+pub use self::tus::{Tus, TusUpload};
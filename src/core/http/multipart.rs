@@ -0,0 +1,890 @@
+use crate::core::error::ServerError;
+use crate::core::http::HttpStatus;
+use crate::core::url::Params;
+use crate::core::util::{generate_random_u64, hex_encode, parse_size};
+use crate::core::util::{percent_decode, secure_random_bytes};
+use crate::core::ConfigFile;
+use crate::debug;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+
+/** Bytes read from the body at a time, which is also about as much as is held in memory. */
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/** Largest block of headers for a single part. */
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/** Longest boundary allowed by RFC 2046. */
+const MAX_BOUNDARY: usize = 70;
+
+/** Text after the closing boundary which is read and thrown away. */
+const MAX_EPILOGUE: u64 = 8 * 1024;
+
+/**
+    Parses a `multipart/form-data` body as it arrives, keeping the text fields in memory
+    and streaming uploaded files into a directory, so a large upload is never held in
+    memory. Each limit is checked while reading and ends the upload with a `413`.
+
+    let uploads = Multipart::new()
+        .upload_dir("./src/data/uploads")
+        .max_file_size(50 * 1024 * 1024);
+    server.route("/upload", move |sr| match sr.multipart(&uploads) {
+        Ok(mut form) => {
+            for file in form.files.iter_mut() {
+                file.persist(format!("./src/data/files/{}", file.filename))?;
+            }
+            sr.send(HttpResponse::redirect("/files"))
+        }
+        Err(err) => sr.send(err.to_response()),
+    });
+*/
+#[derive(Clone, Debug)]
+pub struct Multipart {
+    upload_dir: PathBuf,
+    max_parts: usize,
+    max_field_size: usize,
+    max_file_size: u64,
+}
+
+/**
+    Why a multipart body could not be read. Sizes are the limit which was passed.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum MultipartError {
+    /** The body does not follow the multipart format, which is a `400`. */
+    Malformed(String),
+    /** The body has more parts than the limit, which is a `413`. */
+    TooManyParts(usize),
+    /** A text field is larger than the limit, which is a `413`. */
+    FieldTooLarge(String, usize),
+    /** An uploaded file is larger than the limit, which is a `413`. */
+    FileTooLarge(String, u64),
+    /** An uploaded file could not be written to the upload directory, which is a `500`. */
+    Storage(String),
+}
+
+/**
+    The fields and files of a multipart form. Files which have not been moved with
+    `UploadedFile::persist` are deleted when the form is dropped.
+*/
+#[derive(Debug, Default)]
+pub struct FormData {
+    pub fields: Params,
+    pub files: Vec<UploadedFile>,
+}
+
+/**
+    A file from a multipart form, stored under a random name in the upload directory.
+    The `filename` sent by the client is sanitized, see `sanitize_filename`.
+*/
+#[derive(Debug)]
+pub struct UploadedFile {
+    /** The name of the form field. */
+    pub name: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl Multipart {
+    /**
+        Store uploads in the system temporary directory, with at most 100 parts, 64 KiB
+        for each text field and 100 MiB for each file.
+    */
+    pub fn new() -> Self {
+        Multipart {
+            upload_dir: std::env::temp_dir().join("server-uploads"),
+            max_parts: 100,
+            max_field_size: 64 * 1024,
+            max_file_size: 100 * 1024 * 1024,
+        }
+    }
+
+    /**
+        Create from the `[uploads]` section of a config file, sizes may end in K, M or G.
+
+        [uploads]
+        dir = ./src/data/uploads
+        max_parts = 20
+        max_field_size = 16K
+        max_file_size = 50M
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let mut multipart = Multipart::new();
+        if let Some(dir) = config.get("uploads.dir") {
+            multipart = multipart.upload_dir(dir);
+        }
+        if let Some(max_parts) = config.get_u64("uploads.max_parts") {
+            multipart = multipart.max_parts(max_parts as usize);
+        }
+        if let Some(size) = config_size(config, "max_field_size")? {
+            multipart = multipart.max_field_size(size as usize);
+        }
+        if let Some(size) = config_size(config, "max_file_size")? {
+            multipart = multipart.max_file_size(size);
+        }
+        Ok(multipart)
+    }
+
+    /** The directory uploaded files are streamed into, it is created when needed. */
+    pub fn upload_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.upload_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn max_parts(mut self, max_parts: usize) -> Self {
+        self.max_parts = max_parts;
+        self
+    }
+
+    pub fn max_field_size(mut self, bytes: usize) -> Self {
+        self.max_field_size = bytes;
+        self
+    }
+
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /**
+        Read a multipart body with the boundary from its `Content-Type`. Only about one
+        chunk of the body is held in memory at a time, a boundary which is split across
+        two reads is still found.
+    */
+    pub fn read<R: Read>(
+        &self,
+        reader: R,
+        boundary: &str,
+    ) -> std::result::Result<FormData, MultipartError> {
+        // the body starts with the boundary, the CRLF before it belongs to the delimiter
+        let delimiter = format!("\r\n--{}", boundary).into_bytes();
+        let mut scanner = Scanner::new(reader);
+        scanner.read_until(&delimiter, |_| Ok(()))?;
+
+        let mut form = FormData::default();
+        let mut parts = 0;
+        loop {
+            if !scanner.fill_to(2)? {
+                return Err(unexpected_end());
+            }
+            if scanner.buffer.starts_with(b"--") {
+                break;
+            }
+            // the rest of the boundary line may only have whitespace
+            scanner.read_until(b"\r\n", |padding| {
+                match padding.iter().all(|byte| *byte == b' ' || *byte == b'\t') {
+                    true => Ok(()),
+                    false => Err(malformed("text after a boundary")),
+                }
+            })?;
+
+            parts += 1;
+            if parts > self.max_parts {
+                return Err(MultipartError::TooManyParts(self.max_parts));
+            }
+            let headers = scanner.read_headers()?;
+            let (name, filename) = content_disposition(&headers)
+                .ok_or_else(|| malformed(&format!("part {} has no form-data name", parts)))?;
+            let content_type = header(&headers, "Content-Type");
+
+            match filename {
+                // a file input which was left empty
+                Some(filename) if filename.is_empty() => {
+                    scanner.read_until(&delimiter, |_| Ok(()))?;
+                }
+                Some(filename) => {
+                    let mut file = UploadedFile {
+                        name,
+                        filename: sanitize_filename(&filename),
+                        content_type: content_type
+                            .unwrap_or("application/octet-stream")
+                            .to_string(),
+                        size: 0,
+                        path: PathBuf::new(),
+                        persisted: false,
+                    };
+                    self.read_file(&mut scanner, &delimiter, &mut file)?;
+                    form.files.push(file);
+                }
+                None => {
+                    let value = self.read_field(&mut scanner, &delimiter, &name)?;
+                    form.fields.append(&name, &value);
+                }
+            }
+        }
+
+        // closing with unread data resets the connection, which can lose the response
+        io::copy(&mut scanner.reader.take(MAX_EPILOGUE), &mut io::sink()).ok();
+        Ok(form)
+    }
+
+    fn read_field<R: Read>(
+        &self,
+        scanner: &mut Scanner<R>,
+        delimiter: &[u8],
+        name: &str,
+    ) -> std::result::Result<String, MultipartError> {
+        let mut value = Vec::new();
+        scanner.read_until(delimiter, |data| {
+            if value.len() + data.len() > self.max_field_size {
+                return Err(MultipartError::FieldTooLarge(
+                    name.to_string(),
+                    self.max_field_size,
+                ));
+            }
+            value.extend_from_slice(data);
+            Ok(())
+        })?;
+        String::from_utf8(value)
+            .map_err(|_| malformed(&format!("field {:?} is not valid utf-8", name)))
+    }
+
+    /** Stream a file part into the upload directory, the file is removed on an error. */
+    fn read_file<R: Read>(
+        &self,
+        scanner: &mut Scanner<R>,
+        delimiter: &[u8],
+        file: &mut UploadedFile,
+    ) -> std::result::Result<(), MultipartError> {
+        let (path, mut output) = self.create_file().map_err(storage)?;
+        file.path = path;
+        debug!("uploading {:?} to {}", file.filename, file.path.display());
+        scanner.read_until(delimiter, |data| {
+            file.size += data.len() as u64;
+            if file.size > self.max_file_size {
+                return Err(MultipartError::FileTooLarge(
+                    file.filename.clone(),
+                    self.max_file_size,
+                ));
+            }
+            output.write_all(data).map_err(storage)
+        })?;
+        output.sync_all().map_err(storage)
+    }
+
+    /** Create an empty file with a random name, which can not replace an existing file. */
+    fn create_file(&self) -> Result<(PathBuf, File)> {
+        fs::create_dir_all(&self.upload_dir)?;
+        loop {
+            let name = match secure_random_bytes(8) {
+                Ok(bytes) => hex_encode(&bytes),
+                Err(_) => format!("{:016x}", generate_random_u64()),
+            };
+            let path = self.upload_dir.join(format!("upload-{}", name));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((path, file)),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        Multipart::new()
+    }
+}
+
+impl FormData {
+    /** Get the first value of a text field. */
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.get(name)
+    }
+
+    /** Get the first file uploaded with a field name. */
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+}
+
+impl UploadedFile {
+    /** Where the file is stored, in the upload directory until it is persisted. */
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /**
+        Move the file out of the upload directory so it is kept, copying it when the
+        destination is on another file system.
+    */
+    pub fn persist<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if fs::rename(&self.path, path).is_err() {
+            fs::copy(&self.path, path)?;
+            fs::remove_file(&self.path).ok();
+        }
+        self.path = path.to_path_buf();
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if !self.persisted && !self.path.as_os_str().is_empty() {
+            if let Err(err) = fs::remove_file(&self.path) {
+                debug!("could not remove upload {}: {}", self.path.display(), err);
+            }
+        }
+    }
+}
+
+impl MultipartError {
+    pub fn status(&self) -> HttpStatus {
+        match self {
+            MultipartError::Malformed(_) => HttpStatus::BadRequest,
+            MultipartError::TooManyParts(_)
+            | MultipartError::FieldTooLarge(..)
+            | MultipartError::FileTooLarge(..) => {
+                HttpStatus::Status(413, "Payload Too Large".to_string())
+            }
+            MultipartError::Storage(_) => HttpStatus::InternalServerError,
+        }
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::Malformed(reason) => write!(f, "malformed multipart body: {}", reason),
+            MultipartError::TooManyParts(limit) => write!(f, "more than {} parts", limit),
+            MultipartError::FieldTooLarge(name, limit) => {
+                write!(f, "field {:?} is larger than {} bytes", name, limit)
+            }
+            MultipartError::FileTooLarge(filename, limit) => {
+                write!(f, "file {:?} is larger than {} bytes", filename, limit)
+            }
+            MultipartError::Storage(reason) => write!(f, "could not store upload: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {}
+
+/**
+    Get the boundary from a `multipart/form-data` content type, which is 1 to 70
+    characters and may be quoted.
+*/
+pub fn boundary(content_type: &str) -> Option<String> {
+    parameters(content_type)
+        .into_iter()
+        .find(|(name, _)| name == "boundary")
+        .map(|(_, boundary)| boundary)
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= MAX_BOUNDARY)
+}
+
+/**
+    Reduce a filename sent by a client to a name which is safe to show or to store a
+    file under: only the last path component is kept, control and reserved characters
+    become `_`, leading dots are removed so it can not be `..` or hidden, and it is cut
+    to 255 bytes. An empty result becomes `upload`.
+
+    sanitize_filename("C:\\Users\\ada\\notes.txt") == "notes.txt";
+    sanitize_filename("../../etc/passwd") == "passwd";
+    sanitize_filename("..") == "upload";
+*/
+pub fn sanitize_filename(filename: &str) -> String {
+    let base = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let replaced: String = base
+        .chars()
+        .map(|c| match c.is_control() || "<>:\"|?*".contains(c) {
+            true => '_',
+            false => c,
+        })
+        .collect();
+    let mut name = replaced
+        .trim_start_matches(['.', ' '])
+        .trim_end_matches(['.', ' '])
+        .to_string();
+    while name.len() > 255 {
+        name.pop();
+    }
+    match name.is_empty() {
+        true => "upload".to_string(),
+        false => name,
+    }
+}
+
+/**
+    Reads a body in chunks, searching for a delimiter while keeping enough of the end of
+    each chunk to find one which is split across reads.
+*/
+struct Scanner<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> Scanner<R> {
+    fn new(reader: R) -> Self {
+        Scanner {
+            reader,
+            buffer: b"\r\n".to_vec(),
+        }
+    }
+
+    /** Read another chunk, returns false at the end of the body. */
+    fn fill(&mut self) -> std::result::Result<bool, MultipartError> {
+        let mut chunk = [0; CHUNK_SIZE];
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(bytes) => {
+                    self.buffer.extend_from_slice(&chunk[..bytes]);
+                    return Ok(true);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(malformed(&format!("could not read body: {}", err))),
+            }
+        }
+    }
+
+    /** Read until at least `length` bytes are buffered, returns false if the body ends. */
+    fn fill_to(&mut self, length: usize) -> std::result::Result<bool, MultipartError> {
+        while self.buffer.len() < length {
+            if !self.fill()? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /**
+        Pass everything before the delimiter to `sink` as it is read, then skip past the
+        delimiter. It is an error if the body ends first.
+    */
+    fn read_until<F>(
+        &mut self,
+        delimiter: &[u8],
+        mut sink: F,
+    ) -> std::result::Result<(), MultipartError>
+    where
+        F: FnMut(&[u8]) -> std::result::Result<(), MultipartError>,
+    {
+        loop {
+            if let Some(index) = find(&self.buffer, delimiter) {
+                sink(&self.buffer[..index])?;
+                self.buffer.drain(..index + delimiter.len());
+                return Ok(());
+            }
+            let keep = self.buffer.len().min(delimiter.len() - 1);
+            let ready = self.buffer.len() - keep;
+            if ready > 0 {
+                sink(&self.buffer[..ready])?;
+                self.buffer.drain(..ready);
+            }
+            if !self.fill()? {
+                return Err(unexpected_end());
+            }
+        }
+    }
+
+    /** Read the header lines of a part, which end with a blank line. */
+    fn read_headers(&mut self) -> std::result::Result<Vec<(String, String)>, MultipartError> {
+        if !self.fill_to(2)? {
+            return Err(unexpected_end());
+        }
+        // a part without any headers
+        if self.buffer.starts_with(b"\r\n") {
+            self.buffer.drain(..2);
+            return Ok(Vec::new());
+        }
+        let mut block = Vec::new();
+        self.read_until(b"\r\n\r\n", |data| {
+            if block.len() + data.len() > MAX_HEADER_SIZE {
+                return Err(malformed("part headers are too large"));
+            }
+            block.extend_from_slice(data);
+            Ok(())
+        })?;
+        Ok(String::from_utf8_lossy(&block)
+            .split("\r\n")
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect())
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/**
+    Get the field name and any filename from the `Content-Disposition` of a part. A
+    `filename*` in RFC 5987 form is preferred over a plain `filename`.
+*/
+fn content_disposition(headers: &[(String, String)]) -> Option<(String, Option<String>)> {
+    let value = header(headers, "Content-Disposition")?;
+    if !value
+        .trim_start()
+        .to_ascii_lowercase()
+        .starts_with("form-data")
+    {
+        return None;
+    }
+    let params = parameters(value);
+    let find = |key: &str| {
+        params
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.clone())
+    };
+    let name = find("name")?;
+    let extended = find("filename*").and_then(|value| {
+        let (charset, encoded) = value.split_once("''")?;
+        match charset.eq_ignore_ascii_case("utf-8") {
+            true => percent_decode(encoded),
+            false => None,
+        }
+    });
+    Some((name, extended.or_else(|| find("filename"))))
+}
+
+/**
+    Split the `; key=value` parameters after the first value of a header, names are
+    lowercased and quoted values are unescaped.
+*/
+fn parameters(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = value.split_once(';').map_or("", |(_, rest)| rest);
+    while !rest.trim().is_empty() {
+        let end = rest.find(['=', ';']).unwrap_or(rest.len());
+        let name = rest[..end].trim().to_ascii_lowercase();
+        if !rest[end..].starts_with('=') {
+            params.push((name, String::new()));
+            rest = rest.get(end + 1..).unwrap_or_default();
+            continue;
+        }
+        let after = rest[end + 1..].trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => unquote(quoted),
+            None => {
+                let (value, next) = after.split_once(';').unwrap_or((after, ""));
+                (value.trim().to_string(), next)
+            }
+        };
+        params.push((name, value));
+        rest = next;
+    }
+    params
+}
+
+/** Read a quoted string up to the closing quote, returns what follows the next `;`. */
+fn unquote(input: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next().map(|(_, c)| c)),
+            '"' => {
+                let next = input[index + 1..]
+                    .split_once(';')
+                    .map_or("", |(_, next)| next);
+                return (value, next);
+            }
+            c => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+fn config_size(config: &ConfigFile, key: &str) -> Result<Option<u64>> {
+    match config.get(&format!("uploads.{}", key)) {
+        Some(size) => match parse_size(size) {
+            Some(size) => Ok(Some(size)),
+            None => Err(ServerError::error(&format!(
+                "uploads: invalid {} {:?}",
+                key, size
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn malformed(reason: &str) -> MultipartError {
+    MultipartError::Malformed(reason.to_string())
+}
+
+fn unexpected_end() -> MultipartError {
+    malformed("unexpected end of body")
+}
+
+fn storage(err: io::Error) -> MultipartError {
+    MultipartError::Storage(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** Hands out a body a few bytes at a time, so delimiters are split across reads. */
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let length = self.step.min(buf.len()).min(self.data.len());
+            buf[..length].copy_from_slice(&self.data[..length]);
+            self.data = &self.data[length..];
+            Ok(length)
+        }
+    }
+
+    const BODY: &str = "preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Notes\r\n\
+        --XyZ  \r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"../../a.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line one\r\n--Xy\r\n-XyZ\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"tag\"\r\n\
+        \r\n\
+        a\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\
+        \r\n\
+        \r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"tag\"\r\n\
+        \r\n\
+        b\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    fn upload_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("multipart_{}_{}", name, std::process::id()))
+    }
+
+    fn read(multipart: &Multipart, body: &str) -> std::result::Result<FormData, MultipartError> {
+        multipart.read(body.as_bytes(), "XyZ")
+    }
+
+    #[test]
+    fn reads_fields_and_files_however_the_body_is_split() {
+        let dir = upload_dir("split");
+        let multipart = Multipart::new().upload_dir(&dir);
+        for step in [1, 2, 3, 7, 64, CHUNK_SIZE] {
+            let reader = Trickle {
+                data: BODY.as_bytes(),
+                step,
+            };
+            let form = multipart.read(reader, "XyZ").unwrap();
+            assert_eq!(form.get("title"), Some("Notes"));
+            assert_eq!(form.fields.get_all("tag"), vec!["a", "b"]);
+            assert_eq!(form.files.len(), 1);
+
+            let file = form.file("file").unwrap();
+            assert_eq!(file.filename, "a.txt");
+            assert_eq!(file.content_type, "text/plain");
+            let contents = "line one\r\n--Xy\r\n-XyZ";
+            assert_eq!(file.size, contents.len() as u64);
+            assert_eq!(fs::read_to_string(file.path()).unwrap(), contents);
+            assert!(file.path().starts_with(&dir));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removes_files_which_are_not_persisted() {
+        let dir = upload_dir("persist");
+        let multipart = Multipart::new().upload_dir(&dir);
+        let mut form = read(&multipart, BODY).unwrap();
+        let dropped = form.files[0].path().to_path_buf();
+        let kept = dir.join("kept.txt");
+        drop(read(&multipart, BODY).unwrap());
+
+        form.files[0].persist(&kept).unwrap();
+        assert!(!dropped.exists());
+        assert_eq!(form.files[0].path(), kept);
+        drop(form);
+        assert!(kept.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn enforces_the_limits() {
+        let dir = upload_dir("limits");
+        let multipart = Multipart::new().upload_dir(&dir);
+        assert_eq!(
+            read(&multipart.clone().max_parts(4), BODY).unwrap_err(),
+            MultipartError::TooManyParts(4)
+        );
+        assert_eq!(
+            read(&multipart.clone().max_field_size(4), BODY).unwrap_err(),
+            MultipartError::FieldTooLarge("title".to_string(), 4)
+        );
+        assert_eq!(
+            read(&multipart.clone().max_file_size(8), BODY).unwrap_err(),
+            MultipartError::FileTooLarge("a.txt".to_string(), 8)
+        );
+        // the partly written file is removed
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert!(read(&multipart.max_file_size(20), BODY).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        let multipart = Multipart::new().upload_dir(upload_dir("malformed"));
+        let part = "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n";
+        let cases = [
+            ("", "unexpected end of body"),
+            ("--XyZ\r\n", "unexpected end of body"),
+            (part, "unexpected end of body"),
+            ("--XyZ junk\r\n", "text after a boundary"),
+            (
+                "--XyZ\r\n\r\nvalue\r\n--XyZ--",
+                "part 1 has no form-data name",
+            ),
+            (
+                "--XyZ\r\nContent-Disposition: attachment; name=\"a\"\r\n\r\n1\r\n--XyZ--",
+                "part 1 has no form-data name",
+            ),
+        ];
+        for (body, reason) in cases {
+            assert_eq!(
+                read(&multipart, body).unwrap_err(),
+                MultipartError::Malformed(reason.to_string()),
+                "{:?}",
+                body
+            );
+        }
+
+        let headers = format!(
+            "--XyZ\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_SIZE)
+        );
+        assert_eq!(
+            read(&multipart, &headers).unwrap_err(),
+            malformed("part headers are too large")
+        );
+        let invalid = b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n\xff\r\n--XyZ--";
+        assert_eq!(
+            multipart.read(&invalid[..], "XyZ").unwrap_err(),
+            malformed("field \"a\" is not valid utf-8")
+        );
+    }
+
+    #[test]
+    fn reads_an_empty_form() {
+        let form = read(&Multipart::new(), "--XyZ--\r\n").unwrap();
+        assert!(form.fields.is_empty());
+        assert!(form.files.is_empty());
+    }
+
+    #[test]
+    fn gets_the_boundary() {
+        let boundary = |content_type: &str| super::boundary(content_type);
+        assert_eq!(
+            boundary("multipart/form-data; boundary=----abc123").as_deref(),
+            Some("----abc123")
+        );
+        assert_eq!(
+            boundary("multipart/form-data; charset=utf-8; Boundary=\"a b;c\"").as_deref(),
+            Some("a b;c")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+        assert_eq!(
+            boundary(&format!("multipart/form-data; boundary={}", "a".repeat(71))),
+            None
+        );
+        assert!(boundary(&format!("multipart/form-data; boundary={}", "a".repeat(70))).is_some());
+    }
+
+    #[test]
+    fn sanitizes_filenames() {
+        assert_eq!(sanitize_filename("C:\\Users\\ada\\notes.txt"), "notes.txt");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename(".."), "upload");
+        assert_eq!(sanitize_filename(""), "upload");
+        assert_eq!(sanitize_filename(".hidden"), "hidden");
+        assert_eq!(
+            sanitize_filename("a<b>:c\"d|e?f*\u{1}.txt. "),
+            "a_b__c_d_e_f__.txt"
+        );
+        assert_eq!(sanitize_filename("café.txt"), "café.txt");
+        let long = sanitize_filename(&"é".repeat(200));
+        assert_eq!(long.len(), 254);
+        assert!(long.chars().all(|c| c == 'é'));
+    }
+
+    #[test]
+    fn prefers_the_extended_filename() {
+        let disposition = |value: &str| {
+            content_disposition(&[("content-disposition".to_string(), value.to_string())])
+        };
+        assert_eq!(
+            disposition(
+                "form-data; name=\"f\"; filename=\"a.txt\"; filename*=UTF-8''caf%C3%A9.txt"
+            ),
+            Some(("f".to_string(), Some("café.txt".to_string())))
+        );
+        assert_eq!(
+            disposition("form-data; name=\"f\"; filename=\"a.txt\"; filename*=latin1''x"),
+            Some(("f".to_string(), Some("a.txt".to_string())))
+        );
+        assert_eq!(
+            disposition("Form-Data; name=\"say \\\"hi\\\"\""),
+            Some(("say \"hi\"".to_string(), None))
+        );
+        assert_eq!(disposition("form-data; filename=\"a.txt\""), None);
+        assert_eq!(disposition("inline; name=\"f\""), None);
+    }
+
+    #[test]
+    fn maps_errors_to_status_codes() {
+        assert_eq!(malformed("x").status().code(), 400);
+        assert_eq!(MultipartError::TooManyParts(1).status().code(), 413);
+        assert_eq!(
+            MultipartError::FileTooLarge("a".to_string(), 1)
+                .status()
+                .code(),
+            413
+        );
+        assert_eq!(
+            MultipartError::Storage("x".to_string()).status().code(),
+            500
+        );
+        assert_eq!(
+            MultipartError::FieldTooLarge("title".to_string(), 4).to_string(),
+            "field \"title\" is larger than 4 bytes"
+        );
+    }
+
+    #[test]
+    fn reads_the_uploads_config() {
+        let config = ConfigFile::parse(
+            "[uploads]\ndir = /tmp/up\nmax_parts = 20\nmax_field_size = 16K\nmax_file_size = 50M\n",
+        );
+        let multipart = Multipart::from_config(&config).unwrap();
+        assert_eq!(multipart.upload_dir, PathBuf::from("/tmp/up"));
+        assert_eq!(multipart.max_parts, 20);
+        assert_eq!(multipart.max_field_size, 16 * 1024);
+        assert_eq!(multipart.max_file_size, 50 * 1024 * 1024);
+
+        let config = ConfigFile::parse("[uploads]\nmax_file_size = lots\n");
+        assert!(Multipart::from_config(&config).is_err());
+    }
+}
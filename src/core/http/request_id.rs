@@ -0,0 +1,58 @@
+use crate::core::util::{generate_random_u64, hex_encode, secure_random_bytes};
+
+/** The longest `X-Request-Id` which is accepted from a client or proxy. */
+const MAX_LENGTH: usize = 128;
+
+/**
+    Generate a random request ID of 16 hex characters, e.g. `4bf92f3577b34da6`.
+*/
+pub fn generate_request_id() -> String {
+    match secure_random_bytes(8) {
+        Ok(bytes) => hex_encode(&bytes),
+        Err(_) => format!("{:016x}", generate_random_u64()),
+    }
+}
+
+/**
+    Check if an incoming `X-Request-Id` can be reused, it must be 1 to 128 characters of
+    letters, digits, `-`, `_`, `.`, `:` or `@` so it can not break up a log line.
+*/
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LENGTH
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.:@".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_distinct_hex_ids() {
+        let id = generate_request_id();
+        assert_eq!(id.len(), 16);
+        assert!(id.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert!(is_valid_request_id(&id));
+        assert_ne!(id, generate_request_id());
+    }
+
+    #[test]
+    fn accepts_well_formed_ids() {
+        assert!(is_valid_request_id("4bf92f3577b34da6"));
+        assert!(is_valid_request_id("req_1.2:3@edge-a"));
+        assert!(is_valid_request_id(&"a".repeat(128)));
+    }
+
+    #[test]
+    fn refuses_ids_which_could_break_a_log_line() {
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id(&"a".repeat(129)));
+        assert!(!is_valid_request_id("abc def"));
+        assert!(!is_valid_request_id("abc\n127.0.0.1 - - forged"));
+        assert!(!is_valid_request_id("abc\"def"));
+        assert!(!is_valid_request_id("abc,def"));
+        assert!(!is_valid_request_id("é"));
+    }
+}
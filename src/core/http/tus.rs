@@ -0,0 +1,919 @@
+use crate::core::error::ServerError;
+use crate::core::http::http_headers::{HttpHeaders, HttpMethod};
+use crate::core::http::{HttpRequest, HttpResponse, HttpStatus};
+use crate::core::json::{self, Value};
+use crate::core::middleware::Middleware;
+use crate::core::server::Flag;
+use crate::core::url::Params;
+use crate::core::util::{base64_decode, format_http_date, hex_encode, parse_size};
+use crate::core::util::{generate_random_u64, secure_random_bytes};
+use crate::core::ConfigFile;
+use crate::{debug, info, warn};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/** The version of the protocol which is implemented. */
+const TUS_VERSION: &str = "1.0.0";
+
+const TUS_EXTENSIONS: &str = "creation,termination,expiration";
+
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+/** A `PATCH` which sends nothing for this long is ended, so it does not hold the upload. */
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/** Abandoned uploads are looked for at most this often. */
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/** Called once an upload has received all of its bytes. */
+pub type CompletionHook = Box<dyn Fn(&TusUpload) + Send + Sync>;
+
+/**
+    Middleware which accepts resumable uploads with the tus 1.0 protocol, with the
+    creation, termination and expiration extensions. A client creates an upload, then
+    sends it in as many `PATCH` requests as it needs, asking for the offset with `HEAD`
+    after a connection drops.
+
+    POST   /files             Upload-Length: 1048576, creates /files/{id}
+    HEAD   /files/{id}        responds with the Upload-Offset received so far
+    PATCH  /files/{id}        Upload-Offset: 524288, appends the body
+    DELETE /files/{id}        removes the upload
+
+    Each upload is a data file and a `.info` file in the upload directory, the offset is
+    the size of the data file so it survives a restart. Uploads which have not received
+    any bytes for the expiry time are removed.
+
+    let tus = Tus::new("./src/data/tus")
+        .mount("/uploads")
+        .on_complete(|upload| info!("received {:?}", upload.metadata.get("filename")));
+    server.middleware(tus);
+*/
+pub struct Tus {
+    prefix: String,
+    dir: PathBuf,
+    max_size: u64,
+    expire_after: Duration,
+    on_complete: Option<CompletionHook>,
+    locks: Mutex<HashSet<String>>,
+    last_sweep: Mutex<Option<Instant>>,
+}
+
+/** An upload as stored on disk, which is passed to the completion hook. */
+#[derive(Debug)]
+pub struct TusUpload {
+    pub id: String,
+    pub length: u64,
+    pub offset: u64,
+    /** The decoded `Upload-Metadata`, such as the `filename` and `filetype`. */
+    pub metadata: Params,
+    raw_metadata: String,
+    path: PathBuf,
+}
+
+/** Releases the lock on an upload, which stops two `PATCH` requests writing at once. */
+struct UploadLock<'a> {
+    locks: &'a Mutex<HashSet<String>>,
+    id: String,
+}
+
+impl Tus {
+    /**
+        Store uploads in a directory and mount the endpoints at `/files`, uploads may be
+        up to 1 GiB and expire after 24 hours without any bytes being received.
+    */
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Tus {
+            prefix: "/files".to_string(),
+            dir: dir.as_ref().to_path_buf(),
+            max_size: 1 << 30,
+            expire_after: Duration::from_secs(24 * 60 * 60),
+            on_complete: None,
+            locks: Mutex::new(HashSet::new()),
+            last_sweep: Mutex::new(None),
+        }
+    }
+
+    /**
+        Create from the `[tus]` section of a config file, the size may end in K, M or G
+        and the expiry is in seconds.
+
+        [tus]
+        path = /files
+        dir = ./src/data/tus
+        max_size = 2G
+        expire_after = 86400
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let mut tus = Tus::new(config.get("tus.dir").unwrap_or("./src/data/tus"));
+        if let Some(path) = config.get("tus.path") {
+            if !path.starts_with('/') {
+                return Err(ServerError::error(&format!(
+                    "tus: path must start with '/': {:?}",
+                    path
+                )));
+            }
+            tus = tus.mount(path);
+        }
+        if let Some(size) = config.get("tus.max_size") {
+            let size = parse_size(size)
+                .ok_or_else(|| ServerError::error(&format!("tus: invalid max_size {:?}", size)))?;
+            tus = tus.max_size(size);
+        }
+        if let Some(seconds) = config.get_u64("tus.expire_after") {
+            tus = tus.expire_after(Duration::from_secs(seconds));
+        }
+        Ok(tus)
+    }
+
+    /** Mount the endpoints under a different path, defaults to `/files`. */
+    pub fn mount(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /** The largest `Upload-Length` which is accepted. */
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /** Remove uploads which have not received any bytes for this long. */
+    pub fn expire_after(mut self, expire_after: Duration) -> Self {
+        self.expire_after = expire_after;
+        self
+    }
+
+    /**
+        Call a hook when an upload is complete, before the final `PATCH` is answered. The
+        hook may move the data file at `TusUpload::path`, after which the upload is gone.
+    */
+    pub fn on_complete<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&TusUpload) + Send + Sync + 'static,
+    {
+        self.on_complete = Some(Box::new(hook));
+        self
+    }
+
+    fn options(&self) -> HttpResponse {
+        let mut response = empty_response(HttpStatus::NoContent);
+        response.set_header("Tus-Version", TUS_VERSION);
+        response.set_header("Tus-Extension", TUS_EXTENSIONS);
+        response.set_header("Tus-Max-Size", &self.max_size.to_string());
+        response
+    }
+
+    fn create(&self, request: &HttpRequest) -> HttpResponse {
+        self.sweep();
+        let length = match request
+            .headers
+            .get("Upload-Length")
+            .map(|length| length.parse::<u64>())
+        {
+            Some(Ok(length)) => length,
+            _ => return error(HttpStatus::BadRequest, "missing or invalid Upload-Length"),
+        };
+        if length > self.max_size {
+            return error(
+                payload_too_large(),
+                "Upload-Length is larger than Tus-Max-Size",
+            );
+        }
+        let raw_metadata = request
+            .headers
+            .get("Upload-Metadata")
+            .map(|metadata| metadata.to_string())
+            .unwrap_or_default();
+        let metadata = match parse_metadata(&raw_metadata) {
+            Some(metadata) => metadata,
+            None => return error(HttpStatus::BadRequest, "invalid Upload-Metadata"),
+        };
+
+        let upload = match self.create_files(length, &raw_metadata) {
+            Ok(upload) => TusUpload { metadata, ..upload },
+            Err(err) => {
+                warn!("could not create upload in {}: {}", self.dir.display(), err);
+                return error(HttpStatus::InternalServerError, "could not create upload");
+            }
+        };
+        debug!("created upload {} ({} bytes)", upload.id, length);
+        if length == 0 {
+            self.complete(&upload);
+        }
+        let mut response = empty_response(HttpStatus::Created);
+        response.set_header("Location", &format!("{}/{}", self.prefix, upload.id));
+        response.set_header("Upload-Expires", &self.expires(SystemTime::now()));
+        response
+    }
+
+    /** Create the empty data file and the info file under a new random ID. */
+    fn create_files(&self, length: u64, raw_metadata: &str) -> Result<TusUpload> {
+        fs::create_dir_all(&self.dir)?;
+        loop {
+            let id = match secure_random_bytes(16) {
+                Ok(bytes) => hex_encode(&bytes),
+                Err(_) => format!(
+                    "{:016x}{:016x}",
+                    generate_random_u64(),
+                    generate_random_u64()
+                ),
+            };
+            let path = self.dir.join(&id);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => (),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+            let info = Value::object()
+                .with("length", Value::from(length))
+                .with("metadata", Value::from(raw_metadata));
+            if let Err(err) = fs::write(info_path(&path), info.to_string()) {
+                fs::remove_file(&path).ok();
+                return Err(err);
+            }
+            return Ok(TusUpload {
+                id,
+                length,
+                offset: 0,
+                metadata: Params::new(),
+                raw_metadata: raw_metadata.to_string(),
+                path,
+            });
+        }
+    }
+
+    fn head(&self, id: &str) -> HttpResponse {
+        let upload = match self.load(id) {
+            Some(upload) => upload,
+            None => return empty_response(HttpStatus::NotFound),
+        };
+        let mut response = empty_response(HttpStatus::OK);
+        response.set_header("Upload-Offset", &upload.offset.to_string());
+        response.set_header("Upload-Length", &upload.length.to_string());
+        if !upload.raw_metadata.is_empty() {
+            response.set_header("Upload-Metadata", &upload.raw_metadata);
+        }
+        if let Some(modified) = upload.modified() {
+            response.set_header("Upload-Expires", &self.expires(modified));
+        }
+        response
+    }
+
+    fn patch(&self, request: &mut HttpRequest, id: &str) -> HttpResponse {
+        if !request.has_content_type(OFFSET_OCTET_STREAM) {
+            let status = HttpStatus::Status(415, "Unsupported Media Type".to_string());
+            return error(status, &format!("expected {}", OFFSET_OCTET_STREAM));
+        }
+        let offset = match request
+            .headers
+            .get("Upload-Offset")
+            .map(|offset| offset.parse::<u64>())
+        {
+            Some(Ok(offset)) => offset,
+            _ => return error(HttpStatus::BadRequest, "missing or invalid Upload-Offset"),
+        };
+        let _lock = match self.lock(id) {
+            Some(lock) => lock,
+            None => return locked(),
+        };
+        let mut upload = match self.load(id) {
+            Some(upload) => upload,
+            None => return error(HttpStatus::NotFound, "upload not found"),
+        };
+        if offset != upload.offset {
+            let status = HttpStatus::Status(409, "Conflict".to_string());
+            let mut response = error(status, "Upload-Offset does not match");
+            response.set_header("Upload-Offset", &upload.offset.to_string());
+            return response;
+        }
+        let body_length = request
+            .headers
+            .get("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok());
+        if body_length.is_some_and(|length| offset + length > upload.length) {
+            return error(
+                payload_too_large(),
+                "body is longer than the rest of the upload",
+            );
+        }
+
+        if let Some(stream) = &request.connection {
+            stream.set_read_timeout(Some(READ_TIMEOUT)).ok();
+        }
+        let (written, result) = match request.stream_body(|body| append(&upload.path, body)) {
+            Ok(appended) => appended,
+            Err(err) => return err.to_response(),
+        };
+        upload.offset += written;
+        // a client which goes away ends the body early rather than with an error
+        let result = match result {
+            Ok(()) if body_length.is_some_and(|length| written < length) => Err(Interrupted::Read(
+                std::io::Error::from(ErrorKind::UnexpectedEof),
+            )),
+            result => result,
+        };
+
+        let mut response = match result {
+            Ok(()) => empty_response(HttpStatus::NoContent),
+            // the bytes which did arrive are kept, so the client can resume from them
+            Err(Interrupted::Read(err)) => {
+                warn!("upload {} interrupted at {}: {}", id, upload.offset, err);
+                error(HttpStatus::BadRequest, "upload interrupted")
+            }
+            Err(Interrupted::Write(err)) => {
+                warn!("could not write upload {}: {}", id, err);
+                error(HttpStatus::InternalServerError, "could not write upload")
+            }
+        };
+        response.set_header("Upload-Offset", &upload.offset.to_string());
+        response.set_header("Upload-Expires", &self.expires(SystemTime::now()));
+        if upload.offset == upload.length && written > 0 {
+            self.complete(&upload);
+        }
+        response
+    }
+
+    fn terminate(&self, id: &str) -> HttpResponse {
+        let _lock = match self.lock(id) {
+            Some(lock) => lock,
+            None => return locked(),
+        };
+        match self.load(id) {
+            Some(upload) => {
+                upload.remove();
+                debug!("terminated upload {}", id);
+                empty_response(HttpStatus::NoContent)
+            }
+            None => error(HttpStatus::NotFound, "upload not found"),
+        }
+    }
+
+    fn complete(&self, upload: &TusUpload) {
+        info!("upload {} complete ({} bytes)", upload.id, upload.length);
+        if let Some(hook) = &self.on_complete {
+            hook(upload);
+        }
+    }
+
+    /** Read an upload from disk, an expired upload is removed instead. */
+    fn load(&self, id: &str) -> Option<TusUpload> {
+        if !is_upload_id(id) {
+            return None;
+        }
+        let path = self.dir.join(id);
+        let info = json::parse(&fs::read_to_string(info_path(&path)).ok()?).ok()?;
+        // the data file is gone once the completion hook has moved it
+        let offset = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => {
+                fs::remove_file(info_path(&path)).ok();
+                return None;
+            }
+        };
+        let raw_metadata = info.get("metadata")?.as_str()?.to_string();
+        let upload = TusUpload {
+            id: id.to_string(),
+            length: info.get("length")?.as_u64()?,
+            offset,
+            metadata: parse_metadata(&raw_metadata).unwrap_or_default(),
+            raw_metadata,
+            path,
+        };
+        if upload
+            .modified()
+            .is_some_and(|modified| self.is_expired(modified))
+        {
+            debug!("upload {} expired", id);
+            upload.remove();
+            return None;
+        }
+        Some(upload)
+    }
+
+    /**
+        Remove the uploads which have expired, skipping the ones which are being written.
+        This runs when an upload is created, at most once a minute.
+    */
+    fn sweep(&self) {
+        {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if last_sweep.is_some_and(|last_sweep| last_sweep.elapsed() < SWEEP_INTERVAL) {
+                return;
+            }
+            *last_sweep = Some(Instant::now());
+        }
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let id = match name.strip_suffix(".info") {
+                Some(id) if is_upload_id(id) => id,
+                _ => continue,
+            };
+            if let Some(_lock) = self.lock(id) {
+                // loading removes the upload when it has expired
+                self.load(id);
+            }
+        }
+    }
+
+    fn lock(&self, id: &str) -> Option<UploadLock<'_>> {
+        match self.locks.lock().unwrap().insert(id.to_string()) {
+            true => Some(UploadLock {
+                locks: &self.locks,
+                id: id.to_string(),
+            }),
+            false => None,
+        }
+    }
+
+    fn is_expired(&self, modified: SystemTime) -> bool {
+        modified
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed > self.expire_after)
+    }
+
+    fn expires(&self, modified: SystemTime) -> String {
+        format_http_date(modified + self.expire_after)
+    }
+
+    /**
+        Match a path against the prefix, returns `Some(None)` for the prefix itself and
+        `Some(Some(id))` for an upload below it.
+    */
+    fn upload_path(&self, path: &str) -> Option<Option<String>> {
+        match path.strip_prefix(self.prefix.as_str())? {
+            "" | "/" => Some(None),
+            rest if rest.starts_with('/') => Some(Some(rest[1..].to_string())),
+            _ => None,
+        }
+    }
+}
+
+impl Middleware for Tus {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+        let id = match self.upload_path(request.path()) {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let method = effective_method(request);
+        let is_head = method == HttpMethod::HEAD;
+
+        let mut response = if method != HttpMethod::OPTIONS
+            && request
+                .headers
+                .get("Tus-Resumable")
+                .map(|version| version.as_str())
+                != Some(TUS_VERSION)
+        {
+            let status = HttpStatus::Status(412, "Precondition Failed".to_string());
+            let mut response = error(status, "unsupported Tus-Resumable version");
+            response.set_header("Tus-Version", TUS_VERSION);
+            response
+        } else {
+            match (method, id.as_deref()) {
+                (HttpMethod::OPTIONS, _) => self.options(),
+                (HttpMethod::POST, None) => self.create(request),
+                (HttpMethod::HEAD, Some(id)) => self.head(id),
+                (HttpMethod::PATCH, Some(id)) => self.patch(request, id),
+                (HttpMethod::DELETE, Some(id)) => self.terminate(id),
+                (_, None) => method_not_allowed("OPTIONS, POST"),
+                (_, Some(_)) => method_not_allowed("OPTIONS, HEAD, PATCH, DELETE"),
+            }
+        };
+        response.set_header("Tus-Resumable", TUS_VERSION);
+        response.set_header("Cache-Control", "no-store");
+        if is_head {
+            response.body = None;
+        }
+        request.send(response).map(Some)
+    }
+
+    fn serves(&self, request: &HttpRequest) -> Option<HttpMethod> {
+        self.upload_path(request.path())?;
+        Some(effective_method(request))
+    }
+}
+
+impl TusUpload {
+    /** The data file, which holds the bytes received so far. */
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.length
+    }
+
+    /** When the last bytes were received, or when the upload was created. */
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path)
+            .or_else(|_| fs::metadata(info_path(&self.path)))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    fn remove(&self) {
+        fs::remove_file(&self.path).ok();
+        fs::remove_file(info_path(&self.path)).ok();
+    }
+}
+
+impl Drop for UploadLock<'_> {
+    fn drop(&mut self) {
+        self.locks.lock().unwrap().remove(&self.id);
+    }
+}
+
+/** Why a `PATCH` body was not appended completely. */
+enum Interrupted {
+    Read(std::io::Error),
+    Write(std::io::Error),
+}
+
+/**
+    Append a body to the data file as it arrives, returns how many bytes were written
+    even when the body ends early.
+*/
+fn append(path: &Path, body: &mut dyn Read) -> (u64, std::result::Result<(), Interrupted>) {
+    let mut file = match OpenOptions::new().append(true).open(path) {
+        Ok(file) => file,
+        Err(err) => return (0, Err(Interrupted::Write(err))),
+    };
+    let mut written = 0;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let bytes = match body.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return (written, Err(Interrupted::Read(err))),
+        };
+        if let Err(err) = file.write_all(&chunk[..bytes]) {
+            return (written, Err(Interrupted::Write(err)));
+        }
+        written += bytes as u64;
+    }
+    (written, file.sync_data().map_err(Interrupted::Write))
+}
+
+/**
+    Decode `Upload-Metadata`, which is a comma separated list of keys each followed by an
+    optional base64 value, e.g. `filename d29ybGQuanBn,is_confidential`.
+*/
+fn parse_metadata(metadata: &str) -> Option<Params> {
+    let mut params = Params::new();
+    for pair in metadata
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => (key, base64_decode(value.trim()).ok()?),
+            None => (pair, Vec::new()),
+        };
+        params.append(key, &String::from_utf8_lossy(&value));
+    }
+    Some(params)
+}
+
+/**
+    The method a request is handled as, a `POST` may override it with
+    `X-HTTP-Method-Override` for clients which can only send GET and POST.
+*/
+fn effective_method(request: &HttpRequest) -> HttpMethod {
+    match request.headers.get("X-HTTP-Method-Override") {
+        Some(method) if request.headers.method == HttpMethod::POST => {
+            HttpHeaders::method_from_string(method.trim())
+        }
+        _ => request.headers.method.clone(),
+    }
+}
+
+/** IDs are 32 hex characters, so one from a URL can not point outside the directory. */
+fn is_upload_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn info_path(path: &Path) -> PathBuf {
+    path.with_extension("info")
+}
+
+fn empty_response(status: HttpStatus) -> HttpResponse {
+    let mut response = HttpResponse::new();
+    response.set_status(status);
+    response.set_header("Content-Length", "0");
+    response
+}
+
+fn error(status: HttpStatus, message: &str) -> HttpResponse {
+    HttpResponse::json_error(status, message)
+}
+
+fn method_not_allowed(allow: &str) -> HttpResponse {
+    let mut response = error(HttpStatus::MethodNotAllowed, "method not allowed");
+    response.set_header("Allow", allow);
+    response
+}
+
+/** Another request is writing to or removing the upload. */
+fn locked() -> HttpResponse {
+    error(
+        HttpStatus::Status(423, "Locked".to_string()),
+        "upload is locked",
+    )
+}
+
+fn payload_too_large() -> HttpStatus {
+    HttpStatus::Status(413, "Payload Too Large".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::sync::Arc;
+
+    fn upload_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tus_{}_{}", name, std::process::id()))
+    }
+
+    fn raw(method: &str, path: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut raw = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nTus-Resumable: 1.0.0\r\n{}\r\n",
+            method, path, headers
+        )
+        .into_bytes();
+        raw.extend_from_slice(body);
+        raw
+    }
+
+    /** Send a raw request through the middleware and read back what it responded with. */
+    fn exchange(tus: &Tus, raw: &[u8]) -> HttpResponse {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(raw).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut request = HttpRequest::new(Arc::new(listener.accept().unwrap().0));
+        assert!(tus.before(&mut request).unwrap().is_some());
+        drop(request);
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).unwrap();
+        HttpResponse::parse(&data).unwrap()
+    }
+
+    fn header(response: &HttpResponse, name: &str) -> String {
+        response.headers.get(name).cloned().unwrap_or_default()
+    }
+
+    fn create(tus: &Tus, headers: &str) -> String {
+        let response = exchange(tus, &raw("POST", "/files", headers, b""));
+        assert_eq!(response.status.code(), 201);
+        header(&response, "Location")
+    }
+
+    fn patch(tus: &Tus, location: &str, offset: u64, body: &[u8]) -> HttpResponse {
+        let headers = format!(
+            "Content-Type: application/offset+octet-stream\r\nUpload-Offset: {}\r\nContent-Length: {}\r\n",
+            offset,
+            body.len()
+        );
+        exchange(tus, &raw("PATCH", location, &headers, body))
+    }
+
+    #[test]
+    fn uploads_in_several_patches() {
+        let dir = upload_dir("patches");
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let hook = Arc::clone(&completed);
+        let tus = Tus::new(&dir).mount("/files/").on_complete(move |upload| {
+            let contents = fs::read_to_string(upload.path()).unwrap();
+            let filename = upload.metadata.get("filename").unwrap().to_string();
+            hook.lock()
+                .unwrap()
+                .push((filename, contents, upload.is_complete()));
+        });
+
+        let location = create(
+            &tus,
+            "Upload-Length: 11\r\nUpload-Metadata: filename d29ybGQuanBn,is_confidential\r\n",
+        );
+        let id = location.strip_prefix("/files/").unwrap();
+        assert!(is_upload_id(id));
+
+        let response = exchange(&tus, &raw("HEAD", &location, "", b""));
+        assert_eq!(response.status.code(), 200);
+        assert_eq!(header(&response, "Upload-Offset"), "0");
+        assert_eq!(header(&response, "Upload-Length"), "11");
+        assert_eq!(
+            header(&response, "Upload-Metadata"),
+            "filename d29ybGQuanBn,is_confidential"
+        );
+        assert_eq!(header(&response, "Cache-Control"), "no-store");
+        assert!(response.text().is_empty());
+
+        let response = patch(&tus, &location, 0, b"hello");
+        assert_eq!(response.status.code(), 204);
+        assert_eq!(header(&response, "Upload-Offset"), "5");
+        assert!(completed.lock().unwrap().is_empty());
+
+        let response = patch(&tus, &location, 0, b"hello");
+        assert_eq!(response.status.code(), 409);
+        assert_eq!(header(&response, "Upload-Offset"), "5");
+
+        let response = patch(&tus, &location, 5, b" world");
+        assert_eq!(response.status.code(), 204);
+        assert_eq!(header(&response, "Upload-Offset"), "11");
+        assert_eq!(
+            *completed.lock().unwrap(),
+            vec![("world.jpg".to_string(), "hello world".to_string(), true)]
+        );
+
+        let response = exchange(&tus, &raw("DELETE", &location, "", b""));
+        assert_eq!(response.status.code(), 204);
+        let response = exchange(&tus, &raw("HEAD", &location, "", b""));
+        assert_eq!(response.status.code(), 404);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_bytes_of_an_interrupted_patch() {
+        let dir = upload_dir("interrupted");
+        let tus = Tus::new(&dir);
+        let location = create(&tus, "Upload-Length: 10\r\n");
+        let headers = "Content-Type: application/offset+octet-stream\r\nUpload-Offset: 0\r\nContent-Length: 10\r\n";
+        let response = exchange(&tus, &raw("PATCH", &location, headers, b"half"));
+        assert_eq!(response.status.code(), 400);
+        assert_eq!(header(&response, "Upload-Offset"), "4");
+
+        let response = exchange(&tus, &raw("HEAD", &location, "", b""));
+        assert_eq!(header(&response, "Upload-Offset"), "4");
+        assert_eq!(patch(&tus, &location, 4, b"-resumed").status.code(), 413);
+        assert_eq!(patch(&tus, &location, 4, b"resume").status.code(), 204);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let dir = upload_dir("invalid");
+        let tus = Tus::new(&dir).max_size(100);
+        let status = |raw: Vec<u8>| exchange(&tus, &raw).status.code();
+        assert_eq!(status(raw("POST", "/files", "", b"")), 400);
+        assert_eq!(
+            status(raw("POST", "/files", "Upload-Length: 101\r\n", b"")),
+            413
+        );
+        let metadata = "Upload-Length: 1\r\nUpload-Metadata: filename !!!\r\n";
+        assert_eq!(status(raw("POST", "/files", metadata, b"")), 400);
+
+        let location = create(&tus, "Upload-Length: 5\r\n");
+        let json = "Content-Type: application/json\r\nUpload-Offset: 0\r\nContent-Length: 2\r\n";
+        assert_eq!(status(raw("PATCH", &location, json, b"{}")), 415);
+        let no_offset = "Content-Type: application/offset+octet-stream\r\nContent-Length: 1\r\n";
+        assert_eq!(status(raw("PATCH", &location, no_offset, b"a")), 400);
+        assert_eq!(
+            patch(&tus, "/files/0123456789abcdef0123456789abcdef", 0, b"a")
+                .status
+                .code(),
+            404
+        );
+        assert_eq!(status(raw("HEAD", "/files/secret.info", "", b"")), 404);
+
+        let response = exchange(&tus, &raw("GET", &location, "", b""));
+        assert_eq!(response.status.code(), 405);
+        assert_eq!(header(&response, "Allow"), "OPTIONS, HEAD, PATCH, DELETE");
+        let response = exchange(&tus, &raw("PUT", "/files", "", b""));
+        assert_eq!(header(&response, "Allow"), "OPTIONS, POST");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn requires_the_protocol_version() {
+        let tus = Tus::new(upload_dir("version")).max_size(1024);
+        let response = exchange(
+            &tus,
+            b"POST /files HTTP/1.1\r\nHost: localhost\r\nUpload-Length: 1\r\n\r\n",
+        );
+        assert_eq!(response.status.code(), 412);
+        assert_eq!(header(&response, "Tus-Version"), "1.0.0");
+
+        let response = exchange(&tus, b"OPTIONS /files HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(response.status.code(), 204);
+        assert_eq!(header(&response, "Tus-Extension"), TUS_EXTENSIONS);
+        assert_eq!(header(&response, "Tus-Max-Size"), "1024");
+        assert_eq!(header(&response, "Tus-Resumable"), "1.0.0");
+    }
+
+    #[test]
+    fn locks_an_upload_while_it_is_written() {
+        let dir = upload_dir("locked");
+        let tus = Tus::new(&dir);
+        let location = create(&tus, "Upload-Length: 5\r\n");
+        let id = location.rsplit('/').next().unwrap();
+        let lock = tus.lock(id).unwrap();
+        assert!(tus.lock(id).is_none());
+        assert_eq!(patch(&tus, &location, 0, b"hello").status.code(), 423);
+        assert_eq!(
+            exchange(&tus, &raw("DELETE", &location, "", b""))
+                .status
+                .code(),
+            423
+        );
+        drop(lock);
+        assert_eq!(patch(&tus, &location, 0, b"hello").status.code(), 204);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn completes_an_empty_upload_when_it_is_created() {
+        let dir = upload_dir("empty");
+        let completed = Arc::new(Mutex::new(0));
+        let hook = Arc::clone(&completed);
+        let tus = Tus::new(&dir).on_complete(move |_| *hook.lock().unwrap() += 1);
+        create(&tus, "Upload-Length: 0\r\n");
+        assert_eq!(*completed.lock().unwrap(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removes_expired_uploads() {
+        let dir = upload_dir("expired");
+        let tus = Tus::new(&dir).expire_after(Duration::ZERO);
+        let location = create(&tus, "Upload-Length: 5\r\n");
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            exchange(&tus, &raw("HEAD", &location, "", b""))
+                .status
+                .code(),
+            404
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn overrides_the_method_of_a_post() {
+        let dir = upload_dir("override");
+        let tus = Tus::new(&dir);
+        let location = create(&tus, "Upload-Length: 5\r\n");
+        let override_delete = "X-HTTP-Method-Override: DELETE\r\n";
+        let response = exchange(&tus, &raw("POST", &location, override_delete, b""));
+        assert_eq!(response.status.code(), 204);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_other_paths() {
+        let tus = Tus::new(upload_dir("other"));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET /filesystem HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut request = HttpRequest::new(Arc::new(listener.accept().unwrap().0));
+        assert!(tus.before(&mut request).unwrap().is_none());
+    }
+
+    #[test]
+    fn parses_upload_metadata() {
+        let metadata = parse_metadata("filename d29ybGQuanBn, is_confidential ,,").unwrap();
+        assert_eq!(metadata.get("filename"), Some("world.jpg"));
+        assert_eq!(metadata.get("is_confidential"), Some(""));
+        assert_eq!(metadata.len(), 2);
+        assert!(parse_metadata("").unwrap().is_empty());
+        assert!(parse_metadata("filename not-base64!").is_none());
+    }
+
+    #[test]
+    fn accepts_only_hex_upload_ids() {
+        assert!(is_upload_id("0123456789abcdefABCDEF0123456789"));
+        assert!(!is_upload_id("0123456789abcdef0123456789abcde"));
+        assert!(!is_upload_id("../../../../../../../etc/passwd.."));
+        assert!(!is_upload_id("0123456789abcdef0123456789abcdeg"));
+    }
+
+    #[test]
+    fn reads_the_tus_config() {
+        let config = ConfigFile::parse(
+            "[tus]\npath = /uploads/\ndir = /tmp/tus\nmax_size = 2G\nexpire_after = 60\n",
+        );
+        let tus = Tus::from_config(&config).unwrap();
+        assert_eq!(tus.prefix, "/uploads");
+        assert_eq!(tus.dir, PathBuf::from("/tmp/tus"));
+        assert_eq!(tus.max_size, 2 << 30);
+        assert_eq!(tus.expire_after, Duration::from_secs(60));
+
+        assert!(Tus::from_config(&ConfigFile::parse("[tus]\npath = uploads\n")).is_err());
+        assert!(Tus::from_config(&ConfigFile::parse("[tus]\nmax_size = big\n")).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use crate::core::http3::Http3Connection;
 use crate::core::util::rand;
+use crate::trace;
 
 use std::collections::HashMap;
 use std::hash::RandomState;
@@ -158,7 +159,7 @@ impl Connection {
 
     fn send_handshake_packet(&self, packet: &HandshakePacket) -> Result<(), QuicError> {
         // In a real implementation, this would send the packet over the network
-        println!("Sending handshake packet: {:?}", packet);
+        trace!("Sending handshake packet: {:?}", packet);
         Ok(())
     }
 
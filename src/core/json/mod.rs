@@ -0,0 +1,7 @@
+pub mod number;
+pub mod parser;
+pub mod value;
+
+pub use self::number::Number;
+pub use self::parser::{from_reader, parse, parse_with, JsonError, Limits};
+pub use self::value::Value;
@@ -0,0 +1,150 @@
+use std::fmt;
+
+/**
+    A JSON number, kept as the text it was parsed from so that integers larger than an
+    `f64` can represent exactly, such as IDs from other services, are not rounded. It is
+    converted when read with `as_i64`, `as_u64` or `as_f64`.
+*/
+#[derive(Clone, Debug)]
+pub struct Number {
+    text: String,
+}
+
+impl Number {
+    /**
+        Create from the text of a number which has already been validated against the
+        JSON grammar.
+    */
+    pub(crate) fn from_text(text: String) -> Self {
+        Number { text }
+    }
+
+    /** Returns None for NaN and infinities, which JSON can not represent. */
+    pub fn from_f64(number: f64) -> Option<Self> {
+        match number.is_finite() {
+            true => Some(Number::from_text(number.to_string())),
+            false => None,
+        }
+    }
+
+    /** The number as it is written in JSON. */
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /** Check if the number was written without a fraction or exponent. */
+    pub fn is_integer(&self) -> bool {
+        !self.text.contains(['.', 'e', 'E'])
+    }
+
+    /**
+        Get the number as an integer, if it fits exactly. A number with a fractional part
+        of zero such as `3.0` or `1e3` is also accepted.
+    */
+    pub fn as_i64(&self) -> Option<i64> {
+        if self.is_integer() {
+            return self.text.parse().ok();
+        }
+        let number = self.as_f64();
+        match number.fract() == 0.0 && number.abs() < 9_007_199_254_740_992.0 {
+            true => Some(number as i64),
+            false => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.is_integer() {
+            true => self.text.parse().ok(),
+            false => self.as_i64().and_then(|number| u64::try_from(number).ok()),
+        }
+    }
+
+    /** Get the number as a float, which may round it. */
+    pub fn as_f64(&self) -> f64 {
+        self.text.parse().unwrap_or(f64::NAN)
+    }
+}
+
+/**
+    Numbers are equal if they have the same value, so `1`, `1.0` and `1e0` are equal.
+*/
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.is_integer(), other.is_integer()) {
+            (true, true) => match (self.text.parse::<i128>(), other.text.parse::<i128>()) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => self.text == other.text,
+            },
+            _ => self.as_f64() == other.as_f64(),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl From<i64> for Number {
+    fn from(number: i64) -> Self {
+        Number::from_text(number.to_string())
+    }
+}
+
+impl From<u64> for Number {
+    fn from(number: u64) -> Self {
+        Number::from_text(number.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(text: &str) -> Number {
+        Number::from_text(text.to_string())
+    }
+
+    #[test]
+    fn keeps_large_integers_exact() {
+        let id = number("12345678901234567891");
+        assert_eq!(id.to_string(), "12345678901234567891");
+        assert_eq!(id.as_u64(), Some(12_345_678_901_234_567_891));
+        assert_eq!(id.as_i64(), None);
+        assert_eq!(
+            number("9007199254740993").as_i64(),
+            Some(9_007_199_254_740_993)
+        );
+        assert_eq!(number("-9223372036854775808").as_i64(), Some(i64::MIN));
+    }
+
+    #[test]
+    fn converts_whole_floats_to_integers() {
+        assert_eq!(number("3.0").as_i64(), Some(3));
+        assert_eq!(number("1e3").as_u64(), Some(1000));
+        assert_eq!(number("-2E0").as_i64(), Some(-2));
+        assert_eq!(number("-2E0").as_u64(), None);
+        assert_eq!(number("2.5").as_i64(), None);
+        assert_eq!(number("1e300").as_i64(), None);
+        assert_eq!(number("2.5").as_f64(), 2.5);
+    }
+
+    #[test]
+    fn compares_by_value() {
+        assert_eq!(number("1"), number("1.0"));
+        assert_eq!(number("1"), number("1e0"));
+        assert_eq!(number("-0"), number("0"));
+        assert_ne!(number("9007199254740993"), number("9007199254740992"));
+        assert_ne!(number("1"), number("2"));
+    }
+
+    #[test]
+    fn refuses_numbers_json_can_not_represent() {
+        assert_eq!(Number::from_f64(f64::NAN), None);
+        assert_eq!(Number::from_f64(f64::INFINITY), None);
+        assert_eq!(Number::from_f64(0.5).unwrap().as_str(), "0.5");
+        assert!(Number::from(7u64).is_integer());
+        assert_eq!(Number::from(-7i64).as_str(), "-7");
+    }
+}
@@ -0,0 +1,600 @@
+use super::{Number, Value};
+use std::fmt;
+use std::io::{ErrorKind, Read};
+
+/** Size of the buffer which input is read into. */
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/**
+    Limits on the documents accepted by the parser, so input from a client can not use
+    unbounded memory or overflow the stack.
+
+    let limits = Limits::new().max_depth(32).max_size(64 * 1024);
+    let value = json::parse_with(&body, &limits)?;
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    max_depth: usize,
+    max_size: usize,
+    max_string: usize,
+}
+
+impl Limits {
+    /** A nesting depth of 128, and documents and strings up to 1 MiB. */
+    pub fn new() -> Self {
+        Limits {
+            max_depth: 128,
+            max_size: 1024 * 1024,
+            max_string: 1024 * 1024,
+        }
+    }
+
+    /** Maximum nesting of arrays and objects. */
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /** Maximum size of the document in bytes, including whitespace. */
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /** Maximum length of a single string or key in bytes, after unescaping. */
+    pub fn max_string(mut self, bytes: usize) -> Self {
+        self.max_string = bytes;
+        self
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits::new()
+    }
+}
+
+/**
+    An error while parsing JSON, with the byte offset and the line and column (counted in
+    characters, both starting at 1) where it occurred.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonError {
+    pub message: String,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {} column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/**
+    Parse a JSON document (RFC 8259) into a `Value`, with the default `Limits`.
+*/
+pub fn parse(input: &str) -> Result<Value, JsonError> {
+    parse_with(input, &Limits::new())
+}
+
+pub fn parse_with(input: &str, limits: &Limits) -> Result<Value, JsonError> {
+    from_reader(input.as_bytes(), limits)
+}
+
+/**
+    Parse a JSON document as it is read, e.g. from a file or socket. Reading stops with an
+    error as soon as the document goes over the limits, so it is never held in memory as
+    a whole, and the input must not continue after the document.
+
+    let value = json::from_reader(File::open("users.json")?, &Limits::new())?;
+*/
+pub fn from_reader<R: Read>(reader: R, limits: &Limits) -> Result<Value, JsonError> {
+    let mut parser = Parser {
+        reader,
+        limits,
+        buffer: vec![0; BUFFER_SIZE],
+        start: 0,
+        end: 0,
+        offset: 0,
+        line: 1,
+        column: 1,
+        depth: 0,
+    };
+    parser.skip_whitespace()?;
+    let value = parser.parse_value()?;
+    parser.skip_whitespace()?;
+    if parser.peek()?.is_some() {
+        return Err(parser.error("unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a, R> {
+    reader: R,
+    limits: &'a Limits,
+    buffer: Vec<u8>,
+    /** The unread bytes are `buffer[start..end]`. */
+    start: usize,
+    end: usize,
+    offset: usize,
+    line: usize,
+    column: usize,
+    depth: usize,
+}
+
+impl<R: Read> Parser<'_, R> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError {
+            message: message.to_string(),
+            offset: self.offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /** Look at the next byte without consuming it, reading more input when needed. */
+    fn peek(&mut self) -> Result<Option<u8>, JsonError> {
+        if self.start == self.end {
+            if self.offset >= self.limits.max_size {
+                // a document which ends exactly at the limit is still allowed
+                return match self.reader.read(&mut [0u8; 1]) {
+                    Ok(0) => Ok(None),
+                    Ok(_) => Err(self.error(&format!(
+                        "document is larger than {} bytes",
+                        self.limits.max_size
+                    ))),
+                    Err(err) => Err(self.error(&format!("read error: {}", err))),
+                };
+            }
+            let wanted = BUFFER_SIZE.min(self.limits.max_size - self.offset);
+            loop {
+                match self.reader.read(&mut self.buffer[..wanted]) {
+                    Ok(read) => {
+                        self.start = 0;
+                        self.end = read;
+                        break;
+                    }
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(self.error(&format!("read error: {}", err))),
+                }
+            }
+            if self.end == 0 {
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.buffer[self.start]))
+    }
+
+    /** Consume the byte returned by `peek`, keeping track of the line and column. */
+    fn bump(&mut self) {
+        let byte = self.buffer[self.start];
+        self.start += 1;
+        self.offset += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if byte & 0xC0 != 0x80 {
+            // continuation bytes are part of the same character
+            self.column += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<u8>, JsonError> {
+        let byte = self.peek()?;
+        if byte.is_some() {
+            self.bump();
+        }
+        Ok(byte)
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), JsonError> {
+        while matches!(self.peek()?, Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.bump();
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, literal: &str, value: Value) -> Result<Value, JsonError> {
+        for expected in literal.bytes() {
+            match self.peek()? {
+                Some(byte) if byte == expected => self.bump(),
+                _ => return Err(self.error("invalid literal")),
+            }
+        }
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<Value, JsonError> {
+        match self.peek()? {
+            Some(b'{') => self.nested(Self::parse_object),
+            Some(b'[') => self.nested(Self::parse_array),
+            Some(b'"') => self.parse_string().map(Value::String),
+            Some(b't') => self.expect("true", Value::Bool(true)),
+            Some(b'f') => self.expect("false", Value::Bool(false)),
+            Some(b'n') => self.expect("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    /** Parse an array or object one level deeper, within the depth limit. */
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Value, JsonError>,
+    ) -> Result<Value, JsonError> {
+        if self.depth >= self.limits.max_depth {
+            return Err(self.error("maximum nesting depth exceeded"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_object(&mut self) -> Result<Value, JsonError> {
+        self.bump();
+        let mut entries = Vec::new();
+        self.skip_whitespace()?;
+        if self.peek()? == Some(b'}') {
+            self.bump();
+            return Ok(Value::Object(entries));
+        }
+        loop {
+            self.skip_whitespace()?;
+            if self.peek()? != Some(b'"') {
+                return Err(self.error("expected string key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace()?;
+            if self.peek()? != Some(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            self.bump();
+            self.skip_whitespace()?;
+            let value = self.parse_value()?;
+            entries.push((key, value));
+            self.skip_whitespace()?;
+            match self.peek()? {
+                Some(b',') => self.bump(),
+                Some(b'}') => {
+                    self.bump();
+                    return Ok(Value::Object(entries));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, JsonError> {
+        self.bump();
+        let mut array = Vec::new();
+        self.skip_whitespace()?;
+        if self.peek()? == Some(b']') {
+            self.bump();
+            return Ok(Value::Array(array));
+        }
+        loop {
+            self.skip_whitespace()?;
+            array.push(self.parse_value()?);
+            self.skip_whitespace()?;
+            match self.peek()? {
+                Some(b',') => self.bump(),
+                Some(b']') => {
+                    self.bump();
+                    return Ok(Value::Array(array));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    /** Parse a number, keeping its text so that it is not rounded. */
+    fn parse_number(&mut self) -> Result<Value, JsonError> {
+        let mut text = String::new();
+        if self.peek()? == Some(b'-') {
+            self.push_byte(&mut text);
+        }
+        match self.peek()? {
+            Some(b'0') => self.push_byte(&mut text),
+            Some(b'1'..=b'9') => self.push_digits(&mut text)?,
+            _ => return Err(self.error("invalid number")),
+        }
+        if self.peek()? == Some(b'.') {
+            self.push_byte(&mut text);
+            if !matches!(self.peek()?, Some(b'0'..=b'9')) {
+                return Err(self.error("invalid number"));
+            }
+            self.push_digits(&mut text)?;
+        }
+        if matches!(self.peek()?, Some(b'e' | b'E')) {
+            self.push_byte(&mut text);
+            if matches!(self.peek()?, Some(b'+' | b'-')) {
+                self.push_byte(&mut text);
+            }
+            if !matches!(self.peek()?, Some(b'0'..=b'9')) {
+                return Err(self.error("invalid number"));
+            }
+            self.push_digits(&mut text)?;
+        }
+        if text.len() > self.limits.max_string {
+            return Err(self.error("number is too long"));
+        }
+        Ok(Value::Number(Number::from_text(text)))
+    }
+
+    fn push_byte(&mut self, text: &mut String) {
+        text.push(self.buffer[self.start] as char);
+        self.bump();
+    }
+
+    fn push_digits(&mut self, text: &mut String) -> Result<(), JsonError> {
+        while matches!(self.peek()?, Some(b'0'..=b'9')) {
+            self.push_byte(text);
+        }
+        Ok(())
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.bump();
+        let mut output = Vec::new();
+        loop {
+            if output.len() > self.limits.max_string {
+                return Err(self.error(&format!(
+                    "string is longer than {} bytes",
+                    self.limits.max_string
+                )));
+            }
+            match self.peek()? {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.bump();
+                    break;
+                }
+                Some(b'\\') => {
+                    self.bump();
+                    let escaped = self.parse_escape()?;
+                    let mut buffer = [0u8; 4];
+                    output.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(byte) if byte < 0x20 => return Err(self.error("control character in string")),
+                Some(byte) => {
+                    output.push(byte);
+                    self.bump();
+                }
+            }
+        }
+        String::from_utf8(output).map_err(|_| self.error("invalid utf-8 in string"))
+    }
+
+    /**
+        Parse the character after a backslash in a string, the input is left after the
+        escape sequence.
+    */
+    fn parse_escape(&mut self) -> Result<char, JsonError> {
+        let escaped = match self.peek()? {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.bump();
+                return self.parse_unicode_escape();
+            }
+            _ => return Err(self.error("invalid escape")),
+        };
+        self.bump();
+        Ok(escaped)
+    }
+
+    /**
+        Parse the four hex digits of a `\u` escape, combining surrogate pairs into a
+        single character.
+    */
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.parse_hex4()?;
+        if (0xDC00..0xE000).contains(&high) {
+            return Err(self.error("unexpected low surrogate"));
+        }
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("invalid unicode escape"));
+        }
+        if self.next()? != Some(b'\\') || self.next()? != Some(b'u') {
+            return Err(self.error("unpaired surrogate"));
+        }
+        let low = self.parse_hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.error("invalid low surrogate"));
+        }
+        let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = match self.peek()? {
+                Some(byte) => (byte as char).to_digit(16),
+                None => None,
+            };
+            match digit {
+                Some(digit) => {
+                    code = code * 16 + digit;
+                    self.bump();
+                }
+                None => return Err(self.error("invalid unicode escape")),
+            }
+        }
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** A reader which returns a few bytes at a time, to split tokens across reads. */
+    struct Trickle<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            let count = self.chunk.min(buffer.len()).min(self.data.len());
+            buffer[..count].copy_from_slice(&self.data[..count]);
+            self.data = &self.data[count..];
+            Ok(count)
+        }
+    }
+
+    fn error(input: &str) -> JsonError {
+        parse(input).unwrap_err()
+    }
+
+    #[test]
+    fn parses_every_kind_of_value() {
+        let value =
+            parse(r#" {"a": [1, -2.5e3, true, false, null], "b": {"c": "d"}, "": {}} "#).unwrap();
+        let array = value.get("a").and_then(Value::as_array).unwrap();
+        assert_eq!(array[0].as_i64(), Some(1));
+        assert_eq!(array[1].as_f64(), Some(-2500.0));
+        assert_eq!(array[2].as_bool(), Some(true));
+        assert!(array[4].is_null());
+        assert_eq!(
+            value
+                .get("b")
+                .and_then(|b| b.get("c"))
+                .and_then(Value::as_str),
+            Some("d")
+        );
+        assert_eq!(value.get(""), Some(&Value::object()));
+        assert_eq!(parse("[]").unwrap(), Value::Array(Vec::new()));
+        assert_eq!(parse("\"x\"").unwrap(), Value::from("x"));
+    }
+
+    #[test]
+    fn keeps_the_text_of_numbers() {
+        let value = parse("[12345678901234567891, 1.50, -0, 1E+2]").unwrap();
+        assert_eq!(value.to_string(), "[12345678901234567891,1.50,-0,1E+2]");
+    }
+
+    #[test]
+    fn refuses_invalid_numbers() {
+        for input in [
+            "01", "-", "1.", ".5", "1e", "1e+", "+1", "0x10", "Infinity", "NaN",
+        ] {
+            assert!(parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn decodes_string_escapes() {
+        let value = parse(r#""\"\\\/\b\f\n\r\té😀""#).unwrap();
+        assert_eq!(value.as_str(), Some("\"\\/\u{8}\u{c}\n\r\t\u{e9}\u{1f600}"));
+        assert_eq!(parse("\"caf\u{e9}\"").unwrap().as_str(), Some("caf\u{e9}"));
+    }
+
+    #[test]
+    fn refuses_invalid_strings() {
+        assert_eq!(error(r#""\ud83d""#).message, "unpaired surrogate");
+        assert_eq!(error(r#""\ude00""#).message, "unexpected low surrogate");
+        assert_eq!(error(r#""\ud83dA""#).message, "unpaired surrogate");
+        assert_eq!(error(r#""\ud83d\u0041""#).message, "invalid low surrogate");
+        assert_eq!(error(r#""\x""#).message, "invalid escape");
+        assert_eq!(error(r#""\u12g4""#).message, "invalid unicode escape");
+        assert_eq!(error("\"a\nb\"").message, "control character in string");
+        assert_eq!(error("\"abc").message, "unterminated string");
+        let invalid_utf8 = from_reader(&b"\"\xff\""[..], &Limits::new()).unwrap_err();
+        assert_eq!(invalid_utf8.message, "invalid utf-8 in string");
+    }
+
+    #[test]
+    fn reports_where_an_error_occurred() {
+        let err = error("{\n  \"name\": \"caf\u{e9}\",\n  \"age\" 3\n}");
+        assert_eq!(err.message, "expected ':'");
+        assert_eq!((err.line, err.column), (3, 9));
+        assert_eq!(err.offset, 29);
+        assert_eq!(err.to_string(), "expected ':' at line 3 column 9");
+
+        let err = error("{\"a\": \"\u{e9}\u{e9}\" x}");
+        assert_eq!((err.line, err.column), (1, 12));
+    }
+
+    #[test]
+    fn refuses_malformed_documents() {
+        assert_eq!(error("").message, "unexpected end of input");
+        assert_eq!(error("[1, 2").message, "expected ',' or ']'");
+        assert_eq!(error("[1,]").message, "unexpected character");
+        assert_eq!(error("{\"a\":1,}").message, "expected string key");
+        assert_eq!(error("{\"a\":1 \"b\":2}").message, "expected ',' or '}'");
+        assert_eq!(error("tru").message, "invalid literal");
+        assert_eq!(error("{} {}").message, "unexpected trailing characters");
+    }
+
+    #[test]
+    fn limits_the_nesting_depth() {
+        let limits = Limits::new().max_depth(3);
+        assert!(parse_with("[[[1]]]", &limits).is_ok());
+        let err = parse_with("[[[[1]]]]", &limits).unwrap_err();
+        assert_eq!(err.message, "maximum nesting depth exceeded");
+        assert_eq!(err.offset, 3);
+
+        // deeply nested input fails cleanly instead of overflowing the stack
+        let deep = "[".repeat(100_000);
+        assert_eq!(error(&deep).message, "maximum nesting depth exceeded");
+    }
+
+    #[test]
+    fn limits_the_document_size() {
+        let limits = Limits::new().max_size(8);
+        assert!(parse_with("[1,2,3] ", &limits).is_ok());
+        let err = parse_with("[1,2,3]  ", &limits).unwrap_err();
+        assert_eq!(err.message, "document is larger than 8 bytes");
+        assert_eq!(err.offset, 8);
+    }
+
+    #[test]
+    fn limits_string_length() {
+        let limits = Limits::new().max_string(4);
+        assert!(parse_with(r#"{"abcd": "wxyz"}"#, &limits).is_ok());
+        assert!(parse_with(r#"["abcde"]"#, &limits).is_err());
+        assert!(parse_with(r#"{"abcde": 1}"#, &limits).is_err());
+        assert!(parse_with("123456", &limits).is_err());
+    }
+
+    #[test]
+    fn reads_documents_split_across_reads() {
+        let input = "{\"name\": \"caf\u{e9} \u{1f600}\", \"ids\": [12345678901234567891, 2.5e-3], \"ok\": true}";
+        let expected = parse(input).unwrap();
+        for chunk in [1, 2, 3, 7] {
+            let reader = Trickle {
+                data: input.as_bytes(),
+                chunk,
+            };
+            assert_eq!(from_reader(reader, &Limits::new()).unwrap(), expected);
+        }
+
+        let longer = " ".repeat(BUFFER_SIZE * 2) + "[\"end\"]";
+        let reader = Trickle {
+            data: longer.as_bytes(),
+            chunk: 1000,
+        };
+        assert_eq!(
+            from_reader(reader, &Limits::new()).unwrap(),
+            Value::Array(vec!["end".into()])
+        );
+    }
+}
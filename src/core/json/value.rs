@@ -0,0 +1,349 @@
+use super::Number;
+use std::fmt;
+use std::fmt::Write;
+
+/** Indent used by the pretty serializer. */
+const INDENT: &str = "  ";
+
+/**
+    A JSON value, objects keep their keys in the order they were parsed or inserted.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /** Create an empty JSON object. */
+    pub fn object() -> Self {
+        Value::Object(Vec::new())
+    }
+
+    /** Get the value for a key if this is an object. */
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /**
+        Set the value for a key if this is an object, replacing any existing value.
+    */
+    pub fn set(&mut self, key: &str, value: Value) {
+        if let Value::Object(entries) = self {
+            match entries.iter_mut().find(|(name, _)| name == key) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key.to_string(), value)),
+            }
+        }
+    }
+
+    /** Builder style version of `set` for constructing objects. */
+    pub fn with(mut self, key: &str, value: Value) -> Self {
+        self.set(key, value);
+        self
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(number.as_f64()),
+            _ => None,
+        }
+    }
+
+    /** Get the value as an integer, if it is a number without a fractional part. */
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Number(number) => number.as_i64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(number) => number.as_u64(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(boolean) => Some(*boolean),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    /** The entries of an object in order. */
+    pub fn as_object(&self) -> Option<&Vec<(String, Value)>> {
+        match self {
+            Value::Object(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /** Serialize as compact JSON on a single line, the same as `to_string`. */
+    pub fn to_compact_string(&self) -> String {
+        let mut output = String::new();
+        let _ = write_value(&mut output, self, None, 0);
+        output
+    }
+
+    /**
+        Serialize as JSON indented by two spaces, the same as formatting with `{:#}`.
+
+        {
+          "status": "pass",
+          "checks": []
+        }
+    */
+    pub fn to_pretty_string(&self) -> String {
+        let mut output = String::new();
+        let _ = write_value(&mut output, self, Some(INDENT), 0);
+        output
+    }
+}
+
+impl From<&str> for Value {
+    fn from(string: &str) -> Self {
+        Value::String(string.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(string: String) -> Self {
+        Value::String(string)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(boolean: bool) -> Self {
+        Value::Bool(boolean)
+    }
+}
+
+/** NaN and infinities become `null`, as JSON can not represent them. */
+impl From<f64> for Value {
+    fn from(number: f64) -> Self {
+        match Number::from_f64(number) {
+            Some(number) => Value::Number(number),
+            None => Value::Null,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(number: i64) -> Self {
+        Value::Number(number.into())
+    }
+}
+
+impl From<u64> for Value {
+    fn from(number: u64) -> Self {
+        Value::Number(number.into())
+    }
+}
+
+impl From<Number> for Value {
+    fn from(number: Number) -> Self {
+        Value::Number(number)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(array: Vec<Value>) -> Self {
+        Value::Array(array)
+    }
+}
+
+/**
+    Serialize the value as compact JSON, or indented with the alternate flag `{:#}`.
+*/
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let indent = match f.alternate() {
+            true => Some(INDENT),
+            false => None,
+        };
+        write_value(f, self, indent, 0)
+    }
+}
+
+/**
+    Write a value as JSON, each nested array and object is indented one more level when
+    an indent is given.
+*/
+fn write_value<W: Write>(
+    out: &mut W,
+    value: &Value,
+    indent: Option<&str>,
+    level: usize,
+) -> fmt::Result {
+    match value {
+        Value::Null => out.write_str("null"),
+        Value::Bool(boolean) => write!(out, "{}", boolean),
+        Value::Number(number) => out.write_str(number.as_str()),
+        Value::String(string) => write_string(out, string),
+        Value::Array(array) if array.is_empty() => out.write_str("[]"),
+        Value::Object(entries) if entries.is_empty() => out.write_str("{}"),
+        Value::Array(array) => {
+            out.write_char('[')?;
+            for (i, value) in array.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write_newline(out, indent, level + 1)?;
+                write_value(out, value, indent, level + 1)?;
+            }
+            write_newline(out, indent, level)?;
+            out.write_char(']')
+        }
+        Value::Object(entries) => {
+            out.write_char('{')?;
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write_newline(out, indent, level + 1)?;
+                write_string(out, key)?;
+                out.write_str(match indent {
+                    Some(_) => ": ",
+                    None => ":",
+                })?;
+                write_value(out, value, indent, level + 1)?;
+            }
+            write_newline(out, indent, level)?;
+            out.write_char('}')
+        }
+    }
+}
+
+fn write_newline<W: Write>(out: &mut W, indent: Option<&str>, level: usize) -> fmt::Result {
+    if let Some(indent) = indent {
+        out.write_char('\n')?;
+        for _ in 0..level {
+            out.write_str(indent)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+    Write a string as a quoted JSON string, escaping quotes, backslashes and control
+    characters.
+*/
+fn write_string<W: Write>(out: &mut W, string: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in string.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Value {
+        Value::object()
+            .with("status", "pass".into())
+            .with("count", 2i64.into())
+            .with(
+                "checks",
+                vec![Value::Null, Value::object().with("ok", true.into())].into(),
+            )
+            .with("empty", Value::Array(Vec::new()))
+    }
+
+    #[test]
+    fn serializes_compact_json_in_insertion_order() {
+        assert_eq!(
+            sample().to_string(),
+            r#"{"status":"pass","count":2,"checks":[null,{"ok":true}],"empty":[]}"#
+        );
+        assert_eq!(sample().to_compact_string(), sample().to_string());
+    }
+
+    #[test]
+    fn serializes_pretty_json() {
+        let expected = "{\n  \"status\": \"pass\",\n  \"count\": 2,\n  \"checks\": [\n    null,\n    {\n      \"ok\": true\n    }\n  ],\n  \"empty\": []\n}";
+        assert_eq!(sample().to_pretty_string(), expected);
+        assert_eq!(format!("{:#}", sample()), expected);
+        assert_eq!(Value::object().to_pretty_string(), "{}");
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let value = Value::from("quote \" slash \\ line\n tab\t bell\u{7} caf\u{e9}");
+        assert_eq!(
+            value.to_string(),
+            r#""quote \" slash \\ line\n tab\t bell\u0007 café""#
+        );
+    }
+
+    #[test]
+    fn serialized_values_parse_back() {
+        let value = sample().with("text", "a\u{1}\"\u{1f600}".into());
+        assert_eq!(crate::core::json::parse(&value.to_string()).unwrap(), value);
+        assert_eq!(
+            crate::core::json::parse(&value.to_pretty_string()).unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn sets_and_replaces_keys() {
+        let mut value = sample();
+        value.set("count", 3u64.into());
+        value.set("new", Value::Null);
+        assert_eq!(value.get("count").and_then(Value::as_u64), Some(3));
+        assert_eq!(value.as_object().unwrap().len(), 5);
+        assert_eq!(value.as_object().unwrap()[1].0, "count");
+
+        let mut array = Value::Array(Vec::new());
+        array.set("ignored", Value::Null);
+        assert_eq!(array, Value::Array(Vec::new()));
+        assert_eq!(array.get("ignored"), None);
+    }
+
+    #[test]
+    fn non_finite_floats_become_null() {
+        assert_eq!(Value::from(f64::NAN), Value::Null);
+        assert_eq!(Value::from(f64::NEG_INFINITY), Value::Null);
+        assert_eq!(Value::from(1.5).to_string(), "1.5");
+    }
+}
@@ -0,0 +1,164 @@
+use super::{Level, AUDIT_TARGET};
+use crate::core::error::ServerError;
+use std::io::Result;
+
+/**
+    Which records are logged for each module, in the style of `RUST_LOG`. A filter is a
+    comma separated list of a default level and `target=level` directives, and `off`
+    disables logging.
+
+    let filter = Filter::parse("warn,http_request=debug,server::core::auth=trace")?;
+
+    A target matches a module path when it is the whole path, a leading part of it such
+    as `server::core::auth`, or whole segments within it such as `http_request` or
+    `middleware::ip_filter`. When several directives match the longest one is used.
+    Audit records are always logged, whatever the filter.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    default: Option<Level>,
+    directives: Vec<(String, Option<Level>)>,
+}
+
+impl Filter {
+    /** Log records at `level` and above from every module. */
+    pub fn new(level: Level) -> Self {
+        Filter {
+            default: Some(level),
+            directives: Vec::new(),
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut filter = Filter::new(Level::Info);
+        for directive in input.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let level = parse_level(level)?;
+                    filter.directives.push((target.trim().to_string(), level));
+                }
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+
+    /** Set the level for a target, `None` turns logging off for it. */
+    pub fn target(mut self, target: &str, level: Option<Level>) -> Self {
+        self.directives.push((target.to_string(), level));
+        self
+    }
+
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        if target == AUDIT_TARGET {
+            return true;
+        }
+        let min_level = self
+            .directives
+            .iter()
+            .filter(|(directive, _)| matches_target(directive, target))
+            .max_by_key(|(directive, _)| directive.len())
+            .map_or(self.default, |(_, level)| *level);
+        min_level.is_some_and(|min_level| level >= min_level)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(Level::Info)
+    }
+}
+
+fn parse_level(level: &str) -> Result<Option<Level>> {
+    match level.trim() {
+        "off" => Ok(None),
+        level => Level::parse(level)
+            .map(Some)
+            .ok_or_else(|| ServerError::error(&format!("log: unknown level {:?}", level))),
+    }
+}
+
+/** Check if a directive names whole segments of a module path. */
+fn matches_target(directive: &str, target: &str) -> bool {
+    let mut rest = target;
+    loop {
+        if let Some(after) = rest.strip_prefix(directive) {
+            if after.is_empty() || after.starts_with("::") {
+                return true;
+            }
+        }
+        match rest.split_once("::") {
+            Some((_, next)) => rest = next,
+            None => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_the_default_level_without_directives() {
+        let filter = Filter::default();
+        assert!(filter.enabled(Level::Info, "server::core::server"));
+        assert!(filter.enabled(Level::Error, "server::core::server"));
+        assert!(!filter.enabled(Level::Debug, "server::core::server"));
+    }
+
+    #[test]
+    fn parses_levels_and_directives() {
+        let filter = Filter::parse(" warn , http_request=debug,server::core::auth=trace ").unwrap();
+        assert!(!filter.enabled(Level::Info, "server::core::server"));
+        assert!(filter.enabled(Level::Debug, "server::core::http::http_request"));
+        assert!(!filter.enabled(Level::Trace, "server::core::http::http_request"));
+        assert!(filter.enabled(Level::Trace, "server::core::auth::basic"));
+
+        assert_eq!(Filter::parse("").unwrap(), Filter::default());
+        assert!(Filter::parse("loud").is_err());
+        assert!(Filter::parse("http_request=loud").is_err());
+    }
+
+    #[test]
+    fn off_disables_logging() {
+        let filter = Filter::parse("off,server::core::auth=info").unwrap();
+        assert!(!filter.enabled(Level::Error, "server::core::server"));
+        assert!(filter.enabled(Level::Info, "server::core::auth::jwt"));
+
+        let filter = Filter::new(Level::Trace).target("stdout", None);
+        assert!(!filter.enabled(Level::Error, "server::core::stdout"));
+        assert!(filter.enabled(Level::Trace, "server::core::server"));
+    }
+
+    #[test]
+    fn audit_records_are_never_filtered() {
+        let filter = Filter::parse("off,audit=off").unwrap();
+        assert!(filter.enabled(Level::Warn, AUDIT_TARGET));
+        assert!(!filter.enabled(Level::Error, "server::core::server"));
+    }
+
+    #[test]
+    fn targets_match_whole_segments() {
+        assert!(matches_target("server", "server::core::auth"));
+        assert!(matches_target("server::core::auth", "server::core::auth"));
+        assert!(matches_target("auth", "server::core::auth::basic"));
+        assert!(matches_target(
+            "middleware::ip_filter",
+            "server::core::middleware::ip_filter"
+        ));
+        assert!(!matches_target("auth", "server::core::oauth"));
+        assert!(!matches_target("serv", "server::core"));
+        assert!(!matches_target(
+            "core::auth::basic::x",
+            "server::core::auth::basic"
+        ));
+    }
+
+    #[test]
+    fn the_longest_matching_directive_wins() {
+        let filter = Filter::parse("info,server::core=error,server::core::auth=debug").unwrap();
+        assert!(filter.enabled(Level::Debug, "server::core::auth::digest"));
+        assert!(!filter.enabled(Level::Warn, "server::core::server"));
+        assert!(filter.enabled(Level::Info, "other::module"));
+    }
+}
@@ -0,0 +1,395 @@
+pub mod filter;
+
+pub use self::filter::Filter;
+
+use crate::core::json::Value;
+use crate::core::util::DateTime;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/**
+    The target of audit records such as denied requests, which are logged at `warn` and
+    are never dropped by a filter.
+*/
+pub const AUDIT_TARGET: &str = "audit";
+
+/** The severity of a log record, from the most to the least verbose. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn parse(level: &str) -> Option<Self> {
+        match level.trim().to_ascii_lowercase().as_str() {
+            "trace" => Some(Level::Trace),
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+/** How records are written to the console. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /** `2026-10-19T09:30:12.250000000Z  INFO http_request: new request: /` */
+    Text,
+    /** One JSON object per line with `time`, `level`, `target` and `message`. */
+    Json,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.trim() {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/**
+    A single log message, the target is the module path of the code which logged it.
+*/
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub time: SystemTime,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    /** The request being handled on the thread which logged the record, if any. */
+    pub request_id: Option<String>,
+}
+
+impl Record {
+    /** The last segment of the target, e.g. `http_request`. */
+    pub fn short_target(&self) -> &str {
+        self.target.rsplit("::").next().unwrap_or_default()
+    }
+
+    /** The target and message, prefixed with the request ID e.g. `[4bf92f35] server: ...` */
+    pub fn to_short_text(&self) -> String {
+        match &self.request_id {
+            Some(id) => format!("[{}] {}: {}", id, self.short_target(), self.message),
+            None => format!("{}: {}", self.short_target(), self.message),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "{} {:>5} {}",
+            DateTime::from_system_time(self.time).to_rfc3339(),
+            self.level.as_str().to_ascii_uppercase(),
+            self.to_short_text()
+        )
+    }
+
+    pub fn to_json(&self) -> Value {
+        let json = Value::object()
+            .with(
+                "time",
+                DateTime::from_system_time(self.time).to_rfc3339().into(),
+            )
+            .with("level", self.level.as_str().into())
+            .with("target", self.target.as_str().into())
+            .with("message", self.message.as_str().into());
+        match &self.request_id {
+            Some(id) => json.with("request_id", id.as_str().into()),
+            None => json,
+        }
+    }
+
+    pub fn format(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_text(),
+            Format::Json => self.to_json().to_string(),
+        }
+    }
+}
+
+/**
+    A destination for log records in addition to the console, such as a log file or the
+    `/events` stream. Records logged while a sink is writing are only sent to the console,
+    so a sink can log its own errors without deadlocking.
+*/
+pub trait Sink: Send + Sync {
+    fn write(&self, record: &Record);
+}
+
+struct Logger {
+    filter: Option<Filter>,
+    format: Format,
+    sinks: Vec<Arc<dyn Sink>>,
+}
+
+static LOGGER: RwLock<Logger> = RwLock::new(Logger {
+    filter: None,
+    format: Format::Text,
+    sinks: Vec::new(),
+});
+
+thread_local! {
+    static IN_SINK: Cell<bool> = const { Cell::new(false) };
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/**
+    Tags the records logged on the current thread with a request ID until it is dropped,
+    when the previous ID is restored.
+
+    let _scope = log::request_scope(&request.request_id);
+*/
+pub struct RequestScope {
+    previous: Option<String>,
+}
+
+impl Drop for RequestScope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        REQUEST_ID.with(|id| *id.borrow_mut() = previous);
+    }
+}
+
+pub fn request_scope(request_id: &str) -> RequestScope {
+    let previous = REQUEST_ID.with(|id| id.borrow_mut().replace(request_id.to_string()));
+    RequestScope { previous }
+}
+
+/** The request ID of the current thread, see `request_scope`. */
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/**
+    Set which records are logged, the default is `info` for every module.
+
+    log::set_filter(Filter::parse("info,http_request=debug,server::core::auth=trace")?);
+*/
+pub fn set_filter(filter: Filter) {
+    LOGGER.write().unwrap().filter = Some(filter);
+}
+
+pub fn set_format(format: Format) {
+    LOGGER.write().unwrap().format = format;
+}
+
+/** Send every record which passes the filter to a sink as well as the console. */
+pub fn add_sink(sink: Arc<dyn Sink>) {
+    LOGGER.write().unwrap().sinks.push(sink);
+}
+
+/** Check if a record at this level from this target would be logged. */
+pub fn enabled(level: Level, target: &str) -> bool {
+    match &LOGGER.read().unwrap().filter {
+        Some(filter) => filter.enabled(level, target),
+        None => level >= Level::Info,
+    }
+}
+
+/** Log a record, this is usually called through the `info!` and related macros. */
+pub fn log(level: Level, target: &str, message: fmt::Arguments) {
+    if !enabled(level, target) {
+        return;
+    }
+    let record = Record {
+        time: SystemTime::now(),
+        level,
+        target: target.to_string(),
+        message: message.to_string(),
+        request_id: request_id(),
+    };
+    let (format, sinks) = {
+        let logger = LOGGER.read().unwrap();
+        (logger.format, logger.sinks.clone())
+    };
+
+    let line = record.format(format);
+    let result = match level {
+        Level::Warn | Level::Error => writeln!(std::io::stderr().lock(), "{}", line),
+        _ => writeln!(std::io::stdout().lock(), "{}", line),
+    };
+    // there is nowhere left to report a console which can not be written to
+    drop(result);
+
+    if IN_SINK.with(|in_sink| in_sink.replace(true)) {
+        return;
+    }
+    for sink in sinks.iter() {
+        sink.write(&record);
+    }
+    IN_SINK.with(|in_sink| in_sink.set(false));
+}
+
+/** Log an audit record, see `AUDIT_TARGET`. */
+pub fn audit(message: fmt::Arguments) {
+    log(Level::Warn, AUDIT_TARGET, message);
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        $crate::core::log::log($crate::core::log::Level::Trace, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        $crate::core::log::log($crate::core::log::Level::Debug, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => {
+        $crate::core::log::log($crate::core::log::Level::Info, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        $crate::core::log::log($crate::core::log::Level::Warn, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => {
+        $crate::core::log::log($crate::core::log::Level::Error, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /** Only records from this module are collected, since sinks are global. */
+    const TARGET: &str = "server::core::log::tests";
+
+    struct Collect(Mutex<Vec<Record>>);
+
+    impl Sink for Collect {
+        fn write(&self, record: &Record) {
+            if record.target != TARGET {
+                return;
+            }
+            self.0.lock().unwrap().push(record.clone());
+            if record.message == "reenter" {
+                log(Level::Error, TARGET, format_args!("nested"));
+            }
+        }
+    }
+
+    fn record(request_id: Option<&str>) -> Record {
+        Record {
+            time: UNIX_EPOCH + Duration::new(1_792_402_212, 250_000_000),
+            level: Level::Warn,
+            target: "server::core::http::http_request".to_string(),
+            message: "slow \"request\"".to_string(),
+            request_id: request_id.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn parses_and_orders_levels() {
+        assert_eq!(Level::parse(" WARNING "), Some(Level::Warn));
+        assert_eq!(Level::parse("trace"), Some(Level::Trace));
+        assert_eq!(Level::parse("verbose"), None);
+        assert!(Level::Trace < Level::Debug && Level::Warn < Level::Error);
+        assert_eq!(Level::Error.as_str(), "error");
+        assert_eq!(Format::parse("json"), Some(Format::Json));
+        assert_eq!(Format::parse("yaml"), None);
+    }
+
+    #[test]
+    fn formats_records_as_text() {
+        assert_eq!(record(None).short_target(), "http_request");
+        assert_eq!(
+            record(None).to_short_text(),
+            "http_request: slow \"request\""
+        );
+        assert_eq!(
+            record(Some("4bf92f35")).format(Format::Text),
+            "2026-10-19T09:30:12.250000000Z  WARN [4bf92f35] http_request: slow \"request\""
+        );
+    }
+
+    #[test]
+    fn formats_records_as_json() {
+        let json = record(Some("4bf92f35")).to_json();
+        assert_eq!(
+            json.get("time").and_then(Value::as_str),
+            Some("2026-10-19T09:30:12.250000000Z")
+        );
+        assert_eq!(json.get("level").and_then(Value::as_str), Some("warn"));
+        assert_eq!(
+            json.get("target").and_then(Value::as_str),
+            Some("server::core::http::http_request")
+        );
+        assert_eq!(
+            json.get("message").and_then(Value::as_str),
+            Some("slow \"request\"")
+        );
+        assert_eq!(
+            json.get("request_id").and_then(Value::as_str),
+            Some("4bf92f35")
+        );
+        assert!(record(None).to_json().get("request_id").is_none());
+    }
+
+    #[test]
+    fn request_scopes_nest() {
+        assert_eq!(request_id(), None);
+        let outer = request_scope("outer");
+        {
+            let _inner = request_scope("inner");
+            assert_eq!(request_id().as_deref(), Some("inner"));
+        }
+        assert_eq!(request_id().as_deref(), Some("outer"));
+        drop(outer);
+        assert_eq!(request_id(), None);
+    }
+
+    #[test]
+    fn sinks_receive_records_but_not_their_own() {
+        let sink = Arc::new(Collect(Mutex::new(Vec::new())));
+        add_sink(sink.clone());
+        {
+            let _scope = request_scope("abc123");
+            log(Level::Warn, TARGET, format_args!("reenter"));
+        }
+        log(Level::Trace, TARGET, format_args!("filtered"));
+
+        let records = sink.0.lock().unwrap();
+        let messages = records
+            .iter()
+            .map(|r| r.message.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(messages, vec!["reenter"]);
+        assert_eq!(records[0].request_id.as_deref(), Some("abc123"));
+        assert_eq!(records[0].level, Level::Warn);
+    }
+}
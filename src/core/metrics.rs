@@ -0,0 +1,573 @@
+use crate::core::error::ServerError;
+use crate::core::http::http_headers::HttpMethod;
+use crate::core::ConfigFile;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/** The label used for static files and paths without a route. */
+pub const STATIC_ROUTE: &str = "static";
+
+const METHODS: [&str; 10] = [
+    "GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "TRACE", "CONNECT", "PATCH", "OTHER",
+];
+const MIN_CODE: u16 = 100;
+const MAX_CODE: u16 = 599;
+const CODES: usize = (MAX_CODE - MIN_CODE + 1) as usize;
+
+/** Upper bounds of the latency histogram buckets in seconds, the same as Prometheus. */
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/**
+    Request counters for one route, a counter for every method and status code pair so a
+    request can be counted without a lock, plus a latency histogram.
+*/
+struct RouteMetrics {
+    requests: Box<[AtomicU64]>,
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl RouteMetrics {
+    fn new() -> Self {
+        RouteMetrics {
+            requests: (0..METHODS.len() * CODES)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            buckets: Default::default(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, method: &HttpMethod, code: u16, duration: Duration) {
+        if (MIN_CODE..=MAX_CODE).contains(&code) {
+            let index = method_index(method) * CODES + (code - MIN_CODE) as usize;
+            self.requests[index].fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/**
+    The state of the server when the metrics are scraped, which is read from the parts of
+    the server that own it rather than counted here.
+*/
+pub struct Gauges {
+    pub open_connections: usize,
+    pub event_streams: usize,
+    pub keep_alive_active: bool,
+    pub shed_connections: u64,
+}
+
+/**
+    Counts requests by route, method and status code, request latency, and the bytes read
+    and written, for the `/metrics` endpoint in the Prometheus text format. Counters are
+    atomics, so recording a request never waits on another one.
+
+    server.metrics("/metrics");
+
+    Routes must be added before the server starts, requests for any other path are counted
+    under the `static` route so the number of series stays bounded.
+*/
+pub struct Metrics {
+    path: Option<String>,
+    routes: HashMap<String, RouteMetrics>,
+    static_route: RouteMetrics,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    static_cache_hits: AtomicU64,
+    started: Instant,
+    start_time: SystemTime,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            path: None,
+            routes: HashMap::new(),
+            static_route: RouteMetrics::new(),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            static_cache_hits: AtomicU64::new(0),
+            started: Instant::now(),
+            start_time: SystemTime::now(),
+        }
+    }
+
+    /**
+        Read the path of the endpoint from the `[metrics]` section of a config file, the
+        endpoint is only served when it is enabled, at `/metrics` by default.
+
+        [metrics]
+        enabled = true
+        path = /metrics
+    */
+    pub fn path_from_config(config: &ConfigFile) -> Result<Option<String>> {
+        if !config.get_bool("metrics.enabled").unwrap_or(false) {
+            return Ok(None);
+        }
+        match config.get("metrics.path").unwrap_or("/metrics") {
+            path if path.starts_with('/') => Ok(Some(path.to_string())),
+            path => Err(ServerError::error(&format!(
+                "metrics: path must start with /: {}",
+                path
+            ))),
+        }
+    }
+
+    /** The path the endpoint is served at, if it is enabled. */
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn set_path(&mut self, path: &str) {
+        self.path = Some(path.to_string());
+        self.add_route(path);
+    }
+
+    /** Count the requests for a route under its own label. */
+    pub fn add_route(&mut self, path: &str) {
+        self.routes
+            .entry(path.to_string())
+            .or_insert_with(RouteMetrics::new);
+    }
+
+    /**
+        Record a completed request for a path, which is counted under its route or as a
+        static file. A static file answered with `304 Not Modified` is counted as a cache hit.
+    */
+    pub fn record(
+        &self,
+        path: &str,
+        method: &HttpMethod,
+        code: u16,
+        duration: Duration,
+        bytes_read: usize,
+        bytes_written: usize,
+    ) {
+        match self.routes.get(path) {
+            Some(route_metrics) => route_metrics.record(method, code, duration),
+            None => {
+                self.static_route.record(method, code, duration);
+                if code == 304 {
+                    self.static_cache_hits.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        self.bytes_read
+            .fetch_add(bytes_read as u64, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes_written as u64, Ordering::Relaxed);
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /** Render every metric in the Prometheus text exposition format, version 0.0.4. */
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut routes: Vec<(&str, &RouteMetrics)> = self
+            .routes
+            .iter()
+            .map(|(route, metrics)| (route.as_str(), metrics))
+            .collect();
+        routes.sort_by_key(|(route, _)| *route);
+        routes.push((STATIC_ROUTE, &self.static_route));
+
+        let mut output = String::new();
+        header(
+            &mut output,
+            "server_http_requests_total",
+            "counter",
+            "Requests handled, by route, method and status code.",
+        );
+        for (route, metrics) in routes.iter() {
+            for (index, counter) in metrics.requests.iter().enumerate() {
+                let count = counter.load(Ordering::Relaxed);
+                if count == 0 {
+                    continue;
+                }
+                let method = METHODS[index / CODES];
+                let code = MIN_CODE as usize + index % CODES;
+                let _ = writeln!(
+                    output,
+                    "server_http_requests_total{{route=\"{}\",method=\"{}\",code=\"{}\"}} {}",
+                    escape_label(route),
+                    method,
+                    code,
+                    count
+                );
+            }
+        }
+
+        header(
+            &mut output,
+            "server_http_request_duration_seconds",
+            "histogram",
+            "Time from reading a request to writing its response.",
+        );
+        for (route, metrics) in routes.iter() {
+            let count = metrics.count.load(Ordering::Relaxed);
+            if count == 0 {
+                continue;
+            }
+            let route = escape_label(route);
+            let mut cumulative = 0;
+            for (bound, bucket) in BUCKETS.iter().zip(metrics.buckets.iter()) {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let _ = writeln!(
+                    output,
+                    "server_http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bound, cumulative
+                );
+            }
+            let sum = metrics.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+            let _ = writeln!(
+                output,
+                "server_http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, count
+            );
+            let _ = writeln!(
+                output,
+                "server_http_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route, sum
+            );
+            let _ = writeln!(
+                output,
+                "server_http_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, count
+            );
+        }
+
+        let start_time = self
+            .start_time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let values = [
+            (
+                "server_http_request_bytes_total",
+                "counter",
+                "Bytes read from requests, including headers.",
+                self.bytes_read.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "server_http_response_bytes_total",
+                "counter",
+                "Bytes written in responses, including headers.",
+                self.bytes_written.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "server_static_cache_hits_total",
+                "counter",
+                "Static files the client already had, answered with 304 Not Modified.",
+                self.static_cache_hits.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "server_shed_connections_total",
+                "counter",
+                "Connections and event streams turned away by the connection limits.",
+                gauges.shed_connections as f64,
+            ),
+            (
+                "server_open_connections",
+                "gauge",
+                "Connections which are being handled.",
+                gauges.open_connections as f64,
+            ),
+            (
+                "server_event_streams",
+                "gauge",
+                "Clients subscribed to server sent events.",
+                gauges.event_streams as f64,
+            ),
+            (
+                "server_keep_alive_thread_active",
+                "gauge",
+                "Whether the thread which keeps event streams open is running.",
+                gauges.keep_alive_active as u8 as f64,
+            ),
+            (
+                "server_uptime_seconds",
+                "gauge",
+                "Seconds since the server was created.",
+                self.uptime().as_secs_f64(),
+            ),
+            (
+                "process_start_time_seconds",
+                "gauge",
+                "Start time of the process since the Unix epoch in seconds.",
+                start_time,
+            ),
+        ];
+        for (name, kind, help, value) in values {
+            header(&mut output, name, kind, help);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+        output
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+fn method_index(method: &HttpMethod) -> usize {
+    match method {
+        HttpMethod::GET => 0,
+        HttpMethod::POST => 1,
+        HttpMethod::PUT => 2,
+        HttpMethod::DELETE => 3,
+        HttpMethod::HEAD => 4,
+        HttpMethod::OPTIONS => 5,
+        HttpMethod::TRACE => 6,
+        HttpMethod::CONNECT => 7,
+        HttpMethod::PATCH => 8,
+        HttpMethod::Name(_) => 9,
+    }
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+/** Escape a label value, as routes may contain any character. */
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn gauges() -> Gauges {
+        Gauges {
+            open_connections: 3,
+            event_streams: 2,
+            keep_alive_active: true,
+            shed_connections: 7,
+        }
+    }
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /** The value of the line which starts with `series`. */
+    fn value(output: &str, series: &str) -> Option<String> {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .map(str::to_string)
+    }
+
+    #[test]
+    fn counts_requests_by_route_method_and_code() {
+        let mut metrics = Metrics::new();
+        metrics.add_route("/api");
+        metrics.record("/api", &HttpMethod::GET, 200, millis(1), 0, 0);
+        metrics.record("/api", &HttpMethod::GET, 200, millis(1), 0, 0);
+        metrics.record("/api", &HttpMethod::POST, 500, millis(1), 0, 0);
+        metrics.record(
+            "/api",
+            &HttpMethod::Name("PROPFIND".to_string()),
+            405,
+            millis(1),
+            0,
+            0,
+        );
+        metrics.record("/index.html", &HttpMethod::GET, 200, millis(1), 0, 0);
+
+        let output = metrics.render(&gauges());
+        let requests = |labels: &str| {
+            value(
+                &output,
+                &format!("server_http_requests_total{{{}}}", labels),
+            )
+        };
+        assert_eq!(
+            requests(r#"route="/api",method="GET",code="200""#).as_deref(),
+            Some("2")
+        );
+        assert_eq!(
+            requests(r#"route="/api",method="POST",code="500""#).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            requests(r#"route="/api",method="OTHER",code="405""#).as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            requests(r#"route="static",method="GET",code="200""#).as_deref(),
+            Some("1")
+        );
+        // series which were never seen are left out
+        assert_eq!(requests(r#"route="/api",method="PUT",code="200""#), None);
+    }
+
+    #[test]
+    fn renders_a_cumulative_latency_histogram() {
+        let mut metrics = Metrics::new();
+        metrics.add_route("/api");
+        for duration in [
+            millis(3),
+            millis(20),
+            millis(20),
+            millis(700),
+            millis(30_000),
+        ] {
+            metrics.record("/api", &HttpMethod::GET, 200, duration, 0, 0);
+        }
+        let output = metrics.render(&gauges());
+        let bucket = |le: &str| {
+            value(
+                &output,
+                &format!(
+                    "server_http_request_duration_seconds_bucket{{route=\"/api\",le=\"{}\"}}",
+                    le
+                ),
+            )
+        };
+        assert_eq!(bucket("0.005").as_deref(), Some("1"));
+        assert_eq!(bucket("0.01").as_deref(), Some("1"));
+        assert_eq!(bucket("0.025").as_deref(), Some("3"));
+        assert_eq!(bucket("1").as_deref(), Some("4"));
+        assert_eq!(bucket("10").as_deref(), Some("4"));
+        assert_eq!(bucket("+Inf").as_deref(), Some("5"));
+        assert_eq!(
+            value(
+                &output,
+                "server_http_request_duration_seconds_sum{route=\"/api\"}"
+            )
+            .as_deref(),
+            Some("30.743")
+        );
+        assert_eq!(
+            value(
+                &output,
+                "server_http_request_duration_seconds_count{route=\"/api\"}"
+            )
+            .as_deref(),
+            Some("5")
+        );
+        // routes without requests have no histogram
+        assert_eq!(
+            value(
+                &output,
+                "server_http_request_duration_seconds_count{route=\"static\"}"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn counts_bytes_cache_hits_and_gauges() {
+        let metrics = Metrics::new();
+        metrics.record("/style.css", &HttpMethod::GET, 304, millis(1), 120, 80);
+        metrics.record("/style.css", &HttpMethod::GET, 200, millis(1), 100, 900);
+        let output = metrics.render(&gauges());
+        assert_eq!(
+            value(&output, "server_http_request_bytes_total").as_deref(),
+            Some("220")
+        );
+        assert_eq!(
+            value(&output, "server_http_response_bytes_total").as_deref(),
+            Some("980")
+        );
+        assert_eq!(
+            value(&output, "server_static_cache_hits_total").as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            value(&output, "server_shed_connections_total").as_deref(),
+            Some("7")
+        );
+        assert_eq!(
+            value(&output, "server_open_connections").as_deref(),
+            Some("3")
+        );
+        assert_eq!(value(&output, "server_event_streams").as_deref(), Some("2"));
+        assert_eq!(
+            value(&output, "server_keep_alive_thread_active").as_deref(),
+            Some("1")
+        );
+        assert!(output.contains("# TYPE server_uptime_seconds gauge\n"));
+        assert!(output.contains("# TYPE server_http_requests_total counter\n"));
+    }
+
+    #[test]
+    fn escapes_route_labels() {
+        assert_eq!(escape_label("/a\"b\\c\nd"), "/a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn counts_concurrent_requests_without_losing_any() {
+        let mut metrics = Metrics::new();
+        metrics.add_route("/api");
+        let metrics = Arc::new(metrics);
+        let handles = (0..8)
+            .map(|_| {
+                let metrics = Arc::clone(&metrics);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        metrics.record("/api", &HttpMethod::GET, 200, millis(1), 1, 1);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let output = metrics.render(&gauges());
+        assert_eq!(
+            value(
+                &output,
+                r#"server_http_requests_total{route="/api",method="GET",code="200"}"#
+            )
+            .as_deref(),
+            Some("8000")
+        );
+        assert_eq!(
+            value(&output, "server_http_request_bytes_total").as_deref(),
+            Some("8000")
+        );
+    }
+
+    #[test]
+    fn reads_the_path_from_config() {
+        let path = |text: &str| Metrics::path_from_config(&ConfigFile::parse(text));
+        assert_eq!(path("").unwrap(), None);
+        assert_eq!(path("[metrics]\npath = /_/metrics\n").unwrap(), None);
+        assert_eq!(
+            path("[metrics]\nenabled = true\n").unwrap().as_deref(),
+            Some("/metrics")
+        );
+        assert_eq!(
+            path("[metrics]\nenabled = true\npath = /_/metrics\n")
+                .unwrap()
+                .as_deref(),
+            Some("/_/metrics")
+        );
+        assert!(path("[metrics]\nenabled = true\npath = metrics\n").is_err());
+    }
+}
@@ -0,0 +1,389 @@
+use crate::core::http::http_headers::{HttpHeaders, HttpMethod};
+use crate::core::http::{HttpRequest, HttpResponse, HttpStatus};
+use crate::core::middleware::Middleware;
+use crate::core::server::Flag;
+use crate::core::util::glob_match;
+use std::io::Result;
+
+/**
+    Which origins are allowed to make cross-origin requests.
+*/
+#[derive(Clone, Debug)]
+pub enum AllowedOrigin {
+    Any,
+    Exact(String),
+    Pattern(String),
+}
+
+impl AllowedOrigin {
+    pub fn matches(&self, origin: &str) -> bool {
+        match self {
+            AllowedOrigin::Any => true,
+            AllowedOrigin::Exact(exact) => exact.eq_ignore_ascii_case(origin),
+            AllowedOrigin::Pattern(pattern) => glob_match(pattern, origin),
+        }
+    }
+}
+
+/**
+    Cross-Origin Resource Sharing layer, answers `OPTIONS` preflight requests and attaches
+    the `Access-Control-*` headers to responses for allowed origins.
+
+    let cors = Cors::new()
+        .origin("http://localhost:3000")
+        .origin_pattern("http://127.0.0.1:*")
+        .credentials(true)
+        .max_age(600);
+
+    server.middleware(cors);
+*/
+#[derive(Clone, Debug)]
+pub struct Cors {
+    origins: Vec<AllowedOrigin>,
+    methods: Vec<HttpMethod>,
+    headers: Vec<String>,
+    exposed_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<u64>,
+}
+
+impl Cors {
+    /**
+        Create a new CORS layer which allows no origins, the default methods are the
+        CORS-safelisted methods `GET`, `HEAD` and `POST`.
+    */
+    pub fn new() -> Self {
+        Cors {
+            origins: Vec::new(),
+            methods: vec![HttpMethod::GET, HttpMethod::HEAD, HttpMethod::POST],
+            headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /** Allow requests from any origin. */
+    pub fn any_origin(mut self) -> Self {
+        self.origins.push(AllowedOrigin::Any);
+        self
+    }
+
+    /** Allow requests from an exact origin such as `http://localhost:3000`. */
+    pub fn origin(mut self, origin: &str) -> Self {
+        let origin = origin.trim_end_matches('/');
+        self.origins.push(AllowedOrigin::Exact(origin.to_string()));
+        self
+    }
+
+    /** Allow requests from origins matching a glob pattern such as `http://localhost:*`. */
+    pub fn origin_pattern(mut self, pattern: &str) -> Self {
+        self.origins
+            .push(AllowedOrigin::Pattern(pattern.to_string()));
+        self
+    }
+
+    /** Set the methods which are allowed for cross-origin requests. */
+    pub fn methods(mut self, methods: &[HttpMethod]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /**
+        Set the request headers which are allowed, when none are set the headers requested
+        in the preflight are allowed.
+    */
+    pub fn headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /** Set the response headers which the browser should expose to scripts. */
+    pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+        self.exposed_headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /** Allow cookies and authorization headers to be sent with cross-origin requests. */
+    pub fn credentials(mut self, allow: bool) -> Self {
+        self.credentials = allow;
+        self
+    }
+
+    /** How many seconds the browser may cache the result of a preflight request. */
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn is_allowed_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed.matches(origin))
+    }
+
+    fn is_allowed_method(&self, method: &HttpMethod) -> bool {
+        self.methods.contains(method)
+    }
+
+    /**
+        Check the requested headers against the allowed headers, note that header names
+        are compared case-insensitively.
+    */
+    fn is_allowed_headers(&self, requested: &str) -> bool {
+        if self.headers.is_empty() {
+            return true;
+        }
+        requested
+            .split(',')
+            .map(|header| header.trim())
+            .filter(|header| !header.is_empty())
+            .all(|header| {
+                self.headers
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(header))
+            })
+    }
+
+    /**
+        The value of `Access-Control-Allow-Origin`, the wildcard can only be used when
+        credentials are not allowed, otherwise the origin is echoed back.
+    */
+    fn allow_origin_value(&self, origin: &str) -> String {
+        let allows_any = self
+            .origins
+            .iter()
+            .any(|allowed| matches!(allowed, AllowedOrigin::Any));
+        if allows_any && !self.credentials {
+            "*".to_string()
+        } else {
+            origin.to_string()
+        }
+    }
+
+    /**
+        Set the headers which are shared by both preflight and normal responses.
+    */
+    fn apply_headers(&self, headers: &mut HttpHeaders, origin: &str) {
+        headers.set(
+            "Access-Control-Allow-Origin",
+            &self.allow_origin_value(origin),
+        );
+        headers.set("Vary", "Origin");
+        if self.credentials {
+            headers.set("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    /**
+        Build the response to a preflight request, a preflight which is not allowed is
+        answered without any `Access-Control-*` headers so that the browser rejects it.
+    */
+    pub fn preflight(&self, request: &HttpRequest, origin: &str) -> HttpResponse {
+        let mut response = HttpResponse::new();
+        response.set_status(HttpStatus::NoContent);
+        response.set_header("Content-Length", "0");
+
+        let method = match request.headers.get("Access-Control-Request-Method") {
+            Some(method) => HttpHeaders::method_from_string(method.trim()),
+            None => return response,
+        };
+        let requested_headers = request
+            .headers
+            .get("Access-Control-Request-Headers")
+            .cloned()
+            .unwrap_or_default();
+
+        if !self.is_allowed_origin(origin)
+            || !self.is_allowed_method(&method)
+            || !self.is_allowed_headers(&requested_headers)
+        {
+            response.set_header("Vary", "Origin");
+            return response;
+        }
+
+        self.apply_headers(&mut response.headers, origin);
+
+        let methods = self
+            .methods
+            .iter()
+            .map(|method| method.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        response.set_header("Access-Control-Allow-Methods", &methods);
+
+        let allowed_headers = match self.headers.is_empty() {
+            true => requested_headers,
+            false => self.headers.join(", "),
+        };
+        if !allowed_headers.is_empty() {
+            response.set_header("Access-Control-Allow-Headers", &allowed_headers);
+        }
+
+        if let Some(max_age) = self.max_age {
+            response.set_header("Access-Control-Max-Age", &max_age.to_string());
+        }
+
+        response.set_header(
+            "Vary",
+            "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+        );
+        response
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+        let origin = match request.headers.get("Origin") {
+            Some(origin) => origin.trim().to_string(),
+            None => return Ok(None),
+        };
+
+        let is_preflight = request.headers.method == HttpMethod::OPTIONS
+            && request.headers.contains("Access-Control-Request-Method");
+
+        if is_preflight {
+            let response = self.preflight(request, &origin);
+            return request.send(response).map(Some);
+        }
+
+        if self.is_allowed_origin(&origin) {
+            self.apply_headers(&mut request.response.headers, &origin);
+            if !self.exposed_headers.is_empty() {
+                request.response.set_header(
+                    "Access-Control-Expose-Headers",
+                    &self.exposed_headers.join(", "),
+                );
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preflight_request(method: &str, headers: Option<&str>) -> HttpRequest {
+        let mut request = HttpRequest::to("/api");
+        request.headers.method = HttpMethod::OPTIONS;
+        request.headers.set("Access-Control-Request-Method", method);
+        if let Some(headers) = headers {
+            request
+                .headers
+                .set("Access-Control-Request-Headers", headers);
+        }
+        request
+    }
+
+    fn header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        response.headers.get(name).map(|value| value.as_str())
+    }
+
+    #[test]
+    fn matches_exact_and_pattern_origins() {
+        let cors = Cors::new()
+            .origin("http://localhost:3000/")
+            .origin_pattern("http://127.0.0.1:*");
+        assert!(cors.is_allowed_origin("http://localhost:3000"));
+        assert!(cors.is_allowed_origin("HTTP://LOCALHOST:3000"));
+        assert!(cors.is_allowed_origin("http://127.0.0.1:8080"));
+        assert!(!cors.is_allowed_origin("http://localhost:3001"));
+        assert!(!cors.is_allowed_origin("http://evil.com"));
+    }
+
+    #[test]
+    fn answers_allowed_preflights() {
+        let cors = Cors::new()
+            .origin("http://localhost:3000")
+            .methods(&[HttpMethod::GET, HttpMethod::PUT])
+            .headers(&["Content-Type", "X-Token"])
+            .credentials(true)
+            .max_age(600);
+        let request = preflight_request("PUT", Some("x-token, content-type"));
+        let response = cors.preflight(&request, "http://localhost:3000");
+        assert_eq!(response.status.code(), 204);
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("http://localhost:3000")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Headers"),
+            Some("Content-Type, X-Token")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(header(&response, "Access-Control-Max-Age"), Some("600"));
+    }
+
+    #[test]
+    fn refuses_preflights_without_cors_headers() {
+        let cors = Cors::new()
+            .origin("http://localhost:3000")
+            .headers(&["Content-Type"]);
+        let refused = [
+            (preflight_request("GET", None), "http://evil.com"),
+            (preflight_request("DELETE", None), "http://localhost:3000"),
+            (
+                preflight_request("GET", Some("X-Other")),
+                "http://localhost:3000",
+            ),
+        ];
+        for (request, origin) in refused.iter() {
+            let response = cors.preflight(request, origin);
+            assert_eq!(response.status.code(), 204);
+            assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+            assert_eq!(header(&response, "Vary"), Some("Origin"));
+        }
+    }
+
+    #[test]
+    fn wildcard_is_not_used_with_credentials() {
+        let request = preflight_request("GET", None);
+        let any = Cors::new().any_origin();
+        let response = any.preflight(&request, "http://a.com");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("*"));
+
+        let with_credentials = Cors::new().any_origin().credentials(true);
+        let response = with_credentials.preflight(&request, "http://a.com");
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("http://a.com")
+        );
+    }
+
+    #[test]
+    fn adds_headers_to_simple_requests() {
+        let cors = Cors::new()
+            .origin("http://localhost:3000")
+            .expose_headers(&["X-Request-Id"]);
+        let mut request = HttpRequest::to("/api");
+        request.headers.set("Origin", "http://localhost:3000");
+        assert!(cors.before(&mut request).unwrap().is_none());
+        let response = &request.response;
+        assert_eq!(
+            header(response, "Access-Control-Allow-Origin"),
+            Some("http://localhost:3000")
+        );
+        assert_eq!(
+            header(response, "Access-Control-Expose-Headers"),
+            Some("X-Request-Id")
+        );
+
+        let mut other = HttpRequest::to("/api");
+        other.headers.set("Origin", "http://evil.com");
+        assert!(cors.before(&mut other).unwrap().is_none());
+        assert_eq!(header(&other.response, "Access-Control-Allow-Origin"), None);
+    }
+}
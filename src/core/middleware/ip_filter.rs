@@ -0,0 +1,320 @@
+use super::{has_path_prefix, Middleware};
+use crate::core::error::ServerError;
+use crate::core::http::{HttpRequest, HttpResponse, HttpStatus};
+use crate::core::server::Flag;
+use crate::core::util::Cidr;
+use crate::core::ConfigFile;
+use crate::{error, info};
+use std::fs;
+use std::io::Result;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/** How often a watched config file is checked for changes. */
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum IpAction {
+    Allow,
+    Deny,
+}
+
+/**
+    Allow or deny a set of networks for a path and everything below it.
+*/
+#[derive(Clone, Debug)]
+pub struct IpRule {
+    pub prefix: String,
+    pub action: IpAction,
+    pub networks: Vec<Cidr>,
+}
+
+impl IpRule {
+    pub fn new(prefix: &str, action: IpAction, networks: &[Cidr]) -> Self {
+        IpRule {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            action,
+            networks: networks.to_vec(),
+        }
+    }
+
+    /**
+        Parse a rule from a path prefix, `allow` or `deny`, and a comma separated list of
+        networks where `all` matches every address.
+
+        IpRule::parse("/log allow 10.0.0.0/8, ::1")
+        IpRule::parse("/log deny all")
+    */
+    pub fn parse(line: &str) -> Option<Self> {
+        let (prefix, rest) = line.trim().split_once(char::is_whitespace)?;
+        let (action, networks) = rest.trim_start().split_once(char::is_whitespace)?;
+        let action = match action {
+            "allow" => IpAction::Allow,
+            "deny" => IpAction::Deny,
+            _ => return None,
+        };
+        let networks = networks
+            .split(',')
+            .map(|network| match network.trim() {
+                "all" => Some(vec![Cidr::any_v4(), Cidr::any_v6()]),
+                network => Cidr::parse(network).map(|network| vec![network]),
+            })
+            .collect::<Option<Vec<Vec<Cidr>>>>()?;
+        Some(IpRule::new(prefix, action, &networks.concat()))
+    }
+
+    pub fn matches(&self, path: &str, ip: &IpAddr) -> bool {
+        has_path_prefix(path, &self.prefix) && self.networks.iter().any(|n| n.contains(ip))
+    }
+}
+
+/** A config file which is reloaded when its modification time changes. */
+struct Watched {
+    path: String,
+    modified: Option<SystemTime>,
+    checked_at: Instant,
+}
+
+/**
+    Middleware which allows or denies requests by the client IP, using rules for path
+    prefixes which are checked in order. The first rule which matches both the path and
+    the client IP decides, and requests which match no rule are allowed.
+
+    let filter = IpFilter::new()
+        .allow("/log", Cidr::parse("10.0.0.0/8").unwrap())
+        .allow("/log", Cidr::parse("127.0.0.1").unwrap())
+        .deny("/log", Cidr::any_v4())
+        .deny("/log", Cidr::any_v6());
+
+    server.middleware(filter);
+
+    Denied requests receive a 403. When the rules are loaded with `IpFilter::watch` the
+    config file is reloaded whenever it changes, so rules can be updated without a restart.
+*/
+pub struct IpFilter {
+    rules: RwLock<Vec<IpRule>>,
+    watched: Option<Mutex<Watched>>,
+}
+
+impl IpFilter {
+    pub fn new() -> Self {
+        IpFilter {
+            rules: RwLock::new(Vec::new()),
+            watched: None,
+        }
+    }
+
+    /**
+        Create from the `[ip_filter]` section of a config file, see `IpRule::parse` for the
+        format of each rule.
+
+        [ip_filter]
+        rule = /log allow 10.0.0.0/8, 192.168.0.0/16, 127.0.0.1, ::1
+        rule = /log deny all
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let filter = IpFilter::new();
+        *filter.rules.write().unwrap() = IpFilter::parse_rules(config)?;
+        Ok(filter)
+    }
+
+    /**
+        Load the rules from the `[ip_filter]` section of a config file, which is checked for
+        changes every few seconds and reloaded. If a reload fails the previous rules are kept.
+    */
+    pub fn watch(path: &str) -> Result<Self> {
+        let mut filter = IpFilter::from_config(&ConfigFile::load(path)?)?;
+        filter.watched = Some(Mutex::new(Watched {
+            path: path.to_string(),
+            modified: modified_time(path),
+            checked_at: Instant::now(),
+        }));
+        Ok(filter)
+    }
+
+    fn parse_rules(config: &ConfigFile) -> Result<Vec<IpRule>> {
+        config
+            .get_all("ip_filter.rule")
+            .into_iter()
+            .map(|line| {
+                IpRule::parse(line).ok_or_else(|| {
+                    ServerError::error(&format!("ip_filter: invalid rule {:?}", line))
+                })
+            })
+            .collect()
+    }
+
+    pub fn allow(self, prefix: &str, network: Cidr) -> Self {
+        self.rule(IpRule::new(prefix, IpAction::Allow, &[network]))
+    }
+
+    pub fn deny(self, prefix: &str, network: Cidr) -> Self {
+        self.rule(IpRule::new(prefix, IpAction::Deny, &[network]))
+    }
+
+    pub fn rule(self, rule: IpRule) -> Self {
+        self.rules.write().unwrap().push(rule);
+        self
+    }
+
+    /** Reload the rules from the watched config file. */
+    pub fn reload(&self) -> Result<()> {
+        let watched = match &self.watched {
+            Some(watched) => watched,
+            None => return Ok(()),
+        };
+        let path = watched.lock().unwrap().path.clone();
+        let rules = IpFilter::parse_rules(&ConfigFile::load(&path)?)?;
+        info!("reloaded {} rules from {}", rules.len(), path);
+        *self.rules.write().unwrap() = rules;
+        Ok(())
+    }
+
+    /** Reload the watched config file if the check interval has passed and it changed. */
+    fn maybe_reload(&self) {
+        let watched = match &self.watched {
+            Some(watched) => watched,
+            None => return,
+        };
+        let mut watched = watched.lock().unwrap();
+        if watched.checked_at.elapsed() < RELOAD_INTERVAL {
+            return;
+        }
+        watched.checked_at = Instant::now();
+        let modified = modified_time(&watched.path);
+        if modified == watched.modified {
+            return;
+        }
+        watched.modified = modified;
+        drop(watched);
+        if let Err(err) = self.reload() {
+            error!("failed to reload rules: {}", err);
+        }
+    }
+
+    /**
+        Check if a client IP may access a path, requests without a client IP are denied
+        for any path which has rules.
+    */
+    pub fn is_allowed(&self, path: &str, ip: Option<&IpAddr>) -> bool {
+        let rules = self.rules.read().unwrap();
+        let ip = match ip {
+            Some(ip) => ip,
+            None => return !rules.iter().any(|rule| has_path_prefix(path, &rule.prefix)),
+        };
+        match rules.iter().find(|rule| rule.matches(path, ip)) {
+            Some(rule) => rule.action == IpAction::Allow,
+            None => true,
+        }
+    }
+}
+
+impl Default for IpFilter {
+    fn default() -> Self {
+        IpFilter::new()
+    }
+}
+
+impl Middleware for IpFilter {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+        self.maybe_reload();
+        let path = request.path();
+        if self.is_allowed(path, request.client_ip.as_ref()) {
+            return Ok(None);
+        }
+        info!("denied {:?} {}", request.client_ip, path);
+        request
+            .send(HttpResponse::error(HttpStatus::Forbidden))
+            .map(Some)
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    fn filter() -> IpFilter {
+        IpFilter::new()
+            .allow("/log", Cidr::parse("10.0.0.0/8").unwrap())
+            .allow("/log", Cidr::parse("::1").unwrap())
+            .deny("/log", Cidr::any_v4())
+            .deny("/log", Cidr::any_v6())
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let filter = filter();
+        assert!(filter.is_allowed("/log", Some(&ip("10.1.2.3"))));
+        assert!(filter.is_allowed("/log/today", Some(&ip("::1"))));
+        assert!(!filter.is_allowed("/log", Some(&ip("192.0.2.1"))));
+        assert!(!filter.is_allowed("/log", Some(&ip("2001:db8::1"))));
+        assert!(filter.is_allowed("/login", Some(&ip("192.0.2.1"))));
+        assert!(filter.is_allowed("/index.html", Some(&ip("192.0.2.1"))));
+    }
+
+    #[test]
+    fn unknown_clients_are_denied_where_there_are_rules() {
+        let filter = filter();
+        assert!(!filter.is_allowed("/log", None));
+        assert!(filter.is_allowed("/index.html", None));
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule = IpRule::parse("/log/ allow 10.0.0.0/8, ::1").unwrap();
+        assert_eq!(rule.prefix, "/log");
+        assert_eq!(rule.action, IpAction::Allow);
+        assert_eq!(rule.networks.len(), 2);
+        assert!(rule.matches("/log/today", &ip("10.0.0.1")));
+
+        let all = IpRule::parse("/  deny all").unwrap();
+        assert_eq!(all.action, IpAction::Deny);
+        assert!(all.matches("/anything", &ip("192.0.2.1")));
+        assert!(all.matches("/anything", &ip("2001:db8::1")));
+
+        assert!(IpRule::parse("/log allow").is_none());
+        assert!(IpRule::parse("/log permit 10.0.0.0/8").is_none());
+        assert!(IpRule::parse("/log allow 10.0.0.0/8, nope").is_none());
+    }
+
+    #[test]
+    fn reads_rules_from_config() {
+        let config =
+            ConfigFile::parse("[ip_filter]\nrule = /log allow 127.0.0.1\nrule = /log deny all\n");
+        let filter = IpFilter::from_config(&config).unwrap();
+        assert!(filter.is_allowed("/log", Some(&ip("127.0.0.1"))));
+        assert!(!filter.is_allowed("/log", Some(&ip("127.0.0.2"))));
+
+        let invalid = ConfigFile::parse("[ip_filter]\nrule = /log allow everyone\n");
+        assert!(IpFilter::from_config(&invalid).is_err());
+    }
+
+    #[test]
+    fn reload_keeps_the_rules_when_the_file_is_invalid() {
+        let path = env::temp_dir().join(format!("ip_filter_{}.conf", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "[ip_filter]\nrule = / deny 192.0.2.0/24\n").unwrap();
+        let filter = IpFilter::watch(path).unwrap();
+        assert!(!filter.is_allowed("/", Some(&ip("192.0.2.1"))));
+
+        fs::write(path, "[ip_filter]\nrule = / deny 198.51.100.0/24\n").unwrap();
+        filter.reload().unwrap();
+        assert!(filter.is_allowed("/", Some(&ip("192.0.2.1"))));
+        assert!(!filter.is_allowed("/", Some(&ip("198.51.100.1"))));
+
+        fs::write(path, "[ip_filter]\nrule = / deny\n").unwrap();
+        assert!(filter.reload().is_err());
+        assert!(!filter.is_allowed("/", Some(&ip("198.51.100.1"))));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+pub mod cors;
+pub mod ip_filter;
+pub mod proxy;
+pub mod rate_limit;
+
+pub use self::cors::Cors;
+pub use self::ip_filter::IpFilter;
+pub use self::proxy::TrustedProxies;
+pub use self::rate_limit::{RateLimit, RateLimitKey};
+
+use crate::core::http::http_headers::HttpMethod;
+use crate::core::http::HttpRequest;
+use crate::core::server::Flag;
+use std::io::Result;
+
+/**
+    Middleware runs for every request before the route handler or static file is served,
+    in the order it was registered with `Server::middleware`.
+
+    Returning `Ok(Some(flag))` means the middleware has already sent a response and the
+    request is complete, while `Ok(None)` passes the request on to the next layer.
+*/
+pub trait Middleware: Send + Sync {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>>;
+
+    /**
+        The method a request is served with when this middleware answers it itself, like a
+        route, rather than only checking it. The server authorizes the request for that
+        method before calling `before`, so it must account for e.g. `X-HTTP-Method-Override`.
+    */
+    fn serves(&self, _request: &HttpRequest) -> Option<HttpMethod> {
+        None
+    }
+}
+
+/**
+    Check if a path is equal to or below a prefix, matching whole segments so `/log`
+    covers `/log` and `/log/today` but not `/login`. An empty prefix matches every path.
+*/
+pub fn has_path_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty()
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_prefixes_match_whole_segments() {
+        assert!(has_path_prefix("/log", "/log"));
+        assert!(has_path_prefix("/log/today", "/log"));
+        assert!(has_path_prefix("/log/today", "/log/"));
+        assert!(!has_path_prefix("/login", "/log"));
+        assert!(!has_path_prefix("/", "/log"));
+        assert!(has_path_prefix("/anything", ""));
+        assert!(has_path_prefix("/anything", "/"));
+    }
+}
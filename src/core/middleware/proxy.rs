@@ -0,0 +1,194 @@
+use super::Middleware;
+use crate::core::error::ServerError;
+use crate::core::http::HttpRequest;
+use crate::core::server::Flag;
+use crate::core::util::Cidr;
+use crate::core::ConfigFile;
+use std::io::Result;
+use std::net::{IpAddr, SocketAddr};
+
+/**
+    Middleware which sets `request.client_ip` from the `X-Forwarded-For` header when the
+    request comes from a trusted proxy, it should be registered before any middleware
+    which uses the client IP (e.g. `IpFilter` and `RateLimit`).
+
+    let proxies = TrustedProxies::new(&[Cidr::parse("10.0.0.0/8").unwrap()]);
+    server.middleware(proxies);
+
+    The header is read from right to left skipping trusted proxies, so the client IP is
+    the address of the last hop which was not a trusted proxy. The header is ignored for
+    requests from any other peer since it can be set by the client, and the client IP is
+    unknown if the header is malformed.
+*/
+pub struct TrustedProxies {
+    proxies: Vec<Cidr>,
+    header: String,
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: &[Cidr]) -> Self {
+        TrustedProxies {
+            proxies: proxies.to_vec(),
+            header: "X-Forwarded-For".to_string(),
+        }
+    }
+
+    /**
+        Create from the `[proxy]` section of a config file.
+
+        [proxy]
+        trusted = 127.0.0.1, 10.0.0.0/8
+        header = X-Forwarded-For
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let proxies = config
+            .get_list("proxy.trusted")
+            .iter()
+            .map(|network| {
+                Cidr::parse(network).ok_or_else(|| {
+                    ServerError::error(&format!("proxy: invalid network {}", network))
+                })
+            })
+            .collect::<Result<Vec<Cidr>>>()?;
+        let mut trusted = TrustedProxies::new(&proxies);
+        if let Some(header) = config.get("proxy.header") {
+            trusted.header = header.to_string();
+        }
+        Ok(trusted)
+    }
+
+    /** Read the client IP from a different header, e.g. `X-Real-IP`. */
+    pub fn header(mut self, header: &str) -> Self {
+        self.header = header.to_string();
+        self
+    }
+
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.proxies.iter().any(|network| network.contains(ip))
+    }
+
+    /**
+        Find the client IP in a forwarded header, starting from the trusted peer and
+        walking back while each hop is also a trusted proxy. Returns None if a hop can not
+        be parsed, rather than trusting the proxy's own address.
+    */
+    fn forwarded_ip(&self, peer: IpAddr, header: &str) -> Option<IpAddr> {
+        let mut client = peer;
+        for hop in header.rsplit(',') {
+            if !self.is_trusted(&client) {
+                break;
+            }
+            client = parse_hop(hop)?;
+        }
+        Some(client)
+    }
+}
+
+impl Middleware for TrustedProxies {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+        let peer = match request.peer_addr {
+            Some(addr) if self.is_trusted(&addr.ip()) => addr.ip(),
+            _ => return Ok(None),
+        };
+        if let Some(header) = request.headers.get(&self.header) {
+            request.client_ip = self.forwarded_ip(peer, header);
+        }
+        Ok(None)
+    }
+}
+
+/** Parse an address from a forwarded header, which may include a port. */
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse::<IpAddr>()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new(&[
+            Cidr::parse("10.0.0.0/8").unwrap(),
+            Cidr::parse("::1").unwrap(),
+        ])
+    }
+
+    fn client_ip(proxies: &TrustedProxies, peer: &str, forwarded: Option<&str>) -> Option<IpAddr> {
+        let mut request = HttpRequest::to("/");
+        let peer: SocketAddr = peer.parse().unwrap();
+        request.peer_addr = Some(peer);
+        request.client_ip = Some(peer.ip());
+        if let Some(forwarded) = forwarded {
+            request.headers.set("X-Forwarded-For", forwarded);
+        }
+        assert!(proxies.before(&mut request).unwrap().is_none());
+        request.client_ip
+    }
+
+    fn ip(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    #[test]
+    fn uses_the_last_untrusted_hop() {
+        let proxies = proxies();
+        let peer = "10.0.0.1:4000";
+        assert_eq!(
+            client_ip(&proxies, peer, Some("192.0.2.1")),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            client_ip(&proxies, peer, Some("198.51.100.7, 192.0.2.1, 10.0.0.2")),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            client_ip(&proxies, "[::1]:4000", Some("[2001:db8::1]:1234")),
+            ip("2001:db8::1")
+        );
+        assert_eq!(
+            client_ip(&proxies, peer, Some("192.0.2.1:80")),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn ignores_the_header_from_untrusted_peers() {
+        let proxies = proxies();
+        assert_eq!(
+            client_ip(&proxies, "192.0.2.9:4000", Some("10.0.0.1")),
+            ip("192.0.2.9")
+        );
+        assert_eq!(client_ip(&proxies, "10.0.0.1:4000", None), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn malformed_hops_leave_the_client_unknown() {
+        let proxies = proxies();
+        assert_eq!(client_ip(&proxies, "10.0.0.1:4000", Some("unknown")), None);
+        assert_eq!(
+            client_ip(&proxies, "10.0.0.1:4000", Some("192.0.2.1, , 10.0.0.2")),
+            None
+        );
+        // a malformed hop beyond the first untrusted one is never read
+        assert_eq!(
+            client_ip(&proxies, "10.0.0.1:4000", Some("garbage, 192.0.2.1")),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn reads_proxies_and_header_from_config() {
+        let config =
+            ConfigFile::parse("[proxy]\ntrusted = 127.0.0.1, 10.0.0.0/8\nheader = X-Real-IP\n");
+        let proxies = TrustedProxies::from_config(&config).unwrap();
+        assert!(proxies.is_trusted(&"10.9.9.9".parse().unwrap()));
+        assert!(!proxies.is_trusted(&"127.0.0.2".parse().unwrap()));
+        assert_eq!(proxies.header, "X-Real-IP");
+
+        let invalid = ConfigFile::parse("[proxy]\ntrusted = localhost\n");
+        assert!(TrustedProxies::from_config(&invalid).is_err());
+    }
+}
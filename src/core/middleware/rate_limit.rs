@@ -0,0 +1,454 @@
+use super::{has_path_prefix, Middleware};
+use crate::core::error::ServerError;
+use crate::core::http::{HttpRequest, HttpResponse, HttpStatus};
+use crate::core::server::Flag;
+use crate::core::ConfigFile;
+use crate::info;
+use std::collections::HashMap;
+use std::io::Result;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/** How often idle buckets are swept from memory. */
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/**
+    What identifies a client for rate limiting, requests which do not have the key (e.g.
+    anonymous requests when keyed by user) fall back to the peer IP address.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum RateLimitKey {
+    /** The IP address of the client, see `TrustedProxies` for clients behind a proxy. */
+    PeerAddr,
+    /** The authenticated user, requires an `AuthGuard` to run before the rate limit. */
+    User,
+    /** The value of a header such as `X-API-Key`. */
+    Header(String),
+}
+
+/**
+    A limit of `requests` per `period` for every path below a prefix.
+*/
+#[derive(Clone, Debug)]
+struct Limit {
+    prefix: String,
+    requests: u32,
+    period: Duration,
+}
+
+impl Limit {
+    /** Tokens added to a bucket per second. */
+    fn rate(&self) -> f64 {
+        self.requests as f64 / self.period.as_secs_f64()
+    }
+}
+
+/**
+    A token bucket which starts full and refills continuously, each request takes a token.
+*/
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate()).min(limit.requests as f64);
+        self.updated_at = now;
+    }
+
+    /** Seconds until the bucket has `tokens` tokens, rounded up. */
+    fn seconds_until(&self, limit: &Limit, tokens: f64) -> u64 {
+        ((tokens - self.tokens).max(0.0) / limit.rate()).ceil() as u64
+    }
+}
+
+/**
+    Token bucket rate limiting keyed by client, with a limit per route prefix. The most
+    specific prefix which matches the request is used, and each prefix has its own buckets.
+
+    let rate_limit = RateLimit::new(120, Duration::from_secs(60))
+        .route("/account/login", 10, Duration::from_secs(60))
+        .key_by(RateLimitKey::Header("X-API-Key".to_string()));
+
+    server.middleware(rate_limit);
+
+    Every response includes the `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset`
+    and `RateLimit-Policy` headers, and requests over the limit receive a 429 with a
+    `Retry-After` header. Buckets which have refilled completely are dropped, so memory
+    only grows with the number of clients seen within one period.
+*/
+pub struct RateLimit {
+    limits: Vec<Limit>,
+    key: RateLimitKey,
+    buckets: Mutex<HashMap<(usize, String), Bucket>>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl RateLimit {
+    /** Limit every path to `requests` per `period` for each client. */
+    pub fn new(requests: u32, period: Duration) -> Self {
+        RateLimit::empty().route("/", requests, period)
+    }
+
+    /** Create a rate limit with no limits, routes can then be added with `route`. */
+    pub fn empty() -> Self {
+        RateLimit {
+            limits: Vec::new(),
+            key: RateLimitKey::PeerAddr,
+            buckets: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /**
+        Create from the `[rate_limit]` section of a config file, each route is a prefix
+        followed by the number of requests allowed per number of seconds.
+
+        [rate_limit]
+        key = ip                   # or user, or header:X-API-Key
+        route = / 120 60
+        route = /account/login 10 60
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let mut rate_limit = RateLimit::empty();
+        for route in config.get_all("rate_limit.route") {
+            let fields = route.split_whitespace().collect::<Vec<&str>>();
+            let limit = match fields.as_slice() {
+                [prefix, requests, seconds] => requests
+                    .parse::<u32>()
+                    .ok()
+                    .zip(seconds.parse::<u64>().ok())
+                    .map(|(requests, seconds)| (prefix, requests, seconds)),
+                _ => None,
+            };
+            match limit {
+                Some((prefix, requests, seconds)) if requests > 0 && seconds > 0 => {
+                    rate_limit = rate_limit.route(prefix, requests, Duration::from_secs(seconds));
+                }
+                _ => {
+                    return Err(ServerError::error(&format!(
+                        "rate_limit: invalid route {:?}",
+                        route
+                    )))
+                }
+            }
+        }
+        rate_limit.key = match config.get("rate_limit.key") {
+            None | Some("ip") => RateLimitKey::PeerAddr,
+            Some("user") => RateLimitKey::User,
+            Some(key) => match key.strip_prefix("header:") {
+                Some(header) => RateLimitKey::Header(header.trim().to_string()),
+                None => {
+                    return Err(ServerError::error(&format!(
+                        "rate_limit: unknown key {}",
+                        key
+                    )))
+                }
+            },
+        };
+        Ok(rate_limit)
+    }
+
+    /**
+        Limit a path and everything below it to `requests` per `period` for each client,
+        this replaces any limit already set for the same prefix.
+    */
+    pub fn route(mut self, prefix: &str, requests: u32, period: Duration) -> Self {
+        let limit = Limit {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            requests: requests.max(1),
+            period: period.max(Duration::from_millis(1)),
+        };
+        match self.limits.iter_mut().find(|l| l.prefix == limit.prefix) {
+            Some(existing) => *existing = limit,
+            None => self.limits.push(limit),
+        }
+        self
+    }
+
+    pub fn key_by(mut self, key: RateLimitKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /** Whether requests are counted per user, which needs the `AuthGuard` to run first. */
+    pub fn is_keyed_by_user(&self) -> bool {
+        self.key == RateLimitKey::User
+    }
+
+    /**
+        The same limits keyed by client IP with buckets of their own, to run before
+        authentication so requests which fail it are counted too.
+    */
+    pub fn by_ip(&self) -> Self {
+        RateLimit {
+            limits: self.limits.clone(),
+            ..RateLimit::empty()
+        }
+    }
+
+    /** The key for the client which sent the request. */
+    fn client_key(&self, request: &HttpRequest) -> String {
+        let key = match &self.key {
+            RateLimitKey::PeerAddr => None,
+            RateLimitKey::User => request
+                .identity
+                .as_ref()
+                .map(|identity| format!("user:{}", identity.user)),
+            RateLimitKey::Header(name) => request
+                .headers
+                .get(name)
+                .map(|value| format!("header:{}", value)),
+        };
+        key.unwrap_or_else(|| match request.client_ip {
+            Some(ip) => format!("ip:{}", ip),
+            None => "ip:unknown".to_string(),
+        })
+    }
+
+    /** Find the limit with the longest prefix which matches the path. */
+    fn limit_for(&self, path: &str) -> Option<(usize, &Limit)> {
+        self.limits
+            .iter()
+            .enumerate()
+            .filter(|(_, limit)| has_path_prefix(path, &limit.prefix))
+            .max_by_key(|(_, limit)| limit.prefix.len())
+    }
+
+    /** Drop buckets which have refilled completely if the sweep interval has passed. */
+    fn maybe_sweep(&self, buckets: &mut HashMap<(usize, String), Bucket>, now: Instant) {
+        let mut last_sweep = self.last_sweep.lock().unwrap();
+        if now.duration_since(*last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        *last_sweep = now;
+        buckets.retain(|(index, _), bucket| {
+            let limit = &self.limits[*index];
+            now.duration_since(bucket.updated_at) < limit.period
+        });
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+        let path = request.path().to_string();
+        let (index, limit) = match self.limit_for(&path) {
+            Some(limit) => limit,
+            None => return Ok(None),
+        };
+        let key = (index, self.client_key(request));
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        self.maybe_sweep(&mut buckets, now);
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: limit.requests as f64,
+            updated_at: now,
+        });
+        bucket.refill(limit, now);
+        let is_allowed = bucket.tokens >= 1.0;
+        if is_allowed {
+            bucket.tokens -= 1.0;
+        }
+        let remaining = bucket.tokens.floor() as u64;
+        let reset = bucket.seconds_until(limit, limit.requests as f64);
+        let retry_after = bucket.seconds_until(limit, 1.0).max(1);
+        drop(buckets);
+
+        let headers = [
+            ("RateLimit-Limit", limit.requests.to_string()),
+            ("RateLimit-Remaining", remaining.to_string()),
+            ("RateLimit-Reset", reset.to_string()),
+            (
+                "RateLimit-Policy",
+                format!("{};w={}", limit.requests, limit.period.as_secs().max(1)),
+            ),
+        ];
+        if is_allowed {
+            for (name, value) in headers.iter() {
+                request.response.set_header(name, value);
+            }
+            return Ok(None);
+        }
+
+        info!("limited {} {}", client_label(request), path);
+        let mut response = HttpResponse::error(HttpStatus::TooManyRequests);
+        response.set_header("Retry-After", &retry_after.to_string());
+        for (name, value) in headers.iter() {
+            response.set_header(name, value);
+        }
+        request.send(response).map(Some)
+    }
+}
+
+fn client_label(request: &HttpRequest) -> String {
+    match request.client_ip {
+        Some(ip) => ip.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+
+    fn request(path: &str, ip: &str) -> HttpRequest {
+        let mut request = HttpRequest::to(path);
+        request.client_ip = Some(ip.parse().unwrap());
+        request
+    }
+
+    /** Run the middleware on a request which is expected to be refused. */
+    fn refused(rate_limit: &RateLimit, request: &mut HttpRequest) -> HttpResponse {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        request.connection = Some(Arc::new(listener.accept().unwrap().0));
+        assert!(rate_limit.before(request).unwrap().is_some());
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).unwrap();
+        HttpResponse::parse(&data).unwrap()
+    }
+
+    #[test]
+    fn uses_the_most_specific_prefix() {
+        let rate_limit = RateLimit::new(120, Duration::from_secs(60))
+            .route("/account/login/", 10, Duration::from_secs(60))
+            .route("/account", 60, Duration::from_secs(60));
+        let requests = |path: &str| rate_limit.limit_for(path).unwrap().1.requests;
+        assert_eq!(requests("/index.html"), 120);
+        assert_eq!(requests("/account/settings"), 60);
+        assert_eq!(requests("/account/login"), 10);
+        assert_eq!(requests("/account/login/sso"), 10);
+        assert_eq!(requests("/account/logins"), 60);
+
+        let empty = RateLimit::empty().route("/api", 5, Duration::from_secs(1));
+        assert!(empty.limit_for("/index.html").is_none());
+    }
+
+    #[test]
+    fn route_replaces_an_existing_prefix() {
+        let rate_limit = RateLimit::new(120, Duration::from_secs(60))
+            .route("/api", 10, Duration::from_secs(60))
+            .route("/api/", 20, Duration::from_secs(60));
+        assert_eq!(rate_limit.limits.len(), 2);
+        assert_eq!(rate_limit.limit_for("/api").unwrap().1.requests, 20);
+    }
+
+    #[test]
+    fn buckets_refill_at_the_limit_rate() {
+        let limit = Limit {
+            prefix: String::new(),
+            requests: 10,
+            period: Duration::from_secs(10),
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated_at: start,
+        };
+        assert_eq!(bucket.seconds_until(&limit, 1.0), 1);
+        assert_eq!(bucket.seconds_until(&limit, 10.0), 10);
+
+        bucket.refill(&limit, start + Duration::from_millis(2500));
+        assert!((bucket.tokens - 2.5).abs() < 1e-9);
+        assert_eq!(bucket.seconds_until(&limit, 10.0), 8);
+
+        bucket.refill(&limit, start + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 10.0);
+        assert_eq!(bucket.seconds_until(&limit, 10.0), 0);
+    }
+
+    #[test]
+    fn allowed_requests_get_rate_limit_headers() {
+        let rate_limit = RateLimit::new(3, Duration::from_secs(60));
+        let mut request = request("/", "192.0.2.1");
+        assert!(rate_limit.before(&mut request).unwrap().is_none());
+        let header = |name: &str| request.response.headers.get(name).unwrap().clone();
+        assert_eq!(header("RateLimit-Limit"), "3");
+        assert_eq!(header("RateLimit-Remaining"), "2");
+        assert_eq!(header("RateLimit-Reset"), "20");
+        assert_eq!(header("RateLimit-Policy"), "3;w=60");
+    }
+
+    #[test]
+    fn refuses_requests_over_the_limit() {
+        let rate_limit = RateLimit::new(2, Duration::from_secs(60));
+        for _ in 0..2 {
+            let mut request = request("/", "192.0.2.1");
+            assert!(rate_limit.before(&mut request).unwrap().is_none());
+        }
+        let response = refused(&rate_limit, &mut request("/", "192.0.2.1"));
+        assert_eq!(response.status.code(), 429);
+        assert_eq!(response.headers.get("Retry-After").unwrap(), "30");
+        assert_eq!(response.headers.get("RateLimit-Remaining").unwrap(), "0");
+
+        // other clients have their own bucket
+        let mut other = request("/", "192.0.2.2");
+        assert!(rate_limit.before(&mut other).unwrap().is_none());
+    }
+
+    #[test]
+    fn each_prefix_has_its_own_buckets() {
+        let rate_limit =
+            RateLimit::new(10, Duration::from_secs(60)).route("/login", 1, Duration::from_secs(60));
+        assert!(rate_limit
+            .before(&mut request("/login", "192.0.2.1"))
+            .unwrap()
+            .is_none());
+        assert!(rate_limit
+            .before(&mut request("/index.html", "192.0.2.1"))
+            .unwrap()
+            .is_none());
+        let response = refused(&rate_limit, &mut request("/login", "192.0.2.1"));
+        assert_eq!(response.status.code(), 429);
+    }
+
+    #[test]
+    fn keys_by_header_and_falls_back_to_the_ip() {
+        let rate_limit = RateLimit::new(1, Duration::from_secs(60))
+            .key_by(RateLimitKey::Header("X-API-Key".to_string()));
+        let mut keyed = request("/", "192.0.2.1");
+        keyed.headers.set("X-API-Key", "abc");
+        assert_eq!(rate_limit.client_key(&keyed), "header:abc");
+        assert_eq!(
+            rate_limit.client_key(&request("/", "192.0.2.1")),
+            "ip:192.0.2.1"
+        );
+        assert_eq!(rate_limit.client_key(&HttpRequest::to("/")), "ip:unknown");
+
+        // the same key from another address shares the bucket
+        assert!(rate_limit.before(&mut keyed).unwrap().is_none());
+        let mut moved = request("/", "192.0.2.2");
+        moved.headers.set("X-API-Key", "abc");
+        assert_eq!(refused(&rate_limit, &mut moved).status.code(), 429);
+    }
+
+    #[test]
+    fn reads_routes_and_key_from_config() {
+        let config = ConfigFile::parse(
+            "[rate_limit]\nkey = header:X-API-Key\nroute = / 120 60\nroute = /account/login 10 60\n",
+        );
+        let rate_limit = RateLimit::from_config(&config).unwrap();
+        assert_eq!(
+            rate_limit.key,
+            RateLimitKey::Header("X-API-Key".to_string())
+        );
+        let limit = rate_limit.limit_for("/account/login").unwrap().1;
+        assert_eq!((limit.requests, limit.period.as_secs()), (10, 60));
+
+        for invalid in [
+            "route = / 0 60",
+            "route = /",
+            "route = / ten 60",
+            "key = cookie",
+        ] {
+            let config = ConfigFile::parse(&format!("[rate_limit]\n{}\n", invalid));
+            assert!(RateLimit::from_config(&config).is_err(), "{}", invalid);
+        }
+    }
+}
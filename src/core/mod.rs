@@ -1,17 +1,29 @@
+pub mod access_log;
+pub mod auth;
 pub mod cli;
 pub mod config;
 pub mod data;
 pub mod error;
 pub mod file;
+pub mod health;
 pub mod http;
 pub mod http3;
+pub mod json;
+pub mod log;
+pub mod metrics;
+pub mod middleware;
 pub mod server;
+pub mod session;
 pub mod stdout;
 pub mod url;
 pub mod util;
 
 // pub use self::server::Server;
+pub use self::access_log::{AccessLog, LogFormat};
 pub use self::config::Config;
+pub use self::config::ConfigFile;
 pub use self::data::ServerEvent;
+pub use self::health::Health;
+pub use self::metrics::Metrics;
 pub use self::stdout::Stdout;
-pub use self::url::Path;
+pub use self::url::Url;
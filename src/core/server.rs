@@ -1,18 +1,30 @@
-use crate::core::http::{HttpRequest, HttpResponse};
+use crate::core::access_log::AccessLog;
+use crate::core::auth::rbac::Rule;
+use crate::core::auth::Rules;
+use crate::core::health::{self, Health};
+use crate::core::http::http_headers::HttpMethod;
+use crate::core::http::{ConnectionLimits, HttpRequest, HttpResponse, HttpStatus};
+use crate::core::log;
+use crate::core::metrics::{Gauges, Metrics};
+use crate::core::middleware::Middleware;
+use crate::core::url::Normalize;
 use crate::core::util::get_mime_type;
+use crate::core::util::signal::{self, Signal};
 use crate::core::Config;
 use crate::core::ServerEvent;
 use crate::core::Stdout;
+use crate::{debug, error, info, warn};
 
 use std::borrow::BorrowMut;
-use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::format;
 use std::fs;
-use std::io::{BufWriter, Error, ErrorKind, Result, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::Arc;
-use std::time::Duration;
+use std::io::{BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use super::http::HttpConnections;
 
@@ -22,12 +34,61 @@ pub enum Flag {
     EventStream,
 }
 
+/** How long a read or write may block before the connection is dropped. */
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/** How long a graceful shutdown waits for requests in flight once the listener closes. */
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Handler = Box<dyn Fn(&mut HttpRequest) -> Result<Flag> + Send + Sync + 'static>;
+
+/**
+    A dynamic route registered with `Server::route`, which can declare the roles that
+    are allowed to call it.
+
+    server
+        .route("/admin", |sr| sr.send_file("admin.html"))
+        .roles(&["admin"])
+        .method_roles(&[HttpMethod::DELETE], &["owner"]);
+*/
+pub struct Route {
+    handler: Handler,
+    rules: Rules,
+}
+
+impl Route {
+    /** Only allow users with one of these roles to call the route with any method. */
+    pub fn roles(&mut self, roles: &[&str]) -> &mut Self {
+        self.method_roles(&[], roles)
+    }
+
+    /**
+        Only allow users with one of these roles to call the route with the given methods,
+        rules are checked in the order they are declared.
+    */
+    pub fn method_roles(&mut self, methods: &[HttpMethod], roles: &[&str]) -> &mut Self {
+        self.rules.push(Rule::new("*", methods, roles));
+        self
+    }
+}
+
 pub struct Server {
     config: Config,
     tcp_listener: TcpListener,
-    stdout: RefCell<Stdout>,
-    routes: HashMap<String, Box<dyn Fn(&mut HttpRequest) -> Result<Flag> + 'static>>,
+    stdout: Arc<Mutex<Stdout>>,
+    routes: HashMap<String, Route>,
     connections: HttpConnections,
+    middleware: Vec<Box<dyn Middleware>>,
+    authorization: Rules,
+    limits: ConnectionLimits,
+    access_log: Option<AccessLog>,
+    metrics: Metrics,
+    health: Option<Health>,
+    paths: Normalize,
+    shutdown_delay: Option<Duration>,
+    shutting_down: AtomicBool,
+    stopping: AtomicBool,
 }
 
 impl Server {
@@ -39,27 +100,37 @@ impl Server {
             config,
             tcp_listener,
             connections: HttpConnections::new(),
-            stdout: RefCell::new(Stdout::new("./src/data/events.csv", "development")),
+            stdout: Arc::new(Mutex::new(Stdout::new(
+                "./src/data/events.csv",
+                "development",
+            ))),
             routes: HashMap::new(),
+            middleware: Vec::new(),
+            authorization: Rules::new(),
+            limits: ConnectionLimits::new(),
+            access_log: None,
+            metrics: Metrics::new(),
+            health: None,
+            paths: Normalize::new(),
+            shutdown_delay: None,
+            shutting_down: AtomicBool::new(false),
+            stopping: AtomicBool::new(false),
         }
     }
 
-    /** Log a message to the server's stdout. */
-    fn log(&self, _name: &str, data: String) {
-        // self.stdout.borrow_mut().write(name, data.to_string());
-        self.connections.send_event(ServerEvent::data(&data));
+    /** Log a message, which is also written to the server's stdout and `/events`. */
+    fn log(&self, name: &str, data: String) {
+        info!("{}: {}", name, data);
     }
 
-    /** Log error messages to the server's stdout. */
-    fn log_error(&self, _name: &str, data: String) {
-        eprintln!("[server] server error: {}", data);
-        // self.stdout.borrow_mut().write(name, data.to_string());
-        self.connections.send_event(ServerEvent::data(&data));
+    /** Log an error message, which is also written to the server's stdout and `/events`. */
+    fn log_error(&self, name: &str, data: String) {
+        error!("{}: {}", name, data);
     }
 
     /** Create a new server instance bound to a host and port. */
     pub fn bind(host: &str, port: u16) -> Result<Self> {
-        println!("[serveros] binding http://{}:{}/", host, port);
+        info!("binding http://{}:{}/", host, port);
         let config = Config::new(host, port);
         let domain = config.address();
         let connection = TcpListener::bind(&domain)?;
@@ -71,19 +142,157 @@ impl Server {
     /**
         Start the server and handle incoming connections. NOTE: This method is blocking,
         and should be called after all routes have been defined.
+
+        Each connection is handled on its own thread, up to the connection limits. Past
+        the limits, connections are shed with a 503 on the listening thread. Log records
+        are written to the server's stdout and sent to `/events` once it has started.
+
+        With a graceful shutdown this returns once the listener has closed and the requests
+        in flight have finished, after flushing the log file.
     */
     pub fn start(&mut self) {
-        for stream in self.tcp_listener.incoming() {
-            match stream {
-                Err(error) => self.log_error("err_incoming_stream", error.to_string()),
-                Ok(stream) => match self.handle_stream(Arc::new(stream)) {
-                    Ok(_) => (),
-                    Err(err) => {
-                        self.log_error("err_server_start", err.to_string());
-                        eprintln!("[server] error: {}", err)
+        log::add_sink(self.stdout.clone());
+        log::add_sink(Arc::new(self.connections.clone()));
+        let server: &Server = self;
+        thread::scope(|scope| {
+            if let Some(delay) = server.shutdown_delay {
+                let watcher = thread::Builder::new()
+                    .name("shutdown".to_string())
+                    .spawn_scoped(scope, move || server.watch_shutdown(delay));
+                if let Err(err) = watcher {
+                    server.log_error("err_spawn_thread", err.to_string());
+                }
+            }
+            for stream in server.tcp_listener.incoming() {
+                if server.stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        server.log_error("err_incoming_stream", error.to_string());
+                        continue;
+                    }
+                };
+                let slot = match stream.peer_addr() {
+                    Ok(peer_addr) => server.limits.acquire(peer_addr.ip()),
+                    Err(error) => {
+                        server.log_error("err_peer_addr", error.to_string());
+                        continue;
+                    }
+                };
+                let slot = match slot {
+                    Some(slot) => slot,
+                    None => {
+                        server.shed(stream, "too many connections");
+                        continue;
                     }
-                },
+                };
+                let tcp_stream = Arc::new(stream);
+                let handler_stream = Arc::clone(&tcp_stream);
+                let spawned = thread::Builder::new().spawn_scoped(scope, move || {
+                    let _slot = slot;
+                    if let Err(err) = server.handle_stream(handler_stream) {
+                        server.log_error("err_server_start", err.to_string());
+                        error!("error: {}", err)
+                    }
+                });
+                if let Err(err) = spawned {
+                    server.log_error("err_spawn_thread", err.to_string());
+                    if let Ok(stream) = tcp_stream.try_clone() {
+                        server.shed(stream, "failed to start a thread");
+                    }
+                }
+            }
+        });
+        // the lock must be released before logging, the log file is also a sink
+        let flushed = self.stdout.lock().unwrap().flush();
+        if let Err(err) = flushed {
+            warn!("failed to flush the log file: {}", err);
+        }
+        info!("server stopped");
+    }
+
+    /**
+        Wait for `SIGTERM` or `SIGINT`, then fail readiness for the shutdown delay before
+        closing the listener. A second signal closes it straight away, and one more while
+        requests are in flight exits.
+    */
+    fn watch_shutdown(&self, delay: Duration) {
+        let seen = shutdown_signals();
+        while shutdown_signals() == seen {
+            thread::sleep(Duration::from_millis(100));
+        }
+        self.shutting_down.store(true, Ordering::SeqCst);
+        info!("shutting down in {}s", delay.as_secs_f32());
+
+        let seen = shutdown_signals();
+        let started = Instant::now();
+        while started.elapsed() < delay && shutdown_signals() == seen {
+            thread::sleep(Duration::from_millis(100));
+        }
+        info!("closing the listener, waiting for requests in flight");
+        self.stopping.store(true, Ordering::SeqCst);
+
+        // accept blocks until a connection arrives, so make one to wake it up
+        if let Ok(mut addr) = self.tcp_listener.local_addr() {
+            match addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+                IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+                _ => (),
+            }
+            if let Err(err) = TcpStream::connect_timeout(&addr, Duration::from_secs(1)) {
+                warn!("failed to wake the listener: {}", err);
+            }
+        }
+
+        let seen = shutdown_signals();
+        if !self.drain(DRAIN_TIMEOUT, || shutdown_signals() != seen) {
+            let open = self.limits.open_connections();
+            warn!("exiting with {} requests still in flight", open);
+            let _ = self.stdout.lock().unwrap().flush();
+            std::process::exit(1);
+        }
+    }
+
+    /**
+        Wait until no requests are in flight, returns false if the timeout passes or
+        `cancel` returns true first.
+    */
+    fn drain<F: Fn() -> bool>(&self, timeout: Duration, cancel: F) -> bool {
+        let started = Instant::now();
+        while self.limits.open_connections() > 0 {
+            if started.elapsed() >= timeout || cancel() {
+                return false;
             }
+            thread::sleep(Duration::from_millis(100));
+        }
+        true
+    }
+
+    /** Check if a graceful shutdown has started, readiness fails from then on. */
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /**
+        Turn away a connection with a 503 without reading the request, and count it as
+        shed. Whatever part of the request has already arrived is discarded first, so
+        closing the socket does not reset the connection before the client reads the reply.
+    */
+    fn shed(&self, mut tcp_stream: TcpStream, reason: &str) {
+        let total = self.limits.record_shed();
+        warn!("shedding connection: {} ({} shed)", reason, total);
+        let mut response = HttpResponse::error(HttpStatus::ServiceUnavailable);
+        response.set_header("Retry-After", &self.limits.retry_after_secs().to_string());
+        response.set_header("Connection", "close");
+        if tcp_stream.set_nonblocking(true).is_ok() {
+            let mut buffer = [0u8; 4096];
+            while let Ok(1..) = tcp_stream.read(&mut buffer) {}
+            let _ = tcp_stream.set_nonblocking(false);
+        }
+        if let Err(err) = response.send(&mut tcp_stream) {
+            warn!("failed to shed connection: {}", err);
         }
     }
 
@@ -92,24 +301,72 @@ impl Server {
         back to the client either from a route handler or by serving a static file.
     */
     fn handle_stream(&self, tcp_stream: Arc<TcpStream>) -> Result<()> {
-        println!("+--------------------------------------------------------------------------+");
-
-        let _peer_addr = tcp_stream.peer_addr()?;
+        let received_at = SystemTime::now();
+        let started = Instant::now();
+        let peer_addr = tcp_stream.peer_addr()?;
+        // a client which stops sending or reading must not hold its thread forever
+        tcp_stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        tcp_stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
         let mut request = HttpRequest::from(tcp_stream)?;
+        let _scope = log::request_scope(&request.request_id);
+        request.peer_addr = Some(peer_addr);
+        request.client_ip = Some(peer_addr.ip());
+        // a path which can not be decoded is refused, and one which is not canonical may be
+        // redirected, before any middleware sees it
+        let sent = match request.normalize_path(&self.paths) {
+            Ok(None) => None,
+            Ok(Some(location)) => Some(request.send(HttpResponse::permanent_redirect(&location))),
+            Err(err) => {
+                debug!("invalid path {:?}: {}", request.headers.uri, err);
+                Some(request.send(HttpResponse::error(HttpStatus::BadRequest)))
+            }
+        };
         let url = request.url();
+        let path = request.path().to_string();
 
-        self.log("network_request", request.info());
+        debug!("request headers: {}", request.info());
+        self.log(
+            "network_request",
+            format!("{} {}", request.headers.method_string(), url),
+        );
 
-        let route_flag = match self.routes.get(&url) {
-            Some(handler) => handler(&mut request),
-            None => request.serve_static_file(),
+        let handled = match sent {
+            Some(result) => result.map(Some),
+            None => self.run_middleware(&mut request),
+        };
+        let route_flag = match handled {
+            Ok(Some(flag)) => Ok(flag),
+            Ok(None) if !self.is_authorized(&request) => {
+                let method = request.headers.method.clone();
+                self.deny(&mut request, &method)
+            }
+            Ok(None) if wants_event_stream(&request) && !self.allows_stream(&request) => {
+                self.shed_stream(&mut request)
+            }
+            Ok(None) if self.metrics.path() == Some(path.as_str()) => {
+                self.send_metrics(&mut request)
+            }
+            Ok(None) if self.is_health_path(&path) => self.send_health(&mut request),
+            Ok(None) => match self.routes.get(&path) {
+                Some(route) => (route.handler)(&mut request),
+                None => request.serve_static_file(),
+            },
+            Err(err) => Err(err),
         };
 
         let did_handle = match route_flag {
             Ok(Flag::StaticFile) => Ok(()),
             Ok(Flag::DynamicRoute) => Ok(()),
             Ok(Flag::EventStream) => {
-                println!("[server] adding event stream...");
+                self.log_access(&request, received_at, started);
+                self.record_metrics(&request, started);
+                // the handler may have opened a stream the client did not ask for
+                if !self.allows_stream(&request) {
+                    let total = self.limits.record_shed();
+                    warn!("closing event stream: too many streams ({} shed)", total);
+                    return Ok(());
+                }
+                debug!("adding event stream...");
                 self.connections.add_stream(request);
                 return Ok(());
             }
@@ -121,22 +378,482 @@ impl Server {
 
         // debugging
         if did_handle.is_err() {
-            println!("[server] could not handle request: {:?}", url);
+            warn!("could not handle request: {:?}", url);
             self.log_error("err_url_not_handled", url.to_string())
         }
 
         // send a 404 if the request was not handled
-        did_handle.or(request.send_404())
+        let result = did_handle.or_else(|_| request.send_404());
+        self.log_access(&request, received_at, started);
+        self.record_metrics(&request, started);
+        result
+    }
+
+    /** Write the access log line for a completed request. */
+    fn log_access(&self, request: &HttpRequest, received_at: SystemTime, started: Instant) {
+        if let Some(access_log) = &self.access_log {
+            access_log.record(request, received_at, started.elapsed());
+        }
+    }
+
+    /**
+        Count a completed request under its route, requests for static files and unknown
+        paths are counted together.
+    */
+    fn record_metrics(&self, request: &HttpRequest, started: Instant) {
+        self.metrics.record(
+            request.path(),
+            &request.headers.method,
+            request.response_status.unwrap_or(0),
+            started.elapsed(),
+            request.bytes_read,
+            request.bytes_written,
+        );
+    }
+
+    fn is_health_path(&self, url: &str) -> bool {
+        self.health
+            .as_ref()
+            .is_some_and(|health| url == health.live_path() || url == health.ready_path())
+    }
+
+    /**
+        Send the liveness or readiness report. Readiness also checks that the server is not
+        shutting down and that its log file can be written.
+    */
+    fn send_health(&self, request: &mut HttpRequest) -> Result<Flag> {
+        let health = match &self.health {
+            Some(health) => health,
+            None => return request.send(HttpResponse::error(HttpStatus::NotFound)),
+        };
+        let mut results = Vec::new();
+        if request.path() == health.ready_path() {
+            let shutdown = match self.is_shutting_down() {
+                true => Err("shutting down".to_string()),
+                false => Ok(()),
+            };
+            let log_file = self.stdout.lock().unwrap().path().to_string();
+            results.push(("shutdown".to_string(), shutdown));
+            results.push(("log_file".to_string(), health::file_writable(&log_file)()));
+            results.extend(health.run_checks());
+        }
+        let (status, body) = Health::report(&results);
+        if status.code() != 200 {
+            warn!("not ready: {}", body);
+        }
+        let mut response = HttpResponse::new();
+        response.set_status(status);
+        response.set_body(body.to_string().into_bytes(), "application/json");
+        response.set_header("Cache-Control", "no-store");
+        request.send(response)
+    }
+
+    /** Send the metrics in the Prometheus text format. */
+    fn send_metrics(&self, request: &mut HttpRequest) -> Result<Flag> {
+        let gauges = Gauges {
+            open_connections: self.limits.open_connections(),
+            event_streams: self.connections.len(),
+            keep_alive_active: self.connections.is_keep_alive_active(),
+            shed_connections: self.limits.shed_count(),
+        };
+        let mut response = HttpResponse::new();
+        response.set_body(
+            self.metrics.render(&gauges).into_bytes(),
+            "text/plain; version=0.0.4; charset=utf-8",
+        );
+        response.set_header("Cache-Control", "no-store");
+        request.send(response)
+    }
+
+    /**
+        Run each middleware in the order it was registered, stopping at the first one
+        which has already sent a response. Middleware which serves requests itself is only
+        called once the request is authorized, see `Middleware::serves`.
+    */
+    fn run_middleware(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+        for middleware in self.middleware.iter() {
+            // middleware which serves the request itself is authorized like a route
+            if let Some(method) = middleware.serves(request) {
+                if !self.is_allowed(request, &method) {
+                    return self.deny(request, &method).map(Some);
+                }
+            }
+            if let Some(flag) = middleware.before(request)? {
+                return Ok(Some(flag));
+            }
+        }
+        Ok(None)
+    }
+
+    /**
+        Check the authenticated identity against the rules declared on the route and the
+        server's rules table, both of which must allow the request.
+    */
+    fn is_authorized(&self, request: &HttpRequest) -> bool {
+        self.is_allowed(request, &request.headers.method)
+    }
+
+    /** Check the rules for a request as if it had been sent with another method. */
+    fn is_allowed(&self, request: &HttpRequest, method: &HttpMethod) -> bool {
+        let path = request.path();
+        let identity = request.identity.as_ref();
+        let route_allows = match self.routes.get(path) {
+            Some(route) => route.rules.is_allowed(path, method, identity),
+            None => true,
+        };
+        route_allows && self.authorization.is_allowed(path, method, identity)
+    }
+
+    /**
+        Send a 403 for a request which is not authorized and record it in the audit log,
+        which the log filter can not turn off.
+    */
+    fn deny(&self, request: &mut HttpRequest, method: &HttpMethod) -> Result<Flag> {
+        let user = match &request.identity {
+            Some(identity) => format!("{} ({})", identity.user, identity.scheme),
+            None => "anonymous".to_string(),
+        };
+        log::audit(format_args!(
+            "access_denied: {} to {} {}",
+            user,
+            method.as_str(),
+            request.url()
+        ));
+        request.send(HttpResponse::error(HttpStatus::Forbidden))
     }
 
     /**
-        Register a route handler.
+        Check if the client may open another event stream without going over the limits.
     */
-    pub fn route<F>(&mut self, path: &str, handler: F)
+    fn allows_stream(&self, request: &HttpRequest) -> bool {
+        let from_ip = match &request.client_ip {
+            Some(ip) => self.connections.count_from(ip),
+            None => 0,
+        };
+        self.limits.allows_stream(self.connections.len(), from_ip)
+    }
+
+    /**
+        Send a 503 for an event stream over the limits, before the handler opens it.
+    */
+    fn shed_stream(&self, request: &mut HttpRequest) -> Result<Flag> {
+        let total = self.limits.record_shed();
+        warn!("shedding event stream: too many streams ({} shed)", total);
+        let mut response = HttpResponse::error(HttpStatus::ServiceUnavailable);
+        response.set_header("Retry-After", &self.limits.retry_after_secs().to_string());
+        request.send(response)
+    }
+
+    /**
+        Set the rules table which decides which roles may access which paths, this is
+        checked in addition to any roles declared with `Route::roles`.
+    */
+    pub fn authorize(&mut self, rules: Rules) {
+        self.authorization = rules;
+    }
+
+    /**
+        Set the limits on open connections and event streams, see `ConnectionLimits`.
+    */
+    pub fn connection_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = limits;
+    }
+
+    /** The number of requests which have been shed because of the connection limits. */
+    pub fn shed_count(&self) -> u64 {
+        self.limits.shed_count()
+    }
+
+    /**
+        Replace the log file written by the server, e.g. to enable rotation, see `Stdout`.
+    */
+    pub fn stdout(&mut self, stdout: Stdout) {
+        self.stdout = Arc::new(Mutex::new(stdout));
+    }
+
+    /**
+        Write a line to the access log for every completed request, see `AccessLog`.
+    */
+    pub fn access_log(&mut self, access_log: AccessLog) {
+        self.access_log = Some(access_log);
+    }
+
+    /**
+        Serve the request counts, latencies and server state at a path in the Prometheus text
+        format, see `Metrics`. The path can be restricted like any route with `authorize`.
+    */
+    pub fn metrics(&mut self, path: &str) {
+        self.metrics.set_path(path);
+    }
+
+    /**
+        Serve liveness and readiness endpoints, see `Health`. When the health has a shutdown
+        delay a graceful shutdown is enabled with it, so readiness fails before the listener
+        closes.
+    */
+    pub fn health(&mut self, health: Health) {
+        self.metrics.add_route(health.live_path());
+        self.metrics.add_route(health.ready_path());
+        if let Some(delay) = health.shutdown_delay() {
+            self.graceful_shutdown(delay);
+        }
+        self.health = Some(health);
+    }
+
+    /**
+        Handle `SIGTERM` and `SIGINT` by failing readiness for a delay, then closing the
+        listener and letting `start` return once the requests in flight have finished. If
+        they are still running after 30 seconds, or another signal arrives, the process
+        exits without waiting for them.
+    */
+    pub fn graceful_shutdown(&mut self, delay: Duration) {
+        signal::listen(Signal::Terminate);
+        signal::listen(Signal::Interrupt);
+        self.shutdown_delay = Some(delay);
+    }
+
+    /**
+        Set how request paths are normalized before middleware and routing, by default
+        escapes, dot segments and duplicate slashes are resolved in place, see `Normalize`.
+    */
+    pub fn normalize_paths(&mut self, normalize: Normalize) {
+        self.paths = normalize;
+    }
+
+    /**
+        Register middleware which runs before every route handler and static file.
+    */
+    pub fn middleware<M>(&mut self, middleware: M)
+    where
+        M: Middleware + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /**
+        Register a route handler, the returned route can be used to declare which roles
+        are allowed to call it.
+    */
+    pub fn route<F>(&mut self, path: &str, handler: F) -> &mut Route
     where
-        F: Fn(&mut HttpRequest) -> Result<Flag> + 'static,
+        F: Fn(&mut HttpRequest) -> Result<Flag> + Send + Sync + 'static,
     {
-        println!("[server] dynamic route: {}", path);
-        self.routes.insert(path.to_string(), Box::new(handler));
+        debug!("dynamic route: {}", path);
+        let route = Route {
+            handler: Box::new(handler),
+            rules: Rules::new(),
+        };
+        self.metrics.add_route(path);
+        self.routes.insert(path.to_string(), route);
+        self.routes.get_mut(path).unwrap()
+    }
+}
+
+/** The number of shutdown signals received so far. */
+fn shutdown_signals() -> u64 {
+    signal::received(Signal::Terminate) + signal::received(Signal::Interrupt)
+}
+
+/** Check if the client asked for an event stream, as `EventSource` does. */
+fn wants_event_stream(request: &HttpRequest) -> bool {
+    request
+        .headers
+        .get("Accept")
+        .is_some_and(|accept| accept.contains("text/event-stream"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::{AuthGuard, BasicAuth, Htpasswd, Identity};
+    use crate::core::http::Tus;
+    use crate::core::middleware::RateLimit;
+    use crate::core::ConfigFile;
+    use std::net::Shutdown;
+
+    fn server() -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        Server::new(listener, Config::new("127.0.0.1", 0))
+    }
+
+    /** Handle a raw request as an accepted connection and read back the response. */
+    fn exchange(server: &Server, raw: &str) -> HttpResponse {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(raw.as_bytes()).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let stream = listener.accept().unwrap().0;
+        server.handle_stream(Arc::new(stream)).unwrap();
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).unwrap();
+        HttpResponse::parse(&data).unwrap()
+    }
+
+    /** Signs every request in as a user with the given roles. */
+    struct SignIn(&'static [&'static str]);
+
+    impl Middleware for SignIn {
+        fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+            let roles = self
+                .0
+                .iter()
+                .map(|role| role.to_string())
+                .collect::<Vec<_>>();
+            request.identity = Some(Identity::new("ada", "test").with_roles(&roles));
+            Ok(None)
+        }
+    }
+
+    fn rules(text: &str) -> Rules {
+        Rules::from_config(&ConfigFile::parse(&format!("[authorization]\n{}", text))).unwrap()
+    }
+
+    fn tus_request(method: &str, path: &str, headers: &str) -> String {
+        format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nTus-Resumable: 1.0.0\r\n{}\r\n",
+            method, path, headers
+        )
+    }
+
+    #[test]
+    fn counts_failed_authentication_towards_the_rate_limit() {
+        let mut server = server();
+        server.middleware(RateLimit::new(2, Duration::from_secs(60)));
+        server.middleware(AuthGuard::new(BasicAuth::new("admin", Htpasswd::new())).protect("/"));
+
+        let raw = "GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic YWRhOndyb25n\r\n\r\n";
+        assert_eq!(exchange(&server, raw).status.code(), 401);
+        assert_eq!(exchange(&server, raw).status.code(), 401);
+        let response = exchange(&server, raw);
+        assert_eq!(response.status.code(), 429);
+        assert!(response.headers.get("Retry-After").is_some());
+    }
+
+    #[test]
+    fn drops_a_client_which_stops_sending() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: local").unwrap();
+        let stream = listener.accept().unwrap().0;
+
+        let started = Instant::now();
+        let _ = server().handle_stream(Arc::new(stream));
+        assert!(started.elapsed() >= READ_TIMEOUT);
+        assert!(started.elapsed() < READ_TIMEOUT * 2);
+    }
+
+    #[test]
+    fn stops_draining_at_the_timeout_or_when_cancelled() {
+        let server = server();
+        assert!(server.drain(Duration::ZERO, || false));
+
+        let slot = server
+            .limits
+            .acquire(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .unwrap();
+        assert!(!server.drain(Duration::from_millis(200), || false));
+        assert!(!server.drain(Duration::from_secs(60), || true));
+        drop(slot);
+        assert!(server.drain(Duration::from_secs(60), || false));
+    }
+
+    #[test]
+    fn health_without_a_delay_leaves_signals_alone() {
+        let mut server = server();
+        server.health(Health::new());
+        assert_eq!(server.shutdown_delay, None);
+        server.health(Health::new().shutdown_after(Duration::from_secs(5)));
+        assert_eq!(server.shutdown_delay, Some(Duration::from_secs(5)));
+    }
+
+    /** Collects the audit records for one path, since sinks are global. */
+    struct Audit(&'static str, Mutex<Vec<log::Record>>);
+
+    impl log::Sink for Audit {
+        fn write(&self, record: &log::Record) {
+            if record.target == log::AUDIT_TARGET && record.message.contains(self.0) {
+                self.1.lock().unwrap().push(record.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn records_denied_requests_in_the_audit_log() {
+        let audit = Arc::new(Audit("/audited", Mutex::new(Vec::new())));
+        log::add_sink(audit.clone());
+        let mut server = server();
+        server.middleware(SignIn(&["viewer"]));
+        server.authorize(rules("rule = /audited* * admin\n"));
+
+        let response = exchange(
+            &server,
+            "DELETE /audited HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(response.status.code(), 403);
+        let records = audit.1.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].level, log::Level::Warn);
+        assert_eq!(
+            records[0].message,
+            "access_denied: ada (test) to DELETE /audited"
+        );
+    }
+
+    const UPLOAD: &str = "/files/0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn authorizes_middleware_which_serves_requests() {
+        let dir = std::env::temp_dir().join(format!("server_tus_{}", std::process::id()));
+        let mut server = server();
+        server.middleware(Tus::new(&dir));
+        server.authorize(rules("rule = /files* * uploader\n"));
+
+        let patch = "Content-Type: application/offset+octet-stream\r\nUpload-Offset: 0\r\nContent-Length: 0\r\n";
+        let response = exchange(&server, &tus_request("PATCH", UPLOAD, patch));
+        assert_eq!(response.status.code(), 403);
+        let response = exchange(
+            &server,
+            &tus_request("POST", "/files", "Upload-Length: 5\r\n"),
+        );
+        assert_eq!(response.status.code(), 403);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn authorizes_the_overridden_method() {
+        let dir = std::env::temp_dir().join(format!("server_override_{}", std::process::id()));
+        let mut server = server();
+        server.middleware(SignIn(&["viewer"]));
+        server.middleware(Tus::new(&dir));
+        server.authorize(rules("rule = /files* DELETE,PATCH admin\n"));
+
+        let response = exchange(
+            &server,
+            &tus_request("POST", "/files", "Upload-Length: 5\r\n"),
+        );
+        assert_eq!(response.status.code(), 201);
+        let location = response.headers.get("Location").unwrap().clone();
+        let delete = "X-HTTP-Method-Override: DELETE\r\n";
+        let response = exchange(&server, &tus_request("POST", &location, delete));
+        assert_eq!(response.status.code(), 403);
+        let response = exchange(&server, &tus_request("HEAD", &location, ""));
+        assert_eq!(response.status.code(), 200);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lets_allowed_users_through_to_middleware() {
+        let dir = std::env::temp_dir().join(format!("server_uploader_{}", std::process::id()));
+        let mut server = server();
+        server.middleware(SignIn(&["uploader"]));
+        server.middleware(Tus::new(&dir));
+        server.authorize(rules("rule = /files* * uploader\n"));
+
+        let response = exchange(
+            &server,
+            &tus_request("POST", "/files", "Upload-Length: 5\r\n"),
+        );
+        assert_eq!(response.status.code(), 201);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,285 @@
+use super::{is_session_id, PurgeTimer, SessionData, SessionStore, PURGE_INTERVAL};
+use crate::core::error::ServerError;
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/** Numbers the temporary files so concurrent saves never share one. */
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/**
+    File based session store which keeps one file per session in a directory, so sessions
+    survive a restart. The first line of each file is the expiry time in unix seconds and
+    every following line is a tab separated key and value.
+*/
+pub struct FileStore {
+    directory: PathBuf,
+    purge: PurgeTimer,
+}
+
+impl FileStore {
+    /** Create a file store in the given directory, which is created if it does not exist. */
+    pub fn new(directory: &str) -> Result<Self> {
+        fs::create_dir_all(directory)?;
+        Ok(FileStore {
+            directory: PathBuf::from(directory),
+            purge: PurgeTimer::new(),
+        })
+    }
+
+    /** A temporary file name which is unique to this write, e.g. `<id>.4211-7.tmp`. */
+    fn temp_path(&self, id: &str) -> PathBuf {
+        let count = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{}.{}-{}.tmp", id, std::process::id(), count);
+        self.directory.join(name)
+    }
+
+    fn session_path(&self, id: &str) -> Result<PathBuf> {
+        match is_session_id(id) {
+            true => Ok(self.directory.join(format!("{}.session", id))),
+            false => Err(ServerError::error("invalid session id")),
+        }
+    }
+
+    fn read(&self, id: &str) -> Option<(u64, SessionData)> {
+        let contents = fs::read_to_string(self.session_path(id).ok()?).ok()?;
+        let mut lines = contents.lines();
+        let expires_at = lines.next()?.trim().parse::<u64>().ok()?;
+        let data = lines
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(key, value)| (unescape(key), unescape(value)))
+            .collect();
+        Some((expires_at, data))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let (expires_at, data) = self.read(id)?;
+        if expires_at <= unix_now() {
+            let _ = self.remove(id);
+            return None;
+        }
+        Some(data)
+    }
+
+    /**
+        Write the session to a temporary file and rename it into place, so a concurrent
+        read never sees a partially written session. Each write has its own temporary
+        file, so concurrent saves of the same session can not mix their contents.
+    */
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<()> {
+        self.purge.maybe_purge(self);
+        let path = self.session_path(id)?;
+        let mut contents = format!("{}\n", unix_now() + ttl.as_secs());
+        for (key, value) in data.iter() {
+            contents.push_str(&format!("{}\t{}\n", escape(key), escape(value)));
+        }
+        let temp_path = self.temp_path(id);
+        if let Err(err) = fs::write(&temp_path, contents) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
+        fs::rename(temp_path, path)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        match fs::remove_file(self.session_path(id)?) {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /**
+        Remove expired and unreadable session files, along with temporary files left by a
+        write which was interrupted.
+    */
+    fn purge_expired(&self) {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        let now = unix_now();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(id) = name.strip_suffix(".session") {
+                match self.read(id) {
+                    Some((expires_at, _)) if expires_at > now => {}
+                    _ => {
+                        let _ = fs::remove_file(entry.path());
+                    }
+                }
+            } else if name.ends_with(".tmp") && is_stale(&entry) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/** A temporary file which has not been renamed into place within the purge interval. */
+fn is_stale(entry: &fs::DirEntry) -> bool {
+    entry
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age >= PURGE_INTERVAL)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/** Escape backslashes, tabs and line breaks so each entry fits on a single line. */
+fn escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '\t' => output.push_str("\\t"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+fn unescape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => output.push('\t'),
+            Some('n') => output.push('\n'),
+            Some('r') => output.push('\r'),
+            Some(other) => output.push(other),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn store(name: &str) -> FileStore {
+        let directory =
+            std::env::temp_dir().join(format!("server-sessions-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        FileStore::new(directory.to_str().unwrap()).unwrap()
+    }
+
+    fn id(n: u8) -> String {
+        format!("{:02x}", n).repeat(32)
+    }
+
+    fn files(store: &FileStore) -> Vec<String> {
+        let mut names = fs::read_dir(&store.directory)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn saves_and_loads_escaped_values() {
+        let store = store("roundtrip");
+        let mut data = SessionData::new();
+        data.insert("user".to_string(), "ada".to_string());
+        data.insert("note\tkey".to_string(), "line\nbreak \\ tab\t".to_string());
+        store.save(&id(1), &data, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.load(&id(1)), Some(data));
+        assert_eq!(files(&store), vec![format!("{}.session", id(1))]);
+        let _ = fs::remove_dir_all(&store.directory);
+    }
+
+    #[test]
+    fn expired_sessions_are_not_loaded() {
+        let store = store("expired");
+        store
+            .save(&id(2), &SessionData::new(), Duration::ZERO)
+            .unwrap();
+        assert_eq!(store.load(&id(2)), None);
+        assert!(files(&store).is_empty());
+        let _ = fs::remove_dir_all(&store.directory);
+    }
+
+    #[test]
+    fn refuses_ids_which_are_not_file_names() {
+        let store = store("ids");
+        let data = SessionData::new();
+        assert!(store
+            .save("../escape", &data, Duration::from_secs(60))
+            .is_err());
+        assert_eq!(store.load("../escape"), None);
+        let _ = fs::remove_dir_all(&store.directory);
+    }
+
+    #[test]
+    fn purge_removes_expired_and_stale_files() {
+        let store = store("purge");
+        let data = SessionData::new();
+        store.save(&id(3), &data, Duration::from_secs(60)).unwrap();
+        store.save(&id(4), &data, Duration::ZERO).unwrap();
+        fs::write(
+            store.directory.join(format!("{}.session", id(5))),
+            "garbage",
+        )
+        .unwrap();
+        let stale = store.directory.join(format!("{}.1-1.tmp", id(6)));
+        let stale_file = fs::File::create(&stale).unwrap();
+        stale_file
+            .set_modified(SystemTime::now() - PURGE_INTERVAL * 2)
+            .unwrap();
+        let fresh = store.directory.join(format!("{}.1-2.tmp", id(7)));
+        fs::write(&fresh, "").unwrap();
+
+        store.purge_expired();
+        assert_eq!(
+            files(&store),
+            vec![format!("{}.session", id(3)), format!("{}.1-2.tmp", id(7))]
+        );
+        let _ = fs::remove_dir_all(&store.directory);
+    }
+
+    #[test]
+    fn concurrent_saves_do_not_share_a_temporary_file() {
+        let store = Arc::new(store("concurrent"));
+        let threads = (0..8)
+            .map(|n| {
+                let store = Arc::clone(&store);
+                thread::spawn(move || {
+                    let mut data = SessionData::new();
+                    data.insert("writer".to_string(), n.to_string().repeat(4096));
+                    for _ in 0..20 {
+                        store.save(&id(8), &data, Duration::from_secs(60)).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let data = store.load(&id(8)).unwrap();
+        let value = &data["writer"];
+        assert_eq!(value.len(), 4096);
+        assert!(value.chars().all(|c| c == value.chars().next().unwrap()));
+        assert_eq!(files(&store), vec![format!("{}.session", id(8))]);
+        let _ = fs::remove_dir_all(&store.directory);
+    }
+}
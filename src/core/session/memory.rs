@@ -0,0 +1,148 @@
+use super::{PurgeTimer, SessionData, SessionStore};
+use std::collections::HashMap;
+use std::io::Result;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    data: SessionData,
+    expires_at: Instant,
+}
+
+/**
+    In-memory session store, sessions expire after their time-to-live and are lost when
+    the server restarts.
+*/
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, Entry>>,
+    purge: PurgeTimer,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+            purge: PurgeTimer::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.data.clone()),
+            Some(_) => {
+                sessions.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<()> {
+        self.purge.maybe_purge(self);
+        let entry = Entry {
+            data: data.clone(),
+            expires_at: Instant::now() + ttl,
+        };
+        self.sessions.lock().unwrap().insert(id.to_string(), entry);
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn purge_expired(&self) {
+        let now = Instant::now();
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, entry| entry.expires_at > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::PURGE_INTERVAL;
+
+    fn data(user: &str) -> SessionData {
+        let mut data = SessionData::new();
+        data.insert("user".to_string(), user.to_string());
+        data
+    }
+
+    #[test]
+    fn loads_sessions_until_they_expire() {
+        let store = MemoryStore::new();
+        store
+            .save("a", &data("ada"), Duration::from_secs(60))
+            .unwrap();
+        store.save("b", &data("bob"), Duration::ZERO).unwrap();
+        assert_eq!(store.load("a"), Some(data("ada")));
+        assert_eq!(store.load("unknown"), None);
+
+        // an expired session is dropped when it is loaded
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.load("b"), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn removes_sessions() {
+        let store = MemoryStore::new();
+        store
+            .save("a", &data("ada"), Duration::from_secs(60))
+            .unwrap();
+        store.remove("a").unwrap();
+        store.remove("unknown").unwrap();
+        assert_eq!(store.load("a"), None);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn purges_expired_sessions() {
+        let store = MemoryStore::new();
+        store
+            .save("a", &data("ada"), Duration::from_secs(60))
+            .unwrap();
+        store.save("b", &data("bob"), Duration::ZERO).unwrap();
+        store.save("c", &data("cy"), Duration::ZERO).unwrap();
+        store.purge_expired();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.load("a"), Some(data("ada")));
+    }
+
+    #[test]
+    fn purges_on_save_once_the_interval_has_passed() {
+        let store = MemoryStore::new();
+        store.save("b", &data("bob"), Duration::ZERO).unwrap();
+        store
+            .save("a", &data("ada"), Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(store.len(), 2);
+
+        *store.purge.last_purge.lock().unwrap() = Instant::now() - PURGE_INTERVAL;
+        store
+            .save("a", &data("ada"), Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(store.len(), 1);
+    }
+}
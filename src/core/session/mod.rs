@@ -0,0 +1,391 @@
+pub mod file;
+pub mod memory;
+
+pub use self::file::FileStore;
+pub use self::memory::MemoryStore;
+
+use crate::core::http::{Cookie, HttpRequest, SameSite};
+use crate::core::middleware::Middleware;
+use crate::core::server::Flag;
+use crate::core::util::{constant_time_eq, hex_encode, hmac_sha256, secure_random_bytes};
+use std::collections::HashMap;
+use std::io::Result;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub type SessionData = HashMap<String, String>;
+
+/** How often stores sweep expired sessions. */
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/** Number of random bytes in a session ID, which is hex encoded in the cookie. */
+const SESSION_ID_BYTES: usize = 32;
+
+/**
+    Storage backend for session data, implementations must be safe to share between
+    threads and are responsible for expiring sessions once their time-to-live has passed.
+*/
+pub trait SessionStore: Send + Sync {
+    /** Load the data for a session, returns None if the session is unknown or expired. */
+    fn load(&self, id: &str) -> Option<SessionData>;
+
+    /** Save the data for a session which will expire after the given time-to-live. */
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<()>;
+
+    /** Remove a session from the store. */
+    fn remove(&self, id: &str) -> Result<()>;
+
+    /** Remove all expired sessions from the store. */
+    fn purge_expired(&self) {}
+}
+
+/**
+    When a store last swept its expired sessions, so stores can purge on save at most once
+    per purge interval.
+*/
+struct PurgeTimer {
+    last_purge: Mutex<Instant>,
+}
+
+impl PurgeTimer {
+    fn new() -> Self {
+        PurgeTimer {
+            last_purge: Mutex::new(Instant::now()),
+        }
+    }
+
+    /** Purge expired sessions if the purge interval has passed since the last sweep. */
+    fn maybe_purge(&self, store: &dyn SessionStore) {
+        let mut last_purge = self.last_purge.lock().unwrap();
+        if last_purge.elapsed() >= PURGE_INTERVAL {
+            *last_purge = Instant::now();
+            drop(last_purge);
+            store.purge_expired();
+        }
+    }
+}
+
+/**
+    Shared configuration for the session cookie.
+*/
+#[derive(Clone, Debug)]
+pub struct SessionConfig {
+    pub cookie_name: String,
+    pub path: String,
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub ttl: Duration,
+    secret: Vec<u8>,
+}
+
+impl SessionConfig {
+    /**
+        Sign a session ID with HMAC-SHA256, the cookie value is `{id}.{signature}`.
+    */
+    fn sign(&self, id: &str) -> String {
+        let signature = hmac_sha256(&self.secret, id.as_bytes());
+        format!("{}.{}", id, hex_encode(&signature))
+    }
+
+    /**
+        Verify a signed cookie value and return the session ID if the signature is valid.
+    */
+    fn verify(&self, value: &str) -> Option<String> {
+        let (id, _) = value.split_once('.')?;
+        if !is_session_id(id) {
+            return None;
+        }
+        let expected = self.sign(id);
+        match constant_time_eq(expected.as_bytes(), value.as_bytes()) {
+            true => Some(id.to_string()),
+            false => None,
+        }
+    }
+
+    fn cookie(&self, value: &str) -> Cookie {
+        Cookie::new(&self.cookie_name, value)
+            .path(&self.path)
+            .max_age(self.ttl.as_secs() as i64)
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site.clone())
+    }
+}
+
+/**
+    Check a session ID is the expected length of lowercase hex, which also guarantees it is
+    safe to use as a file name in the `FileStore`.
+*/
+pub fn is_session_id(id: &str) -> bool {
+    id.len() == SESSION_ID_BYTES * 2 && id.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/**
+    Generate a new random session ID from the operating system's secure random source.
+*/
+pub fn generate_session_id() -> Result<String> {
+    Ok(hex_encode(&secure_random_bytes(SESSION_ID_BYTES)?))
+}
+
+/**
+    The session for the current request, which is available to handlers through
+    `request.session()` once the `Sessions` middleware has been registered.
+
+    Changes are written to the store immediately, and a new session is only saved (and its
+    cookie sent) once a value has been set on it.
+*/
+#[derive(Clone)]
+pub struct Session {
+    id: String,
+    data: SessionData,
+    store: Arc<dyn SessionStore>,
+    config: Arc<SessionConfig>,
+    is_saved: bool,
+    cookie: Option<Cookie>,
+}
+
+impl Session {
+    fn new(store: Arc<dyn SessionStore>, config: Arc<SessionConfig>) -> Result<Self> {
+        Ok(Session {
+            id: generate_session_id()?,
+            data: SessionData::new(),
+            store,
+            config,
+            is_saved: false,
+            cookie: None,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /** True if this session has not been saved to the store yet. */
+    pub fn is_new(&self) -> bool {
+        !self.is_saved
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.data.get(key)
+    }
+
+    pub fn data(&self) -> &SessionData {
+        &self.data
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        self.data.insert(key.to_string(), value.to_string());
+        self.save()
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<Option<String>> {
+        let value = self.data.remove(key);
+        if value.is_some() {
+            self.save()?;
+        }
+        Ok(value)
+    }
+
+    /**
+        Issue a new session ID while keeping the session data, this should be called whenever
+        the privilege level changes (e.g. on login) to prevent session fixation.
+    */
+    pub fn rotate(&mut self) -> Result<()> {
+        if self.is_saved {
+            self.store.remove(&self.id)?;
+        }
+        self.id = generate_session_id()?;
+        self.is_saved = false;
+        self.save()
+    }
+
+    /**
+        Remove the session from the store and tell the client to delete the cookie, a new
+        empty session is started in its place.
+    */
+    pub fn destroy(&mut self) -> Result<()> {
+        if self.is_saved {
+            self.store.remove(&self.id)?;
+        }
+        self.id = generate_session_id()?;
+        self.data.clear();
+        self.is_saved = false;
+        self.cookie = Some(Cookie::removal(&self.config.cookie_name).path(&self.config.path));
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        self.store.save(&self.id, &self.data, self.config.ttl)?;
+        if !self.is_saved {
+            self.is_saved = true;
+            self.cookie = Some(self.config.cookie(&self.config.sign(&self.id)));
+        }
+        Ok(())
+    }
+
+    /**
+        Take the cookie which must be sent to the client, if the session was created,
+        rotated or destroyed during this request.
+    */
+    pub fn take_cookie(&mut self) -> Option<Cookie> {
+        self.cookie.take()
+    }
+}
+
+/**
+    Middleware which attaches a `Session` to every request, the session ID is stored in a
+    cookie signed with HMAC-SHA256 and the data is kept in a pluggable `SessionStore`.
+
+    let sessions = Sessions::new(MemoryStore::new(), secret.as_bytes())
+        .cookie_name("sid")
+        .ttl(Duration::from_secs(60 * 60));
+
+    server.middleware(sessions);
+*/
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    config: Arc<SessionConfig>,
+}
+
+impl Sessions {
+    pub fn new<S>(store: S, secret: &[u8]) -> Self
+    where
+        S: SessionStore + 'static,
+    {
+        Sessions {
+            store: Arc::new(store),
+            config: Arc::new(SessionConfig {
+                cookie_name: "sid".to_string(),
+                path: "/".to_string(),
+                secure: false,
+                same_site: SameSite::Lax,
+                ttl: Duration::from_secs(60 * 60 * 24),
+                secret: secret.to_vec(),
+            }),
+        }
+    }
+
+    /**
+        Create sessions with a random secret, note that sessions will not survive a restart
+        since the cookie signatures can no longer be verified.
+    */
+    pub fn with_random_secret<S>(store: S) -> Result<Self>
+    where
+        S: SessionStore + 'static,
+    {
+        Ok(Sessions::new(store, &secure_random_bytes(32)?))
+    }
+
+    fn config_mut(&mut self) -> &mut SessionConfig {
+        Arc::make_mut(&mut self.config)
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.config_mut().cookie_name = name.to_string();
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.config_mut().path = path.to_string();
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.config_mut().secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.config_mut().same_site = same_site;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.config_mut().ttl = ttl;
+        self
+    }
+
+    /**
+        Load the session for the signed cookie sent with the request, if any.
+    */
+    fn load(&self, request: &HttpRequest) -> Option<Session> {
+        let value = request.cookie(&self.config.cookie_name)?;
+        let id = self.config.verify(&value)?;
+        let data = self.store.load(&id)?;
+        Some(Session {
+            id,
+            data,
+            store: Arc::clone(&self.store),
+            config: Arc::clone(&self.config),
+            is_saved: true,
+            cookie: None,
+        })
+    }
+}
+
+impl Middleware for Sessions {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<Flag>> {
+        let session = match self.load(request) {
+            Some(session) => session,
+            None => Session::new(Arc::clone(&self.store), Arc::clone(&self.config))?,
+        };
+        request.session = Some(session);
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SessionConfig {
+        SessionConfig {
+            cookie_name: "sid".to_string(),
+            path: "/".to_string(),
+            secure: true,
+            same_site: SameSite::Lax,
+            ttl: Duration::from_secs(3600),
+            secret: b"secret".to_vec(),
+        }
+    }
+
+    #[test]
+    fn session_ids_are_lowercase_hex() {
+        let id = generate_session_id().unwrap();
+        assert!(is_session_id(&id));
+        assert!(!is_session_id(&id.to_uppercase()));
+        assert!(!is_session_id(&id[1..]));
+        assert!(!is_session_id(&format!("../{}", &id[3..])));
+    }
+
+    #[test]
+    fn verifies_signed_ids() {
+        let config = config();
+        let id = "ab".repeat(32);
+        let value = config.sign(&id);
+        assert_eq!(config.verify(&value), Some(id.clone()));
+
+        let mut other = config.clone();
+        other.secret = b"other".to_vec();
+        assert_eq!(other.verify(&value), None);
+    }
+
+    #[test]
+    fn refuses_tampered_values() {
+        let config = config();
+        let value = config.sign(&"ab".repeat(32));
+        let swapped = format!("{}{}", "cd".repeat(32), &value[64..]);
+        assert_eq!(config.verify(&swapped), None);
+        assert_eq!(config.verify(&value[..value.len() - 1]), None);
+        assert_eq!(config.verify(&"ab".repeat(32)), None);
+    }
+
+    #[test]
+    fn cookie_is_http_only() {
+        let cookie = config().cookie("value");
+        assert_eq!(
+            cookie.to_string(),
+            "sid=value; Path=/; Max-Age=3600; Secure; HttpOnly; SameSite=Lax"
+        );
+    }
+}
@@ -1,32 +1,153 @@
+use crate::error;
 use std::borrow::BorrowMut;
 use std::cell::RefCell;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 use std::{collections::HashMap, net::TcpStream, sync::Arc};
 
 use super::data::ServerEvent;
+use super::error::ServerError;
 use super::file::Doc;
 use super::http::HttpRequest;
+use super::log::{self, Level, Record, Sink};
+use super::util::signal::{self, Signal};
+use super::util::{gzip, parse_size, DateTime};
+use super::ConfigFile;
 
+/** Buffered lines are flushed to the file at least this often while writing. */
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+    Writes log lines to a csv file which is kept open with buffered writes, and can be
+    rotated when it reaches a size or at the start of each day (UTC).
+
+    let stdout = Stdout::new("./logs/events.csv", "production")
+        .max_size(10 * 1024 * 1024)
+        .daily()
+        .keep(7)
+        .compress(true);
+
+    Rotated files are numbered from newest to oldest, e.g. `events.csv.1.gz` and
+    `events.csv.2.gz`, and only `keep` archives are retained. The file is also reopened
+    when the process receives SIGHUP, so it can be rotated by an external logrotate.
+*/
 pub struct Stdout {
     connections: HashMap<String, HttpRequest>,
     output_file: String,
     environment: String,
+    writer: Option<BufWriter<File>>,
+    size: u64,
+    opened_day: i64,
+    flushed_at: Instant,
+    hangups: u64,
+    max_size: Option<u64>,
+    daily: bool,
+    keep: usize,
+    compress: bool,
+    /**
+        Messages about the file itself, which are logged by the sink once the lock has been
+        released since logging while holding it would deadlock.
+    */
+    notices: Vec<(Level, String)>,
 }
 
 impl Stdout {
     pub fn new(output_file: &str, environment: &str) -> Self {
+        // make sure the directory for the output file exists before the first write
+        if let Some(directory) = std::path::Path::new(output_file).parent() {
+            if let Err(err) = std::fs::create_dir_all(directory) {
+                error!("failed to create directory: {:?} {}", directory, err);
+            }
+        }
+        signal::listen(Signal::Hangup);
         Stdout {
             connections: HashMap::new(),
             output_file: output_file.to_string(),
             environment: environment.to_string(),
+            writer: None,
+            size: 0,
+            opened_day: 0,
+            flushed_at: Instant::now(),
+            hangups: signal::received(Signal::Hangup),
+            max_size: None,
+            daily: false,
+            keep: 5,
+            compress: false,
+            notices: Vec::new(),
+        }
+    }
+
+    /**
+        Create from the `[stdout]` section of a config file, sizes may end in K, M or G.
+
+        [stdout]
+        file = ./src/data/events.csv
+        environment = production
+        max_size = 10M
+        rotate = daily
+        keep = 7
+        compress = true
+    */
+    pub fn from_config(config: &ConfigFile) -> Result<Self> {
+        let mut stdout = Stdout::new(
+            config.get("stdout.file").unwrap_or("./src/data/events.csv"),
+            config.get("stdout.environment").unwrap_or("development"),
+        );
+        if let Some(size) = config.get("stdout.max_size") {
+            let size = parse_size(size).ok_or_else(|| {
+                ServerError::error(&format!("stdout: invalid max_size {:?}", size))
+            })?;
+            stdout = stdout.max_size(size);
+        }
+        match config.get("stdout.rotate") {
+            None | Some("size") => (),
+            Some("daily") => stdout = stdout.daily(),
+            Some(rotate) => {
+                return Err(ServerError::error(&format!(
+                    "stdout: unknown rotate {:?}",
+                    rotate
+                )))
+            }
+        }
+        if let Some(keep) = config.get_u64("stdout.keep") {
+            stdout = stdout.keep(keep as usize);
         }
+        if let Some(compress) = config.get_bool("stdout.compress") {
+            stdout = stdout.compress(compress);
+        }
+        Ok(stdout)
+    }
+
+    /** Rotate the file before a write would take it over this many bytes. */
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes.max(1));
+        self
+    }
+
+    /** Rotate the file on the first write of each day. */
+    pub fn daily(mut self) -> Self {
+        self.daily = true;
+        self
+    }
+
+    /** The number of rotated files to retain, older files are deleted. */
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /** Compress rotated files with gzip. */
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
     }
 
     pub fn add_stream(&mut self, stream: HttpRequest) {
         let stream_name = stream.uri.clone();
-        println!("[stdout] adding stream: {}", stream_name);
+        self.notice(Level::Debug, format!("adding stream: {}", stream_name));
         self.connections.insert(stream_name, stream);
     }
 
@@ -34,22 +155,6 @@ impl Stdout {
         // Write data to csv file.
         // log_time, log_type, log_name, log_host, log_data
         // 2021-09-01 12:00:00, environment, name, data
-        let mut file = match OpenOptions::new()
-            .write(true)
-            .append(true)
-            .create(true)
-            .open(&self.output_file)
-        {
-            Ok(csv_file) => csv_file,
-            Err(err) => {
-                eprintln!(
-                    "[stdout] failed to open file: {} {:}",
-                    self.output_file, err
-                );
-                return;
-            }
-        };
-
         let csv_line = format!(
             "{},{},{},{}\n",
             Self::timestamp(),
@@ -58,10 +163,8 @@ impl Stdout {
             data
         );
 
-        let raw_bytes = csv_line.as_bytes();
-        match file.write_all(raw_bytes) {
-            Ok(_) => println!("[stdout] wrote to file: {}", self.output_file),
-            Err(err) => eprintln!("[stdout] failed to write to file: {}", err),
+        if let Err(err) = self.write_line(csv_line.as_bytes()) {
+            self.notice(Level::Error, format!("failed to write to file: {}", err));
         }
 
         // Create server event from which will be broadcasted to all streams.
@@ -73,54 +176,346 @@ impl Stdout {
         // );
 
         // Iterate connections sending server event to each stream, and closing streams that fail.
+        let mut failed = Vec::new();
         self.connections
             .retain(|_, stream| match stream.server_side_event(event.clone()) {
-                Ok(_) => {
-                    println!("[stdout] sent event to stream: {}", stream.uri);
-                    true
-                }
+                Ok(_) => true,
                 Err(err) => {
-                    eprintln!("[stdout] failed to send event: {}", err);
+                    failed.push(format!("failed to send event: {}", err));
                     false
                 }
             });
+        for message in failed {
+            self.notice(Level::Warn, message);
+        }
     }
 
     fn keep_alive(&mut self) {
         let event = ServerEvent::event("keep-alive", "ping".to_string());
+        let mut failed = Vec::new();
         self.connections
             .retain(|_, stream| match stream.server_side_event(event.clone()) {
                 Ok(_) => true,
                 Err(err) => {
-                    eprintln!("[stdout] failed to keep stream alive: {}", err);
+                    failed.push(format!("failed to keep stream alive: {}", err));
                     false
                 }
             });
+        for message in failed {
+            self.notice(Level::Warn, message);
+        }
+    }
+
+    fn notice(&mut self, level: Level, message: String) {
+        self.notices.push((level, message));
+    }
+
+    fn take_notices(&mut self) -> Vec<(Level, String)> {
+        std::mem::take(&mut self.notices)
+    }
+
+    /**
+        Append a line to the output file, reopening or rotating it first if needed.
+    */
+    fn write_line(&mut self, line: &[u8]) -> Result<()> {
+        let hangups = signal::received(Signal::Hangup);
+        if hangups != self.hangups {
+            self.hangups = hangups;
+            let message = format!("reopening {} after SIGHUP", self.output_file);
+            self.notice(Level::Info, message);
+            self.close()?;
+        }
+        if self.writer.is_some() && self.should_rotate(line.len() as u64) {
+            self.rotate()?;
+        }
+        if self.writer.is_none() {
+            self.open()?;
+        }
+        let writer = self.writer.as_mut().unwrap();
+        writer.write_all(line)?;
+        self.size += line.len() as u64;
+        if self.flushed_at.elapsed() >= FLUSH_INTERVAL {
+            writer.flush()?;
+            self.flushed_at = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<()> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.output_file)?;
+        let metadata = file.metadata()?;
+        // a file left from an earlier day is rotated on the first write
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        self.size = metadata.len();
+        self.opened_day = DateTime::from_system_time(modified).days();
+        self.writer = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    /** Flush any buffered lines and close the file, it is opened again on the next write. */
+    pub fn close(&mut self) -> Result<()> {
+        match self.writer.take() {
+            Some(mut writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /** The path of the log file. */
+    pub fn path(&self) -> &str {
+        &self.output_file
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.flushed_at = Instant::now();
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn should_rotate(&self, incoming: u64) -> bool {
+        let too_large = match self.max_size {
+            Some(max_size) => self.size > 0 && self.size + incoming > max_size,
+            None => false,
+        };
+        too_large || (self.daily && DateTime::now().days() != self.opened_day)
+    }
+
+    /**
+        Close the file and move it to the first archive, shifting older archives along and
+        deleting any past the number to keep.
+    */
+    pub fn rotate(&mut self) -> Result<()> {
+        self.close()?;
+        if !Path::new(&self.output_file).exists() {
+            return Ok(());
+        }
+        if self.keep == 0 {
+            return fs::remove_file(&self.output_file);
+        }
+        let extension = if self.compress { ".gz" } else { "" };
+        let archive = |index: usize| format!("{}.{}{}", self.output_file, index, extension);
+        if Path::new(&archive(self.keep)).exists() {
+            fs::remove_file(archive(self.keep))?;
+        }
+        for index in (1..self.keep).rev() {
+            if Path::new(&archive(index)).exists() {
+                fs::rename(archive(index), archive(index + 1))?;
+            }
+        }
+        if self.compress {
+            let data = fs::read(&self.output_file)?;
+            let partial = format!("{}.tmp", archive(1));
+            fs::write(&partial, gzip(&data))?;
+            fs::rename(&partial, archive(1))?;
+            fs::remove_file(&self.output_file)?;
+        } else {
+            fs::rename(&self.output_file, archive(1))?;
+        }
+        let message = format!("rotated {} to {}", self.output_file, archive(1));
+        self.notice(Level::Info, message);
+        Ok(())
     }
 
     fn timestamp() -> String {
-        let now = SystemTime::now();
-        let duration = now.duration_since(UNIX_EPOCH).expect("Time went backwards");
-
-        let secs = duration.as_secs();
-        let nanos = duration.subsec_nanos();
-
-        let (year, month, day, hour, min, sec) = {
-            let secs = secs + 62_167_219_200; // Seconds from year 0 to 1970
-            let years = secs / 31_557_600; // Approximate seconds per year
-            let year = 1970 + years;
-            let remainder = secs % 31_557_600;
-            let month = remainder / 2_629_800; // Approximate seconds per month
-            let day = (remainder % 2_629_800) / 86400;
-            let hour = (remainder % 86400) / 3600;
-            let min = (remainder % 3600) / 60;
-            let sec = remainder % 60;
-            (year, month + 1, day + 1, hour, min, sec)
+        DateTime::now().to_rfc3339()
+    }
+}
+
+/**
+    Write each log record as a csv line named by its level, warnings and errors are
+    flushed straight away rather than waiting for the flush interval.
+*/
+impl Sink for Mutex<Stdout> {
+    fn write(&self, record: &Record) {
+        let notices = {
+            let mut stdout = self.lock().unwrap();
+            stdout.write(record.level.as_str(), record.to_short_text());
+            if record.level >= Level::Warn {
+                if let Err(err) = stdout.flush() {
+                    let message = format!("failed to flush {}: {}", stdout.output_file, err);
+                    stdout.notice(Level::Error, message);
+                }
+            }
+            stdout.take_notices()
         };
+        for (level, message) in notices {
+            log::log(level, module_path!(), format_args!("{}", message));
+        }
+    }
+}
 
-        format!(
-            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
-            year, month, day, hour, min, sec, nanos
-        )
+impl Drop for Stdout {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            error!("failed to flush {}: {}", self.output_file, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /** A fresh directory for a test's log files. */
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("stdout_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn exists(path: &str) -> bool {
+        Path::new(path).exists()
+    }
+
+    fn record(message: &str) -> Record {
+        Record {
+            time: SystemTime::now(),
+            level: Level::Info,
+            target: "server::core::stdout".to_string(),
+            message: message.to_string(),
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn writes_csv_lines() {
+        let directory = directory("lines");
+        let file = directory.join("events.csv");
+        let mut stdout = Stdout::new(file.to_str().unwrap(), "test");
+        stdout.write("info", "started".to_string());
+        stdout.write("warn", "slow".to_string());
+        stdout.close().unwrap();
+
+        let text = fs::read_to_string(&file).unwrap();
+        let lines = text.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(",test,info,started"));
+        assert!(lines[1].ends_with(",test,warn,slow"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rotates_at_max_size_and_keeps_the_newest_archives() {
+        let directory = directory("rotate");
+        let file = directory.join("events.csv");
+        let path = file.to_str().unwrap().to_string();
+        let mut stdout = Stdout::new(&path, "test").max_size(100).keep(2);
+        for index in 0..6 {
+            stdout.write("info", format!("line {} {}", index, "x".repeat(40)));
+        }
+        stdout.close().unwrap();
+
+        assert!(exists(&format!("{}.1", path)));
+        assert!(exists(&format!("{}.2", path)));
+        assert!(!exists(&format!("{}.3", path)));
+        // each file holds one line since two would be over the limit
+        assert!(fs::read_to_string(&path).unwrap().contains("line 5 "));
+        assert!(fs::read_to_string(format!("{}.1", path))
+            .unwrap()
+            .contains("line 4 "));
+        assert!(fs::read_to_string(format!("{}.2", path))
+            .unwrap()
+            .contains("line 3 "));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn compresses_rotated_files() {
+        let directory = directory("compress");
+        let file = directory.join("events.csv");
+        let path = file.to_str().unwrap().to_string();
+        let mut stdout = Stdout::new(&path, "test").keep(3).compress(true);
+        stdout.write("info", "first".to_string());
+        stdout.rotate().unwrap();
+
+        let archive = fs::read(format!("{}.1.gz", path)).unwrap();
+        assert_eq!(&archive[..3], &[0x1f, 0x8b, 8]);
+        assert!(!exists(&path));
+        assert!(!exists(&format!("{}.1.gz.tmp", path)));
+
+        // a missing file is not rotated again
+        stdout.rotate().unwrap();
+        assert!(!exists(&format!("{}.2.gz", path)));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn keeping_no_archives_deletes_the_file() {
+        let directory = directory("keep_none");
+        let file = directory.join("events.csv");
+        let path = file.to_str().unwrap().to_string();
+        let mut stdout = Stdout::new(&path, "test").keep(0);
+        stdout.write("info", "gone".to_string());
+        stdout.rotate().unwrap();
+        assert!(!exists(&path));
+        assert!(!exists(&format!("{}.1", path)));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn queues_notices_instead_of_logging_under_the_lock() {
+        let directory = directory("notices");
+        let file = directory.join("events.csv");
+        let path = file.to_str().unwrap().to_string();
+        let mut stdout = Stdout::new(&path, "test").max_size(10).keep(1);
+        stdout.write("info", "first".to_string());
+        stdout.write("info", "second".to_string());
+        let notices = stdout.take_notices();
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].0, Level::Info);
+        assert!(notices[0].1.starts_with("rotated "));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn sink_logs_notices_after_releasing_the_lock() {
+        let directory = directory("sink");
+        let file = directory.join("events.csv");
+        let path = file.to_str().unwrap().to_string();
+        let sink = Mutex::new(Stdout::new(&path, "test").max_size(10).keep(1));
+        sink.write(&record("first"));
+        sink.write(&record("second"));
+        assert!(sink.lock().unwrap().take_notices().is_empty());
+        sink.lock().unwrap().close().unwrap();
+        assert!(fs::read_to_string(format!("{}.1", path))
+            .unwrap()
+            .contains(",test,info,stdout: first"));
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("stdout: second"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn reads_rotation_from_config() {
+        let directory = directory("config");
+        let file = directory.join("events.csv");
+        let config = ConfigFile::parse(&format!(
+            "[stdout]\nfile = {}\nenvironment = production\nmax_size = 10K\nrotate = daily\nkeep = 7\ncompress = true\n",
+            file.to_str().unwrap()
+        ));
+        let stdout = Stdout::from_config(&config).unwrap();
+        assert_eq!(stdout.path(), file.to_str().unwrap());
+        assert_eq!(stdout.environment, "production");
+        assert_eq!(stdout.max_size, Some(10 * 1024));
+        assert!(stdout.daily);
+        assert_eq!(stdout.keep, 7);
+        assert!(stdout.compress);
+
+        for invalid in ["max_size = lots", "rotate = hourly"] {
+            let config = ConfigFile::parse(&format!(
+                "[stdout]\nfile = {}\n{}\n",
+                file.to_str().unwrap(),
+                invalid
+            ));
+            assert!(Stdout::from_config(&config).is_err(), "{}", invalid);
+        }
+        fs::remove_dir_all(directory).unwrap();
     }
 }
@@ -1,3 +1,7 @@
+pub mod params;
 pub mod path;
+pub mod target;
 
-pub use self::path::Path;
+pub use self::params::{Params, ParamsError};
+pub use self::path::{Normalize, PathError};
+pub use self::target::Url;
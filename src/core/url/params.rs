@@ -0,0 +1,246 @@
+use crate::core::util::{form_decode, percent_encode};
+use std::fmt;
+
+/**
+    Key and value pairs from a form body or URL query, in the order they were sent. A key
+    may appear more than once, e.g. for a `<select multiple>` or repeated checkboxes.
+
+    let form = Params::parse("name=Ada+Lovelace&tag=math&tag=poetry")?;
+    form.get("name") == Some("Ada Lovelace");
+    form.get_all("tag") == vec!["math", "poetry"];
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params {
+    pairs: Vec<(String, String)>,
+}
+
+/**
+    A pair in a form body or query which could not be decoded, because of a malformed
+    `%XX` escape or bytes which are not UTF-8. Pairs are counted from 1.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamsError {
+    pub pair: usize,
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "malformed percent escape or invalid utf-8 in pair {}",
+            self.pair
+        )
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+impl Params {
+    pub fn new() -> Self {
+        Params { pairs: Vec::new() }
+    }
+
+    /**
+        Decode an `application/x-www-form-urlencoded` string, failing on the first pair
+        which can not be decoded. A key without `=` has an empty value.
+    */
+    pub fn parse(input: &str) -> Result<Self, ParamsError> {
+        let mut params = Params::new();
+        for (index, pair) in split_pairs(input).enumerate() {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match (form_decode(key), form_decode(value)) {
+                (Some(key), Some(value)) => params.pairs.push((key, value)),
+                _ => return Err(ParamsError { pair: index + 1 }),
+            }
+        }
+        Ok(params)
+    }
+
+    /** Decode like `parse`, skipping the pairs which can not be decoded. */
+    pub fn parse_lossy(input: &str) -> Self {
+        let pairs = split_pairs(input)
+            .filter_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Some((form_decode(key)?, form_decode(value)?))
+            })
+            .collect();
+        Params { pairs }
+    }
+
+    /** Get the first value for a key. */
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /** Get every value for a key in order. */
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(name, _)| name == key)
+    }
+
+    /** Add a value for a key after any existing values. */
+    pub fn append(&mut self, key: &str, value: &str) {
+        self.pairs.push((key.to_string(), value.to_string()));
+    }
+
+    /**
+        Replace every value for a key with a single value, which keeps the position of
+        the first one.
+    */
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.pairs.iter().position(|(name, _)| name == key) {
+            Some(index) => {
+                self.pairs[index].1 = value.to_string();
+                let mut seen = 0;
+                self.pairs.retain(|(name, _)| {
+                    seen += (name == key) as usize;
+                    name != key || seen == 1
+                });
+            }
+            None => self.append(key, value),
+        }
+    }
+
+    /** Remove every value for a key. */
+    pub fn remove(&mut self, key: &str) {
+        self.pairs.retain(|(name, _)| name != key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+/** Encode the pairs as `application/x-www-form-urlencoded`. */
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (key, value)) in self.pairs.iter().enumerate() {
+            if index > 0 {
+                f.write_str("&")?;
+            }
+            write!(f, "{}={}", percent_encode(key), percent_encode(value))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<(String, String)> for Params {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        Params {
+            pairs: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for Params {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.pairs.into_iter()
+    }
+}
+
+/** Split on `&`, ignoring empty pairs such as from `a=1&&b=2` or a trailing `&`. */
+fn split_pairs(input: &str) -> impl Iterator<Item = &str> {
+    input.split('&').filter(|pair| !pair.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_repeated_keys_in_order() {
+        let form = Params::parse("name=Ada+Lovelace&tag=math&tag=poetry&flag").unwrap();
+        assert_eq!(form.get("name"), Some("Ada Lovelace"));
+        assert_eq!(form.get("tag"), Some("math"));
+        assert_eq!(form.get_all("tag"), vec!["math", "poetry"]);
+        assert_eq!(form.get("flag"), Some(""));
+        assert!(form.contains("flag"));
+        assert_eq!(form.get("missing"), None);
+        assert!(form.get_all("missing").is_empty());
+        assert_eq!(form.len(), 4);
+    }
+
+    #[test]
+    fn decodes_escapes_and_plus() {
+        let form = Params::parse("q=a%2Bb+c&caf%C3%A9=%E2%9C%93&eq=a=b").unwrap();
+        assert_eq!(form.get("q"), Some("a+b c"));
+        assert_eq!(form.get("café"), Some("✓"));
+        assert_eq!(form.get("eq"), Some("a=b"));
+        assert!(Params::parse("").unwrap().is_empty());
+        assert_eq!(Params::parse("&&a=1&").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reports_the_first_pair_which_can_not_be_decoded() {
+        assert_eq!(Params::parse("a=1&b=%zz&c=%"), Err(ParamsError { pair: 2 }));
+        assert_eq!(Params::parse("a=%FF"), Err(ParamsError { pair: 1 }));
+        assert_eq!(
+            ParamsError { pair: 2 }.to_string(),
+            "malformed percent escape or invalid utf-8 in pair 2"
+        );
+    }
+
+    #[test]
+    fn lossy_parsing_skips_bad_pairs() {
+        let params = Params::parse_lossy("a=1&b=%zz&c=3");
+        assert_eq!(
+            params.iter().collect::<Vec<_>>(),
+            vec![("a", "1"), ("c", "3")]
+        );
+    }
+
+    #[test]
+    fn encodes_pairs_in_order() {
+        let mut params = Params::new();
+        params.append("tag", "a b");
+        params.append("q", "x&y=z");
+        params.append("tag", "é");
+        assert_eq!(params.to_string(), "tag=a%20b&q=x%26y%3Dz&tag=%C3%A9");
+        assert_eq!(Params::parse(&params.to_string()).unwrap(), params);
+    }
+
+    #[test]
+    fn set_replaces_every_value_at_the_first_position() {
+        let mut params = Params::parse("tag=a&q=1&tag=b").unwrap();
+        params.set("tag", "c");
+        assert_eq!(params.to_string(), "tag=c&q=1");
+        params.set("new", "2");
+        assert_eq!(params.to_string(), "tag=c&q=1&new=2");
+        params.remove("q");
+        assert_eq!(params.to_string(), "tag=c&new=2");
+    }
+
+    #[test]
+    fn collects_from_and_into_pairs() {
+        let pairs = vec![
+            ("a".to_string(), "1".to_string()),
+            ("a".to_string(), "2".to_string()),
+        ];
+        let params = pairs.clone().into_iter().collect::<Params>();
+        assert_eq!(params.get_all("a"), vec!["1", "2"]);
+        assert_eq!(params.into_iter().collect::<Vec<_>>(), pairs);
+    }
+}
@@ -1,39 +1,111 @@
-use std::{collections::HashMap, io::Error, io::ErrorKind, sync::Arc};
+use crate::core::util::encode_path;
+use crate::core::ConfigFile;
+use std::fmt;
+use std::io;
 
-type PathParams = HashMap<String, String>;
+/**
+    Why a request path was refused, which is a `400`.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathError {
+    /** A `%` which is not followed by two hex digits. */
+    InvalidEscape,
+    /** A `%2F`, which would let a segment act as two. */
+    EncodedSlash,
+    /** A NUL byte, raw or as `%00`. */
+    Nul,
+    /** Escapes which do not decode to UTF-8. */
+    InvalidUtf8,
+}
+
+/**
+    How request paths are normalized before they are routed, see `normalize_path`. By
+    default duplicate slashes are merged and a path which is not canonical is served as if
+    the canonical path had been requested, with `redirect` the client is sent to the
+    canonical path with a `308`.
 
-#[derive(Debug, Clone)]
-pub struct Path {
-    pub path: Arc<str>,
-    pub params: Option<PathParams>,
+    server.normalize_paths(Normalize::new().redirect(true));
+*/
+#[derive(Clone, Debug)]
+pub struct Normalize {
+    merge_slashes: bool,
+    redirect: bool,
 }
 
-impl Path {
-    pub fn to_string(&self) -> String {
-        match &self.params {
-            Some(params) => {
-                let params_str = params
-                    .iter()
-                    .map(|(key, value)| format!("{}={}", key, value))
-                    .collect::<Vec<String>>()
-                    .join("&");
-                format!("{}?{}", self.path, params_str)
-            }
-            None => self.path.to_string(),
+impl Normalize {
+    pub fn new() -> Self {
+        Normalize {
+            merge_slashes: true,
+            redirect: false,
         }
     }
 
-    pub fn from(path_str: &str) -> Self {
-        let (path_str, query_str) = split_path_and_params(path_str);
-        let path = sanitize_path(path_str);
-        let params = parse_query(query_str);
-        Path {
-            path: path.into(),
-            params,
+    /**
+        Create from the `[paths]` section of a config file.
+
+        [paths]
+        merge_slashes = true
+        redirect = true
+    */
+    pub fn from_config(config: &ConfigFile) -> io::Result<Self> {
+        Ok(Normalize::new()
+            .merge_slashes(config.get_bool("paths.merge_slashes").unwrap_or(true))
+            .redirect(config.get_bool("paths.redirect").unwrap_or(false)))
+    }
+
+    /**
+        Treat `//` as `/`, which RFC 3986 does not do since an empty segment is allowed. This
+        is on by default so prefix checks such as `AuthGuard` see the same path as the file
+        server, which refuses paths with empty segments when it is turned off.
+    */
+    pub fn merge_slashes(mut self, merge_slashes: bool) -> Self {
+        self.merge_slashes = merge_slashes;
+        self
+    }
+
+    /** Redirect a request for a path which is not canonical. */
+    pub fn redirect(mut self, redirect: bool) -> Self {
+        self.redirect = redirect;
+        self
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        self.redirect
+    }
+
+    /** Normalize a decoded path with these options. */
+    pub fn path(&self, path: &str) -> Result<String, PathError> {
+        normalize_path(path, self.merge_slashes)
+    }
+
+    /**
+        The canonical form of a request path as it is sent in a URL, which is the
+        normalized path with only the characters a path may not contain escaped.
+    */
+    pub fn canonical(&self, path: &str) -> Result<String, PathError> {
+        self.path(path).map(|path| encode_path(&path))
+    }
+}
+
+impl Default for Normalize {
+    fn default() -> Self {
+        Normalize::new()
+    }
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::InvalidEscape => write!(f, "malformed percent escape in path"),
+            PathError::EncodedSlash => write!(f, "encoded slash in path"),
+            PathError::Nul => write!(f, "nul byte in path"),
+            PathError::InvalidUtf8 => write!(f, "path is not valid utf-8"),
         }
     }
 }
 
+impl std::error::Error for PathError {}
+
 /** Split a path and query into a tuple */
 pub fn split_path_and_params(path_str: &str) -> (&str, Option<&str>) {
     match path_str.split_once('?') {
@@ -43,57 +115,147 @@ pub fn split_path_and_params(path_str: &str) -> (&str, Option<&str>) {
 }
 
 /**
- * Sanitize a path by removing invalid path segments, converting to lowercase,
- * and joining the segments with a forward slash.
- */
+    Normalize a path with the default options, a path which can not be decoded becomes `/`.
+    Use `normalize_path` to tell the two apart.
+*/
 pub fn sanitize_path(path: &str) -> String {
-    let output = path
-        .split("/")
-        .filter(|segment| is_valid_path(segment))
-        .map(|segment| segment.to_lowercase())
-        .collect::<Vec<String>>()
-        .join("/");
-
-    if output.is_empty() {
-        "/".to_string()
-    } else {
-        output
-    }
+    Normalize::new()
+        .path(path)
+        .unwrap_or_else(|_| "/".to_string())
 }
 
 /**
- * Check if a path segment only contains alphanumeric characters,
- * underscores, or dashes and is not empty.
- */
-pub fn is_valid_path(segment: &str) -> bool {
-    match segment.is_empty() {
-        true => false,
-        false => segment
-            .chars()
-            .all(|c| c.is_alphanumeric() || c == '-' || c == '_'),
+    Normalize a path as in RFC 3986: escapes are decoded, `.` and `..` segments are removed
+    without going above the root, and the case is kept. Duplicate slashes are merged when
+    `merge_slashes` is set. The result always starts with `/`.
+
+    normalize_path("/Docs/./old/../My%20File.pdf", false) == Ok("/Docs/My File.pdf")
+    normalize_path("/a//b/", true) == Ok("/a/b/")
+    normalize_path("/a%2Fb", false) == Err(PathError::EncodedSlash)
+*/
+pub fn normalize_path(path: &str, merge_slashes: bool) -> Result<String, PathError> {
+    let raw_segments = path
+        .strip_prefix('/')
+        .unwrap_or(path)
+        .split('/')
+        .collect::<Vec<_>>();
+    let mut segments: Vec<String> = Vec::new();
+    for (index, raw_segment) in raw_segments.iter().enumerate() {
+        // a path which ends in a dot segment still ends in a slash
+        let is_last = index == raw_segments.len() - 1;
+        let segment = decode_segment(raw_segment)?;
+        match segment.as_str() {
+            "." => (),
+            ".." => {
+                segments.pop();
+            }
+            "" if merge_slashes && !is_last => (),
+            _ => {
+                segments.push(segment);
+                continue;
+            }
+        }
+        if is_last && !segment.is_empty() {
+            segments.push(String::new());
+        }
     }
+    Ok(format!("/{}", segments.join("/")))
 }
 
 /**
- * Parse a query string into a HashMap of key-value pairs.
- */
-pub fn parse_query(query_str: Option<&str>) -> Option<PathParams> {
-    let query = match query_str {
-        Some(query_str) => query_str,
-        None => return None,
-    };
-
-    if query.is_empty() {
-        return None;
-    }
-
-    let params = query
-        .split("&")
-        .map(|pair| pair.split_once("="))
-        .filter(|pair| pair.is_some())
-        .map(|pair| pair.unwrap())
-        .map(|(key, value)| (key.to_string(), value.to_string()))
-        .collect();
-
-    Some(params)
+    Decode the escapes in a single segment, a `/` or NUL in the segment could change which
+    file it refers to so they are refused.
+*/
+fn decode_segment(segment: &str) -> Result<String, PathError> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut input = segment.bytes();
+    while let Some(byte) = input.next() {
+        let byte = match byte {
+            b'%' => {
+                let high = input.next().and_then(hex_value);
+                let low = input.next().and_then(hex_value);
+                match (high, low) {
+                    (Some(high), Some(low)) => high << 4 | low,
+                    _ => return Err(PathError::InvalidEscape),
+                }
+            }
+            byte => byte,
+        };
+        match byte {
+            b'/' => return Err(PathError::EncodedSlash),
+            0 => return Err(PathError::Nul),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| PathError::InvalidUtf8)
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_dot_segments() {
+        let path = |input| normalize_path(input, false).unwrap();
+        assert_eq!(path("/a/b/../c"), "/a/c");
+        assert_eq!(path("/a/./b/."), "/a/b/");
+        assert_eq!(path("/a/b/.."), "/a/");
+        assert_eq!(path("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(path(""), "/");
+    }
+
+    #[test]
+    fn decodes_escapes_and_keeps_case() {
+        let path = |input| normalize_path(input, false).unwrap();
+        assert_eq!(path("/Docs/My%20File.pdf"), "/Docs/My File.pdf");
+        assert_eq!(path("/%2e%2E/x"), "/x");
+        assert_eq!(path("/%C3%A9t%C3%A9"), "/été");
+    }
+
+    #[test]
+    fn refuses_paths_which_can_not_be_decoded() {
+        assert_eq!(
+            normalize_path("/a%2Fb", false),
+            Err(PathError::EncodedSlash)
+        );
+        assert_eq!(
+            normalize_path("/a%2fb", false),
+            Err(PathError::EncodedSlash)
+        );
+        assert_eq!(normalize_path("/a%00", false), Err(PathError::Nul));
+        assert_eq!(
+            normalize_path("/a%zz", false),
+            Err(PathError::InvalidEscape)
+        );
+        assert_eq!(normalize_path("/a%", false), Err(PathError::InvalidEscape));
+        assert_eq!(normalize_path("/%FF", false), Err(PathError::InvalidUtf8));
+    }
+
+    #[test]
+    fn merges_slashes_when_asked() {
+        assert_eq!(normalize_path("/a//b//", true).unwrap(), "/a/b/");
+        assert_eq!(normalize_path("/a//b//", false).unwrap(), "/a//b//");
+        assert_eq!(normalize_path("//", true).unwrap(), "/");
+    }
+
+    #[test]
+    fn merges_slashes_by_default() {
+        let normalize = Normalize::new();
+        assert_eq!(normalize.path("//log.html").unwrap(), "/log.html");
+        assert_eq!(normalize.path("/admin//./users").unwrap(), "/admin/users");
+        assert_eq!(Normalize::default().path("//x").unwrap(), "/x");
+    }
+
+    #[test]
+    fn canonical_path_is_encoded() {
+        let normalize = Normalize::new();
+        assert_eq!(
+            normalize.canonical("/a/%7euser/x y/a+b:c@d").unwrap(),
+            "/a/~user/x%20y/a+b:c@d"
+        );
+    }
 }
@@ -0,0 +1,179 @@
+use super::path::{split_path_and_params, Normalize, PathError};
+use super::Params;
+use crate::core::util::encode_path;
+use std::fmt;
+
+/**
+    A request target such as `/search/My%20Docs?tag=a&tag=b`, split into a decoded and
+    normalized path and a query which keeps every value of a repeated key in order. It is
+    encoded again when formatted.
+
+    let url = Url::parse("/search/./My%20Docs?tag=a&tag=b&q=rust+http")?;
+    url.path == "/search/My Docs";
+    url.query.get_all("tag") == vec!["a", "b"];
+    url.to_string() == "/search/My%20Docs?tag=a&tag=b&q=rust%20http";
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Url {
+    pub path: String,
+    pub query: Params,
+    target: String,
+}
+
+impl Url {
+    /** Parse a target with the default normalization, see `normalize_path`. */
+    pub fn parse(target: &str) -> Result<Self, PathError> {
+        Url::parse_with(target, &Normalize::new())
+    }
+
+    /**
+        Parse a target, any fragment is dropped and query pairs which can not be decoded
+        are skipped. A target which is not a path, such as `*`, is kept as it is.
+    */
+    pub fn parse_with(target: &str, normalize: &Normalize) -> Result<Self, PathError> {
+        let (path, query) = split_path_and_params(target.split('#').next().unwrap_or_default());
+        let path = match path.starts_with('/') {
+            true => normalize.path(path)?,
+            false => path.to_string(),
+        };
+        Ok(Url {
+            path,
+            query: query.map(Params::parse_lossy).unwrap_or_default(),
+            target: target.to_string(),
+        })
+    }
+
+    /** The target as it was sent, before decoding. */
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /**
+        Check if the path was sent in its canonical form, so `/a/../b` and `/%7Euser` are
+        not but `/My%20File.pdf` is.
+    */
+    pub fn is_canonical(&self) -> bool {
+        let path = split_path_and_params(self.without_fragment()).0;
+        !path.starts_with('/') || encode_path(&self.path) == path
+    }
+
+    /**
+        The canonical form of the target, the query is kept as it was sent so nothing is
+        lost when redirecting to it.
+    */
+    pub fn canonical(&self) -> String {
+        match split_path_and_params(self.without_fragment()) {
+            (path, _) if !path.starts_with('/') => path.to_string(),
+            (_, Some(query)) => format!("{}?{}", encode_path(&self.path), query),
+            (_, None) => encode_path(&self.path),
+        }
+    }
+
+    fn without_fragment(&self) -> &str {
+        self.target.split('#').next().unwrap_or_default()
+    }
+}
+
+impl Default for Url {
+    fn default() -> Self {
+        Url {
+            path: "/".to_string(),
+            query: Params::new(),
+            target: "/".to_string(),
+        }
+    }
+}
+
+/** Format the encoded path and query, e.g. `/My%20File.pdf?q=a%20b`. */
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.path.starts_with('/') {
+            true => f.write_str(&encode_path(&self.path))?,
+            false => f.write_str(&self.path)?,
+        }
+        if !self.query.is_empty() {
+            write!(f, "?{}", self.query)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_the_path_and_an_ordered_query() {
+        let url = Url::parse("/search/./My%20Docs?tag=a&q=rust+http&tag=b#top").unwrap();
+        assert_eq!(url.path, "/search/My Docs");
+        assert_eq!(url.query.get_all("tag"), vec!["a", "b"]);
+        assert_eq!(url.query.get("q"), Some("rust http"));
+        assert_eq!(
+            url.target(),
+            "/search/./My%20Docs?tag=a&q=rust+http&tag=b#top"
+        );
+        assert_eq!(
+            url.to_string(),
+            "/search/My%20Docs?tag=a&q=rust%20http&tag=b"
+        );
+    }
+
+    #[test]
+    fn drops_the_fragment_and_bad_query_pairs() {
+        let url = Url::parse("/a#b?c=d").unwrap();
+        assert_eq!(url.path, "/a");
+        assert!(url.query.is_empty());
+        assert_eq!(url.to_string(), "/a");
+
+        let url = Url::parse("/a?x=%zz&y=1").unwrap();
+        assert_eq!(url.query.iter().collect::<Vec<_>>(), vec![("y", "1")]);
+    }
+
+    #[test]
+    fn refuses_paths_which_can_not_be_decoded() {
+        assert_eq!(Url::parse("/a%2Fb"), Err(PathError::EncodedSlash));
+        assert_eq!(Url::parse("/a%zz"), Err(PathError::InvalidEscape));
+    }
+
+    #[test]
+    fn keeps_targets_which_are_not_paths() {
+        let url = Url::parse("*").unwrap();
+        assert_eq!(url.path, "*");
+        assert_eq!(url.to_string(), "*");
+        assert!(url.is_canonical());
+        assert_eq!(url.canonical(), "*");
+    }
+
+    #[test]
+    fn checks_for_the_canonical_form() {
+        for target in ["/", "/My%20File.pdf", "/a/b?x=1#frag", "/~user"] {
+            assert!(Url::parse(target).unwrap().is_canonical(), "{}", target);
+        }
+        for target in ["/a/../b", "/%7Euser", "/a//b", "/a/./b?x=1"] {
+            assert!(!Url::parse(target).unwrap().is_canonical(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn canonical_keeps_the_query_as_it_was_sent() {
+        let url = Url::parse("/a/../My%20Docs/?q=a+b&x=%41#frag").unwrap();
+        assert_eq!(url.canonical(), "/My%20Docs/?q=a+b&x=%41");
+        assert_eq!(Url::parse("/%7euser").unwrap().canonical(), "/~user");
+    }
+
+    #[test]
+    fn keeps_slashes_when_normalize_does() {
+        let normalize = Normalize::new().merge_slashes(false);
+        let url = Url::parse_with("/a//b", &normalize).unwrap();
+        assert_eq!(url.path, "/a//b");
+        assert!(url.is_canonical());
+    }
+
+    #[test]
+    fn defaults_to_the_root() {
+        let url = Url::default();
+        assert_eq!(url.path, "/");
+        assert_eq!(url.target(), "/");
+        assert_eq!(url, Url::parse("/").unwrap());
+    }
+}
@@ -1,9 +1,37 @@
 const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 
+const BASE64_URL_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
 /**
  * Encode a byte vector into a base64 string.
  */
 pub fn base64_encode(data: &[u8]) -> String {
+    encode_with(data, BASE64_CHARS, true)
+}
+
+/**
+ * Decode a base64 string into a byte vector.
+ */
+pub fn base64_decode(input: &str) -> Result<Vec<u8>, &'static str> {
+    decode_with(input, BASE64_CHARS)
+}
+
+/**
+ * Encode a byte vector into a URL-safe base64 string without padding (RFC 4648 section 5),
+ * as used by JSON Web Tokens.
+ */
+pub fn base64url_encode(data: &[u8]) -> String {
+    encode_with(data, BASE64_URL_CHARS, false)
+}
+
+/**
+ * Decode a URL-safe base64 string, padding is optional.
+ */
+pub fn base64url_decode(input: &str) -> Result<Vec<u8>, &'static str> {
+    decode_with(input, BASE64_URL_CHARS)
+}
+
+fn encode_with(data: &[u8], alphabet: &[u8], padding: bool) -> String {
     let mut output = String::new();
     let mut i = 0;
     while i < data.len() {
@@ -14,18 +42,18 @@ pub fn base64_encode(data: &[u8]) -> String {
         if i + 2 < data.len() {
             n += u32::from(data[i + 2]);
         }
-        output.push(BASE64_CHARS[(n >> 18 & 63) as usize] as char);
-        output.push(BASE64_CHARS[(n >> 12 & 63) as usize] as char);
-        output.push(if i + 1 < data.len() {
-            BASE64_CHARS[(n >> 6 & 63) as usize] as char
-        } else {
-            '='
-        });
-        output.push(if i + 2 < data.len() {
-            BASE64_CHARS[(n & 63) as usize] as char
-        } else {
-            '='
-        });
+        output.push(alphabet[(n >> 18 & 63) as usize] as char);
+        output.push(alphabet[(n >> 12 & 63) as usize] as char);
+        if i + 1 < data.len() {
+            output.push(alphabet[(n >> 6 & 63) as usize] as char);
+        } else if padding {
+            output.push('=');
+        }
+        if i + 2 < data.len() {
+            output.push(alphabet[(n & 63) as usize] as char);
+        } else if padding {
+            output.push('=');
+        }
 
         i += 3;
     }
@@ -33,16 +61,13 @@ pub fn base64_encode(data: &[u8]) -> String {
     output
 }
 
-/**
- * Decode a base64 string into a byte vector.
- */
-pub fn base64_decode(input: &str) -> Result<Vec<u8>, &'static str> {
+fn decode_with(input: &str, alphabet: &[u8]) -> Result<Vec<u8>, &'static str> {
     let input = input.trim_end_matches('=');
     let mut output = Vec::with_capacity(input.len() * 3 / 4);
 
     // Create a lookup table for base64 characters
     let mut reverse_lookup = [0u8; 256];
-    for (i, &c) in BASE64_CHARS.iter().enumerate() {
+    for (i, &c) in alphabet.iter().enumerate() {
         reverse_lookup[c as usize] = i as u8;
     }
 
@@ -54,7 +79,7 @@ pub fn base64_decode(input: &str) -> Result<Vec<u8>, &'static str> {
             break;
         }
         let v = reverse_lookup[c as usize];
-        if v == 0 && c != BASE64_CHARS[0] {
+        if v == 0 && c != alphabet[0] {
             return Err("Invalid base64 character");
         }
         buf = (buf << 6) | u32::from(v);
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::net::IpAddr;
+
+/**
+    An IPv4 or IPv6 network in CIDR notation, a plain address is treated as a network
+    containing only that address.
+
+    let private = Cidr::parse("10.0.0.0/8").unwrap();
+    assert!(private.contains(&"10.1.2.3".parse().unwrap()));
+
+    IPv4-mapped IPv6 addresses such as `::ffff:10.1.2.3` are matched as IPv4, and a mapped
+    network such as `::ffff:10.0.0.0/104` is the IPv4 network `10.0.0.0/8`.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /** Parse `address/prefix` or a plain address, the host bits are cleared. */
+    pub fn parse(input: &str) -> Option<Self> {
+        let (address, prefix_len) = match input.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len.parse::<u8>().ok()?)),
+            None => (input.trim(), None),
+        };
+        let parsed = address.parse::<IpAddr>().ok()?;
+        let address = parsed.to_canonical();
+        // the prefix of an IPv4-mapped network counts the 96 bits of the `::ffff:` part
+        let prefix_len = match (parsed, prefix_len) {
+            (IpAddr::V6(_), Some(prefix_len)) if address.is_ipv4() => {
+                Some(prefix_len.checked_sub(96)?)
+            }
+            (_, prefix_len) => prefix_len,
+        };
+        let max_len = max_prefix_len(&address);
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        if prefix_len > max_len {
+            return None;
+        }
+        Some(Cidr {
+            network: mask(&address, prefix_len),
+            prefix_len,
+        })
+    }
+
+    /** Every IPv4 address. */
+    pub fn any_v4() -> Self {
+        Cidr::parse("0.0.0.0/0").unwrap()
+    }
+
+    /** Every IPv6 address. */
+    pub fn any_v6() -> Self {
+        Cidr::parse("::/0").unwrap()
+    }
+
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        let address = address.to_canonical();
+        match (&self.network, &address) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                mask(&address, self.prefix_len) == self.network
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+fn max_prefix_len(address: &IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/** Clear every bit of the address after the first `prefix_len` bits. */
+fn mask(address: &IpAddr, prefix_len: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(v4) => {
+            let bits = u32::from(*v4);
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V4((bits & mask).into())
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::from(*v6);
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V6((bits & mask).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_networks_and_clears_host_bits() {
+        assert_eq!(Cidr::parse("10.1.2.3/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(
+            Cidr::parse(" 127.0.0.1 ").unwrap().to_string(),
+            "127.0.0.1/32"
+        );
+        assert_eq!(
+            Cidr::parse("2001:db8::1/32").unwrap().to_string(),
+            "2001:db8::/32"
+        );
+        assert_eq!(Cidr::parse("::1").unwrap().to_string(), "::1/128");
+        assert_eq!(Cidr::any_v4().to_string(), "0.0.0.0/0");
+    }
+
+    #[test]
+    fn refuses_invalid_networks() {
+        assert!(Cidr::parse("10.0.0.0/33").is_none());
+        assert!(Cidr::parse("::/129").is_none());
+        assert!(Cidr::parse("10.0.0.0/").is_none());
+        assert!(Cidr::parse("10.0.0/8").is_none());
+        assert!(Cidr::parse("example.com").is_none());
+    }
+
+    #[test]
+    fn contains_addresses_in_the_network() {
+        let private = Cidr::parse("10.0.0.0/8").unwrap();
+        assert!(private.contains(&ip("10.1.2.3")));
+        assert!(private.contains(&ip("10.255.255.255")));
+        assert!(!private.contains(&ip("11.0.0.0")));
+        assert!(!private.contains(&ip("::1")));
+
+        let v6 = Cidr::parse("2001:db8::/32").unwrap();
+        assert!(v6.contains(&ip("2001:db8:ffff::1")));
+        assert!(!v6.contains(&ip("2001:db9::1")));
+        assert!(!v6.contains(&ip("10.0.0.1")));
+
+        assert!(Cidr::any_v4().contains(&ip("192.0.2.1")));
+        assert!(!Cidr::any_v4().contains(&ip("2001:db8::1")));
+        assert!(Cidr::any_v6().contains(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn matches_ipv4_mapped_addresses_as_ipv4() {
+        let private = Cidr::parse("10.0.0.0/8").unwrap();
+        assert!(private.contains(&ip("::ffff:10.1.2.3")));
+        assert_eq!(
+            Cidr::parse("::ffff:10.1.2.3/128"),
+            Cidr::parse("10.1.2.3/32")
+        );
+        assert_eq!(Cidr::parse("::ffff:10.1.2.3"), Cidr::parse("10.1.2.3/32"));
+        assert_eq!(
+            Cidr::parse("::ffff:10.0.0.0/104"),
+            Cidr::parse("10.0.0.0/8")
+        );
+        assert_eq!(Cidr::parse("::ffff:0.0.0.0/96"), Some(Cidr::any_v4()));
+        assert!(Cidr::parse("::ffff:10.1.2.3/32").is_none());
+        assert!(Cidr::parse("::ffff:10.1.2.3/95").is_none());
+        assert!(Cidr::parse("::ffff:10.1.2.3/129").is_none());
+    }
+}
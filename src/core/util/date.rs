@@ -0,0 +1,378 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/**
+    A calendar date and time in UTC.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub nanos: u32,
+}
+
+impl DateTime {
+    /**
+        Create a date and time, returns None if any field is out of range. A leap second
+        is accepted and counted as the first second of the next minute.
+    */
+    pub fn new(
+        year: i64,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: u32,
+    ) -> Option<Self> {
+        let is_valid = (1..=12).contains(&month)
+            && day >= 1
+            && day <= days_in_month(year, month)
+            && hour < 24
+            && minute < 60
+            && second <= 60;
+        if !is_valid {
+            return None;
+        }
+        Some(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            nanos: 0,
+        })
+    }
+
+    /**
+        Convert a system time into a calendar date in UTC, times before the unix epoch
+        are clamped to the epoch.
+    */
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let duration = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+        let secs = duration.as_secs() as i64;
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let seconds_of_day = secs.rem_euclid(86400) as u32;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: seconds_of_day / 3600,
+            minute: (seconds_of_day % 3600) / 60,
+            second: seconds_of_day % 60,
+            nanos: duration.subsec_nanos(),
+        }
+    }
+
+    pub fn now() -> Self {
+        DateTime::from_system_time(SystemTime::now())
+    }
+
+    /** Convert back into a system time, returns None if it can not be represented. */
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        let secs = self.days() * 86400
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64;
+        let offset = Duration::new(secs.unsigned_abs(), 0);
+        let time = match secs >= 0 {
+            true => UNIX_EPOCH.checked_add(offset)?,
+            false => UNIX_EPOCH.checked_sub(offset)?,
+        };
+        time.checked_add(Duration::from_nanos(self.nanos as u64))
+    }
+
+    /** Days since 1970-01-01 for this date. */
+    pub fn days(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day)
+    }
+
+    /** Abbreviated name of the day of the week, e.g. `Sun`. */
+    pub fn weekday(&self) -> &'static str {
+        // 1970-01-01 was a Thursday, which is index 3 when the week starts on Monday.
+        WEEKDAYS[(self.days() + 3).rem_euclid(7) as usize]
+    }
+
+    /** Abbreviated name of the month, e.g. `Nov`. */
+    pub fn month_name(&self) -> &'static str {
+        MONTHS[(self.month as usize).saturating_sub(1) % 12]
+    }
+
+    /**
+        Format as an IMF-fixdate which is the preferred format for HTTP dates.
+
+        Sun, 06 Nov 1994 08:49:37 GMT
+    */
+    pub fn to_http_date(&self) -> String {
+        format!(
+            "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            self.weekday(),
+            self.day,
+            self.month_name(),
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+
+    /**
+        Format as an RFC 3339 timestamp in UTC, the fraction of a second is only included
+        when it is not zero.
+
+        2026-10-19T09:30:12Z
+        2026-10-19T09:30:12.250000000Z
+    */
+    pub fn to_rfc3339(&self) -> String {
+        let fraction = match self.nanos {
+            0 => String::new(),
+            nanos => format!(".{:09}", nanos),
+        };
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, fraction
+        )
+    }
+
+    /**
+        Parse an HTTP date, which should be an IMF-fixdate but may also be in the obsolete
+        RFC 850 or asctime formats that recipients are required to accept.
+
+        Sun, 06 Nov 1994 08:49:37 GMT
+        Sunday, 06-Nov-94 08:49:37 GMT
+        Sun Nov  6 08:49:37 1994
+    */
+    pub fn parse_http_date(input: &str) -> Option<Self> {
+        let fields = input.split_whitespace().collect::<Vec<&str>>();
+        match fields.as_slice() {
+            [weekday, day, month, year, time, "GMT"] if weekday.ends_with(',') => {
+                if day.len() != 2 || year.len() != 4 {
+                    return None;
+                }
+                DateTime::from_fields(year.parse().ok()?, month, day, time)
+            }
+            [weekday, date, time, "GMT"] if weekday.ends_with(',') => {
+                let mut parts = date.split('-');
+                let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+                if parts.next().is_some() || day.len() != 2 || year.len() != 2 {
+                    return None;
+                }
+                let year = full_year(year.parse().ok()?, DateTime::now().year);
+                DateTime::from_fields(year, month, day, time)
+            }
+            [_weekday, month, day, time, year] if year.len() == 4 => {
+                DateTime::from_fields(year.parse().ok()?, month, day, time)
+            }
+            _ => None,
+        }
+    }
+
+    fn from_fields(year: i64, month: &str, day: &str, time: &str) -> Option<Self> {
+        let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+        let mut parts = time.split(':');
+        let mut number = || {
+            parts
+                .next()
+                .filter(|part| part.len() == 2)
+                .and_then(|part| part.parse::<u32>().ok())
+        };
+        let (hour, minute, second) = (number()?, number()?, number()?);
+        if parts.next().is_some() || day.len() > 2 {
+            return None;
+        }
+        DateTime::new(year, month, day.parse().ok()?, hour, minute, second)
+    }
+}
+
+/**
+    Format a system time as an IMF-fixdate for headers such as `Date` and `Expires`.
+*/
+pub fn format_http_date(time: SystemTime) -> String {
+    DateTime::from_system_time(time).to_http_date()
+}
+
+/**
+    Parse an HTTP date header such as `If-Modified-Since` into a system time.
+*/
+pub fn parse_http_date(input: &str) -> Option<SystemTime> {
+    DateTime::parse_http_date(input)?.to_system_time()
+}
+
+/**
+    Expand a two digit year from an RFC 850 date, a year which would be more than 50 years
+    in the future is taken to be in the past century instead.
+*/
+fn full_year(year: i64, current_year: i64) -> i64 {
+    let year = current_year - current_year.rem_euclid(100) + year;
+    if year > current_year + 50 {
+        year - 100
+    } else {
+        year
+    }
+}
+
+pub fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/**
+    Convert days since 1970-01-01 into a (year, month, day) civil date in the proleptic
+    Gregorian calendar.
+
+    http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+*/
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/**
+    Convert a (year, month, day) civil date into days since 1970-01-01.
+
+    http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+*/
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** 1994-11-06 08:49:37 UTC, the example date of RFC 9110 section 5.6.7. */
+    const EXAMPLE_SECS: u64 = 784_111_777;
+
+    #[test]
+    fn formats_imf_fixdates() {
+        let time = UNIX_EPOCH + Duration::from_secs(EXAMPLE_SECS);
+        assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            format_http_date(UNIX_EPOCH),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn parses_all_three_http_date_formats() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(EXAMPLE_SECS));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+    }
+
+    #[test]
+    fn refuses_malformed_http_dates() {
+        for input in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 6 Nov 1994 08:49:37 GMT",
+            "Sun, 06 November 1994 08:49:37 GMT",
+            "Sun, 06 Nov 94 08:49:37 GMT",
+            "Sun, 06 Nov 1994 8:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49:37:00 GMT",
+            "Sun, 31 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sunday, 06-Nov-1994 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 94",
+        ] {
+            assert_eq!(parse_http_date(input), None, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn expands_two_digit_years_within_fifty_years() {
+        assert_eq!(full_year(94, 2026), 1994);
+        assert_eq!(full_year(26, 2026), 2026);
+        assert_eq!(full_year(76, 2026), 2076);
+        assert_eq!(full_year(77, 2026), 1977);
+        assert_eq!(full_year(0, 2099), 2000);
+    }
+
+    #[test]
+    fn converts_between_days_and_civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for days in (-800_000..800_000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn knows_leap_years() {
+        assert!(is_leap_year(2000));
+        assert!(is_leap_year(2024));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2026));
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2100, 2), 28);
+        assert!(DateTime::new(2024, 2, 29, 0, 0, 0).is_some());
+        assert!(DateTime::new(2026, 2, 29, 0, 0, 0).is_none());
+        assert!(DateTime::new(2026, 13, 1, 0, 0, 0).is_none());
+        assert!(DateTime::new(2026, 1, 0, 0, 0, 0).is_none());
+    }
+
+    #[test]
+    fn leap_seconds_are_the_next_minute() {
+        let leap = DateTime::new(2016, 12, 31, 23, 59, 60).unwrap();
+        let next = DateTime::new(2017, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(leap.to_system_time(), next.to_system_time());
+    }
+
+    #[test]
+    fn round_trips_system_times() {
+        let time = UNIX_EPOCH + Duration::new(1_792_400_000, 250_000_000);
+        let date = DateTime::from_system_time(time);
+        assert_eq!(date.to_system_time(), Some(time));
+        assert_eq!(
+            DateTime::from_system_time(UNIX_EPOCH - Duration::from_secs(1)),
+            DateTime::from_system_time(UNIX_EPOCH)
+        );
+    }
+
+    #[test]
+    fn formats_rfc3339() {
+        let mut date = DateTime::new(2026, 10, 19, 9, 30, 12).unwrap();
+        assert_eq!(date.to_rfc3339(), "2026-10-19T09:30:12Z");
+        date.nanos = 250_000_000;
+        assert_eq!(date.to_rfc3339(), "2026-10-19T09:30:12.250000000Z");
+        assert_eq!(date.weekday(), "Mon");
+        assert_eq!(date.month_name(), "Oct");
+    }
+}
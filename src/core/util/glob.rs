@@ -0,0 +1,60 @@
+/**
+    Match a string against a simple glob pattern, where `*` matches any sequence of
+    characters (including none) and `?` matches exactly one character.
+
+    glob_match("http://localhost:*", "http://localhost:3000") // true
+    glob_match("admin-*", "admin-users") // true
+*/
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // backtrack and let the last star consume one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    while p < pattern.len() && pattern[p] == b'*' {
+        p += 1;
+    }
+
+    p == pattern.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_stars_and_question_marks() {
+        assert!(glob_match("http://localhost:*", "http://localhost:3000"));
+        assert!(glob_match("admin-*", "admin-"));
+        assert!(glob_match("*.example.com", "api.example.com"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn requires_the_whole_text_to_match() {
+        assert!(!glob_match("admin-*", "user-admin-x"));
+        assert!(!glob_match("*.example.com", "example.com"));
+        assert!(!glob_match("*.example.com", "api.example.com.evil.com"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(!glob_match("", "a"));
+    }
+}
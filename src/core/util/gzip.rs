@@ -0,0 +1,354 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/** Size of the sliding window, the furthest back a match can refer to. */
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/** How many earlier positions with the same hash are tried for each match. */
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+/**
+    Base lengths for length codes 257 to 285, and the number of extra bits for each
+    (RFC 1951 section 3.2.5).
+*/
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/** Base distances for distance codes 0 to 29, and the number of extra bits for each. */
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/**
+    Compute the CRC-32 checksum used by gzip and zip (ISO 3309, reflected polynomial
+    0xedb88320).
+*/
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/**
+    Compress data into the gzip format (RFC 1952), which can be read with `gunzip` or
+    `zcat`.
+
+    let archive = gzip(&fs::read("events.csv")?);
+*/
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as u32)
+        .unwrap_or(0);
+    // magic, deflate, no flags, modification time, no extra flags, unknown OS
+    let mut output = vec![0x1f, 0x8b, 8, 0];
+    output.extend_from_slice(&mtime.to_le_bytes());
+    output.extend_from_slice(&[0, 255]);
+    output.extend_from_slice(&deflate(data));
+    output.extend_from_slice(&crc32(data).to_le_bytes());
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output
+}
+
+/**
+    Compress data into a raw DEFLATE stream (RFC 1951), using LZ77 matching with hash
+    chains and a single block of fixed Huffman codes. This compresses text such as logs
+    well without the cost of building dynamic code tables.
+*/
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // final block, fixed Huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = longest_match(data, position, &head, &prev);
+        if length >= MIN_MATCH {
+            write_match(&mut writer, length, distance);
+            for index in position..position + length {
+                insert_hash(data, index, &mut head, &mut prev);
+            }
+            position += length;
+        } else {
+            write_literal(&mut writer, data[position] as u16);
+            insert_hash(data, position, &mut head, &mut prev);
+            position += 1;
+        }
+    }
+
+    write_literal(&mut writer, 256);
+    writer.finish()
+}
+
+fn hash(data: &[u8], position: usize) -> usize {
+    let value = (data[position] as u32) << 16
+        | (data[position + 1] as u32) << 8
+        | data[position + 2] as u32;
+    (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert_hash(data: &[u8], position: usize, head: &mut [usize], prev: &mut [usize]) {
+    if position + MIN_MATCH > data.len() {
+        return;
+    }
+    let hash = hash(data, position);
+    prev[position % WINDOW_SIZE] = head[hash];
+    head[hash] = position;
+}
+
+/** Find the longest earlier match for the data at a position within the window. */
+fn longest_match(data: &[u8], position: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if position + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let max_length = MAX_MATCH.min(data.len() - position);
+    let mut best = (0, 0);
+    let mut candidate = head[hash(data, position)];
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || position - candidate > WINDOW_SIZE {
+            break;
+        }
+        let length = data[candidate..]
+            .iter()
+            .zip(&data[position..position + max_length])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best.0 {
+            best = (length, position - candidate);
+            if length == max_length {
+                break;
+            }
+        }
+        let next = prev[candidate % WINDOW_SIZE];
+        // the slot may have been reused by a newer position, which ends the chain
+        if next == usize::MAX || next >= candidate {
+            break;
+        }
+        candidate = next;
+    }
+    best
+}
+
+/** Write a literal byte or the end of block code with the fixed Huffman codes. */
+fn write_literal(writer: &mut BitWriter, value: u16) {
+    let (code, length) = match value {
+        0..=143 => (0x30 + value, 8),
+        144..=255 => (0x190 + value - 144, 9),
+        256..=279 => (value - 256, 7),
+        _ => (0xc0 + value - 280, 8),
+    };
+    writer.write_code(code, length);
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let index = LENGTH_BASE
+        .iter()
+        .rposition(|base| *base as usize <= length)
+        .unwrap();
+    write_literal(writer, 257 + index as u16);
+    writer.write_bits(
+        (length - LENGTH_BASE[index] as usize) as u32,
+        LENGTH_EXTRA[index] as u32,
+    );
+
+    let index = DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base as usize <= distance)
+        .unwrap();
+    writer.write_code(index as u16, 5);
+    writer.write_bits(
+        (distance - DISTANCE_BASE[index] as usize) as u32,
+        DISTANCE_EXTRA[index] as u32,
+    );
+}
+
+/**
+    Packs bits into bytes starting from the least significant bit, as DEFLATE requires.
+*/
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            output: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /** Huffman codes are written starting from their most significant bit. */
+    fn write_code(&mut self, code: u16, length: u32) {
+        let reversed = (code.reverse_bits() >> (16 - length)) as u32;
+        self.write_bits(reversed, length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+        Decode a DEFLATE stream which only uses fixed Huffman blocks, which is all that
+        `deflate` writes, so the output can be checked without another implementation.
+    */
+    fn inflate_fixed(data: &[u8]) -> Vec<u8> {
+        let mut reader = BitReader { data, position: 0 };
+        let mut output = Vec::new();
+        loop {
+            let is_final = reader.bits(1) == 1;
+            assert_eq!(reader.bits(2), 1, "only fixed Huffman blocks are expected");
+            loop {
+                let symbol = reader.literal();
+                match symbol {
+                    0..=255 => output.push(symbol as u8),
+                    256 => break,
+                    _ => {
+                        let index = (symbol - 257) as usize;
+                        let length = LENGTH_BASE[index] as usize
+                            + reader.bits(LENGTH_EXTRA[index] as u32) as usize;
+                        let index = reader.code(5) as usize;
+                        let distance = DISTANCE_BASE[index] as usize
+                            + reader.bits(DISTANCE_EXTRA[index] as u32) as usize;
+                        assert!(distance <= output.len() && distance <= WINDOW_SIZE);
+                        for _ in 0..length {
+                            output.push(output[output.len() - distance]);
+                        }
+                    }
+                }
+            }
+            if is_final {
+                return output;
+            }
+        }
+    }
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bit(&mut self) -> u32 {
+            let bit = (self.data[self.position / 8] >> (self.position % 8)) & 1;
+            self.position += 1;
+            bit as u32
+        }
+
+        /** Extra bits are packed starting from the least significant bit. */
+        fn bits(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, index| value | self.bit() << index)
+        }
+
+        /** Huffman codes are packed starting from the most significant bit. */
+        fn code(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, _| value << 1 | self.bit())
+        }
+
+        fn literal(&mut self) -> u16 {
+            let code = self.code(7);
+            if code <= 0x17 {
+                return 256 + code as u16;
+            }
+            let code = code << 1 | self.bit();
+            match code {
+                0x30..=0xbf => (code - 0x30) as u16,
+                0xc0..=0xc7 => (280 + code - 0xc0) as u16,
+                _ => (144 + (code << 1 | self.bit()) - 0x190) as u16,
+            }
+        }
+    }
+
+    fn round_trip(data: &[u8]) {
+        assert_eq!(inflate_fixed(&deflate(data)), data);
+    }
+
+    #[test]
+    fn computes_crc32_check_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414f_a339
+        );
+    }
+
+    #[test]
+    fn deflate_round_trips() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"abcabcabcabcabcabc");
+        round_trip(&[0xff; 1000]);
+        round_trip(&(0..=255).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn deflate_round_trips_log_lines_over_the_window() {
+        let mut data = Vec::new();
+        let mut seed = 1u32;
+        while data.len() < 3 * WINDOW_SIZE {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let line = format!(
+                "2026-10-19T09:30:{:02}Z,production,info,request {} took {}ms\n",
+                seed % 60,
+                seed,
+                seed % 997
+            );
+            data.extend_from_slice(line.as_bytes());
+        }
+        round_trip(&data);
+        assert!(deflate(&data).len() < data.len() / 2);
+    }
+
+    #[test]
+    fn writes_gzip_header_and_trailer() {
+        let data = b"hello hello hello\n";
+        let archive = gzip(data);
+        assert_eq!(&archive[..4], &[0x1f, 0x8b, 8, 0]);
+        assert_eq!(&archive[8..10], &[0, 255]);
+        let trailer = &archive[archive.len() - 8..];
+        assert_eq!(trailer[..4], crc32(data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        assert_eq!(inflate_fixed(&archive[10..archive.len() - 8]), data);
+    }
+}
@@ -0,0 +1,35 @@
+const HEX_CHARS: &[u8] = b"0123456789abcdef";
+
+/**
+ * Encode a byte slice as a lowercase hexadecimal string.
+ */
+pub fn hex_encode(data: &[u8]) -> String {
+    let mut output = String::with_capacity(data.len() * 2);
+    for byte in data {
+        output.push(HEX_CHARS[(byte >> 4) as usize] as char);
+        output.push(HEX_CHARS[(byte & 0x0f) as usize] as char);
+    }
+    output
+}
+
+/**
+ * Decode a hexadecimal string (upper or lowercase) into a byte vector.
+ */
+pub fn hex_decode(input: &str) -> Result<Vec<u8>, &'static str> {
+    if !input.len().is_multiple_of(2) {
+        return Err("Invalid hex length");
+    }
+    input
+        .as_bytes()
+        .chunks_exact(2)
+        .map(|pair| {
+            let high = (pair[0] as char)
+                .to_digit(16)
+                .ok_or("Invalid hex character")?;
+            let low = (pair[1] as char)
+                .to_digit(16)
+                .ok_or("Invalid hex character")?;
+            Ok((high * 16 + low) as u8)
+        })
+        .collect()
+}
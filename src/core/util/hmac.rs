@@ -0,0 +1,66 @@
+use super::sha256::Sha256;
+
+/**
+    Compute the HMAC-SHA256 of a message with the given key (RFC 2104).
+*/
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new(key);
+    mac.update(message);
+    mac.finalize()
+}
+
+/**
+    Incremental HMAC-SHA256, which is useful when the key is reused for several messages
+    since the padded key only needs to be hashed once (e.g. for PBKDF2).
+*/
+#[derive(Clone, Debug)]
+pub struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    pub fn new(key: &[u8]) -> Self {
+        // keys longer than the block size are hashed first, then padded with zeros
+        let mut block = [0u8; Sha256::BLOCK_SIZE];
+        if key.len() > Sha256::BLOCK_SIZE {
+            let mut hasher = Sha256::new();
+            hasher.update(key);
+            block[..Sha256::OUTPUT_SIZE].copy_from_slice(&hasher.finalize());
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner = Sha256::new();
+        let mut outer = Sha256::new();
+        inner.update(&block.map(|byte| byte ^ 0x36));
+        outer.update(&block.map(|byte| byte ^ 0x5c));
+        HmacSha256 { inner, outer }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finalize());
+        outer.finalize()
+    }
+}
+
+/**
+    Compare two byte slices in constant time, this should be used whenever comparing
+    secrets such as signatures or password hashes to avoid leaking timing information.
+    NOTE: The length of the slices is not treated as secret.
+*/
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let difference = a
+        .iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    difference == 0
+}
@@ -1,9 +1,40 @@
 pub mod base64;
+pub mod cidr;
+pub mod date;
+pub mod glob;
+pub mod gzip;
+pub mod hex;
+pub mod hmac;
+pub mod md5;
 pub mod mime;
+pub mod p256;
+pub mod pbkdf2;
+pub mod percent;
 pub mod rand;
+pub mod sha256;
+pub mod signal;
+pub mod size;
 
 pub use self::base64::base64_decode;
 pub use self::base64::base64_encode;
+pub use self::base64::base64url_decode;
+pub use self::base64::base64url_encode;
+pub use self::cidr::Cidr;
+pub use self::date::format_http_date;
+pub use self::date::parse_http_date;
+pub use self::date::DateTime;
+pub use self::glob::glob_match;
+pub use self::gzip::{crc32, gzip};
+pub use self::hex::hex_decode;
+pub use self::hex::hex_encode;
+pub use self::hmac::constant_time_eq;
+pub use self::hmac::hmac_sha256;
+pub use self::md5::md5;
 pub use self::mime::get_mime_type;
+pub use self::pbkdf2::pbkdf2_hmac_sha256;
+pub use self::percent::{encode_path, form_decode, percent_decode, percent_encode};
 pub use self::rand::generate_random_u64;
+pub use self::rand::secure_random_bytes;
 pub use self::rand::Rand;
+pub use self::sha256::sha256;
+pub use self::size::parse_size;
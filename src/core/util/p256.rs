@@ -0,0 +1,444 @@
+use super::sha256;
+
+/** A 256-bit unsigned integer stored as four little-endian 64-bit limbs. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct U256([u64; 4]);
+
+impl U256 {
+    const ZERO: U256 = U256([0, 0, 0, 0]);
+    const ONE: U256 = U256([1, 0, 0, 0]);
+
+    fn from_be_bytes(bytes: &[u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 32 - (i + 1) * 8;
+            *limb = u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    fn from_hex(hex: &str) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let end = hex.len() - i * 16;
+            *limb = u64::from_str_radix(&hex[end - 16..end], 16).unwrap();
+        }
+        U256(limbs)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0 == [0, 0, 0, 0]
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        (self.0[index / 64] >> (index % 64)) & 1 == 1
+    }
+
+    fn less_than(&self, other: &U256) -> bool {
+        for i in (0..4).rev() {
+            if self.0[i] != other.0[i] {
+                return self.0[i] < other.0[i];
+            }
+        }
+        false
+    }
+
+    /** Add two numbers, returning the sum and whether it overflowed. */
+    fn add(&self, other: &U256) -> (U256, bool) {
+        let mut result = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (sum, overflow_a) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, overflow_b) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = overflow_a || overflow_b;
+        }
+        (U256(result), carry)
+    }
+
+    /** Subtract two numbers, returning the difference and whether it borrowed. */
+    fn sub(&self, other: &U256) -> (U256, bool) {
+        let mut result = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (difference, borrow_a) = self.0[i].overflowing_sub(other.0[i]);
+            let (difference, borrow_b) = difference.overflowing_sub(borrow as u64);
+            *limb = difference;
+            borrow = borrow_a || borrow_b;
+        }
+        (U256(result), borrow)
+    }
+}
+
+/**
+    Arithmetic modulo an odd 256-bit prime using Montgomery multiplication, numbers in the
+    Montgomery domain are stored as `a * R mod m` where `R = 2^256`.
+*/
+struct Modulus {
+    m: U256,
+    /** -m^-1 mod 2^64 */
+    m_inv: u64,
+    /** R^2 mod m, used to convert into the Montgomery domain. */
+    r2: U256,
+}
+
+impl Modulus {
+    fn new(m: U256) -> Self {
+        // Newton's method doubles the number of correct bits on every iteration.
+        let mut inv = 1u64;
+        for _ in 0..6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(m.0[0].wrapping_mul(inv)));
+        }
+
+        // compute R^2 mod m by doubling 1 a total of 512 times
+        let mut modulus = Modulus {
+            m,
+            m_inv: inv.wrapping_neg(),
+            r2: U256::ONE,
+        };
+        let mut r2 = U256::ONE;
+        for _ in 0..512 {
+            r2 = modulus.add(&r2, &r2);
+        }
+        modulus.r2 = r2;
+        modulus
+    }
+
+    /** Reduce a number which is less than 2m. */
+    fn reduce_once(&self, a: &U256) -> U256 {
+        match a.less_than(&self.m) {
+            true => *a,
+            false => a.sub(&self.m).0,
+        }
+    }
+
+    fn add(&self, a: &U256, b: &U256) -> U256 {
+        let (sum, overflow) = a.add(b);
+        match overflow || !sum.less_than(&self.m) {
+            true => sum.sub(&self.m).0,
+            false => sum,
+        }
+    }
+
+    fn sub(&self, a: &U256, b: &U256) -> U256 {
+        let (difference, borrow) = a.sub(b);
+        match borrow {
+            true => difference.add(&self.m).0,
+            false => difference,
+        }
+    }
+
+    /** Montgomery multiplication, returns `a * b * R^-1 mod m`. */
+    fn mont_mul(&self, a: &U256, b: &U256) -> U256 {
+        let (a, b, m) = (&a.0, &b.0, &self.m.0);
+        let mut t = [0u64; 6];
+        for &a_i in a.iter() {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let value = t[j] as u128 + a_i as u128 * b[j] as u128 + carry;
+                t[j] = value as u64;
+                carry = value >> 64;
+            }
+            let value = t[4] as u128 + carry;
+            t[4] = value as u64;
+            t[5] = (value >> 64) as u64;
+
+            let factor = t[0].wrapping_mul(self.m_inv);
+            let mut carry = (t[0] as u128 + factor as u128 * m[0] as u128) >> 64;
+            for j in 1..4 {
+                let value = t[j] as u128 + factor as u128 * m[j] as u128 + carry;
+                t[j - 1] = value as u64;
+                carry = value >> 64;
+            }
+            let value = t[4] as u128 + carry;
+            t[3] = value as u64;
+            t[4] = t[5] + (value >> 64) as u64;
+            t[5] = 0;
+        }
+
+        let result = U256([t[0], t[1], t[2], t[3]]);
+        match t[4] != 0 || !result.less_than(&self.m) {
+            true => result.sub(&self.m).0,
+            false => result,
+        }
+    }
+
+    fn to_mont(&self, a: &U256) -> U256 {
+        self.mont_mul(a, &self.r2)
+    }
+
+    fn to_int(&self, a: &U256) -> U256 {
+        self.mont_mul(a, &U256::ONE)
+    }
+
+    /** Multiply two numbers which are not in the Montgomery domain. */
+    fn mul(&self, a: &U256, b: &U256) -> U256 {
+        self.mont_mul(&self.mont_mul(a, b), &self.r2)
+    }
+
+    /** Modular inverse of a Montgomery number using Fermat's little theorem. */
+    fn mont_inv(&self, a: &U256) -> U256 {
+        let exponent = self.m.sub(&U256([2, 0, 0, 0])).0;
+        let mut result = self.to_mont(&U256::ONE);
+        for i in (0..256).rev() {
+            result = self.mont_mul(&result, &result);
+            if exponent.bit(i) {
+                result = self.mont_mul(&result, a);
+            }
+        }
+        result
+    }
+}
+
+/** A point in Jacobian coordinates with the field elements in the Montgomery domain. */
+#[derive(Clone, Copy, Debug)]
+struct Point {
+    x: U256,
+    y: U256,
+    z: U256,
+}
+
+struct Curve {
+    p: Modulus,
+    n: Modulus,
+    b: U256,
+    g: Point,
+}
+
+impl Curve {
+    fn p256() -> Self {
+        let p = Modulus::new(U256::from_hex(
+            "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff",
+        ));
+        let n = Modulus::new(U256::from_hex(
+            "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551",
+        ));
+        let b = p.to_mont(&U256::from_hex(
+            "5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b",
+        ));
+        let g = Point {
+            x: p.to_mont(&U256::from_hex(
+                "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296",
+            )),
+            y: p.to_mont(&U256::from_hex(
+                "4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5",
+            )),
+            z: p.to_mont(&U256::ONE),
+        };
+        Curve { p, n, b, g }
+    }
+
+    fn infinity(&self) -> Point {
+        Point {
+            x: self.p.to_mont(&U256::ONE),
+            y: self.p.to_mont(&U256::ONE),
+            z: U256::ZERO,
+        }
+    }
+
+    /** Check an affine point (in the Montgomery domain) satisfies y^2 = x^3 - 3x + b. */
+    fn is_on_curve(&self, x: &U256, y: &U256) -> bool {
+        let p = &self.p;
+        let y2 = p.mont_mul(y, y);
+        let x3 = p.mont_mul(&p.mont_mul(x, x), x);
+        let three_x = p.add(&p.add(x, x), x);
+        let rhs = p.add(&p.sub(&x3, &three_x), &self.b);
+        y2 == rhs
+    }
+
+    /** Point doubling for a = -3, see "dbl-2001-b" in the Explicit-Formulas Database. */
+    fn double(&self, point: &Point) -> Point {
+        if point.z.is_zero() {
+            return *point;
+        }
+        let p = &self.p;
+        let delta = p.mont_mul(&point.z, &point.z);
+        let gamma = p.mont_mul(&point.y, &point.y);
+        let beta = p.mont_mul(&point.x, &gamma);
+        let t = p.mont_mul(&p.sub(&point.x, &delta), &p.add(&point.x, &delta));
+        let alpha = p.add(&p.add(&t, &t), &t);
+        let beta4 = p.add(&p.add(&beta, &beta), &p.add(&beta, &beta));
+        let beta8 = p.add(&beta4, &beta4);
+        let x = p.sub(&p.mont_mul(&alpha, &alpha), &beta8);
+        let yz = p.add(&point.y, &point.z);
+        let z = p.sub(&p.sub(&p.mont_mul(&yz, &yz), &gamma), &delta);
+        let gamma2 = p.mont_mul(&gamma, &gamma);
+        let gamma2_8 = {
+            let g2 = p.add(&gamma2, &gamma2);
+            let g4 = p.add(&g2, &g2);
+            p.add(&g4, &g4)
+        };
+        let y = p.sub(&p.mont_mul(&alpha, &p.sub(&beta4, &x)), &gamma2_8);
+        Point { x, y, z }
+    }
+
+    /** Point addition, see "add-2007-bl" in the Explicit-Formulas Database. */
+    fn add(&self, a: &Point, b: &Point) -> Point {
+        if a.z.is_zero() {
+            return *b;
+        }
+        if b.z.is_zero() {
+            return *a;
+        }
+        let p = &self.p;
+        let z1z1 = p.mont_mul(&a.z, &a.z);
+        let z2z2 = p.mont_mul(&b.z, &b.z);
+        let u1 = p.mont_mul(&a.x, &z2z2);
+        let u2 = p.mont_mul(&b.x, &z1z1);
+        let s1 = p.mont_mul(&p.mont_mul(&a.y, &b.z), &z2z2);
+        let s2 = p.mont_mul(&p.mont_mul(&b.y, &a.z), &z1z1);
+        let h = p.sub(&u2, &u1);
+        let r = p.sub(&s2, &s1);
+        if h.is_zero() {
+            return match r.is_zero() {
+                true => self.double(a),
+                false => self.infinity(),
+            };
+        }
+        let h2 = p.add(&h, &h);
+        let i = p.mont_mul(&h2, &h2);
+        let j = p.mont_mul(&h, &i);
+        let r = p.add(&r, &r);
+        let v = p.mont_mul(&u1, &i);
+        let x = p.sub(&p.sub(&p.mont_mul(&r, &r), &j), &p.add(&v, &v));
+        let s1j = p.mont_mul(&s1, &j);
+        let y = p.sub(&p.mont_mul(&r, &p.sub(&v, &x)), &p.add(&s1j, &s1j));
+        let zz = p.add(&a.z, &b.z);
+        let z = p.mont_mul(&p.sub(&p.sub(&p.mont_mul(&zz, &zz), &z1z1), &z2z2), &h);
+        Point { x, y, z }
+    }
+
+    /** Compute u1 * G + u2 * Q with a single double-and-add pass (Shamir's trick). */
+    fn double_mul(&self, u1: &U256, u2: &U256, q: &Point) -> Point {
+        let gq = self.add(&self.g, q);
+        let mut result = self.infinity();
+        for i in (0..256).rev() {
+            result = self.double(&result);
+            match (u1.bit(i), u2.bit(i)) {
+                (true, true) => result = self.add(&result, &gq),
+                (true, false) => result = self.add(&result, &self.g),
+                (false, true) => result = self.add(&result, q),
+                (false, false) => {}
+            }
+        }
+        result
+    }
+
+    /** Convert a Jacobian point to the affine x coordinate, outside the Montgomery domain. */
+    fn affine_x(&self, point: &Point) -> U256 {
+        let p = &self.p;
+        let z_inv = p.mont_inv(&point.z);
+        let z_inv2 = p.mont_mul(&z_inv, &z_inv);
+        p.to_int(&p.mont_mul(&point.x, &z_inv2))
+    }
+}
+
+/**
+    A P-256 public key for ECDSA signature verification (FIPS 186-4), as used by the ES256
+    algorithm for JSON Web Tokens. Only verification is implemented, so none of the
+    arithmetic needs to be constant time since every input is public.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct PublicKey {
+    x: [u8; 32],
+    y: [u8; 32],
+}
+
+impl PublicKey {
+    /**
+        Create a public key from its big-endian affine coordinates, returns None if the
+        point is not on the curve.
+    */
+    pub fn from_coordinates(x: &[u8], y: &[u8]) -> Option<Self> {
+        let key = PublicKey {
+            x: x.try_into().ok()?,
+            y: y.try_into().ok()?,
+        };
+        key.point(&Curve::p256())?;
+        Some(key)
+    }
+
+    /** Parse an uncompressed SEC1 point, `0x04 || x || y`. */
+    pub fn from_sec1(bytes: &[u8]) -> Option<Self> {
+        match bytes.len() == 65 && bytes[0] == 0x04 {
+            true => PublicKey::from_coordinates(&bytes[1..33], &bytes[33..]),
+            false => None,
+        }
+    }
+
+    /**
+        Parse a DER encoded SubjectPublicKeyInfo for a P-256 key, which is what a PEM
+        `BEGIN PUBLIC KEY` block contains.
+    */
+    pub fn from_spki_der(der: &[u8]) -> Option<Self> {
+        // SEQUENCE { SEQUENCE { id-ecPublicKey, prime256v1 }, BIT STRING (0 unused bits) }
+        const SPKI_PREFIX: [u8; 26] = [
+            0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06,
+            0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+        ];
+        match der.len() == SPKI_PREFIX.len() + 65 && der.starts_with(&SPKI_PREFIX) {
+            true => PublicKey::from_sec1(&der[SPKI_PREFIX.len()..]),
+            false => None,
+        }
+    }
+
+    /** Parse a PEM encoded `-----BEGIN PUBLIC KEY-----` block. */
+    pub fn from_pem(pem: &str) -> Option<Self> {
+        let body = pem
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+            .collect::<String>();
+        PublicKey::from_spki_der(&super::base64_decode(&body).ok()?)
+    }
+
+    fn point(&self, curve: &Curve) -> Option<Point> {
+        let x = U256::from_be_bytes(&self.x);
+        let y = U256::from_be_bytes(&self.y);
+        if !x.less_than(&curve.p.m) || !y.less_than(&curve.p.m) {
+            return None;
+        }
+        let (x, y) = (curve.p.to_mont(&x), curve.p.to_mont(&y));
+        match curve.is_on_curve(&x, &y) {
+            true => Some(Point {
+                x,
+                y,
+                z: curve.p.to_mont(&U256::ONE),
+            }),
+            false => None,
+        }
+    }
+
+    /**
+        Verify an ECDSA signature over the SHA-256 digest of a message, the signature is
+        the 64 byte concatenation of `r || s` as used by JWS.
+    */
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        if signature.len() != 64 {
+            return false;
+        }
+        let curve = Curve::p256();
+        let n = &curve.n;
+        let q = match self.point(&curve) {
+            Some(q) => q,
+            None => return false,
+        };
+
+        let r = U256::from_be_bytes(signature[..32].try_into().unwrap());
+        let s = U256::from_be_bytes(signature[32..].try_into().unwrap());
+        if r.is_zero() || s.is_zero() || !r.less_than(&n.m) || !s.less_than(&n.m) {
+            return false;
+        }
+
+        let e = n.reduce_once(&U256::from_be_bytes(&sha256(message)));
+        let w = n.to_int(&n.mont_inv(&n.to_mont(&s)));
+        let u1 = n.mul(&e, &w);
+        let u2 = n.mul(&r, &w);
+
+        let point = curve.double_mul(&u1, &u2, &q);
+        if point.z.is_zero() {
+            return false;
+        }
+        n.reduce_once(&curve.affine_x(&point)) == r
+    }
+}
@@ -0,0 +1,78 @@
+use super::hmac::HmacSha256;
+use super::sha256::Sha256;
+
+/**
+    Derive a key from a password with PBKDF2 using HMAC-SHA256 as the pseudo-random
+    function (RFC 8018), producing `length` bytes of output.
+
+    let key = pbkdf2_hmac_sha256(b"password", &salt, 100_000, 32);
+*/
+pub fn pbkdf2_hmac_sha256(password: &[u8], salt: &[u8], iterations: u32, length: usize) -> Vec<u8> {
+    // the keyed state is computed once and cloned for every iteration
+    let mac = HmacSha256::new(password);
+    let mut output = Vec::with_capacity(length);
+    let mut block_index: u32 = 1;
+
+    while output.len() < length {
+        let mut first = mac.clone();
+        first.update(salt);
+        first.update(&block_index.to_be_bytes());
+        let mut u = first.finalize();
+        let mut t = u;
+
+        for _ in 1..iterations {
+            let mut next = mac.clone();
+            next.update(&u);
+            u = next.finalize();
+            t.iter_mut().zip(u.iter()).for_each(|(t, u)| *t ^= u);
+        }
+
+        let remaining = (length - output.len()).min(Sha256::OUTPUT_SIZE);
+        output.extend_from_slice(&t[..remaining]);
+        block_index += 1;
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::util::hex_encode;
+
+    fn derive(password: &str, salt: &str, iterations: u32, length: usize) -> String {
+        hex_encode(&pbkdf2_hmac_sha256(
+            password.as_bytes(),
+            salt.as_bytes(),
+            iterations,
+            length,
+        ))
+    }
+
+    #[test]
+    fn matches_rfc_7914_one_iteration() {
+        assert_eq!(
+            derive("passwd", "salt", 1, 64),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+             49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        );
+    }
+
+    #[test]
+    fn matches_rfc_7914_many_iterations() {
+        assert_eq!(
+            derive("Password", "NaCl", 80000, 64),
+            "4ddcd8f60b98be21830cee5ef22701f9641a4418d04c0414aeff08876b34ab56\
+             a1d425a1225833549adb841b51c9b3176a272bdebba1d078478f62b397f33c8d"
+        );
+    }
+
+    #[test]
+    fn truncates_to_the_requested_length() {
+        assert_eq!(
+            derive("password", "salt", 4096, 32),
+            "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+        );
+        assert_eq!(derive("password", "salt", 4096, 7), "c5e478d59288c8");
+    }
+}
@@ -0,0 +1,112 @@
+use super::hex::hex_decode;
+
+/**
+    Percent-encode a string so it is safe to use in a URL query or form body, every byte
+    except the unreserved characters of RFC 3986 is encoded.
+
+    percent_encode("a b&c") == "a%20b%26c"
+*/
+pub fn percent_encode(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                output.push(byte as char)
+            }
+            _ => output.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    output
+}
+
+/**
+    Percent-encode a URL path, the characters which RFC 3986 allows in a path segment and
+    the `/` between segments are kept.
+
+    encode_path("/docs/My File.pdf") == "/docs/My%20File.pdf"
+*/
+pub fn encode_path(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                output.push(byte as char)
+            }
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => {
+                output.push(byte as char)
+            }
+            b':' | b'@' | b'/' => output.push(byte as char),
+            _ => output.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    output
+}
+
+/**
+    Decode the `%XX` escapes in a string, returns None if an escape is malformed or the
+    decoded bytes are not valid UTF-8.
+*/
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let escape = input.get(index + 1..index + 3)?;
+                output.push(*hex_decode(escape).ok()?.first()?);
+                index += 3;
+            }
+            byte => {
+                output.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(output).ok()
+}
+
+/**
+    Decode a single key or value of a form body or URL query, a `+` is a space and `%2B` a
+    literal plus. Returns None if an escape is malformed or the result is not UTF-8.
+*/
+pub fn form_decode(component: &str) -> Option<String> {
+    percent_decode(&component.replace('+', " "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_everything_but_unreserved_characters() {
+        assert_eq!(percent_encode("a b&c"), "a%20b%26c");
+        assert_eq!(percent_encode("AZaz09-._~"), "AZaz09-._~");
+        assert_eq!(percent_encode("/?#+="), "%2F%3F%23%2B%3D");
+        assert_eq!(percent_encode("é"), "%C3%A9");
+    }
+
+    #[test]
+    fn keeps_path_characters() {
+        assert_eq!(encode_path("/docs/My File.pdf"), "/docs/My%20File.pdf");
+        assert_eq!(encode_path("/a:b@c/!$&'()*+,;="), "/a:b@c/!$&'()*+,;=");
+        assert_eq!(encode_path("/a?b#c%"), "/a%3Fb%23c%25");
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("a%20b%26c").as_deref(), Some("a b&c"));
+        assert_eq!(percent_decode("%C3%A9").as_deref(), Some("é"));
+        assert_eq!(percent_decode("a+b").as_deref(), Some("a+b"));
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn form_decoding_turns_plus_into_space() {
+        assert_eq!(form_decode("a+b%2Bc").as_deref(), Some("a b+c"));
+        assert_eq!(form_decode("%zz"), None);
+    }
+}
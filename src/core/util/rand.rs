@@ -1,4 +1,6 @@
+use std::fs::File;
 use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io::{Read, Result};
 
 #[derive(Debug)]
 pub struct Rand {
@@ -27,7 +29,6 @@ impl Rand {
 
         hasher.write_u64(system_time);
         let generated = hasher.finish();
-        println!("[rand] generated hash: {:}", generated);
         self.last_generated = generated;
         generated
     }
@@ -40,3 +41,13 @@ impl Rand {
 pub fn generate_random_u64() -> u64 {
     Rand::new().generate_u64()
 }
+
+/**
+    Fill a buffer with cryptographically secure random bytes from the operating system,
+    this should be used for anything which must not be guessed such as session IDs.
+*/
+pub fn secure_random_bytes(length: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; length];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/**
+    Process signals which can be listened for, the numbers are the same on Linux and macOS.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    Hangup = 1,
+    Interrupt = 2,
+    Terminate = 15,
+}
+
+const SIGNALS: [Signal; 3] = [Signal::Hangup, Signal::Interrupt, Signal::Terminate];
+
+static RECEIVED: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+static LISTENING: [AtomicBool; 3] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+impl Signal {
+    fn index(self) -> usize {
+        SIGNALS.iter().position(|signal| *signal == self).unwrap()
+    }
+}
+
+#[cfg(unix)]
+mod ffi {
+    pub type Handler = extern "C" fn(i32);
+
+    extern "C" {
+        pub fn signal(signum: i32, handler: Handler) -> usize;
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn handle_signal(signum: i32) {
+    // only atomics are safe to touch from a signal handler
+    if let Some(index) = SIGNALS.iter().position(|signal| *signal as i32 == signum) {
+        RECEIVED[index].fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/**
+    Install a handler which counts how many times a signal has been received, instead of
+    the default action of ending the process. Listening more than once has no effect, and
+    on platforms without signals this does nothing.
+
+    signal::listen(Signal::Hangup);
+    let seen = signal::received(Signal::Hangup);
+    // later
+    if signal::received(Signal::Hangup) != seen { reopen() }
+*/
+pub fn listen(signal: Signal) {
+    if LISTENING[signal.index()].swap(true, Ordering::SeqCst) {
+        return;
+    }
+    #[cfg(unix)]
+    unsafe {
+        ffi::signal(signal as i32, handle_signal);
+    }
+}
+
+/** The number of times a signal has been received since `listen` was called. */
+pub fn received(signal: Signal) -> u64 {
+    RECEIVED[signal.index()].load(Ordering::SeqCst)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    extern "C" {
+        fn raise(signum: i32) -> i32;
+    }
+
+    #[test]
+    fn counts_received_signals() {
+        listen(Signal::Hangup);
+        listen(Signal::Hangup);
+        let before = received(Signal::Hangup);
+        assert_eq!(unsafe { raise(Signal::Hangup as i32) }, 0);
+        assert_eq!(unsafe { raise(Signal::Hangup as i32) }, 0);
+        assert!(received(Signal::Hangup) >= before + 2);
+    }
+}
@@ -0,0 +1,11 @@
+/** Parse a size in bytes, which may end in K, M or G for binary multiples. */
+pub fn parse_size(input: &str) -> Option<u64> {
+    let input = input.trim();
+    let (number, multiplier) = match input.char_indices().last()? {
+        (index, 'K' | 'k') => (&input[..index], 1 << 10),
+        (index, 'M' | 'm') => (&input[..index], 1 << 20),
+        (index, 'G' | 'g') => (&input[..index], 1 << 30),
+        _ => (input, 1),
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
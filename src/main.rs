@@ -1,7 +1,19 @@
 use core::cli;
+use core::auth::htpasswd::PasswordHash;
+use core::auth::{
+    Accounts, AuthGuard, BasicAuth, Htpasswd, JwtAuth, OAuth2, Rules, UserStore,
+};
 use core::cli::args;
+use core::health;
+use core::http::{ConnectionLimits, Tus};
+use core::log::{self, Filter};
+use core::middleware::{Cors, IpFilter, RateLimit, TrustedProxies};
 use core::server::Server;
+use core::session::{MemoryStore, Sessions};
+use core::url::Normalize;
 use core::Stdout;
+use core::{AccessLog, ConfigFile, Health, LogFormat, Metrics};
+use std::env;
 use std::future::Future;
 use std::io::Error;
 use std::net::UdpSocket;
@@ -14,6 +26,37 @@ fn main() {
     // Process command line arguments.
     let argv = cli::process_args();
 
+    // Set the log level, e.g. `--log-level info,http_request=debug` or the same in RUST_LOG.
+    let log_filter = args::parse_as_str(&argv, "--log-level").or_else(|| env::var("RUST_LOG").ok());
+    if let Some(filter) = log_filter {
+        match Filter::parse(&filter) {
+            Ok(filter) => log::set_filter(filter),
+            Err(err) => {
+                error!("invalid log level: {}", err);
+                return;
+            }
+        }
+    }
+    // Write log records as text or as JSON lines.
+    if let Some(format) = args::parse_as_str(&argv, "--log-format") {
+        match log::Format::parse(&format) {
+            Some(format) => log::set_format(format),
+            None => {
+                error!("unknown log format: {}", format);
+                return;
+            }
+        }
+    }
+
+    // Print a salted hash for a htpasswd entry and exit.
+    if let Some(password) = args::parse_as_str(&argv, "--hash-password") {
+        match PasswordHash::new(&password) {
+            Ok(hash) => println!("{}", hash),
+            Err(err) => error!("failed to hash password: {}", err),
+        }
+        return;
+    }
+
     // Check if the user has specified a port.
     let port = match args::parse_as_num(&argv, "--port") {
         Some(port) => port as u16,
@@ -30,30 +73,268 @@ fn main() {
     let mut server = match Server::bind(&host, port) {
         Ok(server) => server,
         Err(err) => {
-            eprintln!("[serveros] failed to start server: {}", err);
+            error!("failed to start server: {}", err);
             return;
         }
     };
 
+    // Load optional settings from a config file.
+    let config_path = args::parse_as_str(&argv, "--config");
+    let config = match &config_path {
+        Some(path) => match ConfigFile::load(path) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("failed to load config {}: {}", path, err);
+                return;
+            }
+        },
+        None => ConfigFile::default(),
+    };
+
+    // Rotate the server's log file, it is also reopened on SIGHUP.
+    if config.has_section("stdout") {
+        match Stdout::from_config(&config) {
+            Ok(stdout) => server.stdout(stdout),
+            Err(err) => {
+                error!("failed to configure stdout: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Write a line for every request, `--access-log -` writes to stdout.
+    let access_log_path = args::parse_as_str(&argv, "--access-log");
+    if access_log_path.is_some() || config.has_section("access_log") {
+        let access_log = match access_log_path.as_deref() {
+            Some("-") => Ok(AccessLog::stdout()),
+            Some(path) => AccessLog::file(path),
+            None => AccessLog::from_config(&config),
+        };
+        match access_log {
+            Ok(access_log) => match config.get("access_log.format") {
+                Some(format) => server.access_log(access_log.format(LogFormat::parse(format))),
+                None => server.access_log(access_log),
+            },
+            Err(err) => {
+                error!("failed to open access log: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Cap open connections and event streams, shedding the rest with a 503.
+    if config.has_section("connections") {
+        match ConnectionLimits::from_config(&config) {
+            Ok(limits) => server.connection_limits(limits),
+            Err(err) => {
+                error!("failed to configure connection limits: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Merge duplicate slashes or redirect to canonical paths.
+    if config.has_section("paths") {
+        match Normalize::from_config(&config) {
+            Ok(normalize) => server.normalize_paths(normalize),
+            Err(err) => {
+                error!("failed to configure path normalization: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Serve request counts and server state for Prometheus, when enabled.
+    match Metrics::path_from_config(&config) {
+        Ok(Some(path)) => server.metrics(&path),
+        Ok(None) => (),
+        Err(err) => {
+            error!("failed to configure metrics: {}", err);
+            return;
+        }
+    }
+
+    // Serve liveness and readiness for an orchestrator, readiness fails during shutdown.
+    if config.get_bool("health.enabled").unwrap_or(true) {
+        match Health::from_config(&config) {
+            Ok(health) => {
+                server.health(health.check("public_dir", health::dir_readable("./src/public")))
+            }
+            Err(err) => {
+                error!("failed to configure health checks: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Read the client IP from the forwarded header when behind a trusted proxy.
+    if config.has_section("proxy") {
+        match TrustedProxies::from_config(&config) {
+            Ok(proxies) => server.middleware(proxies),
+            Err(err) => {
+                error!("failed to configure proxies: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Allow or deny client IPs, the rules are reloaded when the config file changes.
+    if let (Some(path), true) = (&config_path, config.has_section("ip_filter")) {
+        match IpFilter::watch(path) {
+            Ok(filter) => server.middleware(filter),
+            Err(err) => {
+                error!("failed to load ip filter: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Limit how many requests each client can make, before authentication so failed
+    // attempts are counted. A limit keyed by user also gets an IP keyed copy here.
+    let mut user_rate_limit = None;
+    if config.has_section("rate_limit") {
+        match RateLimit::from_config(&config) {
+            Ok(rate_limit) if rate_limit.is_keyed_by_user() => {
+                server.middleware(rate_limit.by_ip());
+                user_rate_limit = Some(rate_limit);
+            }
+            Ok(rate_limit) => server.middleware(rate_limit),
+            Err(err) => {
+                error!("failed to configure rate limit: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Allow cross-origin requests from a frontend running on another origin.
+    if let Some(origin) = args::parse_as_str(&argv, "--cors-origin") {
+        server.middleware(Cors::new().origin(&origin).credentials(true));
+    }
+
+    // Sessions are needed to keep users logged in with local accounts or OAuth2.
+    let users_path = args::parse_as_str(&argv, "--users");
+    if users_path.is_some() || config.has_section("oauth2") {
+        match Sessions::with_random_secret(MemoryStore::new()) {
+            Ok(sessions) => server.middleware(sessions),
+            Err(err) => {
+                error!("failed to start sessions: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Require basic authentication for the admin pages.
+    let mut admin_guard = None;
+    if let Some(path) = args::parse_as_str(&argv, "--htpasswd") {
+        let credentials = match Htpasswd::load(&path) {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                error!("failed to load htpasswd {}: {}", path, err);
+                return;
+            }
+        };
+        admin_guard = Some(AuthGuard::new(BasicAuth::new("admin", credentials)));
+    }
+
+    // Mount the login endpoints for a local user store, which also unlocks the admin pages.
+    if let Some(path) = users_path {
+        let users = match UserStore::open(&path) {
+            Ok(users) => users,
+            Err(err) => {
+                error!("failed to load users {}: {}", path, err);
+                return;
+            }
+        };
+        let accounts = Accounts::new(users);
+        let session_auth = accounts.authenticator();
+        admin_guard = match admin_guard {
+            Some(guard) => Some(guard.with(session_auth)),
+            None => Some(AuthGuard::new(session_auth)),
+        };
+        server.middleware(accounts);
+    }
+
+    if let Some(guard) = admin_guard {
+        let guard = guard
+            .protect("/log")
+            .protect("/log.html")
+            .protect("/events");
+        server.middleware(guard);
+    }
+
+    // Log users in with the OAuth2 provider in the `[oauth2]` section.
+    if config.has_section("oauth2") {
+        match OAuth2::from_config(&config) {
+            Ok(oauth) => server.middleware(oauth),
+            Err(err) => {
+                error!("failed to configure oauth2: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Require a bearer token for the paths listed in `[jwt] protect`.
+    if config.has_section("jwt") {
+        let jwt = match JwtAuth::from_config(&config) {
+            Ok(jwt) => jwt,
+            Err(err) => {
+                error!("failed to configure jwt: {}", err);
+                return;
+            }
+        };
+        let guard = config
+            .get_list("jwt.protect")
+            .iter()
+            .fold(AuthGuard::new(jwt), |guard, path| guard.protect(path));
+        server.middleware(guard);
+    }
+
+    // A limit keyed by user runs after authentication, behind the IP limit above.
+    if let Some(rate_limit) = user_rate_limit {
+        server.middleware(rate_limit);
+    }
+
+    // Accept resumable uploads with the tus protocol under `[tus] path`.
+    if config.has_section("tus") {
+        match Tus::from_config(&config) {
+            Ok(tus) => server.middleware(tus),
+            Err(err) => {
+                error!("failed to configure tus uploads: {}", err);
+                return;
+            }
+        }
+    }
+
+    // Restrict which roles may access which paths.
+    if config.has_section("authorization") {
+        match Rules::from_config(&config) {
+            Ok(rules) => server.authorize(rules),
+            Err(err) => {
+                error!("failed to load authorization rules: {}", err);
+                return;
+            }
+        }
+    }
+
     // Define routes.
     server.route("/", |sr| {
-        println!("[main] serving route: /");
+        debug!("serving route: /");
         sr.send_file("index.html")
     });
 
     server.route("/log", |sr| {
-        println!("[main] serving route: events.html");
+        debug!("serving route: events.html");
         sr.send_file("log.html")
     });
 
     // special endpoint for event-streams
     server.route("/events", |sr| {
-        println!("[main] serving route: events.html");
+        debug!("serving route: events.html");
         sr.event_souce()
     });
 
     server.route("/info", |sr| {
-        println!("[main] serving route: info.html");
+        debug!("serving route: info.html");
         sr.send_file("info.html")
     });
 